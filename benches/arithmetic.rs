@@ -0,0 +1,53 @@
+use formula_parser::compat::HashMap;
+use std::rc::Rc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use formula_parser::calculator::FormulaCalc;
+use formula_parser::env::Env;
+use formula_parser::formula::FormulaNode;
+use formula_parser::parser::Parser;
+
+const PRICING: &str = "((Qty * Price) * (1 - Discount)) + (Shipping / Qty) + Rate";
+
+fn row() -> HashMap<String, f64> {
+    let mut row = HashMap::new();
+    row.insert("Qty".to_string(), 12.0);
+    row.insert("Price".to_string(), 9.5);
+    row.insert("Discount".to_string(), 0.15);
+    row.insert("Shipping".to_string(), 30.0);
+    row
+}
+
+/// 对比纯四则运算表达式的后缀指令计算与逐节点计算的耗时
+fn arithmetic(c: &mut Criterion) {
+    let mut parser = Parser::new();
+    parser.parse("Rate := 0.5");
+    let compiled = parser.compile(PRICING);
+    assert!(compiled.is_arithmetic());
+    let row = row();
+
+    c.bench_function("arithmetic/postfix", |b| {
+        b.iter(|| compiled.eval_with(black_box(&row)).value)
+    });
+
+    let node = compiled.node();
+    let env = Env::new();
+    env.borrow_mut()
+        .insert("Rate", Rc::new(FormulaNode::Constant(0.5)));
+    c.bench_function("arithmetic/tree", |b| {
+        b.iter(|| {
+            let scratch = Env::extend(&env);
+            for (name, value) in black_box(&row) {
+                scratch
+                    .borrow_mut()
+                    .insert(name, Rc::new(FormulaNode::Constant(*value)));
+            }
+            let value = node.calc(&scratch);
+            Env::release(scratch);
+            value
+        })
+    });
+}
+
+criterion_group!(benches, arithmetic);
+criterion_main!(benches);
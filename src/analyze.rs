@@ -0,0 +1,217 @@
+use crate::compat::HashMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::calculator::CalculateOption;
+use crate::compiled::CompiledFormula;
+use crate::csv::write_row;
+
+/// 输入变量的取值范围，从 start 到 end (包含两端) 均匀地取 steps 个值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepRange {
+    pub start: f64,
+    pub end: f64,
+    pub steps: usize,
+}
+
+impl SweepRange {
+    pub fn new(start: f64, end: f64, steps: usize) -> Self {
+        SweepRange { start, end, steps }
+    }
+
+    /// 范围内依次取到的值，steps 为 1 时只取 start，为 0 时为空
+    pub fn values(&self) -> Vec<f64> {
+        match self.steps {
+            0 => Vec::new(),
+            1 => vec![self.start],
+            steps => {
+                let step = (self.end - self.start) / (steps - 1) as f64;
+                (0..steps)
+                    .map(|i| match i + 1 == steps {
+                        true => self.end,
+                        false => self.start + step * i as f64,
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// 敏感性分析中的一次计算，inputs 与 SensitivityTable::inputs 中的变量一一对应
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensitivityRow {
+    pub inputs: Vec<f64>,
+    pub value: CalculateOption,
+}
+
+/// 敏感性分析的结果表，每一行为输入变量的一种取值组合及其计算结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensitivityTable {
+    pub inputs: Vec<String>,
+    pub rows: Vec<SensitivityRow>,
+}
+
+impl SensitivityTable {
+    /// 输出为 CSV 文本，列依次为输入变量及 result 列，便于导入表格软件绘制图表
+    pub fn to_csv(&self) -> String {
+        let mut header = self.inputs.clone();
+        header.push("result".to_string());
+        let mut out = vec![write_row(&header)];
+        for row in self.rows.iter() {
+            let mut fields: Vec<String> = row.inputs.iter().map(|v| v.to_string()).collect();
+            fields.push(row.value.to_string());
+            out.push(write_row(&fields));
+        }
+        out.join("\n") + "\n"
+    }
+}
+
+/// 敏感性分析: 在 base 给定的其他输入不变的情况下，让 inputs 中的每个变量在各自的范围内取值，
+/// 对所有取值组合计算 formula，多个变量时后面的变量变化最快，与嵌套循环的顺序一致。
+/// 所有的组合在同一个临时环境中计算，不会修改解析器的环境
+pub fn sensitivity(
+    formula: &CompiledFormula,
+    base: &HashMap<String, f64>,
+    inputs: &[(&str, SweepRange)],
+) -> SensitivityTable {
+    let mut combinations: Vec<Vec<f64>> = vec![Vec::new()];
+    for (_, range) in inputs {
+        let values = range.values();
+        combinations = combinations
+            .into_iter()
+            .flat_map(|prefix| {
+                values.iter().map(move |value| {
+                    let mut combination = prefix.clone();
+                    combination.push(*value);
+                    combination
+                })
+            })
+            .collect();
+    }
+
+    let rows: Vec<HashMap<String, f64>> = combinations
+        .iter()
+        .map(|combination| {
+            let mut row = base.clone();
+            for ((name, _), value) in inputs.iter().zip(combination) {
+                row.insert(name.to_string(), *value);
+            }
+            row
+        })
+        .collect();
+    let results = formula.eval_batch(&rows);
+    SensitivityTable {
+        inputs: inputs.iter().map(|(name, _)| name.to_string()).collect(),
+        rows: combinations
+            .into_iter()
+            .zip(results)
+            .map(|(inputs, result)| SensitivityRow {
+                inputs,
+                value: result.value,
+            })
+            .collect(),
+    }
+}
+
+/// goal_seek 最多的迭代次数
+const MAX_ITERATIONS: usize = 100;
+
+/// 单变量求解的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoalSeekResult {
+    /// 求得的变量取值
+    pub value: f64,
+    /// 变量取 value 时表达式的计算结果
+    pub result: f64,
+    /// 计算到满足精度要求时的迭代次数
+    pub iterations: usize,
+}
+
+/// 单变量求解，与 Excel 的单变量求解相同: 在 bounds 范围内寻找 variable 的取值，使 formula 的计算结果
+/// 等于 target，用于反推盈亏平衡点、目标价格等，base 为其他输入变量的值。
+/// 计算结果在范围两端位于 target 两侧时，使用牛顿法迭代并以二分法保证收敛，否则只从范围中点开始
+/// 使用牛顿法迭代，导数通过差分近似得到。结果与 target 的误差不超过 target 的 1e-9 (至少为 1e-9)，
+/// 找不到满足条件的取值或计算结果不是数值时返回错误信息
+pub fn goal_seek(
+    formula: &CompiledFormula,
+    base: &HashMap<String, f64>,
+    target: f64,
+    variable: &str,
+    bounds: (f64, f64),
+) -> Result<GoalSeekResult, String> {
+    let (mut lo, mut hi) = match bounds.0 <= bounds.1 {
+        true => bounds,
+        false => (bounds.1, bounds.0),
+    };
+    let (min, max) = (lo, hi);
+    let mut row = base.clone();
+    // 计算 variable 取 x 时的结果与 target 之差
+    let mut eval = |x: f64| -> Result<f64, String> {
+        row.insert(variable.to_string(), x);
+        match formula.eval_with(&row).value {
+            CalculateOption::Num(n) if n.is_finite() => Ok(n - target),
+            value => Err(format!(
+                "{} 为 {} 时计算结果 {} 不是有限的数值",
+                variable, x, value
+            )),
+        }
+    };
+    let tolerance = 1e-9 * target.abs().max(1.0);
+    let found = |value: f64, diff: f64, iterations: usize| GoalSeekResult {
+        value,
+        result: diff + target,
+        iterations,
+    };
+
+    let (mut f_lo, f_hi) = (eval(lo)?, eval(hi)?);
+    if f_lo.abs() <= tolerance {
+        return Ok(found(lo, f_lo, 0));
+    }
+    if f_hi.abs() <= tolerance {
+        return Ok(found(hi, f_hi, 0));
+    }
+    let bracketed = f_lo.signum() != f_hi.signum();
+    let mut x = match bracketed {
+        true => lo - f_lo * (hi - lo) / (f_hi - f_lo),
+        false => (lo + hi) / 2.0,
+    };
+    for iterations in 1..=MAX_ITERATIONS {
+        let fx = eval(x)?;
+        if fx.abs() <= tolerance {
+            return Ok(found(x, fx, iterations));
+        }
+        if bracketed {
+            match fx.signum() == f_lo.signum() {
+                true => (lo, f_lo) = (x, fx),
+                false => hi = x,
+            }
+            if hi - lo <= f64::EPSILON * x.abs().max(1.0) {
+                return Err(format!(
+                    "{} 在 {} 附近的计算结果不连续，无法使结果等于 {}",
+                    variable, x, target
+                ));
+            }
+        }
+        // 差分的另一点不超出范围，以免在范围之外计算出错
+        let h = 1e-7 * x.abs().max(1.0);
+        let h = match x + h > max {
+            true => -h,
+            false => h,
+        };
+        let newton = x - fx * h / (eval(x + h)? - fx);
+        x = match newton.is_finite() && newton > lo && newton < hi {
+            true => newton,
+            false if bracketed => (lo + hi) / 2.0,
+            false => {
+                return Err(format!(
+                    "在 {} 的范围 [{}, {}] 内找不到使结果等于 {} 的取值",
+                    variable, min, max, target
+                ))
+            }
+        };
+    }
+    Err(format!(
+        "迭代 {} 次后仍未找到使结果等于 {} 的 {} 的取值",
+        MAX_ITERATIONS, target, variable
+    ))
+}
@@ -0,0 +1,108 @@
+use crate::formula::{FormulaNode, OperatorNode};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// 纯四则运算表达式的一条后缀指令
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArithOp {
+    Const(f64),
+    /// 读取第 n 个变量的值，n 为变量在 ArithProgram::names 中的位置
+    Load(usize),
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// 只由常量、变量及 + - * / 组成的表达式展开得到的后缀指令序列,
+/// 计算时直接在 f64 的栈上执行，不需要创建执行环境，也不会产生中间的 CalculateOption,
+/// 定价类的表达式大多是这种形式，由 CompiledFormula 在创建时自动识别
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArithProgram {
+    ops: Vec<ArithOp>,
+    /// 表达式中引用的变量名，按首次出现的顺序排列，不重复
+    names: Vec<String>,
+    /// 计算过程中栈的最大深度
+    depth: usize,
+}
+
+impl ArithProgram {
+    /// 将 node 展开为后缀指令，node 中包含函数调用、比较或逻辑操作符等其他节点时返回 None
+    pub fn compile(node: &FormulaNode) -> Option<ArithProgram> {
+        let mut program = ArithProgram {
+            ops: Vec::new(),
+            names: Vec::new(),
+            depth: 0,
+        };
+        program.emit(node, 0)?;
+        Some(program)
+    }
+
+    fn emit(&mut self, node: &FormulaNode, depth: usize) -> Option<()> {
+        let op = match node {
+            FormulaNode::Constant(n) => ArithOp::Const(*n),
+            FormulaNode::Variant(name) => match self.names.iter().position(|n| n == name) {
+                Some(index) => ArithOp::Load(index),
+                None => {
+                    self.names.push(name.clone());
+                    ArithOp::Load(self.names.len() - 1)
+                }
+            },
+            FormulaNode::Quote(node) => return self.emit(node, depth),
+            FormulaNode::Operator(op) => {
+                let (left, right, op) = match op.as_ref() {
+                    OperatorNode::Plus { left, right } => (left, right, ArithOp::Add),
+                    OperatorNode::Minus { left, right } => (left, right, ArithOp::Sub),
+                    OperatorNode::Multiply { left, right } => (left, right, ArithOp::Mul),
+                    OperatorNode::Divide { left, right } => (left, right, ArithOp::Div),
+                    _ => return None,
+                };
+                self.emit(left, depth)?;
+                self.emit(right, depth + 1)?;
+                self.ops.push(op);
+                return Some(());
+            }
+            _ => return None,
+        };
+        self.ops.push(op);
+        self.depth = self.depth.max(depth + 1);
+        Some(())
+    }
+
+    /// 表达式中引用的变量名
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// 计算表达式，变量的值通过 lookup 按名称获取，每个变量只会查找一次,
+    /// 任一变量无法得到数值时返回 None，此时调用者应退回到逐节点计算的方式
+    pub fn eval<F>(&self, mut lookup: F) -> Option<f64>
+    where
+        F: FnMut(&str) -> Option<f64>,
+    {
+        let mut values = Vec::with_capacity(self.names.len());
+        for name in self.names.iter() {
+            values.push(lookup(name)?);
+        }
+
+        let mut stack = Vec::with_capacity(self.depth);
+        for op in self.ops.iter() {
+            let value = match *op {
+                ArithOp::Const(n) => n,
+                ArithOp::Load(index) => values[index],
+                op => {
+                    let r = stack.pop()?;
+                    let l = stack.pop()?;
+                    match op {
+                        ArithOp::Add => l + r,
+                        ArithOp::Sub => l - r,
+                        ArithOp::Mul => l * r,
+                        _ => l / r,
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop()
+    }
+}
@@ -0,0 +1,109 @@
+use crate::calculator::{to_float, CalculateOption};
+use crate::env::Env;
+use crate::formula::FormulaNode;
+use crate::rounding::RoundingMode;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// 确定性计算模式的配置，通过 CalculateOptions::deterministic 开启，用于需要复现计算结果的审计场景:
+/// 数值结果按固定的小数位数舍入，通过 reg_nondeterministic_build_in 注册的函数 (如 Rand、Now)
+/// 除非列在 allowed 中否则调用时返回错误，每次计算都会在 CalculateResult::audit 中生成审计记录
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deterministic {
+    /// 数值结果保留的小数位数，按四舍六入五成双舍入，默认为 10
+    pub decimals: u32,
+    /// 允许调用的非确定性内建函数
+    pub allowed: Vec<String>,
+}
+
+impl Default for Deterministic {
+    fn default() -> Self {
+        Deterministic {
+            decimals: 10,
+            allowed: Vec::new(),
+        }
+    }
+}
+
+impl Deterministic {
+    /// 将结果中的数值按 decimals 位小数舍入，-0 统一为 0，非有限的数值保持不变
+    pub fn round(&self, value: CalculateOption) -> CalculateOption {
+        RoundingMode::HalfEven.round_value(value, self.decimals as i32)
+    }
+}
+
+/// 一次确定性计算的审计记录
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    /// 表达式的规范形式，即语法树输出的公式文本
+    pub formula: String,
+    /// formula 的 FNV-1a 哈希值，与 Rust 的版本及平台无关，可以持久化保存
+    pub formula_hash: u64,
+    /// 计算中读取的输入，即值为常量的变量及由外部变量解析器提供的变量，按名称排序
+    pub inputs: Vec<(String, CalculateOption)>,
+    /// 舍入后的计算结果
+    pub result: CalculateOption,
+}
+
+impl AuditRecord {
+    /// 以 node 的规范形式及计算中记录的输入生成审计记录
+    pub fn new(node: &FormulaNode, env: &Env, result: &CalculateOption) -> Self {
+        let formula = node.to_string();
+        AuditRecord {
+            formula_hash: fnv1a(formula.as_bytes()),
+            formula,
+            inputs: env.take_audit_inputs(),
+            result: result.clone(),
+        }
+    }
+}
+
+/// 64 位的 FNV-1a 哈希
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// 变量的定义是否为输入数据，即去掉命名及括号后为常量
+pub fn is_input(node: &FormulaNode) -> bool {
+    match node.unquoted() {
+        FormulaNode::Formula { formula, .. } => is_input(formula),
+        FormulaNode::Constant(_)
+        | FormulaNode::Bool(_)
+        | FormulaNode::Complex(_)
+        | FormulaNode::Vector(_)
+        | FormulaNode::Matrix(_)
+        | FormulaNode::Text(_)
+        | FormulaNode::Money(_)
+        | FormulaNode::Opaque(_) => true,
+        _ => false,
+    }
+}
+
+/// 按 CalculateOptions::precision 舍入表达式 node 的计算结果 value，再按确定性计算模式处理并生成审计记录,
+/// 均未开启时原样返回 value，分数模式下的分数结果转换为数值
+pub fn finish(
+    node: &FormulaNode,
+    env: &Env,
+    value: CalculateOption,
+) -> (CalculateOption, Option<AuditRecord>) {
+    let (precision, mode) = {
+        let options = env.options();
+        (options.precision, options.deterministic.clone())
+    };
+    // 分数模式下的分数只在输出时转换为数值
+    let value = to_float(value);
+    let value = match precision {
+        Some(precision) => precision.apply(value),
+        None => value,
+    };
+    match mode {
+        Some(mode) => {
+            let value = mode.round(value);
+            let record = AuditRecord::new(node, env, &value);
+            (value, Some(record))
+        }
+        None => (value, None),
+    }
+}
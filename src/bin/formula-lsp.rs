@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use formula_parser::lsp::Document;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionOptions, CompletionParams, CompletionResponse, GotoDefinitionParams,
+    GotoDefinitionResponse, HoverParams, HoverProviderCapability, Location, OneOf,
+    PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    Url,
+};
+
+/// 通过标准输入输出与编辑器通信的语言服务器
+fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    main_loop(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// 处理编辑器发来的消息直到收到 shutdown 请求，结束时释放 connection 以便关闭输入输出线程
+fn main_loop(connection: Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let mut documents: HashMap<Url, String> = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = handle_request(&documents, request)?;
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = handle_notification(&mut documents, notification)? {
+                    let source = documents.get(&uri).map_or("", String::as_str);
+                    let params = PublishDiagnosticsParams {
+                        diagnostics: Document::new(source).diagnostics(),
+                        uri,
+                        version: None,
+                    };
+                    let notification = Notification::new(PublishDiagnostics::METHOD.into(), params);
+                    connection
+                        .sender
+                        .send(Message::Notification(notification))?;
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+    Ok(())
+}
+
+/// 更新文档的内容，返回需要重新发布诊断信息的文档
+fn handle_notification(
+    documents: &mut HashMap<Url, String>,
+    notification: Notification,
+) -> Result<Option<Url>, serde_json::Error> {
+    Ok(match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: <DidOpenTextDocument as lsp_types::notification::Notification>::Params =
+                serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            documents.insert(uri.clone(), params.text_document.text);
+            Some(uri)
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: <DidChangeTextDocument as lsp_types::notification::Notification>::Params =
+                serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            // 使用全量同步，最后一次变更即为文档的完整内容
+            if let Some(change) = params.content_changes.into_iter().last() {
+                documents.insert(uri.clone(), change.text);
+            }
+            Some(uri)
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: <DidCloseTextDocument as lsp_types::notification::Notification>::Params =
+                serde_json::from_value(notification.params)?;
+            documents.remove(&params.text_document.uri);
+            Some(params.text_document.uri)
+        }
+        _ => None,
+    })
+}
+
+fn handle_request(
+    documents: &HashMap<Url, String>,
+    request: Request,
+) -> Result<Response, serde_json::Error> {
+    let source = |uri: &Url| documents.get(uri).map_or("", String::as_str);
+    let result = match request.method.as_str() {
+        HoverRequest::METHOD => {
+            let params: HoverParams = serde_json::from_value(request.params)?;
+            let doc = params.text_document_position_params;
+            let hover = Document::new(source(&doc.text_document.uri)).hover(doc.position);
+            serde_json::to_value(hover)?
+        }
+        GotoDefinition::METHOD => {
+            let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+            let doc = params.text_document_position_params;
+            let uri = doc.text_document.uri;
+            let location = Document::new(source(&uri))
+                .definition(doc.position)
+                .map(|range| GotoDefinitionResponse::Scalar(Location { uri, range }));
+            serde_json::to_value(location)?
+        }
+        Completion::METHOD => {
+            let params: CompletionParams = serde_json::from_value(request.params)?;
+            let uri = params.text_document_position.text_document.uri;
+            let items = Document::new(source(&uri)).completion();
+            serde_json::to_value(CompletionResponse::Array(items))?
+        }
+        _ => serde_json::Value::Null,
+    };
+    Ok(Response::new_ok(request.id, result))
+}
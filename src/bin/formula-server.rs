@@ -0,0 +1,32 @@
+use tiny_http::{Header, Method, Response, Server};
+
+fn main() {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let server = Server::http(&addr).unwrap_or_else(|e| panic!("监听 {} 时出错: {}", addr, e));
+    println!("formula-server 正在监听 {}", addr);
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    for mut request in server.incoming_requests() {
+        let (status, body) = match request.method() {
+            Method::Post => {
+                let mut body = String::new();
+                match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => formula_parser::server::handle(request.url(), &body),
+                    Err(e) => (400, format!("{{\"error\":{{\"message\":\"{}\"}}}}", e)),
+                }
+            }
+            _ => (
+                405,
+                "{\"error\":{\"message\":\"仅支持 POST 请求\"}}".to_string(),
+            ),
+        };
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(content_type.clone());
+        if let Err(e) = request.respond(response) {
+            eprintln!("发送响应时出错: {}", e);
+        }
+    }
+}
@@ -0,0 +1,989 @@
+use crate::calculator::{call_function, CalculateOption, FormulaCalc};
+#[cfg(not(any(feature = "std", test)))]
+use crate::compat::Float;
+use crate::env::EnvError;
+use crate::formula::{BuiltinInfo, CalcError, FormulaNode, FuncContext};
+use crate::matrix::Matrix;
+use crate::money::{currency_style, Money};
+use crate::parser::Parser;
+use crate::plugin::FormulaPlugin;
+use crate::rounding::RoundingMode;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// 内建函数及其描述信息: 函数名、说明、参数名、调用示例、参数个数的范围
+type BuildInEntry = (
+    &'static str,
+    &'static str,
+    &'static [&'static str],
+    &'static [&'static str],
+    (usize, Option<usize>),
+    fn(&FuncContext) -> Result<CalculateOption, CalcError>,
+);
+
+const BUILD_INS: &[BuildInEntry] = &[
+    (
+        "prev",
+        "获取变量在之前的计算中得到的值，N 默认为 1",
+        &["A", "N"],
+        &["prev(A)", "prev(A, 2)"],
+        (1, Some(2)),
+        prev,
+    ),
+    (
+        "IfError",
+        "expr 的结果为错误时返回 fallback",
+        &["expr", "fallback"],
+        &["IfError(A / B, 0)"],
+        (2, Some(2)),
+        if_error,
+    ),
+    (
+        "If",
+        "cond 为 true 或非 0 时返回 then，否则返回 otherwise (省略时为 false)，只计算被选中的分支",
+        &["cond", "then", "otherwise"],
+        &["If(A > 0, A * 2, 0)"],
+        (2, Some(3)),
+        if_then,
+    ),
+    (
+        "Concat",
+        "将数值及向量依次连接为一个向量",
+        &["values..."],
+        &["Concat(V, 1, W)"],
+        (1, None),
+        concat,
+    ),
+    (
+        "Apply",
+        "以其余的参数调用函数 f",
+        &["f", "args..."],
+        &["Apply(F, 1, 2)"],
+        (1, None),
+        apply,
+    ),
+    (
+        "Map",
+        "对向量的每一个元素调用 f",
+        &["V", "f"],
+        &["Map(V, Double)"],
+        (2, Some(2)),
+        map,
+    ),
+    (
+        "Filter",
+        "保留向量中使 pred 返回 true 的元素",
+        &["V", "pred"],
+        &["Filter(V, IsPositive)"],
+        (2, Some(2)),
+        filter,
+    ),
+    (
+        "Reduce",
+        "以 init 为初始值，使用 f 依次累积向量的元素",
+        &["V", "f", "init"],
+        &["Reduce(V, Add, 0)"],
+        (3, Some(3)),
+        reduce,
+    ),
+    (
+        "GroupBy",
+        "按 key_fn 的结果将记录分组，对每组调用 agg_fn，返回以 [键, 聚合值] 为行、按键升序排列的矩阵，矩阵的每一行或向量的每个元素为一条记录",
+        &["records", "key_fn", "agg_fn"],
+        &["GroupBy(Orders, Category(r) { r[0] }, Total(g) { Sum(Transpose(g)[1]) })", "GroupBy(Scores, Tier(x) { Floor(x, 0 - 1) }, Count)"],
+        (3, Some(3)),
+        group_by,
+    ),
+    (
+        "Sum",
+        "求和",
+        &["values..."],
+        &["Sum(A, B, C)"],
+        (0, None),
+        sum,
+    ),
+    (
+        "Avg",
+        "求平均值",
+        &["values..."],
+        &["Avg(A, B, C)"],
+        (1, None),
+        avg,
+    ),
+    (
+        "Min",
+        "求最小值",
+        &["values..."],
+        &["Min(A, B)"],
+        (1, None),
+        min,
+    ),
+    (
+        "Max",
+        "求最大值",
+        &["values..."],
+        &["Max(A, B)"],
+        (1, None),
+        max,
+    ),
+    (
+        "Count",
+        "统计数值的个数",
+        &["values..."],
+        &["Count(V)"],
+        (0, None),
+        count,
+    ),
+    (
+        "Len",
+        "求向量的元素个数、矩阵的行数或文本的字符个数",
+        &["V"],
+        &["Len(Prices)"],
+        (1, Some(1)),
+        len,
+    ),
+    (
+        "Product",
+        "求乘积",
+        &["values..."],
+        &["Product(A, B)"],
+        (0, None),
+        product,
+    ),
+    (
+        "Median",
+        "求中位数",
+        &["values..."],
+        &["Median(V)"],
+        (1, None),
+        median,
+    ),
+    (
+        "Variance",
+        "求样本方差",
+        &["values..."],
+        &["Variance(V)"],
+        (1, None),
+        variance,
+    ),
+    (
+        "StdDev",
+        "求样本标准差",
+        &["values..."],
+        &["StdDev(V)"],
+        (1, None),
+        std_dev,
+    ),
+    (
+        "Percentile",
+        "求第 k 百分位数，k 的范围为 [0, 1]",
+        &["V", "k"],
+        &["Percentile(V, 0.9)"],
+        (2, Some(2)),
+        percentile,
+    ),
+    (
+        "Correl",
+        "求两个等长向量的相关系数",
+        &["X", "Y"],
+        &["Correl(X, Y)"],
+        (2, Some(2)),
+        correl,
+    ),
+    (
+        "Round",
+        "按 digits 位小数四舍五入，digits 默认为 0，为负数时舍入到整数部分的对应位",
+        &["x", "digits"],
+        &["Round(Price, 2)", "Round(Total, 0 - 2)"],
+        (1, Some(2)),
+        round,
+    ),
+    (
+        "RoundHalfEven",
+        "按 digits 位小数进行银行家舍入，即四舍六入五成双",
+        &["x", "digits"],
+        &["RoundHalfEven(Amount, 2)"],
+        (1, Some(2)),
+        round_half_even,
+    ),
+    (
+        "Floor",
+        "按 digits 位小数向下舍入",
+        &["x", "digits"],
+        &["Floor(Price, 1)"],
+        (1, Some(2)),
+        floor,
+    ),
+    (
+        "Ceiling",
+        "按 digits 位小数向上舍入",
+        &["x", "digits"],
+        &["Ceiling(Price, 1)"],
+        (1, Some(2)),
+        ceiling,
+    ),
+    (
+        "Transpose",
+        "求矩阵的转置",
+        &["M"],
+        &["Transpose([[1, 2], [3, 4]])"],
+        (1, Some(1)),
+        transpose,
+    ),
+    (
+        "Inverse",
+        "求方阵的逆矩阵",
+        &["M"],
+        &["Inverse([[1, 2], [3, 4]])"],
+        (1, Some(1)),
+        inverse,
+    ),
+    (
+        "Det",
+        "求方阵的行列式",
+        &["M"],
+        &["Det([[1, 2], [3, 4]])"],
+        (1, Some(1)),
+        det,
+    ),
+    (
+        "Lookup",
+        "在矩阵 table 的第一列中查找 value，返回该行第 col 列 (从 1 开始) 的值，approximate 为 true 或非 0 时第一列应为升序，查找不大于 value 的最后一行",
+        &["value", "table", "col", "approximate"],
+        &["Lookup(Code, [[1, 0.1], [2, 0.2]], 2)"],
+        (3, Some(4)),
+        lookup,
+    ),
+    (
+        "MatchRange",
+        "在升序的 breakpoints 中查找不大于 value 的最后一个分界点，返回 results 中对应位置的值",
+        &["value", "breakpoints", "results"],
+        &["MatchRange(Income, [0, 5000, 20000], [0, 0.1, 0.2])"],
+        (3, Some(3)),
+        match_range,
+    ),
+    (
+        "Format",
+        "按 pattern 将数值格式化为文本，0 为必须显示的数字，# 为可省略的数字，, 为千位分隔符，以 % 结尾时显示为百分比",
+        &["x", "pattern"],
+        &["Format(Price, \"#,##0.00\")", "Format(Rate, \"0.0%\")"],
+        (2, Some(2)),
+        format,
+    ),
+    (
+        "FormatPercent",
+        "将数值格式化为保留 decimals 位小数的百分比文本",
+        &["x", "decimals"],
+        &["FormatPercent(0.125, 1)"],
+        (1, Some(2)),
+        format_percent,
+    ),
+    (
+        "FormatCurrency",
+        "将数值或金额格式化为带有货币符号及千位分隔符的金额文本，currency 为 USD、EUR 等货币代码，x 为金额时可以省略",
+        &["x", "currency"],
+        &["FormatCurrency(Total, \"USD\")", "FormatCurrency(Money(12.5, \"EUR\"))"],
+        (1, Some(2)),
+        format_currency,
+    ),
+    (
+        "Money",
+        "以 amount 及 USD、EUR 等货币代码得到货币金额，不同货币的金额之间无法直接计算",
+        &["amount", "currency"],
+        &["Money(12.5, \"EUR\")"],
+        (2, Some(2)),
+        money,
+    ),
+    (
+        "Amount",
+        "金额的数值部分",
+        &["money"],
+        &["Amount(Price)"],
+        (1, Some(1)),
+        amount,
+    ),
+    (
+        "Currency",
+        "金额的货币代码",
+        &["money"],
+        &["Currency(Price)"],
+        (1, Some(1)),
+        currency,
+    ),
+    (
+        "Convert",
+        "将金额换算为 currency，rate 为汇率或以 (from, to) 两个货币代码返回汇率的函数",
+        &["money", "currency", "rate"],
+        &["Convert(Price, \"EUR\", 0.92)", "Convert(Price, \"EUR\", Rate)"],
+        (3, Some(3)),
+        convert,
+    ),
+];
+
+/// 解析器默认加载的标准函数包
+pub struct StandardLibrary;
+
+impl FormulaPlugin for StandardLibrary {
+    fn name(&self) -> &str {
+        "std"
+    }
+
+    fn install(&self, parser: &mut Parser) -> Result<(), EnvError> {
+        load(parser)
+    }
+}
+
+/// 加载解析器默认提供的内建函数
+pub fn load(parser: &Parser) -> Result<(), EnvError> {
+    for (name, description, params, examples, arity, f) in BUILD_INS {
+        let info = BuiltinInfo {
+            name: name.to_string(),
+            description: description.to_string(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+            examples: examples.iter().map(|e| e.to_string()).collect(),
+            arity: Some(*arity),
+        };
+        parser.reg_build_in_with_info(info, *f)?;
+    }
+    Ok(())
+}
+
+/// prev(A) 获取变量 A 在上一次计算中得到的值, prev(A, N) 获取之前第 N 次计算的值
+/// 需通过 CalculateOptions::history_limit 开启历史值的记录，没有对应的历史值时返回 None
+pub fn prev(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let name = ctx.arg_str(0)?;
+    let back = match ctx.args.len() {
+        1 => 1,
+        _ => match ctx.arg_num(1)? {
+            n if n >= 1.0 => n as usize,
+            _ => return Err(CalcError::arg(1, "应为正整数")),
+        },
+    };
+
+    Ok(ctx
+        .env
+        .borrow()
+        .history_value(name, back)
+        .unwrap_or(CalculateOption::None))
+}
+
+/// IfError(expr, fallback) 计算 expr，其结果为错误时返回 fallback 的计算结果，否则返回 expr 的结果
+pub fn if_error(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    match ctx.args[0].calc(&ctx.env) {
+        CalculateOption::Err(_) => Ok(ctx.args[1].calc(&ctx.env)),
+        value => Ok(value),
+    }
+}
+
+/// If(cond, then, otherwise) 根据 cond 计算 then 或 otherwise 之一，省略 otherwise 且 cond 不成立时返回 false
+pub fn if_then(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let cond = match ctx.arg(0)? {
+        CalculateOption::Bool(b) => b,
+        CalculateOption::Num(n) => n != 0.0,
+        v => {
+            return Err(CalcError::arg(
+                0,
+                format!("应为布尔值或数值，实际为 {:?}", v),
+            ))
+        }
+    };
+    match (cond, ctx.args.get(2)) {
+        (true, _) => Ok(ctx.args[1].calc(&ctx.env)),
+        (false, Some(otherwise)) => Ok(otherwise.calc(&ctx.env)),
+        (false, None) => Ok(CalculateOption::Bool(false)),
+    }
+}
+
+/// Concat(values...) 将数值及向量按参数的顺序连接为一个向量
+pub fn concat(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    Ok(CalculateOption::Vector(numbers(ctx)?))
+}
+
+/// Apply(f, args...) 以其余的参数调用函数 f，f 可以是函数名或绑定了函数的参数
+pub fn apply(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let f = ctx.arg_func(0)?;
+    Ok(call_function(&f, &Rc::from(&ctx.args[1..]), &ctx.env))
+}
+
+/// 以已经计算好的值作为参数调用函数 f，调用出错时返回错误
+fn call_with(
+    ctx: &FuncContext,
+    f: &Rc<FormulaNode>,
+    values: Vec<CalculateOption>,
+) -> Result<CalculateOption, CalcError> {
+    let mut args = Vec::with_capacity(values.len());
+    for value in values {
+        match value.to_node() {
+            Some(node) => args.push(Rc::new(node)),
+            None => return Err(CalcError::new(format!("{:?} 无法作为函数的参数", value))),
+        }
+    }
+    match call_function(f, &Rc::from(args), &ctx.env) {
+        CalculateOption::Err(e) => Err(CalcError::new(e)),
+        value => Ok(value),
+    }
+}
+
+/// Map(V, f) 对数值向量 V 的每一个元素调用 f，f 的结果应为数值
+pub fn map(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let (values, f) = (arg_numbers(ctx, 0)?, ctx.arg_func(1)?);
+    let mut mapped = Vec::with_capacity(values.len());
+    for v in values {
+        match call_with(ctx, &f, vec![CalculateOption::Num(v)])? {
+            CalculateOption::Num(n) => mapped.push(n),
+            r => return Err(CalcError::arg(1, format!("应返回数值，实际为 {:?}", r))),
+        }
+    }
+    Ok(CalculateOption::Vector(mapped))
+}
+
+/// Filter(V, pred) 保留数值向量 V 中使 pred 的结果为 true 的元素
+pub fn filter(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let (values, pred) = (arg_numbers(ctx, 0)?, ctx.arg_func(1)?);
+    let mut kept = Vec::new();
+    for v in values {
+        match call_with(ctx, &pred, vec![CalculateOption::Num(v)])? {
+            CalculateOption::Bool(true) => kept.push(v),
+            CalculateOption::Bool(false) => (),
+            r => return Err(CalcError::arg(1, format!("应返回布尔值，实际为 {:?}", r))),
+        }
+    }
+    Ok(CalculateOption::Vector(kept))
+}
+
+/// Reduce(V, f, init) 以 init 为初始值，依次以 f(累积值, 元素) 的结果作为新的累积值
+pub fn reduce(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let (values, f) = (arg_numbers(ctx, 0)?, ctx.arg_func(1)?);
+    let mut acc = ctx.arg(2)?;
+    for v in values {
+        acc = call_with(ctx, &f, vec![acc, CalculateOption::Num(v)])?;
+    }
+    Ok(acc)
+}
+
+/// GroupBy(records, key_fn, agg_fn) 按 key_fn 的结果对记录分组并聚合，records 为矩阵时每一行为一条记录,
+/// key_fn 以该行组成的向量为参数，agg_fn 以同一组的行组成的矩阵为参数；records 为向量时每个元素为一条记录,
+/// agg_fn 以同一组的元素组成的向量为参数。两个函数都应返回数值，结果为每行 [键, 聚合值] 的矩阵，
+/// 按键升序排列，可直接作为 Lookup 的查找表
+pub fn group_by(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let (key_fn, agg_fn) = (ctx.arg_func(1)?, ctx.arg_func(2)?);
+    let (records, cols) = match ctx.arg(0)? {
+        CalculateOption::Vector(v) => (v.into_iter().map(|x| vec![x]).collect::<Vec<_>>(), None),
+        CalculateOption::Matrix(m) => (
+            (0..m.rows()).map(|i| m.row(i).to_vec()).collect(),
+            Some(m.cols()),
+        ),
+        v => return Err(CalcError::arg(0, format!("应为向量或矩阵，实际为 {:?}", v))),
+    };
+    // 记录作为参数时的值，向量中的记录为数值，矩阵中的记录为该行组成的向量
+    let record = |fields: &[f64]| match cols {
+        Some(_) => CalculateOption::Vector(fields.to_vec()),
+        None => CalculateOption::Num(fields[0]),
+    };
+
+    let mut groups: Vec<(f64, Vec<f64>)> = Vec::new();
+    for fields in records {
+        let key = match call_with(ctx, &key_fn, vec![record(&fields)])? {
+            CalculateOption::Num(n) if !n.is_nan() => n,
+            r => return Err(CalcError::arg(1, format!("应返回数值，实际为 {:?}", r))),
+        };
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, data)) => data.extend(fields),
+            None => groups.push((key, fields)),
+        }
+    }
+    groups.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    let mut data = Vec::with_capacity(groups.len() * 2);
+    for (key, values) in groups {
+        let group = match cols {
+            Some(cols) => Matrix::new(values.len() / cols, cols, values)
+                .map(CalculateOption::Matrix)
+                .unwrap_or(CalculateOption::None),
+            None => CalculateOption::Vector(values),
+        };
+        match call_with(ctx, &agg_fn, vec![group])? {
+            CalculateOption::Num(n) => data.extend([key, n]),
+            r => return Err(CalcError::arg(2, format!("应返回数值，实际为 {:?}", r))),
+        }
+    }
+    Ok(Matrix::new(data.len() / 2, 2, data)
+        .map(CalculateOption::Matrix)
+        .unwrap_or(CalculateOption::None))
+}
+
+/// 计算所有的参数并展开为数值列表，参数可以是数值或数值向量
+fn numbers(ctx: &FuncContext) -> Result<Vec<f64>, CalcError> {
+    let mut values = Vec::new();
+    for index in 0..ctx.args.len() {
+        values.extend(arg_numbers(ctx, index)?);
+    }
+    Ok(values)
+}
+
+/// 计算第 index 个参数并转换为数值列表，数值会被转换为只有一个元素的列表
+fn arg_numbers(ctx: &FuncContext, index: usize) -> Result<Vec<f64>, CalcError> {
+    match ctx.arg(index)? {
+        CalculateOption::Num(n) => Ok(vec![n]),
+        CalculateOption::Vector(v) => Ok(v),
+        v => Err(CalcError::arg(
+            index,
+            format!("应为数值或数值向量，实际为 {:?}", v),
+        )),
+    }
+}
+
+/// 对 numbers 得到的数值列表进行聚合，数值个数少于 least 时返回错误
+fn aggregate<F>(ctx: &FuncContext, least: usize, f: F) -> Result<CalculateOption, CalcError>
+where
+    F: Fn(&[f64]) -> f64,
+{
+    let values = numbers(ctx)?;
+    match values.len() < least {
+        true => Err(CalcError::new(format!("至少需要 {} 个数值", least))),
+        false => Ok(CalculateOption::Num(f(&values))),
+    }
+}
+
+/// Sum(A, B, ...) 求和，参数可以是数值或数值向量，没有参数时返回 0
+pub fn sum(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 0, |v| v.iter().sum())
+}
+
+/// Avg(A, B, ...) 求平均值
+pub fn avg(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 1, |v| v.iter().sum::<f64>() / v.len() as f64)
+}
+
+/// Min(A, B, ...) 求最小值
+pub fn min(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 1, |v| v.iter().cloned().fold(f64::INFINITY, f64::min))
+}
+
+/// Max(A, B, ...) 求最大值
+pub fn max(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 1, |v| {
+        v.iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+    })
+}
+
+/// Count(A, B, ...) 统计数值的个数，数值向量按其元素个数计算
+pub fn count(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 0, |v| v.len() as f64)
+}
+
+/// Len(V) 求向量 V 的元素个数，V 为矩阵时求其行数，为文本时求其字符个数，与下标访问 V[i] 的范围对应
+pub fn len(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    match ctx.arg(0)? {
+        CalculateOption::Vector(v) => Ok(CalculateOption::Num(v.len() as f64)),
+        CalculateOption::Matrix(m) => Ok(CalculateOption::Num(m.rows() as f64)),
+        CalculateOption::Text(t) => Ok(CalculateOption::Num(t.chars().count() as f64)),
+        v => Err(CalcError::arg(
+            0,
+            format!("应为向量、矩阵或文本，实际为 {:?}", v),
+        )),
+    }
+}
+
+/// Product(A, B, ...) 求乘积，没有参数时返回 1
+pub fn product(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 0, |v| v.iter().product())
+}
+
+/// 样本方差
+fn sample_variance(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1.0)
+}
+
+/// 对已排序的数值列表按线性插值计算第 k 百分位数，k 的范围为 [0, 1]
+fn sorted_percentile(sorted: &[f64], k: f64) -> f64 {
+    let rank = k * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+fn sorted(mut values: Vec<f64>) -> Vec<f64> {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Equal));
+    values
+}
+
+/// Median(A, B, ...) 求中位数
+pub fn median(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 1, |v| sorted_percentile(&sorted(v.to_vec()), 0.5))
+}
+
+/// Variance(A, B, ...) 求样本方差，至少需要两个数值
+pub fn variance(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 2, sample_variance)
+}
+
+/// StdDev(A, B, ...) 求样本标准差，至少需要两个数值
+pub fn std_dev(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    aggregate(ctx, 2, |v| sample_variance(v).sqrt())
+}
+
+/// Percentile(V, k) 求数值向量 V 的第 k 百分位数，k 的范围为 [0, 1]，在相邻的数值间线性插值
+pub fn percentile(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let values = arg_numbers(ctx, 0)?;
+    if values.is_empty() {
+        return Err(CalcError::arg(0, "至少需要一个数值"));
+    }
+    match ctx.arg_num(1)? {
+        k if (0.0..=1.0).contains(&k) => {
+            Ok(CalculateOption::Num(sorted_percentile(&sorted(values), k)))
+        }
+        _ => Err(CalcError::arg(1, "应为 0 到 1 之间的数值")),
+    }
+}
+
+/// Correl(X, Y) 求两个等长数值向量的皮尔逊相关系数
+pub fn correl(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let (xs, ys) = (arg_numbers(ctx, 0)?, arg_numbers(ctx, 1)?);
+    if xs.len() != ys.len() || xs.len() < 2 {
+        return Err(CalcError::new(
+            "两个参数应为长度相同且至少包含两个数值的向量",
+        ));
+    }
+
+    let n = xs.len() as f64;
+    let (mean_x, mean_y) = (xs.iter().sum::<f64>() / n, ys.iter().sum::<f64>() / n);
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys.iter()) {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x) * (x - mean_x);
+        var_y += (y - mean_y) * (y - mean_y);
+    }
+    Ok(CalculateOption::Num(cov / (var_x * var_y).sqrt()))
+}
+
+/// 按 mode 舍入第一个参数，第二个参数为保留的小数位数，省略时为 0
+fn round_with(ctx: &FuncContext, mode: RoundingMode) -> Result<CalculateOption, CalcError> {
+    let digits = match ctx.args.len() {
+        2 => match ctx.arg_num(1)? {
+            n if n.fract() == 0.0 && n.abs() <= 308.0 => n as i32,
+            _ => return Err(CalcError::arg(1, "应为整数")),
+        },
+        _ => 0,
+    };
+    match ctx.arg(0)? {
+        value @ (CalculateOption::Num(_)
+        | CalculateOption::Vector(_)
+        | CalculateOption::Matrix(_)
+        | CalculateOption::Money(_)) => Ok(mode.round_value(value, digits)),
+        v => Err(CalcError::arg(
+            0,
+            format!("应为数值、向量、矩阵或金额，实际为 {:?}", v),
+        )),
+    }
+}
+
+/// Round(x, digits) 按 digits 位小数四舍五入，恰好为 5 时远离 0 舍入，如 Round(2.5) 为 3、Round(1234, -2) 为 1200,
+/// x 为向量或矩阵时对每个元素舍入，x 为金额时对金额舍入
+pub fn round(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    round_with(ctx, RoundingMode::HalfUp)
+}
+
+/// RoundHalfEven(x, digits) 按 digits 位小数进行银行家舍入，恰好为 5 时舍入到偶数，如 RoundHalfEven(2.5) 为 2
+pub fn round_half_even(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    round_with(ctx, RoundingMode::HalfEven)
+}
+
+/// Floor(x, digits) 按 digits 位小数向负无穷方向舍入
+pub fn floor(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    round_with(ctx, RoundingMode::Floor)
+}
+
+/// Ceiling(x, digits) 按 digits 位小数向正无穷方向舍入
+pub fn ceiling(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    round_with(ctx, RoundingMode::Ceiling)
+}
+
+/// 计算第 index 个参数并转换为矩阵
+fn arg_matrix(ctx: &FuncContext, index: usize) -> Result<Matrix, CalcError> {
+    match ctx.arg(index)? {
+        CalculateOption::Matrix(m) => Ok(m),
+        v => Err(CalcError::arg(index, format!("应为矩阵，实际为 {:?}", v))),
+    }
+}
+
+/// 计算第 index 个参数并要求其为方阵
+fn arg_square(ctx: &FuncContext, index: usize) -> Result<Matrix, CalcError> {
+    let m = arg_matrix(ctx, index)?;
+    match m.is_square() {
+        true => Ok(m),
+        false => Err(CalcError::arg(
+            index,
+            format!("应为方阵，实际为 {} 行 {} 列", m.rows(), m.cols()),
+        )),
+    }
+}
+
+/// Transpose(M) 求矩阵 M 的转置
+pub fn transpose(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    Ok(CalculateOption::Matrix(arg_matrix(ctx, 0)?.transpose()))
+}
+
+/// Inverse(M) 求方阵 M 的逆矩阵，M 为奇异矩阵时返回错误
+pub fn inverse(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    arg_square(ctx, 0)?
+        .inverse()
+        .map(CalculateOption::Matrix)
+        .ok_or_else(|| CalcError::arg(0, "为奇异矩阵，不存在逆矩阵"))
+}
+
+/// Det(M) 求方阵 M 的行列式
+pub fn det(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let m = arg_square(ctx, 0)?;
+    Ok(CalculateOption::Num(m.determinant().unwrap_or(f64::NAN)))
+}
+
+/// 在升序的 keys 中查找不大于 value 的最后一个位置，keys 不是升序时返回错误
+fn last_not_greater(keys: &[f64], value: f64, index: usize) -> Result<Option<usize>, CalcError> {
+    if keys.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err(CalcError::arg(index, "应为升序"));
+    }
+    Ok(keys.iter().rposition(|key| *key <= value))
+}
+
+/// Lookup(value, table, col, approximate) 在矩阵 table 的第一列中查找 value 并返回该行第 col 列的值,
+/// col 从 1 开始，approximate 为 true 或非 0 时按升序的第一列查找不大于 value 的最后一行，如税率表
+pub fn lookup(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let value = ctx.arg_num(0)?;
+    let table = arg_matrix(ctx, 1)?;
+    let col = match ctx.arg_num(2)? {
+        n if n >= 1.0 && n.fract() == 0.0 && (n as usize) <= table.cols() => n as usize - 1,
+        _ => {
+            return Err(CalcError::arg(
+                2,
+                format!("应为 1 到 {} 之间的整数", table.cols()),
+            ))
+        }
+    };
+    let approximate = match ctx.args.len() {
+        4 => match ctx.arg(3)? {
+            CalculateOption::Bool(b) => b,
+            CalculateOption::Num(n) => n != 0.0,
+            v => {
+                return Err(CalcError::arg(
+                    3,
+                    format!("应为布尔值或数值，实际为 {:?}", v),
+                ))
+            }
+        },
+        _ => false,
+    };
+
+    let keys: Vec<f64> = (0..table.rows()).map(|row| table.row(row)[0]).collect();
+    let row = match approximate {
+        true => last_not_greater(&keys, value, 1)?,
+        false => keys.iter().position(|key| *key == value),
+    };
+    row.and_then(|row| table.get(row, col))
+        .map(CalculateOption::Num)
+        .ok_or_else(|| CalcError::new(format!("表中找不到 {}", value)))
+}
+
+/// MatchRange(value, breakpoints, results) 在升序的 breakpoints 中查找不大于 value 的最后一个分界点,
+/// 返回 results 中相同位置的值，用于税率档次、阶梯价格等，value 小于第一个分界点时返回错误
+pub fn match_range(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let value = ctx.arg_num(0)?;
+    let (breakpoints, results) = (arg_numbers(ctx, 1)?, arg_numbers(ctx, 2)?);
+    if breakpoints.len() != results.len() {
+        return Err(CalcError::new(format!(
+            "breakpoints 与 results 的长度应相同，分别为 {} 与 {}",
+            breakpoints.len(),
+            results.len()
+        )));
+    }
+    last_not_greater(&breakpoints, value, 1)?
+        .map(|index| CalculateOption::Num(results[index]))
+        .ok_or_else(|| CalcError::new(format!("{} 小于第一个分界点", value)))
+}
+
+/// 将 x 的绝对值格式化为数字文本，小数位数在 [min_decimals, max_decimals] 之间，多余的 0 会被去除,
+/// 整数部分至少有 min_integers 位，grouping 为 true 时每三位整数之间插入 ,
+fn format_digits(
+    x: f64,
+    min_decimals: usize,
+    max_decimals: usize,
+    min_integers: usize,
+    grouping: bool,
+) -> String {
+    // 与表格软件一致，按四舍五入而不是银行家舍入保留小数
+    let scale = 10f64.powi(max_decimals as i32);
+    let fixed = format!("{:.*}", max_decimals, (x.abs() * scale).round() / scale);
+    let (integer, decimals) = fixed.split_once('.').unwrap_or((&fixed, ""));
+    let mut decimals = decimals.to_string();
+    while decimals.len() > min_decimals && decimals.ends_with('0') {
+        decimals.pop();
+    }
+    let integer = integer.trim_start_matches('0');
+    let integer = format!("{:0>width$}", integer, width = min_integers);
+    let integer = match grouping {
+        true => {
+            let digits: Vec<char> = integer.chars().collect();
+            let groups: Vec<String> = digits
+                .rchunks(3)
+                .rev()
+                .map(|chunk| chunk.iter().collect())
+                .collect();
+            groups.join(",")
+        }
+        false => integer,
+    };
+    match decimals.is_empty() {
+        true => integer,
+        false => format!("{}.{}", integer, decimals),
+    }
+}
+
+/// 为格式化后的数字加上前后缀，负数的 - 位于最前面
+fn signed(x: f64, prefix: &str, digits: &str, suffix: &str) -> CalculateOption {
+    let negative = x < 0.0 && digits.chars().any(|c| c.is_ascii_digit() && c != '0');
+    let sign = if negative { "-" } else { "" };
+    CalculateOption::Text(format!("{}{}{}{}", sign, prefix, digits, suffix))
+}
+
+/// Format(x, pattern) 按 pattern 将 x 格式化为文本，pattern 中第一段由 0 # , . 组成的部分为数字的格式,
+/// 0 为必须显示的数字，# 为可省略的数字，整数部分包含 , 时插入千位分隔符，其前后的字符原样保留,
+/// 包含 % 时 x 会先乘以 100，如 Format(1234.5, "#,##0.00") 得到 "1,234.50"
+pub fn format(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let x = ctx.arg_num(0)?;
+    let pattern = ctx.arg_text(1)?;
+    let is_digit = |c: char| matches!(c, '0' | '#' | ',' | '.');
+    let start = pattern
+        .find(is_digit)
+        .ok_or_else(|| CalcError::arg(1, "应包含由 0、#、, 及 . 组成的数字格式"))?;
+    let end = pattern[start..]
+        .find(|c: char| !is_digit(c))
+        .map_or(pattern.len(), |end| start + end);
+    let (prefix, number, suffix) = (&pattern[..start], &pattern[start..end], &pattern[end..]);
+    let (integer, decimals) = number.split_once('.').unwrap_or((number, ""));
+    let x = match pattern.contains('%') {
+        true => x * 100.0,
+        false => x,
+    };
+    if !x.is_finite() {
+        return Ok(CalculateOption::Text(x.to_string()));
+    }
+    let digits = format_digits(
+        x,
+        decimals.chars().filter(|c| *c == '0').count(),
+        decimals.chars().filter(|c| matches!(c, '0' | '#')).count(),
+        integer.chars().filter(|c| *c == '0').count(),
+        integer.contains(','),
+    );
+    Ok(signed(x, prefix, &digits, suffix))
+}
+
+/// FormatPercent(x, decimals) 将 x 格式化为百分比文本，decimals 默认为 0，如 FormatPercent(0.125, 1) 得到 "12.5%"
+pub fn format_percent(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let x = ctx.arg_num(0)? * 100.0;
+    let decimals = match ctx.args.len() {
+        2 => arg_count(ctx, 1)?,
+        _ => 0,
+    };
+    if !x.is_finite() {
+        return Ok(CalculateOption::Text(x.to_string()));
+    }
+    let digits = format_digits(x, decimals, decimals, 1, false);
+    Ok(signed(x, "", &digits, "%"))
+}
+
+/// FormatCurrency(x, currency) 将 x 格式化为带有千位分隔符的金额文本，常用的货币代码使用对应的符号,
+/// 如 FormatCurrency(-1234.5, "USD") 得到 "-$1,234.50"，JPY 等没有辅币的货币不保留小数，
+/// 其他货币代码以 "代码 " 作为前缀，x 为金额时可以省略 currency，指定时应与金额的货币一致
+pub fn format_currency(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let (x, currency) = match (ctx.arg(0)?, ctx.args.len()) {
+        (CalculateOption::Money(m), 1) => (m.amount, m.currency),
+        (CalculateOption::Money(m), _) => {
+            let currency = ctx.arg_text(1)?.to_uppercase();
+            if currency != m.currency {
+                let message = format!("与金额的货币 {} 不一致，请先通过 Convert 换算", m.currency);
+                return Err(CalcError::arg(1, message));
+            }
+            (m.amount, currency)
+        }
+        (CalculateOption::Num(x), _) => (x, ctx.arg_text(1)?),
+        (v, _) => return Err(CalcError::arg(0, format!("应为数值或金额，实际为 {:?}", v))),
+    };
+    let (prefix, decimals) = currency_style(&currency)
+        .ok_or_else(|| CalcError::arg(1, "应为 USD、EUR 等三个字母的货币代码"))?;
+    if !x.is_finite() {
+        return Ok(CalculateOption::Text(x.to_string()));
+    }
+    let digits = format_digits(x, decimals, decimals, 1, true);
+    Ok(signed(x, &prefix, &digits, ""))
+}
+
+/// Money(amount, currency) 得到以 currency 计价的金额，如 Money(12.5, "EUR")
+pub fn money(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let amount = ctx.arg_num(0)?;
+    let currency = ctx.arg_text(1)?;
+    match Money::new(amount, &currency) {
+        Some(m) => Ok(CalculateOption::Money(m)),
+        None => Err(CalcError::arg(1, "应为 USD、EUR 等三个字母的货币代码")),
+    }
+}
+
+/// Amount(money) 金额的数值部分
+pub fn amount(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    Ok(CalculateOption::Num(ctx.arg_money(0)?.amount))
+}
+
+/// Currency(money) 金额的货币代码
+pub fn currency(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    Ok(CalculateOption::Text(ctx.arg_money(0)?.currency))
+}
+
+/// Convert(money, currency, rate) 将金额换算为 currency，rate 为 1 单位原货币可兑换的目标货币数量,
+/// 也可以是以原货币及目标货币的代码调用后返回汇率的函数，如由宿主程序注册的查询汇率的内建函数,
+/// 原货币与目标货币相同时直接返回 money，不会使用 rate
+pub fn convert(ctx: &FuncContext) -> Result<CalculateOption, CalcError> {
+    let money = ctx.arg_money(0)?;
+    let target = Money::new(0.0, &ctx.arg_text(1)?)
+        .ok_or_else(|| CalcError::arg(1, "应为 USD、EUR 等三个字母的货币代码"))?
+        .currency;
+    if target == money.currency {
+        return Ok(CalculateOption::Money(money));
+    }
+    let rate = match ctx.arg(2)? {
+        CalculateOption::Num(rate) => rate,
+        CalculateOption::Function(f) => {
+            let codes = vec![
+                CalculateOption::Text(money.currency.clone()),
+                CalculateOption::Text(target.clone()),
+            ];
+            match call_with(ctx, &f, codes)? {
+                CalculateOption::Num(rate) => rate,
+                v => {
+                    let message = format!("汇率函数的结果应为数值，实际为 {:?}", v);
+                    return Err(CalcError::arg(2, message));
+                }
+            }
+        }
+        v => {
+            let message = format!("应为汇率或返回汇率的函数，实际为 {:?}", v);
+            return Err(CalcError::arg(2, message));
+        }
+    };
+    if !(rate.is_finite() && rate > 0.0) {
+        return Err(CalcError::arg(2, format!("汇率应为正数，实际为 {}", rate)));
+    }
+    Ok(CalculateOption::Money(Money {
+        amount: money.amount * rate,
+        currency: target,
+    }))
+}
+
+/// 计算第 index 个参数并要求其为非负整数
+fn arg_count(ctx: &FuncContext, index: usize) -> Result<usize, CalcError> {
+    match ctx.arg_num(index)? {
+        n if n >= 0.0 && n.fract() == 0.0 => Ok(n as usize),
+        _ => Err(CalcError::arg(index, "应为非负整数")),
+    }
+}
@@ -0,0 +1,105 @@
+use crate::compat::HashMap;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use core::hash::{Hash, Hasher};
+
+use crate::formula::FormulaNode;
+
+/// 解析结果的缓存，以公式源码的哈希值为 key，超过容量时淘汰最久未使用的一项,
+/// 只用于缓存不包含定义的表达式，这些表达式重复解析得到的语法树总是相同的
+#[derive(Default)]
+pub struct ParseCache {
+    capacity: usize,
+    /// 每次访问时递增，记录在被访问的项中，用于找出最久未使用的一项
+    tick: u64,
+    entries: HashMap<u64, CacheEntry>,
+}
+
+struct CacheEntry {
+    /// 哈希值相同时用于确认是否为同一个公式
+    source: String,
+    node: Rc<FormulaNode>,
+    used: u64,
+}
+
+impl ParseCache {
+    /// 创建最多保存 capacity 个解析结果的缓存，capacity 为 0 时不缓存
+    pub fn new(capacity: usize) -> Self {
+        ParseCache {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 修改缓存的容量，缩小容量时淘汰最久未使用的项
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 获取 source 的解析结果，并将其标记为最近使用
+    pub fn get(&mut self, source: &str) -> Option<Rc<FormulaNode>> {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.entries.get_mut(&hash_of(source)) {
+            Some(entry) if entry.source == source => {
+                entry.used = tick;
+                Some(Rc::clone(&entry.node))
+            }
+            _ => None,
+        }
+    }
+
+    /// 保存 source 的解析结果，缓存已满时先淘汰最久未使用的一项
+    pub fn insert(&mut self, source: &str, node: Rc<FormulaNode>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = hash_of(source);
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.tick += 1;
+        let entry = CacheEntry {
+            source: source.to_string(),
+            node,
+            used: self.tick,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(key, _)| *key);
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+fn hash_of(source: &str) -> u64 {
+    let mut hasher = crate::compat::hasher();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,9 +1,29 @@
+use crate::audit::{self, AuditRecord, Deterministic};
+#[cfg(not(any(feature = "std", test)))]
+use crate::compat::Float;
+use crate::compat::HashMap;
+use crate::complex::Complex;
 use crate::env::{Env, EnvType};
 use crate::formula::*;
-use std::borrow::Borrow;
-use std::cell::RefCell;
-use std::cmp::PartialEq;
-use std::rc::Rc;
+use crate::locale::{ErrorCode, Locale, NumberLocale};
+use crate::matrix::Matrix;
+use crate::money::Money;
+#[cfg(feature = "rational")]
+use crate::rational;
+use crate::rounding::Precision;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+use core::cmp::{Ordering, PartialEq};
+use core::convert::TryFrom;
+use core::fmt::Display;
+use core::ops::{Add, Div, Mul, Sub};
+use core::time::Duration;
+#[cfg(feature = "rational")]
+use num_rational::Rational64;
 
 pub trait FormulaCalc {
     fn calc(&self, e: &EnvType) -> CalculateOption;
@@ -11,384 +31,1484 @@ pub trait FormulaCalc {
 
 impl FormulaCalc for FormulaNode {
     fn calc(&self, env: &EnvType) -> CalculateOption {
+        let (tracing, observer, exhausted) = {
+            let e = RefCell::borrow(env);
+            (e.is_tracing(), e.observer(), e.consume_fuel())
+        };
+        if let Some(limit) = exhausted {
+            return error(env, ErrorCode::FuelExhausted, &[&limit]);
+        }
+        if !tracing && observer.is_none() {
+            return self.calc_node(env);
+        }
+
+        if tracing {
+            RefCell::borrow(env).trace_enter(self.to_string());
+        }
+        if let Some(ref observer) = observer {
+            RefCell::borrow(env).observe_enter(observer.as_ref(), self);
+        }
+        let value = self.calc_node(env);
+        if let Some(ref observer) = observer {
+            RefCell::borrow(env).observe_exit(observer.as_ref(), self, &value);
+        }
+        if tracing {
+            RefCell::borrow(env).trace_exit(&value);
+        }
+        value
+    }
+}
+
+impl FormulaNode {
+    fn calc_node(&self, env: &EnvType) -> CalculateOption {
         match self {
-            FormulaNode::Constant(f) => CalculateOption::Num(*f),
+            FormulaNode::Constant(f) => constant(env, *f),
             FormulaNode::Bool(b) => CalculateOption::Bool(*b),
-            FormulaNode::Variant(v) => match RefCell::borrow(&env).get(v) {
-                Some(v) => {
-                    return v.calc(env);
+            FormulaNode::Complex(c) => CalculateOption::Complex(*c),
+            FormulaNode::Vector(v) => CalculateOption::Vector(v.clone()),
+            FormulaNode::Matrix(m) => CalculateOption::Matrix(m.clone()),
+            FormulaNode::Text(t) => CalculateOption::Text(t.clone()),
+            FormulaNode::Money(m) => CalculateOption::Money(m.clone()),
+            FormulaNode::Opaque(v) => CalculateOption::Opaque(Rc::clone(v)),
+            FormulaNode::Variant(v) => {
+                let node = RefCell::borrow(env).get(v);
+                match node {
+                    Some(node) if node.is_function() => CalculateOption::Function(node),
+                    Some(node) => {
+                        let started = match node.as_ref() {
+                            FormulaNode::Formula { .. } if RefCell::borrow(env).is_profiling() => {
+                                Some(RefCell::borrow(env).clock().now())
+                            }
+                            _ => None,
+                        };
+                        let value = calc_formula(v, &node, env);
+                        let env = RefCell::borrow(env);
+                        if let Some(started) = started {
+                            env.profile_record(v, env.clock().now().saturating_sub(started));
+                        }
+                        if env.records_values() {
+                            env.set_node_value(v, value.clone());
+                        }
+                        if env.is_auditing() && audit::is_input(&node) {
+                            env.audit_input(v, &value);
+                        }
+                        value
+                    }
+                    None => match RefCell::borrow(env).resolve(v) {
+                        Some(value) => {
+                            if RefCell::borrow(env).is_auditing() {
+                                RefCell::borrow(env).audit_input(v, &value);
+                            }
+                            value
+                        }
+                        None => error(env, ErrorCode::UndefinedVariable, &[v]),
+                    },
                 }
-                None => {
-                    return CalculateOption::Err(format!("无法从执行环境中获取指定的变量名 {}", v));
+            }
+            FormulaNode::History { name, back } => {
+                match RefCell::borrow(env).history_value(name, *back) {
+                    Some(value) => value,
+                    None => CalculateOption::None,
                 }
-            },
+            }
+            FormulaNode::Index { target, index } => subscript(target, index, env),
             FormulaNode::Operator(op_node) => op_node.calc(env),
             FormulaNode::Formula { name: _, formula } => formula.calc(env),
             FormulaNode::Quote(formula) => formula.calc(env),
-            FormulaNode::Function {
-                name: _,
-                args: _,
-                expressions: _,
-            } => CalculateOption::Func,
+            // 与变量引用相同，返回执行环境中保存的定义，而不是复制出新的节点
+            FormulaNode::Function { name, .. } | FormulaNode::BuildInFunction { func: name } => {
+                match RefCell::borrow(env).get(name) {
+                    Some(node) if core::ptr::eq(node.as_ref(), self) || node.as_ref() == self => {
+                        CalculateOption::Function(node)
+                    }
+                    _ => CalculateOption::Function(Rc::new(self.clone())),
+                }
+            }
             FormulaNode::FunctionCall { name, args } => {
-                let new_env = Env::extend_with_stack(env);
-
-                // record the stack
-                RefCell::borrow(&new_env).set_stack("FunctionCall", name, args.clone());
-
-                let func = match RefCell::borrow(&new_env).get(name) {
+                let func = match RefCell::borrow(env).get(name) {
                     Some(f) => f.clone(),
                     _ => {
-                        return CalculateOption::Err(
-                            format!("从执行环境中获取函数 {} 时出错，对应的函数不存在环境变量中，是否未定义该函数", name));
+                        return error(env, ErrorCode::UndefinedFunction, &[name]);
                     }
                 };
+                call_function(&func, args, env)
+            }
+            FormulaNode::None | FormulaNode::Import(_) => CalculateOption::None,
+            // 解析出错的语句返回其中的错误信息，已带有错误码的信息 (如安全策略的限制) 原样返回
+            FormulaNode::UnKnow(msg) => match ErrorCode::of(msg) {
+                Some(_) => CalculateOption::Err(msg.clone()),
+                None => error(env, ErrorCode::SyntaxError, &[msg]),
+            },
+            _ => error(env, ErrorCode::InvalidExpression, &[]),
+        }
+    }
+}
 
-                let mut result = CalculateOption::None;
-                match func.as_ref() {
-                    FormulaNode::Function {
-                        name,
-                        args: args_define,
-                        expressions,
-                    } => {
-                        if args.len() != args_define.len() {
-                            return CalculateOption::Err(format!(
-                                "函数 {} 定义的参数个数为 {}, 与函数调用的参数个数{}不匹配",
-                                name,
-                                args_define.len(),
-                                args.len()
-                            ));
-                        }
+/// 计算变量 name 所引用的定义 node，值不为常量的定义计为一层调用，定义直接或间接地引用了自身时返回错误
+fn calc_formula(name: &str, node: &Rc<FormulaNode>, env: &EnvType) -> CalculateOption {
+    let input = audit::is_input(node);
+    if !input {
+        if !RefCell::borrow(env).enter_formula(node) {
+            return error(env, ErrorCode::CircularReference, &[&name]);
+        }
+        if let Some(limit) = RefCell::borrow(env).enter_call() {
+            RefCell::borrow(env).exit_formula();
+            return error(env, ErrorCode::CallDepthExceeded, &[&limit]);
+        }
+    }
+    // 命名空间中的表达式在该命名空间中计算，以便引用同一命名空间中的其他定义
+    let namespace = RefCell::borrow(env).namespace_of(name);
+    let value = match namespace {
+        Some(ns) => {
+            let scope = Env::extend_namespace(env, ns);
+            let value = node.calc(&scope);
+            Env::release(scope);
+            value
+        }
+        None => node.calc(env),
+    };
+    if !input {
+        RefCell::borrow(env).exit_call();
+        RefCell::borrow(env).exit_formula();
+    }
+    value
+}
 
-                        // 处理 Args, 将 Args 的值放入函数对应的参数名中
-                        for (index, arg) in args.iter().enumerate() {
-                            let arg_def: Rc<FormulaNode> = args_define.get(index).cloned().unwrap();
-                            let arg_name = match arg_def.as_ref() {
-                                FormulaNode::Variant(name) => name,
-                                _ => {
-                                    return CalculateOption::Err(format!(
-                                        "为函数 {} 获取执行变量时出错，错误变量位置为 {}",
-                                        name, index
-                                    ))
-                                }
-                            };
-
-                            let v = match arg.calc(env) {
-                                CalculateOption::Bool(b) => Rc::new(FormulaNode::Bool(b)),
-                                CalculateOption::Num(f) => Rc::new(FormulaNode::Constant(f)),
-                                CalculateOption::Func => {
-                                    // 这是把函数当为参数传递的情形
-                                    match arg.borrow() {
-                                        FormulaNode::Variant(s) => {
-                                            RefCell::borrow(&new_env).get(&s).expect(&format!("获取不到指定的变量 {}", s))
-                                        }
-                                        _ => return CalculateOption::Err(format!(
-                                            "执行函数 {} 时出错，变量 {} 所绑定的函数 {:?} 不存在。",
-                                            name,
-                                            arg_name,
-                                            arg
-                                        ))
-                                    }
-                                }
-                                CalculateOption::Err(s) => {
-                                    return CalculateOption::Err(format!(
-                                        "为函数 {} 计算参数值时出错，错误信息为 {}",
-                                        name, s
-                                    ))
-                                }
-                                CalculateOption::None => {
-                                    return CalculateOption::Err(format!(
-                                    "为函数 {} 计算参数值时出错，错误信息为该参数返回结果为 None",
-                                    name
-                                ))
-                                }
-                            };
-                            new_env.borrow_mut().insert(&arg_name, v);
-                        }
+/// 以 args 为参数调用函数 func，func 为脚本中定义的函数或内建函数，参数在调用者的环境 env 中计算
+pub fn call_function(
+    func: &Rc<FormulaNode>,
+    args: &Rc<[Rc<FormulaNode>]>,
+    env: &EnvType,
+) -> CalculateOption {
+    let name = match func.as_ref() {
+        FormulaNode::Function { name, .. } => name,
+        FormulaNode::BuildInFunction { func } => func,
+        _ => "",
+    };
+    if let Some(observer) = RefCell::borrow(env).observer() {
+        observer.on_function_call(name, args, &RefCell::borrow(env));
+    }
 
-                        for exp in expressions {
-                            result = exp.calc(&new_env);
-                            match exp.as_ref() {
-                                FormulaNode::Formula { name, formula: _ } => {
-                                    new_env.borrow_mut().insert(
-                                        &name,
-                                        Rc::new(match result {
-                                            CalculateOption::Num(f) => FormulaNode::Constant(f),
-                                            CalculateOption::Bool(b) => FormulaNode::Bool(b),
-                                            _ => {
-                                                return CalculateOption::Err(format!(
-                                                    "计算函数体时出错！，后续增加具体的错误表达式"
-                                                ))
-                                            }
-                                        }),
-                                    );
-                                }
-                                _ => (),
-                            };
-                        }
-                    }
-                    FormulaNode::BuildInFunction { func } => {
-                        RefCell::borrow(&env).set_stack("BuildInFunction", func, args.clone());
+    let started = match RefCell::borrow(env).is_profiling() {
+        true => Some(RefCell::borrow(env).clock().now()),
+        false => None,
+    };
+    if let Some(limit) = RefCell::borrow(env).enter_call() {
+        return error(env, ErrorCode::CallDepthExceeded, &[&limit]);
+    }
+    RefCell::borrow(env).call_enter(name, args);
+    let value = invoke(func, args, env);
+    RefCell::borrow(env).call_exit(&value);
+    RefCell::borrow(env).exit_call();
+    if let Some(started) = started {
+        let env = RefCell::borrow(env);
+        env.profile_record(name, env.clock().now().saturating_sub(started));
+    }
+    value
+}
 
-                        match RefCell::borrow(&env).get_build_in(func) {
-                            Some(f) => {
-                                let context = FuncContext::new(args, Rc::clone(env));
-                                result = f(&context);
-                            }
-                            None => {
-                                return CalculateOption::Err(format!(
-                                    "获取内建函数 {} 时出错，运行环境中不存在该函数",
-                                    func
-                                ))
-                            }
-                        }
+fn invoke(func: &Rc<FormulaNode>, args: &Rc<[Rc<FormulaNode>]>, env: &EnvType) -> CalculateOption {
+    match func.as_ref() {
+        FormulaNode::Function {
+            name,
+            args: args_define,
+            expressions,
+        } => {
+            // 尾调用自身时在同一个循环中继续执行，参数在上一次调用的环境中计算,
+            // 之后的调用不再单独记录，都合并在第一次调用的记录中
+            let mut args = Rc::clone(args);
+            let mut caller = Rc::clone(env);
+            loop {
+                let first = Rc::ptr_eq(&caller, env);
+                let frame = match bind_args(name, args_define, &args, &caller, env, first) {
+                    Ok(frame) => frame,
+                    Err(e) => return e,
+                };
+                if !first {
+                    Env::release(caller);
+                }
+                match eval_body(func, name, expressions, &frame, env) {
+                    Tail::Value(value) => {
+                        Env::release(frame);
+                        return value;
+                    }
+                    Tail::Call(next) => {
+                        args = next;
+                        caller = frame;
+                    }
+                }
+            }
+        }
+        FormulaNode::BuildInFunction { func } => {
+            if RefCell::borrow(env).forbids(func) {
+                return error(env, ErrorCode::NondeterministicCall, &[func]);
+            }
+            if !RefCell::borrow(env).policy().allows(func) {
+                return error(env, ErrorCode::BuildInNotAllowed, &[func]);
+            }
+            let build_in = RefCell::borrow(env).get_build_in(func);
+            match build_in {
+                Some(f) => {
+                    let context = FuncContext::new(Rc::clone(args), Rc::clone(env));
+                    match f(&context) {
+                        Ok(value) => value,
+                        Err(e) => error(env, ErrorCode::BuildInError, &[func, &e]),
                     }
+                }
+                None => error(env, ErrorCode::BuildInNotFound, &[func]),
+            }
+        }
+        node => error(env, ErrorCode::NotCallable, &[node]),
+    }
+}
 
-                    _ => panic!("从函数节点提取表达式时出错，该错误不可能发生"),
+/// 创建执行函数体的环境，并将在 caller 中计算得到的参数值绑定到参数名上，
+/// env 为最初调用该函数的环境，用于共享计算过程的记录及生成错误信息,
+/// record 为 false 时不记录参数值，用于尾调用
+fn bind_args(
+    name: &str,
+    args_define: &[Rc<FormulaNode>],
+    args: &Rc<[Rc<FormulaNode>]>,
+    caller: &EnvType,
+    env: &EnvType,
+    record: bool,
+) -> Result<EnvType, CalculateOption> {
+    if args.len() != args_define.len() {
+        return Err(error(
+            env,
+            ErrorCode::ArityMismatch,
+            &[&name, &args_define.len(), &args.len()],
+        ));
+    }
+
+    // 函数体使用词法作用域，在定义该函数的层级之下执行，而不是在调用者的环境中,
+    // 因此函数体只能看到自身的参数、局部变量及定义处可见的变量，看不到调用者的局部变量
+    let scope = Env::scope_of(env, name).unwrap_or_else(|| Rc::clone(env));
+    let new_env = Env::extend_scope(&scope, env);
+    let namespace = RefCell::borrow(env).namespace_of(name);
+    new_env.borrow_mut().set_namespace(namespace);
+
+    // 处理 Args, 将 Args 的值放入函数对应的参数名中
+    for (index, arg) in args.iter().enumerate() {
+        let arg_name = match args_define[index].as_ref() {
+            FormulaNode::Variant(name) => name,
+            _ => return Err(error(env, ErrorCode::InvalidParameter, &[&name, &index])),
+        };
+
+        // 函数作为参数传递时，参数的值为函数本身，在函数体中可以直接通过参数名调用
+        let v = match arg.calc(caller) {
+            CalculateOption::Err(s) => {
+                return Err(error(env, ErrorCode::ArgumentError, &[&name, &s]))
+            }
+            CalculateOption::None => return Err(error(env, ErrorCode::ArgumentNone, &[&name])),
+            value => {
+                if record {
+                    RefCell::borrow(env).call_arg(&value);
                 }
+                Rc::new(value.to_node().unwrap())
+            }
+        };
+        new_env.borrow_mut().insert(arg_name, v);
+    }
+    Ok(new_env)
+}
+
+/// 函数体最后一个表达式的计算结果，Call 表示该表达式是对函数自身的尾调用，其中为尚未计算的参数
+enum Tail {
+    Value(CalculateOption),
+    Call(Rc<[Rc<FormulaNode>]>),
+}
 
-                return result;
+/// 依次计算函数体中的表达式，最后一个表达式为对函数 name 自身的尾调用时不进行该调用，而是返回其参数,
+/// 由调用者在循环中继续执行，以免递归实现的循环随着递归的深度消耗调用栈,
+/// 开启了计算过程记录、耗时统计或观察者时不做这样的处理，以保留每一次调用的记录
+fn eval_body(
+    func: &Rc<FormulaNode>,
+    name: &str,
+    expressions: &[Rc<FormulaNode>],
+    frame: &EnvType,
+    env: &EnvType,
+) -> Tail {
+    let mut result = CalculateOption::None;
+    for (index, exp) in expressions.iter().enumerate() {
+        if index + 1 == expressions.len() && has_tail_call(exp, name) {
+            let plain = {
+                let e = RefCell::borrow(env);
+                !e.is_tracing() && !e.is_profiling() && e.observer().is_none()
+            };
+            if plain {
+                return eval_tail(exp, func, frame, env);
             }
-            _ => CalculateOption::Err(format!("无法计算该表达式，格式出错？")),
         }
+        result = exp.calc(frame);
+        if let FormulaNode::Formula { name, formula: _ } = exp.as_ref() {
+            frame.borrow_mut().insert(
+                name,
+                Rc::new(match result.to_node() {
+                    Some(node) => node,
+                    None => return Tail::Value(error(env, ErrorCode::FunctionBodyError, &[])),
+                }),
+            );
+        };
+    }
+    Tail::Value(result)
+}
+
+/// exp 是否为对 name 的调用，或为分支中含有此类调用的 If，命名空间中的函数可以省略命名空间调用自身
+fn has_tail_call(exp: &FormulaNode, name: &str) -> bool {
+    match exp {
+        FormulaNode::Quote(inner) => has_tail_call(inner, name),
+        FormulaNode::FunctionCall { name: callee, .. }
+            if callee == name || name.rsplit_once('.').is_some_and(|(_, n)| n == callee) =>
+        {
+            true
+        }
+        FormulaNode::FunctionCall { name: callee, args }
+            if callee == "If" && (2..=3).contains(&args.len()) =>
+        {
+            args[1..].iter().any(|branch| has_tail_call(branch, name))
+        }
+        _ => false,
+    }
+}
+
+/// 计算处于尾部位置的表达式 exp，exp 为对 func 自身的调用，或为分支中含有此类调用的 If 时,
+/// 返回该调用的参数，其中的 If 按照内建函数 If 的语义直接计算，不单独记录该调用
+fn eval_tail(exp: &FormulaNode, func: &Rc<FormulaNode>, frame: &EnvType, env: &EnvType) -> Tail {
+    let (callee, args) = match exp {
+        FormulaNode::Quote(inner) => return eval_tail(inner, func, frame, env),
+        FormulaNode::FunctionCall { name, args } => (name, args),
+        _ => return Tail::Value(exp.calc(frame)),
+    };
+    let target = RefCell::borrow(frame).get(callee);
+    match target {
+        Some(ref target) if Rc::ptr_eq(target, func) => Tail::Call(Rc::clone(args)),
+        Some(ref target)
+            if matches!(target.as_ref(), FormulaNode::BuildInFunction { func } if func == "If")
+                && (2..=3).contains(&args.len())
+                && inline_if(env) =>
+        {
+            // 与普通的函数调用一样计入嵌套深度，条件在该层调用中计算
+            if let Some(limit) = RefCell::borrow(env).enter_call() {
+                return Tail::Value(error(env, ErrorCode::CallDepthExceeded, &[&limit]));
+            }
+            let cond = to_float(args[0].calc(frame));
+            RefCell::borrow(env).exit_call();
+            let cond = match cond {
+                CalculateOption::Bool(b) => Ok(b),
+                CalculateOption::Num(n) => Ok(n != 0.0),
+                CalculateOption::Err(e) => Err(CalcError::arg(0, e)),
+                v => Err(CalcError::arg(
+                    0,
+                    format!("应为布尔值或数值，实际为 {:?}", v),
+                )),
+            };
+            match (cond, args.get(2)) {
+                (Ok(true), _) => eval_tail(&args[1], func, frame, env),
+                (Ok(false), Some(otherwise)) => eval_tail(otherwise, func, frame, env),
+                (Ok(false), None) => Tail::Value(CalculateOption::Bool(false)),
+                (Err(e), _) => Tail::Value(error(env, ErrorCode::BuildInError, &[callee, &e])),
+            }
+        }
+        _ => Tail::Value(exp.calc(frame)),
+    }
+}
+
+/// 尾调用中的 If 能否按 If 的语义直接计算，即 If 为默认加载的版本，且与普通调用一样允许被调用,
+/// 否则按普通的函数调用处理
+fn inline_if(env: &EnvType) -> bool {
+    let e = RefCell::borrow(env);
+    e.is_std_if() && !e.forbids("If") && e.policy().allows("If")
+}
+
+/// 计算下标访问 target[index]，下标从 0 开始，负数从末尾开始计数，
+/// 片段的起止位置省略时分别为开头及末尾，下标越界时返回错误。
+/// 矩阵按行访问，M[i] 为第 i 行组成的向量，M[start:end] 为这些行组成的矩阵
+fn subscript(target: &FormulaNode, index: &Subscript, env: &EnvType) -> CalculateOption {
+    let target = target.calc(env);
+    let len = match &target {
+        CalculateOption::Vector(v) => v.len(),
+        CalculateOption::Matrix(m) => m.rows(),
+        CalculateOption::Err(e) => return CalculateOption::Err(e.clone()),
+        value => return error(env, ErrorCode::NotIndexable, &[&value.type_name()]),
+    };
+    // 将下标转换为 [0, limit) 范围内的位置
+    let position = |node: &FormulaNode, limit: usize| -> Result<usize, CalculateOption> {
+        let i = match to_float(node.calc(env)) {
+            CalculateOption::Num(n) if n.fract() == 0.0 => n,
+            CalculateOption::Err(e) => return Err(CalculateOption::Err(e)),
+            value => return Err(error(env, ErrorCode::InvalidIndex, &[&value])),
+        };
+        let at = match i < 0.0 {
+            true => i + len as f64,
+            false => i,
+        };
+        match at >= 0.0 && at < limit as f64 {
+            true => Ok(at as usize),
+            false => Err(error(env, ErrorCode::IndexOutOfRange, &[&i, &len])),
+        }
+    };
+    let value = match index {
+        Subscript::At(node) => position(node, len).map(|i| match &target {
+            CalculateOption::Matrix(m) => CalculateOption::Vector(m.row(i).to_vec()),
+            CalculateOption::Vector(v) => CalculateOption::Num(v[i]),
+            _ => CalculateOption::None,
+        }),
+        Subscript::Slice(start, end) => {
+            let bound = |node: &Option<Box<FormulaNode>>, default: usize| match node {
+                Some(node) => position(node, len + 1),
+                None => Ok(default),
+            };
+            bound(start, 0).and_then(|start| {
+                let end = bound(end, len)?.max(start);
+                Ok(match &target {
+                    CalculateOption::Matrix(m) => CalculateOption::Matrix(m.slice_rows(start..end)),
+                    CalculateOption::Vector(v) => CalculateOption::Vector(v[start..end].to_vec()),
+                    _ => CalculateOption::None,
+                })
+            })
+        }
+    };
+    value.unwrap_or_else(|e| e)
+}
+
+/// 数值常量 f 的计算结果，分数模式下为 f 按十进制书写所表示的分数
+#[cfg(feature = "rational")]
+fn constant(env: &EnvType, f: f64) -> CalculateOption {
+    match RefCell::borrow(env).options().rational {
+        true => rational::from_f64(f).map_or(CalculateOption::Num(f), CalculateOption::Rational),
+        false => CalculateOption::Num(f),
+    }
+}
+
+#[cfg(not(feature = "rational"))]
+fn constant(_env: &EnvType, f: f64) -> CalculateOption {
+    CalculateOption::Num(f)
+}
+
+/// 将分数模式下的分数转换为数值，供只接受数值的运算及内建函数使用，其他结果原样返回
+pub(crate) fn to_float(value: CalculateOption) -> CalculateOption {
+    match value {
+        #[cfg(feature = "rational")]
+        CalculateOption::Rational(r) => CalculateOption::Num(rational::to_f64(r)),
+        value => value,
+    }
+}
+
+/// 生成 env 中配置的语言的错误信息
+fn error(env: &EnvType, code: ErrorCode, args: &[&dyn Display]) -> CalculateOption {
+    CalculateOption::Err(code.message(locale_of(env), args))
+}
+
+fn locale_of(env: &EnvType) -> Locale {
+    RefCell::borrow(env).options().locale
+}
+
+/// 当两个操作数中至少有一个是复数时，将两者统一转换为复数，以支持复数与实数的混合运算
+fn complex_operands(left: &CalculateOption, right: &CalculateOption) -> Option<(Complex, Complex)> {
+    match (left, right) {
+        (CalculateOption::Complex(l), CalculateOption::Complex(r)) => Some((*l, *r)),
+        (CalculateOption::Complex(l), CalculateOption::Num(r)) => Some((*l, Complex::from(*r))),
+        (CalculateOption::Num(l), CalculateOption::Complex(r)) => Some((Complex::from(*l), *r)),
+        _ => None,
+    }
+}
+
+/// 对向量进行逐元素计算，标量会被广播为与向量等长的向量，两个向量参与计算时要求长度一致
+/// 两个操作数均不是向量时返回 None
+fn broadcast(
+    locale: Locale,
+    left: &CalculateOption,
+    right: &CalculateOption,
+    f: fn(f64, f64) -> f64,
+) -> Option<CalculateOption> {
+    let result = match (left, right) {
+        (CalculateOption::Vector(l), CalculateOption::Vector(r)) => {
+            if l.len() != r.len() {
+                return Some(CalculateOption::Err(
+                    ErrorCode::VectorLengthMismatch.message(locale, &[&l.len(), &r.len()]),
+                ));
+            }
+            l.iter().zip(r.iter()).map(|(l, r)| f(*l, *r)).collect()
+        }
+        (CalculateOption::Vector(l), CalculateOption::Num(r)) => {
+            l.iter().map(|l| f(*l, *r)).collect()
+        }
+        (CalculateOption::Num(l), CalculateOption::Vector(r)) => {
+            r.iter().map(|r| f(*l, *r)).collect()
+        }
+        _ => return None,
+    };
+    Some(CalculateOption::Vector(result))
+}
+
+/// 矩阵参与的四则运算，两个矩阵之间的 * 为矩阵乘法，矩阵与向量之间的 * 将向量作为列向量相乘,
+/// 数值会被广播到矩阵的每个元素，两个矩阵的加减运算逐元素计算，其余的组合返回 None
+fn matrix_arithmetic(
+    locale: Locale,
+    left: &CalculateOption,
+    right: &CalculateOption,
+    op: &str,
+    f: fn(f64, f64) -> f64,
+) -> Option<CalculateOption> {
+    let mismatch = |l: &Matrix, rows: usize, cols: usize| {
+        CalculateOption::Err(
+            ErrorCode::MatrixDimensionMismatch
+                .message(locale, &[&l.rows(), &l.cols(), &rows, &cols, &op]),
+        )
+    };
+    let result = match (left, right) {
+        (CalculateOption::Matrix(l), CalculateOption::Matrix(r)) => {
+            let result = match op {
+                "*" => l.matmul(r),
+                "+" | "-" => l.zip_with(r, f),
+                _ => return None,
+            };
+            match result {
+                Some(m) => CalculateOption::Matrix(m),
+                None => mismatch(l, r.rows(), r.cols()),
+            }
+        }
+        (CalculateOption::Matrix(l), CalculateOption::Vector(r)) if op == "*" => {
+            match l.mul_vector(r) {
+                Some(v) => CalculateOption::Vector(v),
+                None => mismatch(l, r.len(), 1),
+            }
+        }
+        (CalculateOption::Matrix(l), CalculateOption::Num(r)) => {
+            CalculateOption::Matrix(l.map(|l| f(l, *r)))
+        }
+        (CalculateOption::Num(l), CalculateOption::Matrix(r)) => {
+            CalculateOption::Matrix(r.map(|r| f(*l, r)))
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// 四则运算，支持数值、复数、向量及矩阵，任一操作数为 Err 时返回该错误，op 仅用于错误信息
+fn arithmetic(
+    locale: Locale,
+    left: CalculateOption,
+    right: CalculateOption,
+    op: &str,
+    f: fn(f64, f64) -> f64,
+    complex: fn(Complex, Complex) -> Complex,
+) -> CalculateOption {
+    // 两个分数之间的四则运算保持精确，溢出时及与其他类型的值计算时转换为数值
+    #[cfg(feature = "rational")]
+    let (left, right) = match (left, right) {
+        (CalculateOption::Rational(l), CalculateOption::Rational(r)) => {
+            match rational::arithmetic(l, r, op) {
+                Some(v) => return CalculateOption::Rational(v),
+                None => (
+                    CalculateOption::Num(rational::to_f64(l)),
+                    CalculateOption::Num(rational::to_f64(r)),
+                ),
+            }
+        }
+        (l, r) => (to_float(l), to_float(r)),
+    };
+    match (left, right) {
+        (CalculateOption::Num(l), CalculateOption::Num(r)) => CalculateOption::Num(f(l, r)),
+        (CalculateOption::Err(e), _) => CalculateOption::Err(e),
+        (_, CalculateOption::Err(e)) => CalculateOption::Err(e),
+        (l @ CalculateOption::Money(_), r) | (l, r @ CalculateOption::Money(_)) => {
+            money_arithmetic(locale, &l, &r, op, f)
+        }
+        (l, r) => match complex_operands(&l, &r) {
+            Some((l, r)) => CalculateOption::Complex(complex(l, r)),
+            None => matrix_arithmetic(locale, &l, &r, op, f)
+                .or_else(|| broadcast(locale, &l, &r, f))
+                .unwrap_or_else(|| {
+                    CalculateOption::Err(ErrorCode::NonNumericArithmetic.message(locale, &[&op]))
+                }),
+        },
+    }
+}
+
+/// 计算 <、<=、> 及 >= 的大小比较，相同货币的金额之间按金额比较，f 判断比较的结果是否成立,
+/// 与 NaN 比较时 f 的参数为 None，op 仅用于错误信息
+fn compare(
+    env: &EnvType,
+    left: CalculateOption,
+    right: CalculateOption,
+    op: &str,
+    f: fn(Option<Ordering>) -> bool,
+) -> CalculateOption {
+    #[cfg(feature = "rational")]
+    let (left, right) = match (left, right) {
+        (CalculateOption::Rational(l), CalculateOption::Rational(r)) => {
+            return CalculateOption::Bool(f(Some(l.cmp(&r))))
+        }
+        (l, r) => (to_float(l), to_float(r)),
+    };
+    match (left, right) {
+        (CalculateOption::Num(l), CalculateOption::Num(r)) => {
+            CalculateOption::Bool(f(l.partial_cmp(&r)))
+        }
+        (CalculateOption::Err(e), _) => CalculateOption::Err(e),
+        (_, CalculateOption::Err(e)) => CalculateOption::Err(e),
+        (CalculateOption::Complex(_), _) | (_, CalculateOption::Complex(_)) => {
+            error(env, ErrorCode::ComplexCompare, &[&op])
+        }
+        (CalculateOption::Money(l), CalculateOption::Money(r)) if l.currency == r.currency => {
+            CalculateOption::Bool(f(l.amount.partial_cmp(&r.amount)))
+        }
+        (CalculateOption::Money(l), CalculateOption::Money(r)) => error(
+            env,
+            ErrorCode::CurrencyMismatch,
+            &[&l.currency, &r.currency, &op],
+        ),
+        _ => error(env, ErrorCode::NonNumericCompare, &[&op]),
+    }
+}
+
+/// 金额参与的四则运算，相同货币的金额之间可以加减，相除得到两者的比值，
+/// 金额可以乘以数值或除以数值，其余的组合及不同货币的金额之间的运算返回错误
+fn money_arithmetic(
+    locale: Locale,
+    left: &CalculateOption,
+    right: &CalculateOption,
+    op: &str,
+    f: fn(f64, f64) -> f64,
+) -> CalculateOption {
+    match (left, right, op) {
+        (CalculateOption::Money(l), CalculateOption::Money(r), _) if l.currency != r.currency => {
+            CalculateOption::Err(
+                ErrorCode::CurrencyMismatch.message(locale, &[&l.currency, &r.currency, &op]),
+            )
+        }
+        (CalculateOption::Money(l), CalculateOption::Money(r), "+" | "-") => {
+            CalculateOption::Money(l.with_amount(f(l.amount, r.amount)))
+        }
+        (CalculateOption::Money(l), CalculateOption::Money(r), "/") => {
+            CalculateOption::Num(l.amount / r.amount)
+        }
+        (CalculateOption::Money(l), CalculateOption::Num(r), "*" | "/") => {
+            CalculateOption::Money(l.with_amount(f(l.amount, *r)))
+        }
+        (CalculateOption::Num(l), CalculateOption::Money(r), "*") => {
+            CalculateOption::Money(r.with_amount(f(*l, r.amount)))
+        }
+        (l, r, op) => CalculateOption::Err(
+            ErrorCode::InvalidMoneyOperation
+                .message(locale, &[&op, &l.type_name(), &r.type_name()]),
+        ),
+    }
+}
+
+/// 判断两个浮点数是否相等，两者的差值不超过 epsilon 即认为相等
+fn float_eq(l: f64, r: f64, epsilon: f64) -> bool {
+    l == r || (l - r).abs() <= epsilon
+}
+
+/// 计算 == 及 != 的相等性判断，op 仅用于错误信息
+fn calc_equal(
+    env: &EnvType,
+    left: CalculateOption,
+    right: CalculateOption,
+    epsilon: f64,
+    op: &str,
+) -> CalculateOption {
+    #[cfg(feature = "rational")]
+    let (left, right) = match (left, right) {
+        (CalculateOption::Rational(l), CalculateOption::Rational(r)) => {
+            return CalculateOption::Bool(l == r)
+        }
+        (l, r) => (to_float(l), to_float(r)),
+    };
+    match (left, right) {
+        (CalculateOption::Num(l), CalculateOption::Num(r)) => {
+            CalculateOption::Bool(float_eq(l, r, epsilon))
+        }
+        (CalculateOption::Text(l), CalculateOption::Text(r)) => CalculateOption::Bool(l == r),
+        // 与 < 及四则运算一致，不同货币的金额需要先换算才能比较
+        (CalculateOption::Money(l), CalculateOption::Money(r)) if l.currency == r.currency => {
+            CalculateOption::Bool(float_eq(l.amount, r.amount, epsilon))
+        }
+        (CalculateOption::Money(l), CalculateOption::Money(r)) => error(
+            env,
+            ErrorCode::CurrencyMismatch,
+            &[&l.currency, &r.currency, &op],
+        ),
+        (CalculateOption::Err(e), _) => CalculateOption::Err(e),
+        (_, CalculateOption::Err(e)) => CalculateOption::Err(e),
+        (l, r) => match complex_operands(&l, &r) {
+            Some((l, r)) => CalculateOption::Bool(
+                float_eq(l.re, r.re, epsilon) && float_eq(l.im, r.im, epsilon),
+            ),
+            None => error(env, ErrorCode::NonNumericCompare, &[&op]),
+        },
     }
 }
 
 impl FormulaCalc for OperatorNode {
     fn calc(&self, env: &EnvType) -> CalculateOption {
         match self {
-            OperatorNode::Plus { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Num(l + r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试使用加法来计算非数值类型")),
+            OperatorNode::Plus { left, right } => arithmetic(
+                locale_of(env),
+                left.calc(env),
+                right.calc(env),
+                "+",
+                |l, r| l + r,
+                |l, r| l + r,
+            ),
+            OperatorNode::Minus { left, right } => arithmetic(
+                locale_of(env),
+                left.calc(env),
+                right.calc(env),
+                "-",
+                |l, r| l - r,
+                |l, r| l - r,
+            ),
+            OperatorNode::Divide { left, right } => arithmetic(
+                locale_of(env),
+                left.calc(env),
+                right.calc(env),
+                "/",
+                |l, r| l / r,
+                |l, r| l / r,
+            ),
+            OperatorNode::Multiply { left, right } => arithmetic(
+                locale_of(env),
+                left.calc(env),
+                right.calc(env),
+                "*",
+                |l, r| l * r,
+                |l, r| l * r,
+            ),
+            OperatorNode::Power { left, right } => match (left.calc(env), right.calc(env)) {
+                (CalculateOption::Err(e), _) | (_, CalculateOption::Err(e)) => {
+                    CalculateOption::Err(e)
                 }
-            }
-            OperatorNode::Minus { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Num(l - r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试使用减法来计算非数值类型")),
+                (CalculateOption::Complex(_), _) | (_, CalculateOption::Complex(_)) => {
+                    error(env, ErrorCode::ComplexPower, &[])
                 }
-            }
-            OperatorNode::Divide { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Num(l / r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试使用除法来计算非数值类型")),
-                }
-            }
-            OperatorNode::Multiply { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Num(l * r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试使用乘法来计算非数值类型")),
-                }
-            }
+                // 已排除复数，不会使用复数的计算方式
+                (left, right) => arithmetic(locale_of(env), left, right, "^", f64::powf, |l, _| l),
+            },
             OperatorNode::Less { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Bool(l < r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试用 < 比较两个非数值类型")),
-                }
+                compare(env, left.calc(env), right.calc(env), "<", |o| {
+                    o == Some(Ordering::Less)
+                })
             }
             OperatorNode::LessEqual { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Bool(l <= r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试用 <= 比较两个非数值类型")),
-                }
+                compare(env, left.calc(env), right.calc(env), "<=", |o| {
+                    matches!(o, Some(Ordering::Less | Ordering::Equal))
+                })
             }
             OperatorNode::Great { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Bool(l > r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试用 > 比较两个非数值类型")),
-                }
+                compare(env, left.calc(env), right.calc(env), ">", |o| {
+                    o == Some(Ordering::Greater)
+                })
             }
             OperatorNode::GreatEqual { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Bool(l >= r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试用 >= 比较两个非数值类型")),
-                }
+                compare(env, left.calc(env), right.calc(env), ">=", |o| {
+                    matches!(o, Some(Ordering::Greater | Ordering::Equal))
+                })
             }
             OperatorNode::Equal { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
-                match (left, right) {
-                    (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return CalculateOption::Bool(l == r)
-                    }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试用 == 比较两个非数值类型")),
+                let epsilon = RefCell::borrow(env).options().float_eq_epsilon;
+                calc_equal(env, left.calc(env), right.calc(env), epsilon, "==")
+            }
+            OperatorNode::NotEqual { left, right } => {
+                let epsilon = RefCell::borrow(env).options().float_eq_epsilon;
+                match calc_equal(env, left.calc(env), right.calc(env), epsilon, "!=") {
+                    CalculateOption::Bool(b) => CalculateOption::Bool(!b),
+                    other => other,
                 }
             }
             OperatorNode::Not(node) => {
-                let node = node.calc(env);
+                let strict = RefCell::borrow(env).options().strict_logic;
+                let node = to_float(node.calc(env));
                 match node {
-                    CalculateOption::Bool(b) => return CalculateOption::Bool(!b),
-                    CalculateOption::Num(n) => return CalculateOption::Bool(n != 0.0),
-                    CalculateOption::Err(e) => return CalculateOption::Err(e),
-                    _ => return CalculateOption::Err(format!("尝试对非逻辑结果取反")),
+                    CalculateOption::Bool(b) => CalculateOption::Bool(!b),
+                    CalculateOption::Num(n) if !strict => CalculateOption::Bool(n == 0.0),
+                    CalculateOption::Err(e) => CalculateOption::Err(e),
+                    _ => error(env, ErrorCode::NonBoolNot, &[]),
                 }
             }
             OperatorNode::And { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
+                let strict = RefCell::borrow(env).options().strict_logic;
+                let left = to_float(left.calc(env));
+                let right = to_float(right.calc(env));
+                if strict {
+                    return strict_logic(env, left, right, "&&", |l, r| l && r);
+                }
                 match (left, right) {
                     (CalculateOption::Bool(l), CalculateOption::Bool(r)) => {
-                        return CalculateOption::Bool(l && r)
-                    }
-                    (CalculateOption::Bool(l), CalculateOption::Num(r)) => {
-                        return match (l, r != 0.0) {
-                            (true, _) => CalculateOption::Num(r),
-                            (false, _) => CalculateOption::Bool(false),
-                        }
+                        CalculateOption::Bool(l && r)
                     }
+                    (CalculateOption::Bool(l), CalculateOption::Num(r)) => match (l, r != 0.0) {
+                        (true, _) => CalculateOption::Num(r),
+                        (false, _) => CalculateOption::Bool(false),
+                    },
                     (CalculateOption::Num(l), CalculateOption::Bool(r)) => {
-                        return match (l != 0.0, r) {
+                        match (l != 0.0, r) {
                             (true, _) => CalculateOption::Bool(r),
                             (false, _) => CalculateOption::Num(l), // 0.0
-                        };
+                        }
                     }
                     (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return match (l != 0.0, r != 0.0) {
+                        match (l != 0.0, r != 0.0) {
                             (true, _) => CalculateOption::Num(r),
                             (false, _) => CalculateOption::Num(l),
                         }
                     }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => {
-                        return CalculateOption::Err(format!("尝试对两个非数值类型使用逻辑与操作"))
-                    }
+                    (CalculateOption::Err(e), _) => CalculateOption::Err(e),
+                    (_, CalculateOption::Err(e)) => CalculateOption::Err(e),
+                    _ => error(env, ErrorCode::NonNumericLogic, &[&"&&"]),
                 }
             }
             OperatorNode::Or { left, right } => {
-                let left = left.calc(env);
-                let right = right.calc(env);
+                let strict = RefCell::borrow(env).options().strict_logic;
+                let left = to_float(left.calc(env));
+                let right = to_float(right.calc(env));
+                if strict {
+                    return strict_logic(env, left, right, "||", |l, r| l || r);
+                }
                 match (left, right) {
                     (CalculateOption::Bool(l), CalculateOption::Bool(r)) => {
-                        return CalculateOption::Bool(l || r)
-                    }
-                    (CalculateOption::Bool(l), CalculateOption::Num(r)) => {
-                        return match (l, r != 0.0) {
-                            (true, _) => CalculateOption::Bool(l),
-                            (false, _) => CalculateOption::Num(r),
-                        }
-                    }
-                    (CalculateOption::Num(l), CalculateOption::Bool(r)) => {
-                        return match (l != 0.0, r) {
-                            (true, _) => CalculateOption::Num(l),
-                            (false, _) => CalculateOption::Bool(r),
-                        }
+                        CalculateOption::Bool(l || r)
                     }
+                    (CalculateOption::Bool(l), CalculateOption::Num(r)) => match (l, r != 0.0) {
+                        (true, _) => CalculateOption::Bool(l),
+                        (false, _) => CalculateOption::Num(r),
+                    },
+                    (CalculateOption::Num(l), CalculateOption::Bool(r)) => match (l != 0.0, r) {
+                        (true, _) => CalculateOption::Num(l),
+                        (false, _) => CalculateOption::Bool(r),
+                    },
                     (CalculateOption::Num(l), CalculateOption::Num(r)) => {
-                        return match (l != 0.0, r != 0.0) {
+                        match (l != 0.0, r != 0.0) {
                             (true, _) => CalculateOption::Num(l),
                             (false, _) => CalculateOption::Num(r),
                         }
                     }
-                    (CalculateOption::Err(e), _) => return CalculateOption::Err(e),
-                    (_, CalculateOption::Err(e)) => return CalculateOption::Err(e),
-                    _ => {
-                        return CalculateOption::Err(format!("尝试对两个非数值类型使用逻辑或操作"))
-                    }
+                    (CalculateOption::Err(e), _) => CalculateOption::Err(e),
+                    (_, CalculateOption::Err(e)) => CalculateOption::Err(e),
+                    _ => error(env, ErrorCode::NonNumericLogic, &[&"||"]),
                 }
             }
         }
     }
 }
 
+/// 严格逻辑模式下的 && 及 ||，两个操作数均应为逻辑值
+fn strict_logic(
+    env: &EnvType,
+    left: CalculateOption,
+    right: CalculateOption,
+    symbol: &str,
+    op: fn(bool, bool) -> bool,
+) -> CalculateOption {
+    match (left, right) {
+        (CalculateOption::Bool(l), CalculateOption::Bool(r)) => CalculateOption::Bool(op(l, r)),
+        (CalculateOption::Err(e), _) => CalculateOption::Err(e),
+        (_, CalculateOption::Err(e)) => CalculateOption::Err(e),
+        _ => error(env, ErrorCode::NonBoolLogic, &[&symbol]),
+    }
+}
+
+/// 表达式计算时的可选配置，保存在执行环境中，对该环境中所有的计算生效
+#[derive(Debug, Clone)]
+pub struct CalculateOptions {
+    /// 判断两个浮点数相等 (==, != 及连续比较) 时允许的误差，默认为 0，即要求严格相等,
+    /// 操作符没有优先级且从右向左结合，比较算式的结果时需加括号，如 (0.1 + 0.2) == 0.3
+    pub float_eq_epsilon: f64,
+    /// 每个变量最多保留的历史值个数，默认为 0，即不记录历史值，
+    /// 需要在表达式中通过 prev(A) 或 A@-1 读取历史值时需设置该值
+    pub history_limit: usize,
+    /// 是否记录计算过程，默认为 false，开启后 CalculateResult::trace 中会保存每个节点的计算结果
+    pub trace: bool,
+    /// 是否统计每个函数及具名表达式的调用次数及耗时，默认为 false，
+    /// 开启后 CalculateResult::profile 中会保存统计结果
+    pub profile: bool,
+    /// 错误信息所使用的语言，默认为中文
+    pub locale: Locale,
+    /// 是否记录函数调用，默认为 true，记录结果保存在 CalculateResult::more 中,
+    /// 批量计算等不需要调用记录的场景可以关闭，以省去每次函数调用时的记录开销
+    pub record_stack: bool,
+    /// 确定性计算模式，默认为 None，开启后结果按固定的小数位数舍入，禁止调用结果不确定的内建函数,
+    /// 并在 CalculateResult::audit 中保存审计记录
+    pub deterministic: Option<Deterministic>,
+    /// 计算结果的默认精度，默认为 None，设置后最终结果按指定的小数位数及舍入方式舍入
+    pub precision: Option<Precision>,
+    /// 数值的书写习惯，默认以 . 为小数点，决定了公式中数值常量的解析方式，
+    /// 以及通过 Parser::format_value 输出计算结果时数值的格式
+    pub number_locale: NumberLocale,
+    /// 是否为严格逻辑模式，默认为 false。
+    /// 严格模式下 &&、|| 及 ! 的操作数只能为逻辑值，否则返回错误 E0311 / E0305;
+    /// 默认的宽松模式下数值也可以参与逻辑运算，0 为假，其他数值 (包括 NaN) 为真,
+    /// a && b 在 a 为假时返回 a，否则返回 b，a || b 在 a 为真时返回 a，否则返回 b，
+    /// 两个操作数均为逻辑值时结果为逻辑值，!n 在 n 为 0 时为 true，否则为 false
+    pub strict_logic: bool,
+    /// 是否为分数模式，默认为 false。开启后数值常量按其十进制书写转换为分数，分数之间的四则运算及比较是精确的,
+    /// 如 (1/3) * 3 == 1 及 0.1 + 0.2 == 0.3 均成立; 分数在作为最终结果输出、传递给内建函数、
+    /// 参与乘方及逻辑运算或与其他类型的值一起计算时转换为 f64，运算溢出 i64 时也退回 f64 计算
+    #[cfg(feature = "rational")]
+    pub rational: bool,
+}
+
+impl Default for CalculateOptions {
+    fn default() -> Self {
+        CalculateOptions {
+            float_eq_epsilon: 0.0,
+            history_limit: 0,
+            trace: false,
+            profile: false,
+            locale: Locale::default(),
+            record_stack: true,
+            deterministic: None,
+            precision: None,
+            number_locale: NumberLocale::default(),
+            strict_logic: false,
+            #[cfg(feature = "rational")]
+            rational: false,
+        }
+    }
+}
+
+/// 计算过程中的一个节点，expr 为该节点的表达式，value 为其计算结果,
+/// children 为计算该节点时依次计算的子节点，如操作符的操作数、函数的参数及函数体中的表达式
 #[derive(Debug, Clone)]
-pub struct StackInfo {
-    /// 当前有保存信息的 op 有 FunctionCall 跟 BuildInFunction
-    pub op: String,
-    /// 调用的函数名称
-    pub func: String,
-    /// 调用函数所使用的参数
-    pub args: Vec<Rc<FormulaNode>>,
+pub struct TraceNode {
+    pub expr: String,
+    pub value: CalculateOption,
+    pub children: Vec<TraceNode>,
 }
 
-/// 表达式计算的结果， value 保存了表达式计算的最终结果， more 保存了当前表达式中执行过程中的调用信息,
-/// 调用的信息主要包括，当前操作名称，函数名、函数调用的参数
-/// 这些调用信息一般只有自定义或内建函数才会保存，简单的 Num、Var 等操作都还没保存到其中
+impl TraceNode {
+    fn fmt_with_indent(&self, f: &mut core::fmt::Formatter, indent: usize) -> core::fmt::Result {
+        writeln!(
+            f,
+            "{}{} => {:?}",
+            "  ".repeat(indent),
+            self.expr,
+            self.value
+        )?;
+        for child in self.children.iter() {
+            child.fmt_with_indent(f, indent + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// 按层级缩进输出完整的计算过程，每行为 表达式 => 计算结果
+impl core::fmt::Display for TraceNode {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.fmt_with_indent(f, 0)
+    }
+}
+
+/// 表达式计算的结果， value 保存了表达式计算的最终结果,
+/// 开启 CalculateOptions::trace 时 trace 中保存了完整的计算过程，可用于解释计算结果是如何得到的
 #[derive(Debug, Clone)]
 pub struct CalculateResult {
     /// 本次计算的结果
     pub value: CalculateOption,
-    /// 用于保存调用信息
-    /// TODO: 暂时使用 hash map，如果需要完整的堆栈信息，则改为树
-    pub more: Vec<StackInfo>,
+    /// 计算过程树，未开启记录时为 None
+    pub trace: Option<TraceNode>,
+    /// 本次计算中最外层的函数调用，嵌套的调用保存在各自的 children 中
+    pub more: Vec<CallFrame>,
+    /// 每个函数及具名表达式的耗时统计，未开启 CalculateOptions::profile 时为 None
+    pub profile: Option<ProfileReport>,
+    /// 确定性计算模式下的审计记录，未开启 CalculateOptions::deterministic 时为 None
+    pub audit: Option<AuditRecord>,
+}
+
+/// 输出为 值 (类型)，之后按层级缩进列出本次计算中的函数调用，
+/// 最外层的调用超过 MAX_DISPLAY_CALLS 个时只输出前面的部分及省略的个数
+impl core::fmt::Display for CalculateResult {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} ({})", self.value, self.value.type_name())?;
+        for frame in self.more.iter().take(MAX_DISPLAY_CALLS) {
+            frame.fmt_with_indent(f, 1)?;
+        }
+        if self.more.len() > MAX_DISPLAY_CALLS {
+            write!(f, "\n  ... (+{})", self.more.len() - MAX_DISPLAY_CALLS)?;
+        }
+        Ok(())
+    }
 }
 
+/// CalculateResult 输出时最多列出的最外层函数调用个数
+const MAX_DISPLAY_CALLS: usize = 10;
+
+/// 函数或具名表达式的耗时统计，total 包括其中调用的其他函数及表达式的耗时，
+/// 递归调用时内层调用的耗时会被重复计入
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProfileEntry {
+    pub calls: usize,
+    pub total: Duration,
+}
+
+/// 一次或多次计算的耗时统计，key 为函数名或具名表达式的名称
+#[derive(Debug, Clone, Default)]
+pub struct ProfileReport {
+    pub entries: HashMap<String, ProfileEntry>,
+}
+
+impl ProfileReport {
+    /// 记录 name 的一次调用
+    pub fn record(&mut self, name: &str, elapsed: Duration) {
+        let entry = self.entries.entry(name.to_string()).or_default();
+        entry.calls += 1;
+        entry.total += elapsed;
+    }
+
+    /// 合并另一次计算的统计，如将批量计算中每一行的统计汇总
+    pub fn merge(&mut self, other: &ProfileReport) {
+        for (name, e) in other.entries.iter() {
+            let entry = self.entries.entry(name.clone()).or_default();
+            entry.calls += e.calls;
+            entry.total += e.total;
+        }
+    }
+
+    /// 按总耗时从高到低排列的统计
+    pub fn slowest(&self) -> Vec<(&str, &ProfileEntry)> {
+        let mut entries: Vec<(&str, &ProfileEntry)> =
+            self.entries.iter().map(|(n, e)| (n.as_str(), e)).collect();
+        entries.sort_by_key(|e| core::cmp::Reverse(e.1.total));
+        entries
+    }
+}
+
+/// 一次函数调用，包括调用的函数名、参数、参数的计算结果、返回值及在其中发生的函数调用,
+/// 内建函数的参数由函数自身按需计算，因此 arg_values 只记录脚本中定义的函数的参数值
 #[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub func: String,
+    pub args: Rc<[Rc<FormulaNode>]>,
+    pub arg_values: Vec<CalculateOption>,
+    pub value: CalculateOption,
+    pub children: Vec<CallFrame>,
+}
+
+impl CallFrame {
+    fn fmt_with_indent(&self, f: &mut core::fmt::Formatter, indent: usize) -> core::fmt::Result {
+        // 内建函数没有记录参数值，此时输出参数的表达式
+        let args: Vec<String> = match self.arg_values.is_empty() {
+            true => self.args.iter().map(|arg| arg.to_string()).collect(),
+            false => self.arg_values.iter().map(|v| v.to_string()).collect(),
+        };
+        write!(
+            f,
+            "\n{}{}({}) => {}",
+            "  ".repeat(indent),
+            self.func,
+            args.join(", "),
+            self.value
+        )?;
+        for child in self.children.iter() {
+            child.fmt_with_indent(f, indent + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// 公式计算的结果值
 pub enum CalculateOption {
     Bool(bool),
     Num(f64),
+    /// 复数，由虚数常量参与计算得到
+    Complex(Complex),
+    /// 数值向量，如绑定到变量上的一整列数据，参与四则运算时逐元素计算
+    Vector(Vec<f64>),
+    /// 数值矩阵，由矩阵常量或 Transpose、Inverse 等函数得到
+    Matrix(Matrix),
+    /// 文本，由字符串常量或 Format 等格式化函数得到，用于展示计算结果
+    Text(String),
+    /// 货币金额，由 Money(amount, currency) 得到
+    Money(Money),
+    /// 分数，分数模式下由数值常量及其四则运算得到，见 CalculateOptions::rational
+    #[cfg(feature = "rational")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Rational(Rational64),
     Err(String),
-    /// 如果计算的结果是函数定义，说明要调用
-    Func,
+    /// 函数值，即脚本中定义的函数或内建函数本身，可以作为参数传递并在被调用的函数中调用
+    Function(Rc<FormulaNode>),
+    /// 宿主对象，如数据库游标、曲线对象等公式无法表示的值，由一个内建函数返回后交给另一个内建函数使用,
+    /// 公式中只能传递而不能参与运算，无法序列化
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Opaque(Rc<dyn Any>),
     /// None 表示该计算没有结果
     None,
 }
 
 impl CalculateOption {
-    pub fn eq(&self, other: &CalculateOption) -> bool {
+    /// 将计算结果转换为对应的常量节点，以便将其保存到执行环境中，如绑定函数的参数
+    /// 非值类型的结果 (Err、None) 返回 None，函数值转换为对应的函数定义
+    pub fn to_node(&self) -> Option<FormulaNode> {
+        match self {
+            CalculateOption::Bool(b) => Some(FormulaNode::Bool(*b)),
+            CalculateOption::Num(f) => Some(FormulaNode::Constant(*f)),
+            CalculateOption::Complex(c) => Some(FormulaNode::Complex(*c)),
+            CalculateOption::Vector(v) => Some(FormulaNode::Vector(v.clone())),
+            CalculateOption::Matrix(m) => Some(FormulaNode::Matrix(m.clone())),
+            CalculateOption::Text(t) => Some(FormulaNode::Text(t.clone())),
+            CalculateOption::Money(m) => Some(FormulaNode::Money(m.clone())),
+            #[cfg(feature = "rational")]
+            CalculateOption::Rational(r) => Some(rational::to_node(*r)),
+            CalculateOption::Function(f) => Some(f.as_ref().clone()),
+            CalculateOption::Opaque(v) => Some(FormulaNode::Opaque(Rc::clone(v))),
+            _ => None,
+        }
+    }
+
+    /// 计算结果为数值时返回该数值
+    pub fn as_num(&self) -> Option<f64> {
+        match self {
+            CalculateOption::Num(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// 计算结果为逻辑值时返回该逻辑值
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            CalculateOption::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// 计算结果为文本时返回该文本
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            CalculateOption::Text(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// 将宿主对象 value 包装为计算结果，供内建函数返回
+    pub fn opaque<T: Any>(value: T) -> Self {
+        CalculateOption::Opaque(Rc::new(value))
+    }
+
+    /// 计算结果为 T 类型的宿主对象时返回该对象
+    pub fn as_opaque<T: Any>(&self) -> Option<&T> {
+        match self {
+            CalculateOption::Opaque(v) => v.downcast_ref::<T>(),
+            _ => None,
+        }
+    }
+
+    /// 计算结果的类型名称，如 num、bool、vector，错误为 error
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CalculateOption::Bool(_) => "bool",
+            CalculateOption::Num(_) => "num",
+            CalculateOption::Complex(_) => "complex",
+            CalculateOption::Vector(_) => "vector",
+            CalculateOption::Matrix(_) => "matrix",
+            CalculateOption::Text(_) => "text",
+            CalculateOption::Money(_) => "money",
+            #[cfg(feature = "rational")]
+            CalculateOption::Rational(_) => "num",
+            CalculateOption::Err(_) => "error",
+            CalculateOption::Function(_) => "function",
+            CalculateOption::Opaque(_) => "opaque",
+            CalculateOption::None => "none",
+        }
+    }
+
+    /// 与 Display 相同，但其中的数值按 number_locale 的书写习惯输出，如 1.234,5 及 [1,5, 2]
+    pub fn to_string_with(&self, number_locale: &NumberLocale) -> String {
+        let format = |n: f64| number_locale.format(n);
+        let vector = |v: &[f64]| {
+            let items: Vec<String> = v.iter().map(|&x| format(x)).collect();
+            format!("[{}]", items.join(", "))
+        };
+        match self {
+            CalculateOption::Num(n) => format(*n),
+            CalculateOption::Complex(c) if c.re == 0.0 => format!("{}i", format(c.im)),
+            CalculateOption::Complex(c) => format!("({} + {}i)", format(c.re), format(c.im)),
+            CalculateOption::Money(m) => format!("Money({}, \"{}\")", format(m.amount), m.currency),
+            CalculateOption::Vector(v) => vector(v),
+            CalculateOption::Matrix(m) => {
+                let rows: Vec<String> = (0..m.rows()).map(|i| vector(m.row(i))).collect();
+                format!("[{}]", rows.join(", "))
+            }
+            value => value.to_string(),
+        }
+    }
+
+    /// 以普通 JSON 值表示计算结果：数值、逻辑值、文本及向量为对应的 JSON 值，复数为 {"re", "im"},
+    /// 函数为其定义的文本，错误为错误信息，None 为 null
+    #[cfg(feature = "serde")]
+    pub fn to_json_value(&self) -> serde_json::Value {
+        use serde_json::json;
+        match self {
+            CalculateOption::Bool(b) => json!(b),
+            CalculateOption::Num(f) => json!(f),
+            CalculateOption::Complex(c) => json!({ "re": c.re, "im": c.im }),
+            CalculateOption::Vector(v) => json!(v),
+            CalculateOption::Matrix(m) => {
+                json!((0..m.rows()).map(|i| m.row(i)).collect::<Vec<_>>())
+            }
+            CalculateOption::Text(t) => json!(t),
+            CalculateOption::Money(m) => json!({ "amount": m.amount, "currency": m.currency }),
+            #[cfg(feature = "rational")]
+            CalculateOption::Rational(r) => json!(rational::to_f64(*r)),
+            CalculateOption::Err(e) => json!(e),
+            CalculateOption::Function(f) => json!(f.to_string()),
+            CalculateOption::Opaque(_) => json!("<opaque>"),
+            CalculateOption::None => serde_json::Value::Null,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl CalculateResult {
+    /// 输出为 {"value": ..., "type": "num", "trace": [...]}，trace 为本次计算中的函数调用,
+    /// 确定性计算模式下还包括 audit: {"formula", "formula_hash", "inputs", "result"}
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "value": self.value.to_json_value(),
+            "type": self.value.type_name(),
+            "trace": self.more.iter().map(CallFrame::to_json).collect::<Vec<_>>(),
+        });
+        if let Some(ref audit) = self.audit {
+            let inputs: serde_json::Map<String, serde_json::Value> = audit
+                .inputs
+                .iter()
+                .map(|(name, value)| (name.clone(), value.to_json_value()))
+                .collect();
+            json["audit"] = serde_json::json!({
+                "formula": audit.formula,
+                "formula_hash": audit.formula_hash,
+                "inputs": inputs,
+                "result": audit.result.to_json_value(),
+            });
+        }
+        json
+    }
+}
+
+#[cfg(feature = "serde")]
+impl CallFrame {
+    /// 输出为 {"func", "args", "arg_values", "value", "children"}，args 为参数表达式的文本
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "func": self.func,
+            "args": self.args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>(),
+            "arg_values": self.arg_values.iter().map(CalculateOption::to_json_value).collect::<Vec<_>>(),
+            "value": self.value.to_json_value(),
+            "children": self.children.iter().map(CallFrame::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// 值类型的结果输出为对应的公式文本，如 1.5、true、[1, 2]，错误输出其错误信息
+impl core::fmt::Display for CalculateOption {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.to_node() {
+            Some(node) => write!(f, "{}", node),
+            None => match self {
+                CalculateOption::Err(e) => write!(f, "{}", e),
+                _ => write!(f, "None"),
+            },
+        }
+    }
+}
+
+impl From<f64> for CalculateOption {
+    fn from(f: f64) -> Self {
+        CalculateOption::Num(f)
+    }
+}
+
+impl From<Money> for CalculateOption {
+    fn from(m: Money) -> Self {
+        CalculateOption::Money(m)
+    }
+}
+
+impl From<bool> for CalculateOption {
+    fn from(b: bool) -> Self {
+        CalculateOption::Bool(b)
+    }
+}
+
+/// 类型不匹配时返回原来的计算结果，以便调用方获取其中的错误信息
+impl TryFrom<CalculateOption> for f64 {
+    type Error = CalculateOption;
+
+    fn try_from(value: CalculateOption) -> Result<Self, Self::Error> {
+        value.as_num().ok_or(value)
+    }
+}
+
+impl TryFrom<CalculateOption> for bool {
+    type Error = CalculateOption;
+
+    fn try_from(value: CalculateOption) -> Result<Self, Self::Error> {
+        value.as_bool().ok_or(value)
+    }
+}
+
+/// 值类型的结果转换为其文本形式，文本结果不带引号，Err 与 None 无法转换
+impl TryFrom<CalculateOption> for String {
+    type Error = CalculateOption;
+
+    fn try_from(value: CalculateOption) -> Result<Self, Self::Error> {
+        match value {
+            CalculateOption::Text(t) => Ok(t),
+            CalculateOption::Err(_) | CalculateOption::None => Err(value),
+            value => Ok(value.to_string()),
+        }
+    }
+}
+
+impl PartialEq for CalculateOption {
+    fn eq(&self, other: &Self) -> bool {
         use CalculateOption::*;
         match (self, other) {
             (Bool(a), Bool(b)) => a == b,
             (Num(f1), Num(f2)) => f1 == f2,
+            (Complex(c1), Complex(c2)) => c1 == c2,
+            (Vector(v1), Vector(v2)) => v1 == v2,
+            (Matrix(m1), Matrix(m2)) => m1 == m2,
+            (Text(t1), Text(t2)) => t1 == t2,
+            (Money(m1), Money(m2)) => m1 == m2,
+            #[cfg(feature = "rational")]
+            (Rational(r1), Rational(r2)) => r1 == r2,
+            (Function(f1), Function(f2)) => Rc::ptr_eq(f1, f2),
+            (Opaque(v1), Opaque(v2)) => Rc::ptr_eq(v1, v2),
             (_, _) => false,
         }
     }
 }
 
-impl PartialEq for CalculateOption {
+/// 计算成功时得到的值，即除 Err 以外的 CalculateOption，由 Parser::try_calculate 返回
+#[derive(Debug, Clone)]
+pub enum TypedValue {
+    Bool(bool),
+    Num(f64),
+    Complex(Complex),
+    Vector(Vec<f64>),
+    Matrix(Matrix),
+    Text(String),
+    Money(Money),
+    Function(Rc<FormulaNode>),
+    Opaque(Rc<dyn Any>),
+    /// 表达式没有结果，如只包含定义的语句
+    None,
+}
+
+impl TypedValue {
+    pub fn as_num(&self) -> Option<f64> {
+        match self {
+            TypedValue::Num(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            TypedValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+/// 计算结果为 Err 时返回其中的错误信息
+impl TryFrom<CalculateOption> for TypedValue {
+    type Error = String;
+
+    fn try_from(value: CalculateOption) -> Result<Self, Self::Error> {
+        Ok(match value {
+            CalculateOption::Bool(b) => TypedValue::Bool(b),
+            CalculateOption::Num(f) => TypedValue::Num(f),
+            CalculateOption::Complex(c) => TypedValue::Complex(c),
+            CalculateOption::Vector(v) => TypedValue::Vector(v),
+            CalculateOption::Matrix(m) => TypedValue::Matrix(m),
+            CalculateOption::Text(t) => TypedValue::Text(t),
+            CalculateOption::Money(m) => TypedValue::Money(m),
+            #[cfg(feature = "rational")]
+            CalculateOption::Rational(r) => TypedValue::Num(rational::to_f64(r)),
+            CalculateOption::Function(f) => TypedValue::Function(f),
+            CalculateOption::Opaque(v) => TypedValue::Opaque(v),
+            CalculateOption::None => TypedValue::None,
+            CalculateOption::Err(e) => return Err(e),
+        })
+    }
+}
+
+impl From<TypedValue> for CalculateOption {
+    fn from(value: TypedValue) -> Self {
+        match value {
+            TypedValue::Bool(b) => CalculateOption::Bool(b),
+            TypedValue::Num(f) => CalculateOption::Num(f),
+            TypedValue::Complex(c) => CalculateOption::Complex(c),
+            TypedValue::Vector(v) => CalculateOption::Vector(v),
+            TypedValue::Matrix(m) => CalculateOption::Matrix(m),
+            TypedValue::Text(t) => CalculateOption::Text(t),
+            TypedValue::Money(m) => CalculateOption::Money(m),
+            TypedValue::Function(f) => CalculateOption::Function(f),
+            TypedValue::Opaque(v) => CalculateOption::Opaque(v),
+            TypedValue::None => CalculateOption::None,
+        }
+    }
+}
+
+impl PartialEq for TypedValue {
     fn eq(&self, other: &Self) -> bool {
-        self.eq(other)
+        match (self, other) {
+            (TypedValue::None, TypedValue::None) => true,
+            (l, r) => CalculateOption::from(l.clone()) == CalculateOption::from(r.clone()),
+        }
+    }
+}
+
+impl core::fmt::Display for TypedValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", CalculateOption::from(self.clone()))
+    }
+}
+
+/// 只有数值之间及逻辑值之间可以比较大小，相等的值返回 Equal，其他情况返回 None
+impl PartialOrd for CalculateOption {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (CalculateOption::Num(l), CalculateOption::Num(r)) => l.partial_cmp(r),
+            (CalculateOption::Bool(l), CalculateOption::Bool(r)) => l.partial_cmp(r),
+            (l, r) if l == r => Some(Ordering::Equal),
+            _ => None,
+        }
     }
 }
+
+/// 与表达式中的四则运算相同，支持数值、复数及向量，任一操作数为 Err 时返回该错误,
+/// 由于不在执行环境中计算，错误信息使用默认的语言
+macro_rules! impl_arithmetic {
+    ($($trait:ident, $method:ident, $op:tt;)*) => {
+        $(
+            impl $trait for CalculateOption {
+                type Output = CalculateOption;
+
+                fn $method(self, rhs: CalculateOption) -> CalculateOption {
+                    arithmetic(
+                        Locale::default(),
+                        self,
+                        rhs,
+                        stringify!($op),
+                        |l, r| l $op r,
+                        |l, r| l $op r,
+                    )
+                }
+            }
+        )*
+    };
+}
+
+impl_arithmetic! {
+    Add, add, +;
+    Sub, sub, -;
+    Mul, mul, *;
+    Div, div, /;
+}
@@ -0,0 +1,72 @@
+use crate::calculator::CalculateOption;
+use crate::env::VariableResolver;
+
+/// 电子表格的单元格数据源，column 与 row 均从 0 开始，即 A1 对应 (0, 0)，B3 对应 (1, 2),
+/// 单元格为空或不存在时返回 None
+pub trait CellResolver {
+    fn cell(&self, column: usize, row: usize) -> Option<f64>;
+}
+
+impl<F> CellResolver for F
+where
+    F: Fn(usize, usize) -> Option<f64>,
+{
+    fn cell(&self, column: usize, row: usize) -> Option<f64> {
+        self(column, row)
+    }
+}
+
+/// 将 A1、B2:B10 形式的变量名解析为单元格引用的外部变量解析器,
+/// 单个单元格得到数值，区域按行依次得到其中非空单元格组成的向量
+pub struct CellReferences<R> {
+    cells: R,
+}
+
+impl<R: CellResolver> CellReferences<R> {
+    pub fn new(cells: R) -> Self {
+        CellReferences { cells }
+    }
+}
+
+impl<R: CellResolver> VariableResolver for CellReferences<R> {
+    fn resolve(&self, name: &str) -> Option<CalculateOption> {
+        match name.split_once(':') {
+            None => {
+                let (column, row) = parse_cell(name)?;
+                self.cells.cell(column, row).map(CalculateOption::Num)
+            }
+            Some((from, to)) => {
+                let (c1, r1) = parse_cell(from)?;
+                let (c2, r2) = parse_cell(to)?;
+                let values = (r1.min(r2)..=r1.max(r2))
+                    .flat_map(|row| (c1.min(c2)..=c1.max(c2)).map(move |column| (column, row)))
+                    .filter_map(|(column, row)| self.cells.cell(column, row))
+                    .collect();
+                Some(CalculateOption::Vector(values))
+            }
+        }
+    }
+}
+
+/// 单元格名称中列名最多的字母个数，与 Excel 相同 (最大为 XFD)
+const MAX_COLUMN_LETTERS: usize = 3;
+
+/// 将 A1 形式的单元格名称解析为从 0 开始的 (列, 行)，列名不区分大小写
+pub fn parse_cell(name: &str) -> Option<(usize, usize)> {
+    let split = name.find(|c: char| !c.is_ascii_alphabetic())?;
+    let (letters, digits) = name.split_at(split);
+    if letters.is_empty() || letters.len() > MAX_COLUMN_LETTERS {
+        return None;
+    }
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let column = letters.chars().fold(0, |acc, c| {
+        acc * 26 + (c.to_ascii_uppercase() as usize - 'A' as usize + 1)
+    });
+    match digits.parse::<usize>().ok()? {
+        0 => None,
+        row => Some((column - 1, row - 1)),
+    }
+}
@@ -0,0 +1,68 @@
+use alloc::rc::Rc;
+use core::cell::Cell;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// 提供当前时间的时钟，用于判断延迟生效的变量是否到期及统计计算耗时，
+/// now 返回自某个固定时间点起经过的时长，在没有系统时钟的环境中可以自行实现该 trait
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+/// 基于 Instant 的系统时钟，开启 std 特性时为执行环境默认使用的时钟
+#[cfg(feature = "std")]
+pub struct SystemClock {
+    start: Instant,
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// 手动推进的时钟，时间只在调用 advance 或 set 时变化，
+/// 未开启 std 特性时为执行环境默认使用的时钟，也可用于测试延迟生效的变量
+#[derive(Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration)
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now.set(now)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// 执行环境默认使用的时钟
+#[cfg(feature = "std")]
+pub(crate) fn default_clock() -> Rc<dyn Clock> {
+    Rc::new(SystemClock::default())
+}
+
+/// 执行环境默认使用的时钟
+#[cfg(not(feature = "std"))]
+pub(crate) fn default_clock() -> Rc<dyn Clock> {
+    Rc::new(ManualClock::default())
+}
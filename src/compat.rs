@@ -0,0 +1,70 @@
+//! 开启与关闭 std 特性时行为一致的集合类型、哈希算法及数学函数，
+//! 关闭 std 特性时分别由 hashbrown、foldhash 及 libm 提供
+
+use core::hash::Hasher;
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
+
+/// 计算公式源码及语法树哈希值的哈希器，同一进程中相同的输入总是得到相同的哈希值
+#[cfg(feature = "std")]
+pub fn hasher() -> impl Hasher {
+    std::collections::hash_map::DefaultHasher::new()
+}
+
+/// 计算公式源码及语法树哈希值的哈希器，同一进程中相同的输入总是得到相同的哈希值
+#[cfg(not(feature = "std"))]
+pub fn hasher() -> impl Hasher {
+    use core::hash::BuildHasher;
+    foldhash::fast::FixedState::default().build_hasher()
+}
+
+/// core 中的 f64 缺少的数学函数，关闭 std 特性时引入该 trait 即可使用与 std 同名的方法
+#[cfg(not(any(feature = "std", test)))]
+pub trait Float: Sized {
+    fn ceil(self) -> Self;
+    fn floor(self) -> Self;
+    fn fract(self) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn round(self) -> Self;
+    fn round_ties_even(self) -> Self;
+    fn sqrt(self) -> Self;
+}
+
+#[cfg(not(any(feature = "std", test)))]
+impl Float for f64 {
+    fn ceil(self) -> f64 {
+        libm::ceil(self)
+    }
+
+    fn floor(self) -> f64 {
+        libm::floor(self)
+    }
+
+    fn fract(self) -> f64 {
+        self - libm::trunc(self)
+    }
+
+    fn powf(self, n: f64) -> f64 {
+        libm::pow(self, n)
+    }
+
+    fn powi(self, n: i32) -> f64 {
+        libm::pow(self, n as f64)
+    }
+
+    fn round(self) -> f64 {
+        libm::round(self)
+    }
+
+    fn round_ties_even(self) -> f64 {
+        libm::rint(self)
+    }
+
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+}
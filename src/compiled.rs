@@ -0,0 +1,260 @@
+use crate::compat::HashMap;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::arith::ArithProgram;
+use crate::audit;
+use crate::calculator::{CalculateOption, CalculateResult, FormulaCalc};
+use crate::env::{Env, EnvType};
+use crate::formula::FormulaNode;
+
+/// 预先解析好的表达式，可在不同的输入数据上重复计算而无需再次解析，
+/// 由 Parser::compile 创建，计算时会使用创建该表达式的解析器中已定义的变量及函数
+pub struct CompiledFormula {
+    node: Rc<FormulaNode>,
+    env: EnvType,
+    /// 表达式只由常量、变量及四则运算组成时，预先展开得到的后缀指令
+    arith: Option<ArithProgram>,
+}
+
+impl CompiledFormula {
+    pub fn new(node: Rc<FormulaNode>, env: EnvType) -> Self {
+        let arith = ArithProgram::compile(&node);
+        CompiledFormula { node, env, arith }
+    }
+
+    /// 是否识别为纯四则运算的表达式，是则计算时会优先使用后缀指令直接计算
+    pub fn is_arithmetic(&self) -> bool {
+        self.arith.is_some()
+    }
+
+    /// 获取解析后的表达式节点
+    pub fn node(&self) -> Rc<FormulaNode> {
+        Rc::clone(&self.node)
+    }
+
+    /// 在解析器当前的环境中计算表达式
+    pub fn eval(&self) -> CalculateResult {
+        self.eval_with(&HashMap::new())
+    }
+
+    /// 将 row 中的数据绑定为变量后计算表达式，row 中的变量只对本次计算生效
+    pub fn eval_with(&self, row: &HashMap<String, f64>) -> CalculateResult {
+        if let Some(result) = self.eval_arith(row) {
+            return result;
+        }
+        let scratch = Env::extend(&self.env);
+        let result = self.eval_in(&scratch, row);
+        Env::release(scratch);
+        result
+    }
+
+    /// 对多行输入数据逐行计算表达式，所有的行共用同一个临时环境，每行计算前会清空该环境
+    pub fn eval_batch(&self, rows: &[HashMap<String, f64>]) -> Vec<CalculateResult> {
+        let scratch = Env::extend(&self.env);
+        let results = rows.iter().map(|row| self.eval_in(&scratch, row)).collect();
+        Env::release(scratch);
+        results
+    }
+
+    fn eval_in(&self, scratch: &EnvType, row: &HashMap<String, f64>) -> CalculateResult {
+        if let Some(result) = self.eval_arith(row) {
+            return result;
+        }
+        {
+            let mut env = scratch.borrow_mut();
+            env.reset();
+            env.next_epoch();
+            for (name, value) in row {
+                env.insert(name, Rc::new(FormulaNode::Constant(*value)));
+            }
+        }
+        let value = self.node.calc(scratch);
+        let (value, audit) = audit::finish(&self.node, &scratch.borrow(), value);
+        let trace = scratch.borrow().take_trace();
+        let more = scratch.borrow().take_calls();
+        let profile = scratch.borrow().take_profile();
+        CalculateResult {
+            value,
+            trace,
+            more,
+            profile,
+            audit,
+        }
+    }
+
+    /// 使用后缀指令计算表达式，变量依次从 row 及解析器的环境中查找，
+    /// 只接受常量或值为常量的具名表达式，变量为其他表达式、开启了计算过程记录、耗时统计、
+    /// 观察者、历史值、确定性计算模式或限制了 fuel 时返回 None，由调用者按节点逐一计算，以保证两种方式的结果一致
+    fn eval_arith(&self, row: &HashMap<String, f64>) -> Option<CalculateResult> {
+        let program = self.arith.as_ref()?;
+        let env = self.env.borrow();
+        if env.is_tracing()
+            || env.is_profiling()
+            || env.records_values()
+            || env.observer().is_some()
+            || env.is_auditing()
+            || env.is_fuel_limited()
+        {
+            return None;
+        }
+        // 快速路径按 f64 计算，分数模式下需逐节点计算
+        #[cfg(feature = "rational")]
+        if env.options().rational {
+            return None;
+        }
+        env.next_epoch();
+        let value = program.eval(|name| match row.get(name) {
+            Some(value) => Some(*value),
+            None => match env.get(name) {
+                Some(node) => match node.as_ref() {
+                    FormulaNode::Constant(n) => Some(*n),
+                    FormulaNode::Formula { formula, .. } => match formula.unquoted() {
+                        FormulaNode::Constant(n) => Some(*n),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+                None => match env.resolve(name) {
+                    Some(CalculateOption::Num(n)) => Some(n),
+                    _ => None,
+                },
+            },
+        })?;
+        // 与逐节点计算一样按 CalculateOptions::precision 舍入最终结果
+        let value = match env.options().precision {
+            Some(precision) => precision.apply(CalculateOption::Num(value)),
+            None => CalculateOption::Num(value),
+        };
+        Some(CalculateResult {
+            value,
+            trace: None,
+            more: Vec::new(),
+            profile: None,
+            audit: None,
+        })
+    }
+}
+
+/// 二进制格式的文件头标识
+#[cfg(feature = "msgpack")]
+const BINARY_MAGIC: &[u8; 4] = b"FRML";
+
+/// 二进制格式的版本，语法树的结构发生不兼容的变化时需要递增
+#[cfg(feature = "msgpack")]
+pub const BINARY_VERSION: u16 = 1;
+
+#[cfg(feature = "msgpack")]
+impl CompiledFormula {
+    /// 将表达式的语法树序列化为二进制格式，开头为 4 字节的标识 FRML 及 2 字节的格式版本,
+    /// 之后为 MessagePack 编码的语法树，只包含表达式本身，不包含其引用的变量及函数
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend_from_slice(&BINARY_VERSION.to_be_bytes());
+        rmp_serde::encode::write(&mut bytes, self.node.as_ref())
+            .map_err(|e| format!("序列化表达式时出错: {}", e))?;
+        Ok(bytes)
+    }
+
+    /// 从 to_bytes 得到的二进制数据中读取语法树，标识或格式版本不一致时返回错误
+    pub fn node_from_bytes(bytes: &[u8]) -> Result<FormulaNode, String> {
+        if bytes.len() < 6 || &bytes[..4] != BINARY_MAGIC {
+            return Err("不是预解析表达式的二进制格式".to_string());
+        }
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if version != BINARY_VERSION {
+            return Err(format!(
+                "二进制格式的版本为 {}，当前支持的版本为 {}",
+                version, BINARY_VERSION
+            ));
+        }
+        rmp_serde::from_slice(&bytes[6..]).map_err(|e| format!("反序列化表达式时出错: {}", e))
+    }
+}
+
+/// 使用 rayon 并行地对多行输入数据计算 formula
+/// 由于执行环境无法跨线程共享，每个工作线程都会通过 init 创建自己的解析器并解析一次 formula，
+/// 因此 init 中需要完成内建函数的注册及公共变量的定义, 返回结果的顺序与 rows 一致
+#[cfg(feature = "parallel")]
+pub fn eval_batch_par<F>(
+    init: F,
+    formula: &str,
+    rows: &[HashMap<String, f64>],
+) -> Vec<crate::calculator::CalculateOption>
+where
+    F: Fn() -> crate::parser::Parser + Sync,
+{
+    use rayon::prelude::*;
+
+    let chunk_size = (rows.len() / rayon::current_num_threads()).max(1);
+    rows.par_chunks(chunk_size)
+        .map(|chunk| {
+            let mut parser = init();
+            let compiled = parser.compile(formula);
+            compiled
+                .eval_batch(chunk)
+                .into_iter()
+                .map(|result| PlainValue::from(result.value))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .flatten()
+        .map(PlainValue::into)
+        .collect()
+}
+
+/// 可以跨线程传递的计算结果，函数值只在定义它的执行环境中有效，跨线程返回时转换为错误
+#[cfg(feature = "parallel")]
+enum PlainValue {
+    Bool(bool),
+    Num(f64),
+    Complex(crate::complex::Complex),
+    Vector(Vec<f64>),
+    Matrix(crate::matrix::Matrix),
+    Text(String),
+    Money(crate::money::Money),
+    Err(String),
+    None,
+}
+
+#[cfg(feature = "parallel")]
+impl From<crate::calculator::CalculateOption> for PlainValue {
+    fn from(value: crate::calculator::CalculateOption) -> Self {
+        use crate::calculator::CalculateOption::*;
+        match value {
+            Bool(b) => PlainValue::Bool(b),
+            Num(n) => PlainValue::Num(n),
+            Complex(c) => PlainValue::Complex(c),
+            Vector(v) => PlainValue::Vector(v),
+            Matrix(m) => PlainValue::Matrix(m),
+            Text(t) => PlainValue::Text(t),
+            Money(m) => PlainValue::Money(m),
+            #[cfg(feature = "rational")]
+            Rational(r) => PlainValue::Num(crate::rational::to_f64(r)),
+            Err(e) => PlainValue::Err(e),
+            Function(_) => PlainValue::Err("函数值无法在线程间传递".to_string()),
+            Opaque(_) => PlainValue::Err("宿主对象无法在线程间传递".to_string()),
+            None => PlainValue::None,
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl From<PlainValue> for crate::calculator::CalculateOption {
+    fn from(value: PlainValue) -> Self {
+        use crate::calculator::CalculateOption;
+        match value {
+            PlainValue::Bool(b) => CalculateOption::Bool(b),
+            PlainValue::Num(n) => CalculateOption::Num(n),
+            PlainValue::Complex(c) => CalculateOption::Complex(c),
+            PlainValue::Vector(v) => CalculateOption::Vector(v),
+            PlainValue::Matrix(m) => CalculateOption::Matrix(m),
+            PlainValue::Text(t) => CalculateOption::Text(t),
+            PlainValue::Money(m) => CalculateOption::Money(m),
+            PlainValue::Err(e) => CalculateOption::Err(e),
+            PlainValue::None => CalculateOption::None,
+        }
+    }
+}
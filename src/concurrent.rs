@@ -0,0 +1,67 @@
+use alloc::rc::Rc;
+use std::collections::{HashMap, HashSet};
+
+use crate::formula::{CalcError, FormulaNode};
+
+/// 可以在多个线程中同时调用的内建函数，参数及返回值都是数值，无法访问执行环境,
+/// 通常用于获取外部数据等耗时但彼此独立的操作
+pub type ConcurrentBuildInType = dyn Fn(&[f64]) -> Result<f64, CalcError> + Send + Sync;
+
+/// 找出 node 中可以并行计算的函数调用，即函数名属于 names 且参数中不再包含此类调用的调用,
+/// 只在操作符的操作数、括号以及一定会在当前环境中计算全部参数的调用 (脚本函数及 names 中的函数,
+/// 由 is_function 判断是否为脚本函数) 的参数中查找，
+/// 其他内建函数的参数可能只会按条件计算 (如 If)，因此不会深入其中查找
+pub fn collect_calls<'a>(
+    node: &'a FormulaNode,
+    names: &HashSet<String>,
+    is_function: &dyn Fn(&str) -> bool,
+    calls: &mut Vec<&'a FormulaNode>,
+) {
+    match node {
+        FormulaNode::FunctionCall { name, args } if names.contains(name) => {
+            let found = calls.len();
+            for arg in args.iter() {
+                collect_calls(arg, names, is_function, calls);
+            }
+            if calls.len() == found {
+                calls.push(node);
+            }
+        }
+        FormulaNode::FunctionCall { name, args } if is_function(name) => {
+            for arg in args.iter() {
+                collect_calls(arg, names, is_function, calls);
+            }
+        }
+        FormulaNode::Operator(op) => {
+            for operand in op.operands() {
+                collect_calls(operand, names, is_function, calls);
+            }
+        }
+        FormulaNode::Quote(node) => collect_calls(node, names, is_function, calls),
+        _ => (),
+    }
+}
+
+/// 将 node 中已经计算完成的函数调用替换为 values 中对应的节点，values 的 key 为调用节点的地址
+pub fn substitute(
+    node: &FormulaNode,
+    values: &HashMap<*const FormulaNode, FormulaNode>,
+) -> FormulaNode {
+    if let Some(value) = values.get(&(node as *const FormulaNode)) {
+        return value.clone();
+    }
+    match node {
+        FormulaNode::Operator(op) => {
+            FormulaNode::Operator(Box::new(op.map_operands(|n| substitute(n, values))))
+        }
+        FormulaNode::Quote(node) => FormulaNode::Quote(Box::new(substitute(node, values))),
+        FormulaNode::FunctionCall { name, args } => FormulaNode::FunctionCall {
+            name: name.clone(),
+            args: args
+                .iter()
+                .map(|arg| Rc::new(substitute(arg, values)))
+                .collect(),
+        },
+        node => node.clone(),
+    }
+}
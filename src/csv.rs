@@ -0,0 +1,53 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// 解析 CSV 文本，返回每一行的字段，支持以双引号包围的字段及其中转义的 "" 与换行
+pub fn read_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(core::mem::take(&mut field)),
+            (false, '\r') => (),
+            (false, '\n') => {
+                row.push(core::mem::take(&mut field));
+                rows.push(core::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("CSV 第 {} 行的引号未闭合", rows.len() + 1));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// 将一行字段输出为 CSV 格式，包含逗号、引号或换行的字段会以双引号包围
+pub fn write_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            match field.contains([',', '"', '\n', '\r']) {
+                true => format!("\"{}\"", field.replace('"', "\"\"")),
+                false => field.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,208 @@
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+use crate::calculator::CalculateOption;
+use crate::env::{Env, EvalObserver};
+use crate::formula::FormulaNode;
+use crate::parser::Parser;
+
+/// 断点，在引用变量或调用函数时暂停
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// 计算变量 name 时暂停
+    Variable(String),
+    /// 调用函数 name 时暂停
+    FunctionCall(String),
+}
+
+/// 调试步骤的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    /// 开始计算节点
+    Enter,
+    /// 节点计算完成
+    Exit,
+    /// 调用函数
+    Call,
+}
+
+/// 调试器中的一个步骤，记录了当时计算的节点及所在的执行环境
+#[derive(Debug, Clone)]
+pub struct DebugStep {
+    pub kind: StepKind,
+    /// 节点的表达式，调用函数时为函数名
+    pub expr: String,
+    /// 变量名或调用的函数名，用于匹配断点
+    pub name: Option<String>,
+    /// 节点的计算结果，只有 Exit 步骤才有值
+    pub value: Option<CalculateOption>,
+    /// 节点的嵌套深度，最外层的节点为 0
+    pub depth: usize,
+    /// 当时所在层级的变量及其定义，如函数调用时的参数值及局部变量
+    pub frame: Vec<(String, String)>,
+}
+
+impl DebugStep {
+    fn hits(&self, breakpoint: &Breakpoint) -> bool {
+        match (breakpoint, self.kind, &self.name) {
+            (Breakpoint::Variable(b), StepKind::Enter, Some(name)) => b == name,
+            (Breakpoint::FunctionCall(b), StepKind::Call, Some(name)) => b == name,
+            _ => false,
+        }
+    }
+}
+
+/// 记录计算过程中每一个步骤的观察者
+struct StepRecorder {
+    steps: Rc<RefCell<Vec<DebugStep>>>,
+    depth: Cell<usize>,
+}
+
+impl StepRecorder {
+    fn record(
+        &self,
+        kind: StepKind,
+        expr: String,
+        name: Option<String>,
+        value: Option<CalculateOption>,
+        env: &Env,
+    ) {
+        let frame = env
+            .local_names()
+            .into_iter()
+            .map(|name| {
+                let node = env.get(&name).map(|n| n.to_string()).unwrap_or_default();
+                (name, node)
+            })
+            .collect();
+        self.steps.borrow_mut().push(DebugStep {
+            kind,
+            expr,
+            name,
+            value,
+            depth: self.depth.get(),
+            frame,
+        })
+    }
+}
+
+impl EvalObserver for StepRecorder {
+    fn on_enter_node(&self, node: &FormulaNode, env: &Env) {
+        let name = match node {
+            FormulaNode::Variant(name) => Some(name.clone()),
+            _ => None,
+        };
+        self.record(StepKind::Enter, node.to_string(), name, None, env);
+        self.depth.set(self.depth.get() + 1);
+    }
+
+    fn on_exit_node(&self, node: &FormulaNode, value: &CalculateOption, env: &Env) {
+        self.depth.set(self.depth.get() - 1);
+        self.record(
+            StepKind::Exit,
+            node.to_string(),
+            None,
+            Some(value.clone()),
+            env,
+        );
+    }
+
+    fn on_function_call(&self, name: &str, _args: &[Rc<FormulaNode>], env: &Env) {
+        self.record(
+            StepKind::Call,
+            name.to_string(),
+            Some(name.to_string()),
+            None,
+            env,
+        );
+    }
+}
+
+/// 表达式的调试器，计算表达式时记录每一个步骤，之后可以通过 step 逐步查看，
+/// 或通过 resume 跳到下一个断点，并查看每一步所在层级的变量,
+/// 调试期间会替换解析器中已设置的观察者，调试结束后解析器中不再有观察者
+pub struct Debugger<'a> {
+    parser: &'a mut Parser,
+    breakpoints: Vec<Breakpoint>,
+    steps: Vec<DebugStep>,
+    /// 当前所在的步骤，None 表示还未开始
+    position: Option<usize>,
+    value: Option<CalculateOption>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(parser: &'a mut Parser) -> Self {
+        Debugger {
+            parser,
+            breakpoints: Vec::new(),
+            steps: Vec::new(),
+            position: None,
+            value: None,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) {
+        self.breakpoints.retain(|b| b != breakpoint)
+    }
+
+    /// 计算 formula 并记录其计算过程，调用后位于第一个步骤之前
+    pub fn start(&mut self, formula: impl AsRef<str>) {
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        self.parser.set_observer(StepRecorder {
+            steps: Rc::clone(&steps),
+            depth: Cell::new(0),
+        });
+        self.value = Some(self.parser.calculate(formula).value);
+        self.parser.clear_observer();
+
+        self.steps = steps.take();
+        self.position = None;
+    }
+
+    /// 前进一步，已经是最后一步时返回 None
+    pub fn step(&mut self) -> Option<&DebugStep> {
+        let next = self.position.map_or(0, |p| p + 1);
+        self.position = Some(next.min(self.steps.len()));
+        self.steps.get(next)
+    }
+
+    /// 继续执行直到下一个断点 (即 continue)，之后没有断点时执行到结束并返回 None
+    pub fn resume(&mut self) -> Option<&DebugStep> {
+        let start = self.position.map_or(0, |p| p + 1);
+        let hit = (start..self.steps.len())
+            .find(|&i| self.breakpoints.iter().any(|b| self.steps[i].hits(b)));
+        self.position = Some(hit.unwrap_or(self.steps.len()));
+        self.current()
+    }
+
+    /// 当前所在的步骤
+    pub fn current(&self) -> Option<&DebugStep> {
+        self.position.and_then(|p| self.steps.get(p))
+    }
+
+    /// 当前步骤所在层级的变量及其定义
+    pub fn frame(&self) -> &[(String, String)] {
+        self.current().map(|s| s.frame.as_slice()).unwrap_or(&[])
+    }
+
+    /// 是否已执行到结束
+    pub fn is_finished(&self) -> bool {
+        self.position.is_some_and(|p| p >= self.steps.len())
+    }
+
+    /// 表达式的计算结果，调用 start 之后才有值
+    pub fn value(&self) -> Option<&CalculateOption> {
+        self.value.as_ref()
+    }
+
+    /// 记录的所有步骤
+    pub fn steps(&self) -> &[DebugStep] {
+        &self.steps
+    }
+}
@@ -0,0 +1,422 @@
+use crate::compat::{HashMap, HashSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::ops::Range;
+
+use crate::env::Env;
+use crate::formula::FormulaNode;
+use crate::locale::{ErrorCode, Locale};
+
+/// 诊断信息的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// 表达式无法正确计算，如语法错误、引用了未定义的变量、函数的参数个数不匹配等
+    Error,
+    /// 表达式可以计算，但可能与预期不符，如覆盖了已有的定义
+    Warning,
+}
+
+/// 检查表达式时发现的问题，span 为问题在源码中所处的字节范围
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 错误码，与语言无关，可用于程序识别问题的类型
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+/// 解析或计算表达式时产生的错误，span 为出错位置在源码中的字节范围，无法确定位置时为 None
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormulaError {
+    /// 表达式存在语法错误，或其中的定义无法加入执行环境
+    Parse {
+        message: String,
+        span: Option<Range<usize>>,
+    },
+    /// 计算表达式时出错，code 为错误信息中的错误码
+    Eval {
+        code: Option<ErrorCode>,
+        message: String,
+        span: Option<Range<usize>>,
+    },
+}
+
+impl FormulaError {
+    pub fn message(&self) -> &str {
+        match self {
+            FormulaError::Parse { message, .. } | FormulaError::Eval { message, .. } => message,
+        }
+    }
+
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            FormulaError::Parse { span, .. } | FormulaError::Eval { span, .. } => span.clone(),
+        }
+    }
+}
+
+impl core::fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl core::error::Error for FormulaError {}
+
+/// 内建函数声明的参数个数范围 [min, max]
+type Arity = (usize, Option<usize>);
+
+/// 名称在检查时解析得到的结果
+enum Resolved {
+    /// 函数的参数或函数体中的局部变量，参数可能绑定了函数值，因此不检查调用时的参数个数
+    Local,
+    Value,
+    /// 脚本中定义的函数及其参数个数
+    Function(usize),
+    /// 内建函数及其声明的参数个数范围
+    BuildIn(Option<Arity>),
+}
+
+/// 在不计算表达式的前提下检查语法树，将其中的名称与执行环境及源码中的定义进行匹配
+pub(crate) struct Analyzer<'a> {
+    source: &'a str,
+    env: &'a Env,
+    arity_of: &'a dyn Fn(&str) -> Option<Arity>,
+    /// 诊断信息所使用的语言
+    locale: Locale,
+    /// 源码中定义的表达式及函数，函数记录其参数个数
+    defined: HashMap<String, Option<usize>>,
+    /// 函数的参数及局部变量，每一层函数定义对应一个作用域
+    scopes: Vec<HashSet<String>>,
+    namespace: Option<String>,
+    /// 源码中包含 import 语句时，未定义的名称可能由被引用的文件提供
+    has_import: bool,
+    spans: SpanFinder<'a>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// 未定义的变量及函数名，按首次出现的顺序排列且不重复
+    pub unresolved: Vec<String>,
+}
+
+impl<'a> Analyzer<'a> {
+    pub fn new(source: &'a str, env: &'a Env, arity_of: &'a dyn Fn(&str) -> Option<Arity>) -> Self {
+        Analyzer {
+            source,
+            env,
+            arity_of,
+            locale: env.options().locale,
+            defined: HashMap::new(),
+            scopes: Vec::new(),
+            namespace: None,
+            has_import: false,
+            spans: SpanFinder::new(source),
+            diagnostics: Vec::new(),
+            unresolved: Vec::new(),
+        }
+    }
+
+    /// 检查源码中的所有语句
+    pub fn analyze(&mut self, nodes: &[FormulaNode]) {
+        for node in nodes {
+            self.collect_definitions(node, "");
+        }
+        for node in nodes {
+            self.visit(node);
+        }
+    }
+
+    fn collect_definitions(&mut self, node: &FormulaNode, prefix: &str) {
+        match node {
+            FormulaNode::Formula { name, .. } => {
+                self.defined.insert(format!("{}{}", prefix, name), None);
+            }
+            FormulaNode::Function { name, args, .. } => {
+                self.defined
+                    .insert(format!("{}{}", prefix, name), Some(args.len()));
+            }
+            FormulaNode::Namespace { name, definitions } => {
+                let prefix = format!("{}{}.", prefix, name);
+                for definition in definitions {
+                    self.collect_definitions(definition, &prefix);
+                }
+            }
+            FormulaNode::Import(_) => self.has_import = true,
+            _ => (),
+        }
+    }
+
+    fn visit(&mut self, node: &FormulaNode) {
+        match node {
+            FormulaNode::Variant(name) | FormulaNode::History { name, .. } => {
+                let span = self.span_of(name);
+                if self.resolve(name).is_none() {
+                    self.unresolved(name, span, ErrorCode::UndefinedVariable);
+                }
+            }
+            FormulaNode::Index { target, index } => {
+                self.visit(target);
+                for node in index.nodes() {
+                    self.visit(node);
+                }
+            }
+            FormulaNode::Operator(op) => {
+                for operand in op.operands() {
+                    match operand {
+                        FormulaNode::None => {
+                            self.error(0..self.source.len(), ErrorCode::MissingOperand, &[])
+                        }
+                        operand => self.visit(operand),
+                    }
+                }
+            }
+            FormulaNode::FunctionCall { name, args } => {
+                let span = self.span_of(name);
+                match self.resolve(name) {
+                    None => self.unresolved(name, span, ErrorCode::UndefinedFunction),
+                    Some(Resolved::Function(n)) if n != args.len() => {
+                        self.error(span, ErrorCode::ArityMismatch, &[name, &n, &args.len()])
+                    }
+                    Some(Resolved::BuildIn(Some((min, max))))
+                        if args.len() < min || max.is_some_and(|max| args.len() > max) =>
+                    {
+                        let expected = match max {
+                            Some(max) if max == min => format!("{}", min),
+                            Some(max) => format!("{}~{}", min, max),
+                            None => format!("{}+", min),
+                        };
+                        self.error(
+                            span,
+                            ErrorCode::BuildInArityMismatch,
+                            &[name, &expected, &args.len()],
+                        )
+                    }
+                    Some(Resolved::Value) => self.error(span, ErrorCode::NotCallable, &[name]),
+                    _ => (),
+                }
+                for arg in args.iter() {
+                    self.visit(arg);
+                }
+            }
+            FormulaNode::Function {
+                name,
+                args,
+                expressions,
+            } => {
+                let span = self.span_of(name);
+                self.check_define(name, span);
+                let mut scope = HashSet::new();
+                for arg in args {
+                    match arg.as_ref() {
+                        FormulaNode::Variant(arg) => {
+                            self.span_of(arg);
+                            scope.insert(arg.clone());
+                        }
+                        _ => {
+                            let span = self.span_of(name);
+                            self.error(span, ErrorCode::InvalidParameterName, &[name])
+                        }
+                    }
+                }
+                self.scopes.push(scope);
+                for exp in expressions {
+                    self.visit(exp);
+                }
+                self.scopes.pop();
+            }
+            FormulaNode::Formula { name, formula } => {
+                let span = self.span_of(name);
+                match self.scopes.last_mut() {
+                    // 函数体中的表达式定义的是局部变量
+                    Some(scope) => {
+                        scope.insert(name.clone());
+                    }
+                    None => self.check_define(name, span),
+                }
+                self.visit(formula);
+            }
+            FormulaNode::Namespace { name, definitions } => {
+                let outer = self.namespace.take();
+                self.namespace = Some(match outer {
+                    Some(ref outer) => format!("{}.{}", outer, name),
+                    None => name.clone(),
+                });
+                for definition in definitions {
+                    self.visit(definition);
+                }
+                self.namespace = outer;
+            }
+            FormulaNode::Arg { value, .. } => self.visit(value),
+            FormulaNode::Quote(node) => self.visit(node),
+            FormulaNode::UnKnow(msg) => {
+                self.error(0..self.source.len(), ErrorCode::SyntaxError, &[msg])
+            }
+            _ => (),
+        }
+    }
+
+    /// 依次在局部作用域、源码中的定义、执行环境及外部变量解析器中查找 name，
+    /// 在命名空间中时优先查找 命名空间.name
+    fn resolve(&self, name: &str) -> Option<Resolved> {
+        if self.scopes.iter().any(|scope| scope.contains(name)) {
+            return Some(Resolved::Local);
+        }
+
+        let mut candidates = Vec::new();
+        if let Some(ref ns) = self.namespace {
+            candidates.push(format!("{}.{}", ns, name));
+        }
+        candidates.push(name.to_string());
+
+        for key in candidates.iter() {
+            if let Some(def) = self.defined.get(key) {
+                return Some(match def {
+                    Some(n) => Resolved::Function(*n),
+                    None => Resolved::Value,
+                });
+            }
+            if let Some(node) = self.env.get(key) {
+                return Some(match node.as_ref() {
+                    FormulaNode::Function { args, .. } => Resolved::Function(args.len()),
+                    FormulaNode::BuildInFunction { func } => {
+                        Resolved::BuildIn((self.arity_of)(func))
+                    }
+                    _ => Resolved::Value,
+                });
+            }
+        }
+        self.env.resolve(name).map(|_| Resolved::Value)
+    }
+
+    /// 检查是否可以在执行环境中定义 name
+    fn check_define(&mut self, name: &str, span: Range<usize>) {
+        let key = match self.namespace {
+            Some(ref ns) => format!("{}.{}", ns, name),
+            None => name.to_string(),
+        };
+        if let Err(e) = self.env.check_write(&key) {
+            self.error(span, ErrorCode::ReadOnly, &[&e]);
+        } else if self.env.get(&key).is_some() {
+            let message = ErrorCode::Redefinition.text(self.locale, &[&key]);
+            self.push(Severity::Warning, span, ErrorCode::Redefinition, message);
+        }
+    }
+
+    fn unresolved(&mut self, name: &str, span: Range<usize>, code: ErrorCode) {
+        if !self.unresolved.iter().any(|n| n == name) {
+            self.unresolved.push(name.to_string());
+        }
+        let message = code.text(self.locale, &[&name]);
+        match self.has_import {
+            true => {
+                let message = ErrorCode::MaybeImported.text(self.locale, &[&message]);
+                self.push(Severity::Warning, span, code, message)
+            }
+            false => self.push(Severity::Error, span, code, message),
+        }
+    }
+
+    fn error(&mut self, span: Range<usize>, code: ErrorCode, args: &[&dyn Display]) {
+        let message = code.text(self.locale, args);
+        self.push(Severity::Error, span, code, message)
+    }
+
+    fn push(&mut self, severity: Severity, span: Range<usize>, code: ErrorCode, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            code,
+            message,
+            span,
+        });
+    }
+
+    fn span_of(&mut self, name: &str) -> Range<usize> {
+        self.spans.find(name)
+    }
+}
+
+/// 按名称在源码中出现的顺序查找其位置，检查语法树时需要按源码的顺序查找每一次出现的名称
+pub(crate) struct SpanFinder<'a> {
+    source: &'a str,
+    /// 每个名称下一次在源码中查找的起始位置，使重复出现的名称对应到各自的位置
+    cursors: HashMap<String, usize>,
+}
+
+impl<'a> SpanFinder<'a> {
+    pub fn new(source: &'a str) -> Self {
+        SpanFinder {
+            source,
+            cursors: HashMap::new(),
+        }
+    }
+
+    /// 查找 name 在源码中下一次作为完整名称出现的位置，找不到时返回整个源码的范围
+    pub fn find(&mut self, name: &str) -> Range<usize> {
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+        let from = self.cursors.get(name).cloned().unwrap_or(0);
+        let mut start = from;
+        while let Some(offset) = self.source.get(start..).and_then(|s| s.find(name)) {
+            let (begin, end) = (start + offset, start + offset + name.len());
+            let before = self.source[..begin].chars().next_back();
+            let after = self.source[end..].chars().next();
+            if !before.is_some_and(is_name_char) && !after.is_some_and(is_name_char) {
+                self.cursors.insert(name.to_string(), end);
+                return begin..end;
+            }
+            start = end;
+        }
+        0..self.source.len()
+    }
+}
+
+/// 收集语句 node 中的语法问题，包括无法识别的语句及缺少操作数的操作符,
+/// span 为语句在源码中的字节范围，每个问题都以该范围作为位置
+pub(crate) fn syntax_diagnostics(
+    node: &FormulaNode,
+    span: &Range<usize>,
+    locale: Locale,
+    out: &mut Vec<Diagnostic>,
+) {
+    let error = |code: ErrorCode, args: &[&dyn Display]| Diagnostic {
+        severity: Severity::Error,
+        code,
+        message: code.text(locale, args),
+        span: span.clone(),
+    };
+    match node {
+        FormulaNode::UnKnow(msg) => out.push(error(ErrorCode::SyntaxError, &[msg])),
+        FormulaNode::Operator(op) => {
+            for operand in op.operands() {
+                match operand {
+                    FormulaNode::None => out.push(error(ErrorCode::MissingOperand, &[])),
+                    operand => syntax_diagnostics(operand, span, locale, out),
+                }
+            }
+        }
+        FormulaNode::Index { target, index } => {
+            syntax_diagnostics(target, span, locale, out);
+            for node in index.nodes() {
+                syntax_diagnostics(node, span, locale, out);
+            }
+        }
+        FormulaNode::FunctionCall { args, .. } => {
+            for arg in args.iter() {
+                syntax_diagnostics(arg, span, locale, out);
+            }
+        }
+        FormulaNode::Function { expressions, .. } => {
+            for exp in expressions {
+                syntax_diagnostics(exp, span, locale, out);
+            }
+        }
+        FormulaNode::Namespace { definitions, .. } => {
+            for definition in definitions {
+                syntax_diagnostics(definition, span, locale, out);
+            }
+        }
+        FormulaNode::Formula { formula, .. } => syntax_diagnostics(formula, span, locale, out),
+        FormulaNode::Arg { value, .. } => syntax_diagnostics(value, span, locale, out),
+        FormulaNode::Quote(node) => syntax_diagnostics(node, span, locale, out),
+        _ => (),
+    }
+}
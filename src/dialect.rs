@@ -0,0 +1,162 @@
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::formula::{FormulaNode, OperatorNode};
+use crate::scanner::Scanner;
+
+/// 解析器接受的公式写法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// 本项目的公式语法
+    #[default]
+    Native,
+    /// Excel 风格的公式，如 =IF(A1<>0, A1*2, 0)，支持以 = 开头、<> 表示不等于、
+    /// & 表示连接 (得到向量)、TRUE/FALSE 常量、AND/OR/NOT 函数，且函数名不区分大小写,
+    /// 运算按 Excel 的优先级 (^ 高于 * 及 /，再高于 + 及 -，比较运算最低) 从左向右组合
+    Excel,
+}
+
+/// Excel 中与内建函数名称不同的函数
+const EXCEL_FUNCTIONS: &[(&str, &str)] =
+    &[("AVERAGE", "Avg"), ("STDEV", "StdDev"), ("VAR", "Variance")];
+
+impl Dialect {
+    /// 将源码转换为本项目的语法，Excel 公式开头的 = 会被替换为空格以保持各字符的位置,
+    /// <> 替换为 !=，单个 & 替换为 && 后再由 rewrite 转换为 Concat 的调用
+    pub(crate) fn translate<'a>(&self, source: &'a str) -> Cow<'a, str> {
+        if *self == Dialect::Native {
+            return Cow::Borrowed(source);
+        }
+
+        let body = source.trim_start();
+        let mut out = String::with_capacity(source.len() + 8);
+        out.push_str(&source[..source.len() - body.len()]);
+        let mut chars = body.chars().peekable();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            out.push(' ');
+        }
+
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    out.push(c);
+                }
+                '<' if !quoted && chars.peek() == Some(&'>') => {
+                    chars.next();
+                    out.push_str("!=");
+                }
+                '&' if !quoted && chars.peek() != Some(&'&') => out.push_str("&&"),
+                '&' if !quoted => {
+                    chars.next();
+                    out.push_str("&&");
+                }
+                _ => out.push(c),
+            }
+        }
+        Cow::Owned(out)
+    }
+
+    /// 扫描 translate 转换后源码的 Scanner，Excel 公式按运算符的优先级从左向右组合运算
+    pub(crate) fn scanner<'a>(&self, source: &'a str) -> Scanner<'a> {
+        Scanner::new(source).with_precedence(*self == Dialect::Excel)
+    }
+
+    /// 将解析得到的节点转换为本项目的语义，functions 为执行环境中可调用的函数名，
+    /// 用于将不区分大小写的函数名对应到实际注册的函数
+    pub(crate) fn rewrite(&self, node: FormulaNode, functions: &[String]) -> FormulaNode {
+        match self {
+            Dialect::Native => node,
+            Dialect::Excel => excel_node(&node, functions),
+        }
+    }
+}
+
+fn excel_node(node: &FormulaNode, functions: &[String]) -> FormulaNode {
+    let rewrite = |n: &FormulaNode| excel_node(n, functions);
+    let rewrite_all =
+        |nodes: &[Rc<FormulaNode>]| nodes.iter().map(|n| Rc::new(rewrite(n))).collect();
+    match node {
+        FormulaNode::Variant(name) if name.eq_ignore_ascii_case("true") => FormulaNode::Bool(true),
+        FormulaNode::Variant(name) if name.eq_ignore_ascii_case("false") => {
+            FormulaNode::Bool(false)
+        }
+        FormulaNode::Operator(op) => match op.as_ref() {
+            // Excel 中没有 && 操作符，因此 And 节点只可能来自于 & 连接
+            OperatorNode::And { left, right } => FormulaNode::FunctionCall {
+                name: "Concat".to_string(),
+                args: vec![Rc::new(rewrite(left)), Rc::new(rewrite(right))].into(),
+            },
+            op => FormulaNode::Operator(Box::new(op.map_operands(rewrite))),
+        },
+        FormulaNode::FunctionCall { name, args } => {
+            let args: Vec<Rc<FormulaNode>> = rewrite_all(args);
+            match name.to_ascii_uppercase().as_str() {
+                "AND" | "OR" if !args.is_empty() => logic_chain(name, args),
+                "NOT" if args.len() == 1 => FormulaNode::Operator(Box::new(OperatorNode::Not(
+                    Box::new(args[0].as_ref().clone()),
+                ))),
+                _ => FormulaNode::FunctionCall {
+                    name: function_name(name, functions),
+                    args: args.into(),
+                },
+            }
+        }
+        FormulaNode::Function {
+            name,
+            args,
+            expressions,
+        } => FormulaNode::Function {
+            name: name.clone(),
+            args: args.clone(),
+            expressions: rewrite_all(expressions),
+        },
+        FormulaNode::Formula { name, formula } => FormulaNode::Formula {
+            name: name.clone(),
+            formula: Rc::new(rewrite(formula)),
+        },
+        FormulaNode::Index { target, index } => FormulaNode::Index {
+            target: Box::new(rewrite(target)),
+            index: index.map_nodes(rewrite),
+        },
+        FormulaNode::Quote(node) => FormulaNode::Quote(Box::new(rewrite(node))),
+        node => node.clone(),
+    }
+}
+
+/// 将 AND(a, b, c) 及 OR(a, b, c) 转换为对应的逻辑操作符
+fn logic_chain(name: &str, args: Vec<Rc<FormulaNode>>) -> FormulaNode {
+    let is_and = name.eq_ignore_ascii_case("and");
+    let mut iter = args.into_iter().rev().map(|a| a.as_ref().clone());
+    let last = iter.next().unwrap_or(FormulaNode::None);
+    iter.fold(last, |right, left| {
+        let (left, right) = (Box::new(left), Box::new(right));
+        FormulaNode::Operator(Box::new(match is_and {
+            true => OperatorNode::And { left, right },
+            false => OperatorNode::Or { left, right },
+        }))
+    })
+}
+
+/// 查找与 name 对应的函数名，依次查找完全相同的名称、Excel 中的别名及忽略大小写后相同的名称
+fn function_name(name: &str, functions: &[String]) -> String {
+    if functions.iter().any(|f| f == name) {
+        return name.to_string();
+    }
+    if let Some((_, alias)) = EXCEL_FUNCTIONS
+        .iter()
+        .find(|(excel, _)| excel.eq_ignore_ascii_case(name))
+    {
+        return alias.to_string();
+    }
+    functions
+        .iter()
+        .find(|f| f.eq_ignore_ascii_case(name))
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
@@ -0,0 +1,301 @@
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::formula::FormulaNode;
+
+/// 两个版本的语法树之间的一处语义变化，path 为变化所在的位置，
+/// 由定义名、left、right、operand、args[i] 等以 . 连接而成，整个表达式被修改时为空
+#[derive(Debug, Clone, PartialEq)]
+pub enum AstChange {
+    /// 操作符被替换，操作数的变化另行报告
+    OperatorChanged {
+        path: String,
+        old: &'static str,
+        new: &'static str,
+    },
+    /// 数值常量被修改
+    ConstantChanged { path: String, old: f64, new: f64 },
+    /// 引用的变量、调用的函数或定义的名称被修改
+    Renamed {
+        path: String,
+        old: String,
+        new: String,
+    },
+    /// 函数调用或函数定义中增加了参数，index 为该参数的位置
+    ArgumentAdded {
+        path: String,
+        index: usize,
+        node: String,
+    },
+    /// 函数调用或函数定义中删除了参数
+    ArgumentRemoved {
+        path: String,
+        index: usize,
+        node: String,
+    },
+    /// 增加了定义或函数体中的表达式
+    Added { path: String, node: String },
+    /// 删除了定义或函数体中的表达式
+    Removed { path: String, node: String },
+    /// 节点的类型不同，无法进一步比较，整体替换
+    Replaced {
+        path: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl core::fmt::Display for AstChange {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let at = |path: &str| match path {
+            "" => String::new(),
+            path => format!("{}: ", path),
+        };
+        match self {
+            AstChange::OperatorChanged { path, old, new } => {
+                write!(f, "{}操作符 {} 改为 {}", at(path), old, new)
+            }
+            AstChange::ConstantChanged { path, old, new } => {
+                write!(f, "{}常量 {} 改为 {}", at(path), old, new)
+            }
+            AstChange::Renamed { path, old, new } => {
+                write!(f, "{}名称 {} 改为 {}", at(path), old, new)
+            }
+            AstChange::ArgumentAdded { path, index, node } => {
+                write!(f, "{}增加了第 {} 个参数 {}", at(path), index + 1, node)
+            }
+            AstChange::ArgumentRemoved { path, index, node } => {
+                write!(f, "{}删除了第 {} 个参数 {}", at(path), index + 1, node)
+            }
+            AstChange::Added { path, node } => write!(f, "{}增加了 {}", at(path), node),
+            AstChange::Removed { path, node } => write!(f, "{}删除了 {}", at(path), node),
+            AstChange::Replaced { path, old, new } => {
+                write!(f, "{}{} 改为 {}", at(path), old, new)
+            }
+        }
+    }
+}
+
+/// 比较两个语法树，报告从 old 到 new 的语义变化，只有括号及空白不同的表达式没有变化
+pub fn diff(old: &FormulaNode, new: &FormulaNode) -> Vec<AstChange> {
+    let mut changes = Vec::new();
+    diff_node("", old, new, &mut changes);
+    changes
+}
+
+/// 比较两组语句，如同一个公式文件的两个版本，具名的表达式及函数按名称对应,
+/// 其余的语句按位置对应
+pub fn diff_statements(old: &[Rc<FormulaNode>], new: &[Rc<FormulaNode>]) -> Vec<AstChange> {
+    let mut changes = Vec::new();
+    diff_list("", old, new, &mut changes);
+    changes
+}
+
+fn join(path: &str, segment: &str) -> String {
+    match path {
+        "" => segment.to_string(),
+        path => format!("{}.{}", path, segment),
+    }
+}
+
+fn diff_node(path: &str, old: &FormulaNode, new: &FormulaNode, out: &mut Vec<AstChange>) {
+    let (old, new) = (old.unquoted(), new.unquoted());
+    if old == new {
+        return;
+    }
+    let renamed = |out: &mut Vec<AstChange>, old: &str, new: &str| {
+        if old != new {
+            out.push(AstChange::Renamed {
+                path: path.to_string(),
+                old: old.to_string(),
+                new: new.to_string(),
+            });
+        }
+    };
+    match (old, new) {
+        (FormulaNode::Constant(o), FormulaNode::Constant(n)) => {
+            out.push(AstChange::ConstantChanged {
+                path: path.to_string(),
+                old: *o,
+                new: *n,
+            })
+        }
+        (FormulaNode::Variant(o), FormulaNode::Variant(n)) => renamed(out, o, n),
+        (FormulaNode::Operator(o), FormulaNode::Operator(n)) => {
+            let (o, n) = (o.as_ref(), n.as_ref());
+            let (o_operands, n_operands) = (o.operands(), n.operands());
+            if o_operands.len() != n_operands.len() {
+                return replaced(path, old, new, out);
+            }
+            if core::mem::discriminant(o) != core::mem::discriminant(n) {
+                out.push(AstChange::OperatorChanged {
+                    path: path.to_string(),
+                    old: o.symbol(),
+                    new: n.symbol(),
+                });
+            }
+            let segments: &[&str] = match o_operands.len() {
+                1 => &["operand"],
+                _ => &["left", "right"],
+            };
+            for ((o, n), segment) in o_operands.into_iter().zip(n_operands).zip(segments) {
+                diff_node(&join(path, segment), o, n, out);
+            }
+        }
+        (
+            FormulaNode::FunctionCall { name: o, args: oa },
+            FormulaNode::FunctionCall { name: n, args: na },
+        ) => {
+            renamed(out, o, n);
+            diff_args(path, oa, na, out);
+        }
+        (
+            FormulaNode::Function {
+                name: o,
+                args: oa,
+                expressions: oe,
+            },
+            FormulaNode::Function {
+                name: n,
+                args: na,
+                expressions: ne,
+            },
+        ) => {
+            renamed(out, o, n);
+            diff_args(path, oa, na, out);
+            diff_list(path, oe, ne, out);
+        }
+        (
+            FormulaNode::Formula {
+                name: o,
+                formula: of,
+            },
+            FormulaNode::Formula {
+                name: n,
+                formula: nf,
+            },
+        ) => {
+            renamed(out, o, n);
+            diff_node(path, of, nf, out);
+        }
+        (
+            FormulaNode::Namespace {
+                name: o,
+                definitions: od,
+            },
+            FormulaNode::Namespace {
+                name: n,
+                definitions: nd,
+            },
+        ) => {
+            renamed(out, o, n);
+            diff_list(path, od, nd, out);
+        }
+        _ => replaced(path, old, new, out),
+    }
+}
+
+fn replaced(path: &str, old: &FormulaNode, new: &FormulaNode, out: &mut Vec<AstChange>) {
+    out.push(AstChange::Replaced {
+        path: path.to_string(),
+        old: old.to_string(),
+        new: new.to_string(),
+    });
+}
+
+/// 按位置比较参数，多出或缺少的参数报告为增加或删除
+fn diff_args(
+    path: &str,
+    old: &[Rc<FormulaNode>],
+    new: &[Rc<FormulaNode>],
+    out: &mut Vec<AstChange>,
+) {
+    for (index, (o, n)) in old.iter().zip(new.iter()).enumerate() {
+        diff_node(&join(path, &format!("args[{}]", index)), o, n, out);
+    }
+    for (index, node) in old.iter().enumerate().skip(new.len()) {
+        out.push(AstChange::ArgumentRemoved {
+            path: path.to_string(),
+            index,
+            node: node.to_string(),
+        });
+    }
+    for (index, node) in new.iter().enumerate().skip(old.len()) {
+        out.push(AstChange::ArgumentAdded {
+            path: path.to_string(),
+            index,
+            node: node.to_string(),
+        });
+    }
+}
+
+/// 语句定义的名称，不是定义的语句返回 None
+fn defined_name(node: &FormulaNode) -> Option<&str> {
+    match node {
+        FormulaNode::Formula { name, .. }
+        | FormulaNode::Function { name, .. }
+        | FormulaNode::Namespace { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+/// 比较语句列表，定义按名称对应，其余语句按其在非定义语句中的位置对应
+fn diff_list(
+    path: &str,
+    old: &[Rc<FormulaNode>],
+    new: &[Rc<FormulaNode>],
+    out: &mut Vec<AstChange>,
+) {
+    let find = |nodes: &[Rc<FormulaNode>], name: &str| {
+        nodes
+            .iter()
+            .find(|node| defined_name(node) == Some(name))
+            .cloned()
+    };
+    let anonymous = |nodes: &[Rc<FormulaNode>]| -> Vec<Rc<FormulaNode>> {
+        nodes
+            .iter()
+            .filter(|node| defined_name(node).is_none())
+            .cloned()
+            .collect()
+    };
+    for o in old {
+        if let Some(name) = defined_name(o) {
+            match find(new, name) {
+                Some(n) => diff_node(&join(path, name), o, &n, out),
+                None => out.push(AstChange::Removed {
+                    path: path.to_string(),
+                    node: o.to_string(),
+                }),
+            }
+        }
+    }
+    for n in new {
+        if let Some(name) = defined_name(n) {
+            if find(old, name).is_none() {
+                out.push(AstChange::Added {
+                    path: path.to_string(),
+                    node: n.to_string(),
+                });
+            }
+        }
+    }
+
+    let (old, new) = (anonymous(old), anonymous(new));
+    for (index, (o, n)) in old.iter().zip(new.iter()).enumerate() {
+        diff_node(&join(path, &format!("[{}]", index)), o, n, out);
+    }
+    for node in old.iter().skip(new.len()) {
+        out.push(AstChange::Removed {
+            path: path.to_string(),
+            node: node.to_string(),
+        });
+    }
+    for node in new.iter().skip(old.len()) {
+        out.push(AstChange::Added {
+            path: path.to_string(),
+            node: node.to_string(),
+        });
+    }
+}
@@ -0,0 +1,188 @@
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::dialect::Dialect;
+use crate::formula::FormulaNode;
+use crate::token::Span;
+
+/// 文档中的一条语句，span 为语句在文档中的字节范围，包括结束该语句的 ;
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub span: Span,
+    pub node: Rc<FormulaNode>,
+}
+
+/// 对文档的一次修改，将 range 范围内的文本替换为 text，range 为修改前文档中的字节范围
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub range: Span,
+    pub text: String,
+}
+
+/// 修改的范围无法应用到文档上时产生的错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditError {
+    /// 修改的范围超出了文档的长度，或起始位置大于结束位置，len 为文档的长度
+    OutOfBounds { range: Span, len: usize },
+    /// 修改范围的边界位于多字节字符的中间
+    NotCharBoundary(usize),
+}
+
+impl core::fmt::Display for EditError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            EditError::OutOfBounds { range, len } => write!(
+                f,
+                "修改的范围 {}..{} 超出了文档的范围 0..{}",
+                range.start, range.end, len
+            ),
+            EditError::NotCharBoundary(pos) => {
+                write!(f, "修改范围的边界 {} 位于字符的中间", pos)
+            }
+        }
+    }
+}
+
+impl core::error::Error for EditError {}
+
+impl TextEdit {
+    /// 将修改应用到 source 上，得到修改后的源码，range 超出 source 或不在字符边界上时返回错误
+    pub fn apply_to(&self, source: &str) -> Result<String, EditError> {
+        let range = self.range.clone();
+        if range.start > range.end || range.end > source.len() {
+            return Err(EditError::OutOfBounds {
+                range,
+                len: source.len(),
+            });
+        }
+        for pos in [range.start, range.end] {
+            if !source.is_char_boundary(pos) {
+                return Err(EditError::NotCharBoundary(pos));
+            }
+        }
+        let mut source = source.to_string();
+        source.replace_range(range, &self.text);
+        Ok(source)
+    }
+}
+
+/// 解析后的公式文档，保存了源码及其中每条语句的语法树，由 Parser::parse_document 创建,
+/// 文档被修改后可以通过 Parser::reparse 只重新解析受影响的语句
+#[derive(Debug, Clone)]
+pub struct Document {
+    source: String,
+    statements: Vec<Statement>,
+}
+
+impl Document {
+    /// 扫描 source 中的所有语句，rewrite 用于将语句转换为解析器所使用的写法
+    pub fn parse<F: Fn(FormulaNode) -> FormulaNode>(source: &str, rewrite: F) -> Self {
+        Document {
+            source: source.to_string(),
+            statements: scan(source, 0, Dialect::Native, &rewrite),
+        }
+    }
+
+    /// 按 dialect 的运算规则扫描已转换为本项目语法的 source，用于 Excel 等写法
+    pub(crate) fn parse_dialect<F: Fn(FormulaNode) -> FormulaNode>(
+        source: &str,
+        dialect: Dialect,
+        rewrite: F,
+    ) -> Self {
+        Document {
+            source: source.to_string(),
+            statements: scan(source, 0, dialect, &rewrite),
+        }
+    }
+
+    /// 替换文档的源码而保留其中的语句，用于语句由转换后的源码扫描得到的情况
+    pub(crate) fn with_source(mut self, source: &str) -> Self {
+        self.source = source.to_string();
+        self
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    /// 得到应用 edit 之后的文档，只重新扫描与修改范围相交的语句，
+    /// 之前的语句保持不变，之后的语句只调整其位置，语法树都直接复用,
+    /// 修改使语句之间的 ; 发生变化时会继续向两侧扩大重新扫描的范围,
+    /// edit 的范围超出文档或不在字符边界上时返回错误
+    pub fn apply<F: Fn(FormulaNode) -> FormulaNode>(
+        &self,
+        edit: &TextEdit,
+        rewrite: F,
+    ) -> Result<Self, EditError> {
+        let source = edit.apply_to(&self.source)?;
+        let shift = |pos: usize| pos + edit.text.len() - edit.range.len();
+        let terminated = |s: &Statement| self.source[s.span.clone()].ends_with(';');
+
+        // [first, last) 为需要重新扫描的语句，修改范围之外但与其相邻的语句也需要重新扫描
+        let mut first = self
+            .statements
+            .iter()
+            .position(|s| s.span.end >= edit.range.start)
+            .unwrap_or(self.statements.len());
+        while first > 0 && !terminated(&self.statements[first - 1]) {
+            first -= 1;
+        }
+        let mut last = self
+            .statements
+            .iter()
+            .position(|s| s.span.start > edit.range.end)
+            .unwrap_or(self.statements.len())
+            .max(first);
+
+        loop {
+            let start = match first {
+                0 => 0,
+                _ => self.statements[first - 1].span.end,
+            };
+            let end = match self.statements.get(last) {
+                Some(next) => shift(next.span.start),
+                None => source.len(),
+            };
+            let rescanned = scan(&source[start..end], start, Dialect::Native, &rewrite);
+            // 重新扫描的最后一条语句没有以 ; 结束时可能与之后的语句连在一起，需要一起扫描
+            let open = rescanned
+                .last()
+                .is_some_and(|s| !source[s.span.clone()].ends_with(';'));
+            if open && last < self.statements.len() {
+                last += 1;
+                continue;
+            }
+
+            let mut statements = self.statements[..first].to_vec();
+            statements.extend(rescanned);
+            statements.extend(self.statements[last..].iter().map(|s| Statement {
+                span: shift(s.span.start)..shift(s.span.end),
+                node: Rc::clone(&s.node),
+            }));
+            return Ok(Document { source, statements });
+        }
+    }
+}
+
+/// 扫描 source 中的语句，offset 为 source 在整个文档中的起始位置
+fn scan<F: Fn(FormulaNode) -> FormulaNode>(
+    source: &str,
+    offset: usize,
+    dialect: Dialect,
+    rewrite: &F,
+) -> Vec<Statement> {
+    let mut scanner = dialect.scanner(source);
+    let mut statements = Vec::new();
+    while let Some((span, node)) = scanner.next_statement_span() {
+        statements.push(Statement {
+            span: span.start + offset..span.end + offset,
+            node: Rc::new(rewrite(node)),
+        });
+    }
+    statements
+}
@@ -1,27 +1,36 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use crate::compat::{HashMap, HashSet};
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::{Cell, Ref, RefCell};
 
-use crate::calculator::StackInfo;
-use crate::calculator::{CalculateOption, FormulaCalc};
+use crate::calculator::{CalculateOption, CalculateOptions, FormulaCalc};
+use crate::calculator::{CallFrame, ProfileReport, TraceNode};
+use crate::clock::{self, Clock};
 use crate::formula::BuildInFunctionType;
 use crate::formula::FormulaNode;
-use std::time::{Duration, Instant};
+use crate::policy::SecurityPolicy;
+use crate::provenance::Provenance;
+use core::time::Duration;
 
 pub type EnvType = Rc<RefCell<Env>>;
 
+#[derive(Clone)]
 pub struct DelayInfo {
-    point: Instant,
+    point: Duration,
     delay: Duration,
     delayed: bool,
+    clock: Rc<dyn Clock>,
 }
 
 impl DelayInfo {
-    pub fn new(delay: Duration) -> Self {
+    pub fn new(delay: Duration, clock: Rc<dyn Clock>) -> Self {
         DelayInfo {
-            point: Instant::now(),
+            point: clock.now(),
             delayed: false,
             delay,
+            clock,
         }
     }
 
@@ -29,7 +38,7 @@ impl DelayInfo {
         match self.delayed {
             true => true,
             false => {
-                if Instant::now().duration_since(self.point.clone()) > self.delay {
+                if self.clock.now().saturating_sub(self.point) > self.delay {
                     self.delayed = true;
                 }
                 self.delayed
@@ -51,6 +60,68 @@ pub struct EnvValue {
     pub node: Rc<FormulaNode>,
     pub value: RefCell<CalculateOption>,
     pub hist_value: RefCell<Vec<CalculateOption>>,
+    /// hist_value 中最后一个历史值所属的计算批次
+    pub hist_epoch: Cell<usize>,
+    /// 延迟生效的节点，在延迟时间到达之前读取该变量得到的仍然是 node
+    pub pending: RefCell<Option<(Rc<FormulaNode>, DelayInfo)>>,
+    /// 定义的来源信息，重新定义时被清除
+    pub provenance: Option<Rc<Provenance>>,
+    /// 定义的源码，由解析器在定义加入环境时记录，重新定义时被清除
+    pub source: Option<Rc<str>>,
+}
+
+impl EnvValue {
+    fn new(node: Rc<FormulaNode>) -> Self {
+        EnvValue {
+            hist_value: RefCell::new(Vec::new()),
+            hist_epoch: Cell::new(0),
+            node,
+            value: RefCell::new(CalculateOption::None),
+            pending: RefCell::new(None),
+            provenance: None,
+            source: None,
+        }
+    }
+
+    /// 获取当前生效的节点，如果延迟生效的节点已到达生效时间，则返回延迟生效的节点
+    pub fn effective_node(&self) -> Rc<FormulaNode> {
+        match self.pending.borrow_mut().as_mut() {
+            Some((node, delay)) => match delay.check_delay() {
+                true => Rc::clone(node),
+                false => Rc::clone(&self.node),
+            },
+            None => Rc::clone(&self.node),
+        }
+    }
+
+    /// 记录该变量在第 epoch 次计算中得到的值，同一次计算中多次记录时只保留最后一次的结果,
+    /// 历史值的个数超过 limit 时丢弃最早的记录
+    fn record_history(&self, value: &CalculateOption, epoch: usize, limit: usize) {
+        let mut hist = self.hist_value.borrow_mut();
+        if !hist.is_empty() && self.hist_epoch.get() == epoch {
+            hist.pop();
+        }
+        hist.push(value.clone());
+        self.hist_epoch.set(epoch);
+        if hist.len() > limit {
+            let overflow = hist.len() - limit;
+            hist.drain(..overflow);
+        }
+    }
+
+    /// 获取之前的计算中得到的第 back 个历史值，当前这次计算中记录的值不计算在内
+    fn history_value(&self, back: usize, epoch: usize) -> Option<CalculateOption> {
+        let hist = self.hist_value.borrow();
+        let len = match self.hist_epoch.get() == epoch {
+            true => hist.len().saturating_sub(1),
+            false => hist.len(),
+        };
+        match back {
+            0 => None,
+            _ if back > len => None,
+            _ => hist.get(len - back).cloned(),
+        }
+    }
 }
 
 impl FormulaCalc for EnvValue {
@@ -61,39 +132,387 @@ impl FormulaCalc for EnvValue {
     }
 }
 
+/// 执行环境中某一层级的快照，保存了该层级中所有变量的定义、计算结果及历史值，
+/// 可通过 Env::restore 将执行环境恢复到创建快照时的状态
+#[derive(Clone)]
+pub struct EnvSnapshot {
+    env: HashMap<String, EnvValue>,
+    build_in_map: Option<HashMap<String, Rc<BuildInFunctionType>>>,
+}
+
+/// 外部变量解析器，变量在执行环境中不存在时会通过该解析器获取变量的值，
+/// 可用于从数据库、配置服务等外部数据源按需获取变量，而无需预先加载所有的数据
+pub trait VariableResolver {
+    fn resolve(&self, name: &str) -> Option<CalculateOption>;
+}
+
+impl<F> VariableResolver for F
+where
+    F: Fn(&str) -> Option<CalculateOption>,
+{
+    fn resolve(&self, name: &str) -> Option<CalculateOption> {
+        self(name)
+    }
+}
+
+/// 表达式计算过程的观察者，可用于性能分析、调试及审计日志等场景，所有的回调默认不做任何处理,
+/// env 为计算该节点时所在的执行环境
+pub trait EvalObserver {
+    /// 开始计算节点 node
+    fn on_enter_node(&self, _node: &FormulaNode, _env: &Env) {}
+    /// 节点 node 计算完成，value 为其计算结果
+    fn on_exit_node(&self, _node: &FormulaNode, _value: &CalculateOption, _env: &Env) {}
+    /// 调用函数 name，args 为调用时传入的参数
+    fn on_function_call(&self, _name: &str, _args: &[Rc<FormulaNode>], _env: &Env) {}
+    /// 节点 node 计算出错，同一个错误只会在最先产生该错误的节点上通知一次
+    fn on_error(&self, _node: &FormulaNode, _message: &str, _env: &Env) {}
+}
+
+/// 整个环境链共享的观察者，errors 记录每个正在计算的节点中是否已经通知过错误
+#[derive(Default)]
+struct ObserverSlot {
+    observer: Option<Rc<dyn EvalObserver>>,
+    errors: Vec<bool>,
+}
+
+/// 序列化为 JSON 时每个变量保存的内容
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EnvJsonEntry {
+    node: Rc<FormulaNode>,
+    value: CalculateOption,
+}
+
+/// 环境变量的类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvEntryKind {
+    /// 命名的表达式，如 A := B + 1
+    Formula,
+    /// 脚本中定义的函数
+    Function,
+    /// 通过 Rust 注册的内建函数
+    BuildIn,
+    /// 常量，如 A := 1 或宿主程序直接绑定的值
+    Constant,
+    /// 命名空间，如 namespace tax { ... } 中的 tax，其中的定义以 tax.名称 的形式保存
+    Namespace,
+}
+
+/// 环境变量的描述信息，用于列出执行环境中已定义的内容
+#[derive(Debug, Clone)]
+pub struct EnvEntryInfo {
+    pub name: String,
+    pub kind: EnvEntryKind,
+    /// 变量当前生效的定义
+    pub node: Rc<FormulaNode>,
+    /// 变量最后一次计算得到的值，需开启历史值记录或存在监听函数时才会保存，否则为 None
+    pub value: CalculateOption,
+    /// 定义的来源信息，如所在的公式文件及文档注释中的作者、版本和说明
+    pub provenance: Option<Rc<Provenance>>,
+    /// 定义的源码，如 A := B + 1，宿主程序直接绑定的值、内建函数及命名空间中的各个定义为 None
+    pub source: Option<Rc<str>>,
+}
+
+/// 环境变量发生的变化
+#[derive(Debug, Clone)]
+pub enum EnvChange {
+    /// 变量被定义或重新定义，保存的是新的定义
+    Define(Rc<FormulaNode>),
+    /// 变量的计算结果发生了变化
+    Value {
+        old: CalculateOption,
+        new: CalculateOption,
+    },
+    /// 变量被移除，变量被重命名时旧的变量名也会收到该事件
+    Remove,
+}
+
+/// 修改执行环境时产生的错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvError {
+    /// 尝试修改只读层级中的变量
+    ReadOnly(String),
+    /// 尝试定义与只读层级中同名的变量，以覆盖系统定义的常量或函数
+    Protected(String),
+    /// 内建函数已被注册
+    Duplicate(String),
+}
+
+impl core::fmt::Display for EnvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            EnvError::ReadOnly(name) => write!(f, "执行环境为只读，无法修改变量 {}", name),
+            EnvError::Protected(name) => {
+                write!(f, "变量 {} 为只读的系统定义，不允许被重新定义", name)
+            }
+            EnvError::Duplicate(name) => write!(f, "内建函数 {} 已被注册", name),
+        }
+    }
+}
+
+impl core::error::Error for EnvError {}
+
+/// 变量变化的监听函数，参数分别为变量名及发生的变化
+pub type EnvListenerType = dyn Fn(&str, &EnvChange);
+
 pub struct Env {
     prev: Option<Rc<RefCell<Env>>>,
     env: HashMap<String, EnvValue>,
     build_in_map: Option<HashMap<String, Rc<BuildInFunctionType>>>,
-    stack: Rc<RefCell<Vec<StackInfo>>>,
+    /// 计算结果不确定的内建函数，与 build_in_map 一样只有最上级的 Env 才会保存
+    nondeterministic: HashSet<String>,
+    /// 默认加载的内建函数 If，尾调用中的 If 只有在其未被重新注册时才按 If 的语义直接计算,
+    /// 与 build_in_map 一样只有最上级的 Env 才会保存
+    std_if: Option<Rc<BuildInFunctionType>>,
+    /// 当前计算的计算过程记录，由同一次计算中的所有子环境共享
+    trace: Rc<RefCell<Trace>>,
+    options: Rc<RefCell<CalculateOptions>>,
+    /// 执行不可信公式时的安全策略，由整个环境链共享
+    policy: Rc<RefCell<SecurityPolicy>>,
+    /// 当前的计算批次，每次通过解析器计算表达式时递增，用于区分变量历史值所属的计算
+    epoch: Rc<Cell<usize>>,
+    /// 当前层级中变量的监听函数，key 为变量名
+    listeners: HashMap<String, Vec<(usize, Rc<EnvListenerType>)>>,
+    next_listener_id: usize,
+    /// 当前层级所属的命名空间，调用命名空间中的函数或计算其中的表达式时设置,
+    /// 在该层级中查找变量时会优先查找命名空间中的同名定义
+    namespace: Option<String>,
+    /// 当前层级是否只读，只读层级中的变量不能被修改，其子环境也不能定义同名的变量
+    readonly: bool,
+    /// 外部变量解析器，只有最上级的 Env 才会保存
+    resolver: Option<Rc<dyn VariableResolver>>,
+    /// 整个环境链中是否存在监听函数，存在时计算变量后会保存其计算结果以便通知变化
+    watched: Rc<Cell<bool>>,
+    /// 计算过程的观察者，由整个环境链共享
+    observer: Rc<RefCell<ObserverSlot>>,
+    /// 可重复使用的子环境，由整个环境链共享，已回收到池中的 Env 不持有该池，以免形成循环引用
+    pool: Option<Rc<RefCell<Vec<EnvType>>>>,
+    /// 判断延迟生效的变量是否到期及统计耗时所用的时钟
+    clock: Rc<dyn Clock>,
+    /// 环境链中变量名的布局版本，由整个环境链共享，
+    /// 被扩展过的层级中新增、移除变量或修改命名空间时递增，使子环境中的查找缓存失效
+    layout: Rc<Cell<u64>>,
+    /// 当前层级是否创建过子环境，未创建过子环境的层级 (如函数调用的参数层) 修改变量时无需递增布局版本
+    extended: Cell<bool>,
+    /// 从上级环境中查找到的变量所在的层级，只缓存跨越了 LOOKUP_CACHE_DEPTH 个层级的查找结果
+    lookups: RefCell<HashMap<String, Lookup>>,
 }
 
+/// 查找缓存中的一项，key 为变量在 level 中的完整名称，命名空间中的定义为 命名空间.变量名
+struct Lookup {
+    layout: u64,
+    level: EnvType,
+    key: String,
+}
+
+/// 计算过程的记录，frames 为正在计算的节点，最外层的节点计算完成后保存到 root 中,
+/// calls 为正在执行的函数调用，最外层的调用完成后保存到 call_roots 中
+#[derive(Default)]
+struct Trace {
+    frames: Vec<TraceNode>,
+    root: Option<TraceNode>,
+    calls: Vec<CallFrame>,
+    call_roots: Vec<CallFrame>,
+    profile: ProfileReport,
+    /// 确定性计算模式下读取的输入
+    inputs: BTreeMap<String, CalculateOption>,
+    /// 本次计算已经计算的节点个数，只在安全策略限制了 fuel 时统计
+    fuel_used: u64,
+    /// 当前函数调用及具名表达式引用的嵌套深度
+    call_depth: usize,
+    /// 正在计算的具名表达式，用于发现定义中的循环引用
+    formulas: Vec<Rc<FormulaNode>>,
+}
+
+/// 子环境缓存池中最多保存的 Env 个数
+const ENV_POOL_LIMIT: usize = 64;
+
+/// 查找变量时向上跨越的层级数达到该值时缓存变量所在的层级，之后的查找直接读取该层级,
+/// 层级较浅时直接查找的开销小于维护缓存的开销
+const LOOKUP_CACHE_DEPTH: usize = 3;
+
 impl Env {
     pub fn new() -> EnvType {
         Rc::new(RefCell::new(Env {
             prev: None,
             env: HashMap::new(),
             build_in_map: Some(HashMap::new()),
-            stack: Rc::new(RefCell::new(Vec::new())),
+            nondeterministic: HashSet::new(),
+            std_if: None,
+            trace: Rc::new(RefCell::new(Trace::default())),
+            options: Rc::new(RefCell::new(CalculateOptions::default())),
+            policy: Rc::new(RefCell::new(SecurityPolicy::default())),
+            epoch: Rc::new(Cell::new(0)),
+            listeners: HashMap::new(),
+            next_listener_id: 0,
+            namespace: None,
+            readonly: false,
+            resolver: None,
+            watched: Rc::new(Cell::new(false)),
+            observer: Rc::new(RefCell::new(ObserverSlot::default())),
+            pool: Some(Rc::new(RefCell::new(Vec::new()))),
+            clock: clock::default_clock(),
+            layout: Rc::new(Cell::new(0)),
+            extended: Cell::new(false),
+            lookups: RefCell::new(HashMap::new()),
         }))
     }
 
     pub fn extend(env: &EnvType) -> EnvType {
-        Rc::new(RefCell::new(Env {
-            prev: Some(Rc::clone(env)),
-            env: HashMap::new(),
-            build_in_map: None,
-            stack: Rc::new(RefCell::new(Vec::new())),
-        }))
+        Env::acquire(env, Rc::new(RefCell::new(Trace::default())))
     }
 
+    /// 创建 env 的子环境，与 env 共享计算过程的记录
     pub fn extend_with_stack(env: &EnvType) -> EnvType {
-        Rc::new(RefCell::new(Env {
-            prev: Some(Rc::clone(env)),
-            env: HashMap::new(),
-            build_in_map: None,
-            stack: Rc::clone(&RefCell::borrow(env).stack),
-        }))
+        let trace = Rc::clone(&RefCell::borrow(env).trace);
+        Env::acquire(env, trace)
+    }
+
+    /// 创建 scope 的子环境用于执行函数体，与调用者 caller 共享计算过程的记录
+    pub fn extend_scope(scope: &EnvType, caller: &EnvType) -> EnvType {
+        let trace = Rc::clone(&RefCell::borrow(caller).trace);
+        Env::acquire(scope, trace)
+    }
+
+    /// 查找定义了 key 的层级，查找顺序与 get 相同，即当前层级、当前命名空间及上级环境
+    pub fn scope_of(env: &EnvType, key: &str) -> Option<EnvType> {
+        let e = RefCell::borrow(env);
+        if e.env.contains_key(key) {
+            return Some(Rc::clone(env));
+        }
+        if let Some(ref ns) = e.namespace {
+            if let Some(scope) = Env::scope_of_exact(env, &format!("{}.{}", ns, key)) {
+                return Some(scope);
+            }
+        }
+        e.prev.as_ref().and_then(|prev| Env::scope_of(prev, key))
+    }
+
+    /// 按完整的名称查找定义了 key 的层级，不考虑命名空间
+    fn scope_of_exact(env: &EnvType, key: &str) -> Option<EnvType> {
+        let e = RefCell::borrow(env);
+        match e.env.contains_key(key) {
+            true => Some(Rc::clone(env)),
+            false => e
+                .prev
+                .as_ref()
+                .and_then(|prev| Env::scope_of_exact(prev, key)),
+        }
+    }
+
+    /// 创建 env 的子环境，优先从缓存池中取出已回收的 Env 重复使用，以减少频繁计算时的内存分配
+    fn acquire(env: &EnvType, trace: Rc<RefCell<Trace>>) -> EnvType {
+        let parent = RefCell::borrow(env);
+        parent.extended.set(true);
+        let pooled = parent
+            .pool
+            .as_ref()
+            .and_then(|pool| pool.borrow_mut().pop());
+        match pooled {
+            Some(child) => {
+                {
+                    let mut e = child.borrow_mut();
+                    e.prev = Some(Rc::clone(env));
+                    e.trace = trace;
+                    e.options = Rc::clone(&parent.options);
+                    e.policy = Rc::clone(&parent.policy);
+                    e.epoch = Rc::clone(&parent.epoch);
+                    e.watched = Rc::clone(&parent.watched);
+                    e.observer = Rc::clone(&parent.observer);
+                    e.pool = parent.pool.clone();
+                    e.clock = Rc::clone(&parent.clock);
+                    e.layout = Rc::clone(&parent.layout);
+                }
+                child
+            }
+            None => Rc::new(RefCell::new(Env {
+                prev: Some(Rc::clone(env)),
+                env: HashMap::new(),
+                build_in_map: None,
+                nondeterministic: HashSet::new(),
+                std_if: None,
+                trace,
+                options: Rc::clone(&parent.options),
+                policy: Rc::clone(&parent.policy),
+                epoch: Rc::clone(&parent.epoch),
+                listeners: HashMap::new(),
+                next_listener_id: 0,
+                namespace: None,
+                readonly: false,
+                resolver: None,
+                watched: Rc::clone(&parent.watched),
+                observer: Rc::clone(&parent.observer),
+                pool: parent.pool.clone(),
+                clock: Rc::clone(&parent.clock),
+                layout: Rc::clone(&parent.layout),
+                extended: Cell::new(false),
+                lookups: RefCell::new(HashMap::new()),
+            })),
+        }
+    }
+
+    /// 回收不再使用的子环境，清空其中的变量后放入缓存池，供之后的 extend 重复使用,
+    /// env 仍被其他地方引用或缓存池已满时直接丢弃
+    pub fn release(env: EnvType) {
+        if Rc::strong_count(&env) != 1 || Rc::weak_count(&env) != 0 {
+            return;
+        }
+        let pool = {
+            let mut e = env.borrow_mut();
+            if e.build_in_map.is_some() {
+                return;
+            }
+            e.env.clear();
+            e.listeners.clear();
+            e.next_listener_id = 0;
+            e.namespace = None;
+            e.readonly = false;
+            e.prev = None;
+            e.extended.set(false);
+            e.lookups.get_mut().clear();
+            e.pool.take()
+        };
+        if let Some(pool) = pool {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < ENV_POOL_LIMIT {
+                pool.push(env);
+            }
+        }
+    }
+
+    /// 缓存池中可重复使用的子环境个数
+    pub fn pooled(&self) -> usize {
+        self.pool.as_ref().map_or(0, |pool| pool.borrow().len())
+    }
+
+    /// 创建属于命名空间 namespace 的子环境，与上级环境共享调用堆栈
+    pub fn extend_namespace(env: &EnvType, namespace: &str) -> EnvType {
+        let new_env = Env::extend_with_stack(env);
+        new_env.borrow_mut().set_namespace(Some(namespace));
+        new_env
+    }
+
+    /// 设置当前层级所属的命名空间
+    pub fn set_namespace(&mut self, namespace: Option<&str>) {
+        self.namespace = namespace.map(|ns| ns.to_string());
+        self.lookups.get_mut().clear();
+        self.layout_changed();
+    }
+
+    /// 当前层级中的变量名发生了变化，当前层级被扩展过时使整个环境链中的查找缓存失效
+    fn layout_changed(&self) {
+        if self.extended.get() {
+            self.layout.set(self.layout.get() + 1);
+        }
+    }
+
+    /// 获取限定名称 (如 tax.Rate) 所在的命名空间，. 之前的部分不是已定义的命名空间时返回 None
+    pub fn namespace_of<'k>(&self, key: &'k str) -> Option<&'k str> {
+        let (ns, _) = key.rsplit_once('.')?;
+        match self.get(ns)?.as_ref() {
+            FormulaNode::Namespace { .. } => Some(ns),
+            _ => None,
+        }
     }
 
     /// 从 Env 中获取 BuildIn 函数，只有最上级的 Env 才会保存注册的函数，其他的子集 Env build_in_map 中保存的都是 None
@@ -103,15 +522,16 @@ impl Env {
             None => self
                 .prev
                 .as_ref()
-                .map_or(None, |prev| RefCell::borrow(prev).get_build_in(func_key)),
+                .and_then(|prev| RefCell::borrow(prev).get_build_in(func_key)),
         }
     }
 
+    /// 注册 BuildIn 函数，函数总是注册到最上级的 Env 中，
+    /// 由宿主程序调用，因此不受只读层级的限制，可以覆盖系统层中的同名函数
     pub fn set_build_in(&mut self, func_key: &str, f: Rc<BuildInFunctionType>) {
         match self.build_in_map.as_mut() {
             Some(m) => {
-                m.insert(func_key.to_string(), f.clone());
-
+                m.insert(func_key.to_string(), f);
                 self.set(
                     func_key,
                     Rc::new(FormulaNode::BuildInFunction {
@@ -119,67 +539,778 @@ impl Env {
                     }),
                 );
             }
-            None => (),
+            None => {
+                if let Some(ref prev) = self.prev {
+                    prev.borrow_mut().set_build_in(func_key, f);
+                }
+            }
+        }
+    }
+
+    /// 与 set_build_in 相同，但 func_key 为只读层级中的系统定义时返回错误而不覆盖原有的定义
+    pub fn try_set_build_in(
+        &mut self,
+        func_key: &str,
+        f: Rc<BuildInFunctionType>,
+    ) -> Result<(), EnvError> {
+        if self.is_protected(func_key) {
+            return Err(EnvError::Protected(func_key.to_string()));
+        }
+        self.set_build_in(func_key, f);
+        Ok(())
+    }
+
+    /// 将当前注册的内建函数 If 记录为默认的 If，由 Parser::new 在加载标准库后调用
+    pub fn mark_std_if(&mut self) {
+        let f = self.get_build_in("If");
+        match self.prev {
+            Some(ref prev) if self.build_in_map.is_none() => prev.borrow_mut().std_if = f,
+            _ => self.std_if = f,
+        }
+    }
+
+    /// 当前注册的内建函数 If 是否为默认加载的 If，即没有被宿主程序重新注册
+    pub fn is_std_if(&self) -> bool {
+        match (&self.build_in_map, &self.prev) {
+            (None, Some(prev)) => RefCell::borrow(prev).is_std_if(),
+            _ => match (&self.std_if, self.get_build_in("If")) {
+                (Some(std), Some(current)) => Rc::ptr_eq(std, &current),
+                _ => false,
+            },
+        }
+    }
+
+    /// 将内建函数 func_key 标记为计算结果不确定 (如 Rand、Now)，确定性计算模式下禁止调用
+    pub fn set_nondeterministic(&mut self, func_key: &str) {
+        match self.build_in_map {
+            Some(_) => {
+                self.nondeterministic.insert(func_key.to_string());
+            }
+            None => {
+                if let Some(ref prev) = self.prev {
+                    prev.borrow_mut().set_nondeterministic(func_key);
+                }
+            }
+        }
+    }
+
+    /// 确定性计算模式下是否禁止调用内建函数 func_key，即该函数被标记为计算结果不确定且不在允许的列表中
+    pub fn forbids(&self, func_key: &str) -> bool {
+        match self.options.borrow().deterministic {
+            Some(ref mode) => {
+                self.is_nondeterministic(func_key) && !mode.allowed.iter().any(|f| f == func_key)
+            }
+            None => false,
         }
     }
 
+    fn is_nondeterministic(&self, func_key: &str) -> bool {
+        match self.build_in_map {
+            Some(_) => self.nondeterministic.contains(func_key),
+            None => self
+                .prev
+                .as_ref()
+                .is_some_and(|prev| RefCell::borrow(prev).is_nondeterministic(func_key)),
+        }
+    }
+
+    /// 与 try_set_build_in 相同，但 func_key 已注册为内建函数时也返回错误而不覆盖原有的函数
+    pub fn add_build_in(
+        &mut self,
+        func_key: &str,
+        f: Rc<BuildInFunctionType>,
+    ) -> Result<(), EnvError> {
+        if self.get_build_in(func_key).is_some() {
+            return Err(EnvError::Duplicate(func_key.to_string()));
+        }
+        self.try_set_build_in(func_key, f)
+    }
+
+    /// 将当前层级标记为只读，用于保存常量及内建函数等系统定义,
+    /// 在其子环境中计算的表达式无法覆盖这些定义
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// 检查是否允许在当前层级中定义变量 key，当前层级为只读，
+    /// 或上级环境中的只读层级已定义了 key 时返回错误
+    pub fn check_write(&self, key: &str) -> Result<(), EnvError> {
+        if self.readonly {
+            return Err(EnvError::ReadOnly(key.to_string()));
+        }
+        match self.prev {
+            Some(ref prev) if RefCell::borrow(prev).is_protected(key) => {
+                Err(EnvError::Protected(key.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 当前层级或其上级环境中的只读层级是否定义了 key
+    fn is_protected(&self, key: &str) -> bool {
+        (self.readonly && self.env.contains_key(key))
+            || match self.prev {
+                Some(ref prev) => RefCell::borrow(prev).is_protected(key),
+                None => false,
+            }
+    }
+
     /// 从 当前执行环境中根据变量名获取信息，获取到的结果可以是脚本允许的任意一种类型, 如变量，函数等
     /// 如果从当前层次的上下文中获取不到，则尝试从上级上下文中获取, 具体实现的能力体现为：获取变量优先从当前
     /// 作用域获取，如果没有则从上一级作用域获取，直到最后一级，也就是获取全局变量
+    /// 如果当前层级属于某个命名空间，则在当前作用域之后、上一级作用域之前查找该命名空间中的同名定义，
+    /// 即命名空间 tax 中引用的 Rate 会优先解析为 tax.Rate
     pub fn get(&self, key: &str) -> Option<Rc<FormulaNode>> {
+        if let Some(e) = self.env.get(key) {
+            return Some(e.effective_node());
+        }
+        if let Some(ref ns) = self.namespace {
+            if let Some(node) = self.get_exact(&format!("{}.{}", ns, key)) {
+                return Some(node);
+            }
+        }
+        let prev = self.prev.as_ref()?;
+
+        if let Some(lookup) = self.lookups.borrow().get(key) {
+            if lookup.layout == self.layout.get() {
+                return RefCell::borrow(&lookup.level)
+                    .env
+                    .get(&lookup.key)
+                    .map(|e| e.effective_node());
+            }
+        }
+        let (level, full_key, depth) = Env::locate(prev, key, 1)?;
+        let node = RefCell::borrow(&level)
+            .env
+            .get(&full_key)
+            .map(|e| e.effective_node());
+        if depth >= LOOKUP_CACHE_DEPTH {
+            let lookup = Lookup {
+                layout: self.layout.get(),
+                level,
+                key: full_key,
+            };
+            self.lookups.borrow_mut().insert(key.to_string(), lookup);
+        }
+        node
+    }
+
+    /// 按照与 get 相同的顺序查找定义了 key 的层级，返回该层级、变量在其中的完整名称,
+    /// 及该层级与发起查找的层级之间相隔的层数，depth 为 env 与发起查找的层级之间相隔的层数
+    fn locate(env: &EnvType, key: &str, depth: usize) -> Option<(EnvType, String, usize)> {
+        let e = RefCell::borrow(env);
+        if e.env.contains_key(key) {
+            return Some((Rc::clone(env), key.to_string(), depth));
+        }
+        if let Some(ref ns) = e.namespace {
+            let full_key = format!("{}.{}", ns, key);
+            if let Some(level) = Env::scope_of_exact(env, &full_key) {
+                return Some((level, full_key, depth));
+            }
+        }
+        e.prev
+            .as_ref()
+            .and_then(|prev| Env::locate(prev, key, depth + 1))
+    }
+
+    /// 按完整的名称查找变量，不考虑命名空间
+    fn get_exact(&self, key: &str) -> Option<Rc<FormulaNode>> {
         self.env
             .get(key)
-            .map(|e| e.node.clone())
+            .map(|e| e.effective_node())
             .or_else(|| match self.prev {
-                Some(ref prev) => prev.borrow_mut().get(key),
+                Some(ref prev) => RefCell::borrow(prev).get_exact(key),
                 None => None,
             })
     }
 
+    /// 设置外部变量解析器，解析器保存在最上级的 Env 中，对整个环境链生效
+    pub fn set_resolver(&mut self, resolver: Rc<dyn VariableResolver>) {
+        match self.prev {
+            Some(ref prev) => prev.borrow_mut().set_resolver(resolver),
+            None => self.resolver = Some(resolver),
+        }
+    }
+
+    /// 通过外部变量解析器获取变量 key 的值，未设置解析器或解析器中不存在该变量时返回 None
+    pub fn resolve(&self, key: &str) -> Option<CalculateOption> {
+        match self.prev {
+            Some(ref prev) => RefCell::borrow(prev).resolve(key),
+            None => self.resolver.as_ref().and_then(|r| r.resolve(key)),
+        }
+    }
+
     /// 将 value 指定的信息保存到环境变量中
+    /// 重新定义已有的变量时，该变量的历史值会被保留
     pub fn set(&mut self, key: &str, value: Rc<FormulaNode>) -> Option<EnvValue> {
-        self.env.insert(
-            key.to_string(),
-            EnvValue {
-                hist_value: RefCell::new(Vec::new()),
-                node: Rc::clone(&value),
-                value: RefCell::new(CalculateOption::None),
-            },
-        )
+        let old = self.replace(key, Rc::clone(&value));
+        self.notify(key, &EnvChange::Define(value));
+        old
+    }
+
+    /// 与 set 相同，但会检查是否允许定义变量 key，不允许时返回错误而不修改环境
+    pub fn try_set(
+        &mut self,
+        key: &str,
+        value: Rc<FormulaNode>,
+    ) -> Result<Option<EnvValue>, EnvError> {
+        self.check_write(key)?;
+        Ok(self.set(key, value))
+    }
+
+    /// 替换变量的定义并保留其历史值，不通知监听函数
+    fn replace(&mut self, key: &str, value: Rc<FormulaNode>) -> Option<EnvValue> {
+        let ev = EnvValue::new(value);
+        match self.env.get(key) {
+            Some(old) => {
+                ev.hist_value.replace(old.hist_value.borrow().clone());
+                ev.hist_epoch.set(old.hist_epoch.get());
+            }
+            None => self.layout_changed(),
+        }
+        self.env.insert(key.to_string(), ev)
+    }
+
+    /// 设置当前层级中变量 key 的来源信息，变量不存在时返回 false
+    pub fn set_provenance(&mut self, key: &str, provenance: Rc<Provenance>) -> bool {
+        match self.env.get_mut(key) {
+            Some(ev) => {
+                ev.provenance = Some(provenance);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 记录当前层级中变量 key 的定义的源码，key 不在当前层级中时返回 false
+    pub fn set_source(&mut self, key: &str, source: Rc<str>) -> bool {
+        match self.env.get_mut(key) {
+            Some(ev) => {
+                ev.source = Some(source);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 从当前层级中移除变量 key，如果 key 是内建函数，则同时移除注册的函数，返回被移除的变量
+    pub fn remove(&mut self, key: &str) -> Option<EnvValue> {
+        if let Some(m) = self.build_in_map.as_mut() {
+            m.remove(key);
+        }
+        let removed = self.env.remove(key);
+        if removed.is_some() {
+            self.layout_changed();
+            self.notify(key, &EnvChange::Remove);
+        }
+        removed
+    }
+
+    /// 将当前层级中的变量 old 重命名为 new，变量的历史值会被保留，new 已存在时会被覆盖,
+    /// rewrite_references 为 true 时，当前层级中其他定义里对 old 的引用也会被改写为 new，
+    /// 否则这些引用仍然指向 old。不存在变量 old 时返回 false
+    pub fn rename(&mut self, old: &str, new: &str, rewrite_references: bool) -> bool {
+        let mut ev = match self.env.remove(old) {
+            Some(ev) => ev,
+            None => return false,
+        };
+        if let Some(m) = self.build_in_map.as_mut() {
+            if let Some(f) = m.remove(old) {
+                m.insert(new.to_string(), f);
+            }
+        }
+
+        let rename_node = |node: &Rc<FormulaNode>| match rewrite_references {
+            true => Rc::new(node.rename(old, new)),
+            false => Rc::new(node.with_name(new)),
+        };
+        ev.node = rename_node(&ev.node);
+        if let Some((node, _)) = ev.pending.get_mut() {
+            *node = rename_node(node);
+        }
+        if rewrite_references {
+            for other in self.env.values_mut() {
+                other.node = Rc::new(other.node.rename(old, new));
+                if let Some((node, _)) = other.pending.get_mut() {
+                    *node = Rc::new(node.rename(old, new));
+                }
+            }
+        }
+
+        let node = Rc::clone(&ev.node);
+        self.env.insert(new.to_string(), ev);
+        self.layout_changed();
+        self.notify(old, &EnvChange::Remove);
+        self.notify(new, &EnvChange::Define(node));
+        true
+    }
+
+    /// 监听当前层级中变量 key 的变化，变量被重新定义 (set) 或计算结果发生变化 (set_node_value) 时
+    /// 会调用 listener, 返回的 id 可用于取消监听。
+    /// listener 被调用时该环境正处于借用状态，因此 listener 中不能再访问该环境
+    pub fn subscribe<F>(&mut self, key: &str, listener: F) -> usize
+    where
+        F: Fn(&str, &EnvChange) + 'static,
+    {
+        let id = self.next_listener_id;
+        self.next_listener_id += 1;
+        self.listeners
+            .entry(key.to_string())
+            .or_default()
+            .push((id, Rc::new(listener)));
+        self.watched.set(true);
+        id
+    }
+
+    /// 取消对变量 key 的监听，返回是否存在对应的监听
+    pub fn unsubscribe(&mut self, key: &str, id: usize) -> bool {
+        match self.listeners.get_mut(key) {
+            Some(listeners) => {
+                let len = listeners.len();
+                listeners.retain(|(i, _)| *i != id);
+                len != listeners.len()
+            }
+            None => false,
+        }
+    }
+
+    /// 获取当前环境中可访问的所有变量名，包括上级环境中的变量，按名称排序
+    pub fn names(&self) -> Vec<String> {
+        let mut names = match self.prev {
+            Some(ref prev) => RefCell::borrow(prev).names(),
+            None => Vec::new(),
+        };
+        names.extend(self.env.keys().cloned());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// 获取当前层级中定义的变量名，不包括上级环境中的变量，按名称排序
+    pub fn local_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.env.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// 获取变量 key 的描述信息，包括其类型、定义及最后一次计算的结果
+    pub fn get_typed(&self, key: &str) -> Option<EnvEntryInfo> {
+        match self.env.get(key) {
+            Some(ev) => {
+                let node = ev.effective_node();
+                let kind = match node.as_ref() {
+                    FormulaNode::Function { .. } => EnvEntryKind::Function,
+                    FormulaNode::BuildInFunction { .. } => EnvEntryKind::BuildIn,
+                    FormulaNode::Namespace { .. } => EnvEntryKind::Namespace,
+                    FormulaNode::Formula { formula, .. } if formula.is_constant() => {
+                        EnvEntryKind::Constant
+                    }
+                    FormulaNode::Formula { .. } => EnvEntryKind::Formula,
+                    n if n.is_constant() => EnvEntryKind::Constant,
+                    _ => EnvEntryKind::Formula,
+                };
+                Some(EnvEntryInfo {
+                    name: key.to_string(),
+                    kind,
+                    node,
+                    value: ev.value.borrow().clone(),
+                    provenance: ev.provenance.clone(),
+                    source: ev.source.clone(),
+                })
+            }
+            None => self
+                .prev
+                .as_ref()
+                .and_then(|prev| RefCell::borrow(prev).get_typed(key)),
+        }
+    }
+
+    /// 计算变量后是否需要保存其计算结果，开启了历史值记录或存在监听函数时需要保存
+    pub fn records_values(&self) -> bool {
+        self.watched.get() || self.options.borrow().history_limit > 0
+    }
+
+    fn notify(&self, key: &str, change: &EnvChange) {
+        if let Some(listeners) = self.listeners.get(key) {
+            for (_, listener) in listeners {
+                listener(key, change);
+            }
+        }
+    }
+
+    /// 设置时钟，只影响之后创建的子环境及之后设置的延迟变量
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> Rc<dyn Clock> {
+        Rc::clone(&self.clock)
+    }
+
+    /// 将 value 保存到环境变量中，但 value 需要经过 delay 的时间后才会生效,
+    /// 在此之前读取该变量得到的是它之前的值，如果之前未定义过该变量，则得到 None
+    pub fn set_delayed(&mut self, key: &str, value: Rc<FormulaNode>, delay: Duration) {
+        let current = self
+            .env
+            .get(key)
+            .map(|e| e.effective_node())
+            .unwrap_or_else(|| Rc::new(FormulaNode::None));
+        self.replace(key, current);
+        if let Some(ev) = self.env.get(key) {
+            ev.pending
+                .replace(Some((value, DelayInfo::new(delay, Rc::clone(&self.clock)))));
+        }
     }
 
     /// 将 key 对应 FormulaNode 节点的当前计算结果保存到 Env 中, 并返回旧的计算结果
-    pub fn set_node_value(&mut self, key: &str, value: CalculateOption) -> CalculateOption {
+    /// 计算结果同时会被记录到该变量的历史值中
+    pub fn set_node_value(&self, key: &str, value: CalculateOption) -> CalculateOption {
         match self.env.get(key) {
             Some(ev) => {
-                let mut v = ev.value.borrow_mut();
-                let olv_v = v.clone();
-                *v = value;
-                olv_v
+                let limit = self.options.borrow().history_limit;
+                if limit > 0 {
+                    ev.record_history(&value, self.epoch.get(), limit);
+                }
+                let old = ev.value.replace(value.clone());
+                if old != value {
+                    self.notify(
+                        key,
+                        &EnvChange::Value {
+                            old: old.clone(),
+                            new: value,
+                        },
+                    );
+                }
+                old
             }
             None => match self.prev {
-                Some(ref prev) => prev.borrow_mut().set_node_value(key, value),
+                Some(ref prev) => RefCell::borrow(prev).set_node_value(key, value),
                 None => CalculateOption::None,
             },
         }
     }
 
+    /// 获取变量 key 在之前的计算中得到的第 back 个值，back 为 1 时表示上一次计算的结果,
+    /// 当前这次计算中得到的值不计算在内，没有对应的历史值时返回 None
+    pub fn history_value(&self, key: &str, back: usize) -> Option<CalculateOption> {
+        match self.env.get(key) {
+            Some(ev) => ev.history_value(back, self.epoch.get()),
+            None => self
+                .prev
+                .as_ref()
+                .and_then(|prev| RefCell::borrow(prev).history_value(key, back)),
+        }
+    }
+
+    /// 获取变量 key 所有的历史值，按计算的先后顺序排列
+    pub fn history(&self, key: &str) -> Vec<CalculateOption> {
+        match self.env.get(key) {
+            Some(ev) => ev.hist_value.borrow().clone(),
+            None => self
+                .prev
+                .as_ref()
+                .map_or_else(Vec::new, |prev| RefCell::borrow(prev).history(key)),
+        }
+    }
+
+    /// 开始新一轮的计算，之后记录的变量值都属于新的计算批次
+    pub fn next_epoch(&self) {
+        self.epoch.set(self.epoch.get() + 1);
+    }
+
     /// 用于保持向下兼容的函数，后续考虑移除
     pub fn insert(&mut self, key: &str, value: Rc<FormulaNode>) -> Option<EnvValue> {
         self.set(key, value)
     }
 
-    /// 保存当前调用的堆栈信息
-    pub fn set_stack(&self, op: &str, func: &str, args: Vec<Rc<FormulaNode>>) {
-        self.stack.borrow_mut().push(StackInfo {
-            op: op.to_string(),
+    /// 获取当前执行环境的计算配置，子环境与其上级环境共享同一份配置
+    pub fn options(&self) -> Ref<'_, CalculateOptions> {
+        self.options.borrow()
+    }
+
+    /// 修改当前执行环境的计算配置，修改对共享该配置的所有环境生效
+    pub fn set_options(&self, options: CalculateOptions) {
+        *self.options.borrow_mut() = options;
+    }
+
+    /// 获取执行不可信公式时的安全策略
+    pub fn policy(&self) -> Ref<'_, SecurityPolicy> {
+        self.policy.borrow()
+    }
+
+    /// 修改安全策略，修改对共享该策略的所有环境生效
+    pub fn set_policy(&self, policy: SecurityPolicy) {
+        *self.policy.borrow_mut() = policy;
+    }
+
+    /// 是否限制了每次计算的节点个数，限制时需要逐个节点计算以统计个数
+    pub fn is_fuel_limited(&self) -> bool {
+        self.policy.borrow().fuel.is_some()
+    }
+
+    /// 计算一个节点前消耗一份 fuel，超过安全策略的限制时返回该限制
+    pub fn consume_fuel(&self) -> Option<u64> {
+        let limit = self.policy.borrow().fuel?;
+        let mut trace = self.trace.borrow_mut();
+        trace.fuel_used += 1;
+        match trace.fuel_used > limit {
+            true => Some(limit),
+            false => None,
+        }
+    }
+
+    /// 进入一层函数调用，嵌套深度超过安全策略的限制时返回该限制，此时不会进入该调用
+    pub fn enter_call(&self) -> Option<usize> {
+        let limit = self.policy.borrow().max_call_depth;
+        let mut trace = self.trace.borrow_mut();
+        match limit {
+            Some(limit) if trace.call_depth >= limit => Some(limit),
+            _ => {
+                trace.call_depth += 1;
+                None
+            }
+        }
+    }
+
+    /// 退出由 enter_call 进入的函数调用
+    pub fn exit_call(&self) {
+        let mut trace = self.trace.borrow_mut();
+        trace.call_depth = trace.call_depth.saturating_sub(1);
+    }
+
+    /// 开始计算具名表达式 node，node 正在计算中，即其定义直接或间接地引用了自身时返回 false
+    pub fn enter_formula(&self, node: &Rc<FormulaNode>) -> bool {
+        let mut trace = self.trace.borrow_mut();
+        if trace.formulas.iter().any(|f| Rc::ptr_eq(f, node)) {
+            return false;
+        }
+        trace.formulas.push(Rc::clone(node));
+        true
+    }
+
+    /// 结束由 enter_formula 开始的具名表达式的计算
+    pub fn exit_formula(&self) {
+        self.trace.borrow_mut().formulas.pop();
+    }
+
+    /// 创建当前层级的快照，上级环境中的变量不包含在快照中
+    pub fn snapshot(&self) -> EnvSnapshot {
+        EnvSnapshot {
+            env: self.env.clone(),
+            build_in_map: self.build_in_map.clone(),
+        }
+    }
+
+    /// 将当前层级恢复到 snapshot 对应的状态，创建快照之后新增的变量会被移除，
+    /// 恢复过程不会通知变量的监听函数
+    pub fn restore(&mut self, snapshot: EnvSnapshot) {
+        self.env = snapshot.env;
+        self.build_in_map = snapshot.build_in_map;
+        self.layout_changed();
+    }
+
+    /// 将当前层级中变量及函数的定义和最后一次计算得到的值序列化为 JSON，
+    /// 内建函数及上级环境中的变量不会被序列化
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, String> {
+        let entries: alloc::collections::BTreeMap<&String, EnvJsonEntry> = self
+            .env
+            .iter()
+            .map(|(name, ev)| {
+                let entry = EnvJsonEntry {
+                    node: ev.effective_node(),
+                    value: ev.value.borrow().clone(),
+                };
+                (name, entry)
+            })
+            .collect();
+        serde_json::to_string(&entries).map_err(|e| format!("序列化执行环境时出错: {}", e))
+    }
+
+    /// 将 to_json 得到的 JSON 中的定义及值加入当前层级，同名的定义会被覆盖
+    #[cfg(feature = "serde")]
+    pub fn load_json(&mut self, json: &str) -> Result<(), String> {
+        let entries: HashMap<String, EnvJsonEntry> = serde_json::from_str(json)
+            .map_err(|e| format!("从 JSON 中恢复执行环境时出错: {}", e))?;
+        for (name, entry) in entries {
+            self.insert(&name, entry.node);
+            if let Some(ev) = self.env.get(&name) {
+                ev.value.replace(entry.value);
+            }
+        }
+        Ok(())
+    }
+
+    /// 从 to_json 得到的 JSON 中创建新的执行环境
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<EnvType, String> {
+        let env = Env::new();
+        env.borrow_mut().load_json(json)?;
+        Ok(env)
+    }
+
+    /// 清空当前层级的变量及计算过程的记录，以便重复使用该环境进行下一次计算
+    pub fn reset(&mut self) {
+        self.env.clear();
+        self.layout_changed();
+        *self.trace.borrow_mut() = Trace::default();
+    }
+
+    /// 设置计算过程的观察者，为 None 时移除已设置的观察者，对整个环境链生效
+    pub fn set_observer(&self, observer: Option<Rc<dyn EvalObserver>>) {
+        *self.observer.borrow_mut() = ObserverSlot {
+            observer,
+            errors: Vec::new(),
+        };
+    }
+
+    /// 获取计算过程的观察者
+    pub fn observer(&self) -> Option<Rc<dyn EvalObserver>> {
+        self.observer.borrow().observer.clone()
+    }
+
+    /// 通知观察者开始计算节点 node
+    pub fn observe_enter(&self, observer: &dyn EvalObserver, node: &FormulaNode) {
+        self.observer.borrow_mut().errors.push(false);
+        observer.on_enter_node(node, self);
+    }
+
+    /// 通知观察者节点 node 计算完成，value 为错误且其子节点中未通知过错误时通知该错误
+    pub fn observe_exit(
+        &self,
+        observer: &dyn EvalObserver,
+        node: &FormulaNode,
+        value: &CalculateOption,
+    ) {
+        let reported = self.observer.borrow_mut().errors.pop().unwrap_or(false);
+        observer.on_exit_node(node, value, self);
+        if let CalculateOption::Err(message) = value {
+            if !reported {
+                observer.on_error(node, message, self);
+            }
+            if let Some(parent) = self.observer.borrow_mut().errors.last_mut() {
+                *parent = true;
+            }
+        }
+    }
+
+    /// 是否需要记录计算过程，由 CalculateOptions::trace 控制
+    pub fn is_tracing(&self) -> bool {
+        self.options.borrow().trace
+    }
+
+    /// 开始记录节点 expr 的计算过程，之后计算的节点会作为其子节点
+    pub fn trace_enter(&self, expr: String) {
+        self.trace.borrow_mut().frames.push(TraceNode {
+            expr,
+            value: CalculateOption::None,
+            children: Vec::new(),
+        })
+    }
+
+    /// 结束当前节点的记录并保存其计算结果
+    pub fn trace_exit(&self, value: &CalculateOption) {
+        let mut trace = self.trace.borrow_mut();
+        if let Some(mut node) = trace.frames.pop() {
+            node.value = value.clone();
+            match trace.frames.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => trace.root = Some(node),
+            }
+        }
+    }
+
+    /// 是否需要记录函数调用，由 CalculateOptions::record_stack 控制
+    pub fn records_stack(&self) -> bool {
+        self.options.borrow().record_stack
+    }
+
+    /// 开始调用函数 func，之后的函数调用会作为其子调用
+    pub fn call_enter(&self, func: &str, args: &Rc<[Rc<FormulaNode>]>) {
+        if !self.records_stack() {
+            return;
+        }
+        self.trace.borrow_mut().calls.push(CallFrame {
             func: func.to_string(),
-            args,
+            args: Rc::clone(args),
+            arg_values: Vec::new(),
+            value: CalculateOption::None,
+            children: Vec::new(),
         })
     }
 
-    /// 消费自身，得到该 env 调用的堆栈信息
-    pub fn call_stack(&self) -> Vec<StackInfo> {
-        self.stack.borrow().clone()
+    /// 记录当前函数调用的一个参数的计算结果
+    pub fn call_arg(&self, value: &CalculateOption) {
+        if !self.records_stack() {
+            return;
+        }
+        if let Some(frame) = self.trace.borrow_mut().calls.last_mut() {
+            frame.arg_values.push(value.clone());
+        }
+    }
+
+    /// 结束当前的函数调用并保存其返回值
+    pub fn call_exit(&self, value: &CalculateOption) {
+        if !self.records_stack() {
+            return;
+        }
+        let mut trace = self.trace.borrow_mut();
+        if let Some(mut frame) = trace.calls.pop() {
+            frame.value = value.clone();
+            match trace.calls.last_mut() {
+                Some(parent) => parent.children.push(frame),
+                None => trace.call_roots.push(frame),
+            }
+        }
+    }
+
+    /// 取出已完成的最外层函数调用
+    pub fn take_calls(&self) -> Vec<CallFrame> {
+        core::mem::take(&mut self.trace.borrow_mut().call_roots)
+    }
+
+    /// 是否需要统计耗时，由 CalculateOptions::profile 控制
+    pub fn is_profiling(&self) -> bool {
+        self.options.borrow().profile
+    }
+
+    /// 记录函数或具名表达式 name 的一次调用耗时
+    pub fn profile_record(&self, name: &str, elapsed: Duration) {
+        self.trace.borrow_mut().profile.record(name, elapsed)
+    }
+
+    /// 取出本次计算的耗时统计，未开启统计时返回 None
+    pub fn take_profile(&self) -> Option<ProfileReport> {
+        match self.is_profiling() {
+            true => Some(core::mem::take(&mut self.trace.borrow_mut().profile)),
+            false => None,
+        }
+    }
+
+    /// 是否开启了确定性计算模式，由 CalculateOptions::deterministic 控制
+    pub fn is_auditing(&self) -> bool {
+        self.options.borrow().deterministic.is_some()
+    }
+
+    /// 记录确定性计算模式下读取的输入
+    pub fn audit_input(&self, key: &str, value: &CalculateOption) {
+        let mut trace = self.trace.borrow_mut();
+        trace.inputs.insert(key.to_string(), value.clone());
+    }
+
+    /// 取出本次计算读取的输入，按名称排序
+    pub fn take_audit_inputs(&self) -> Vec<(String, CalculateOption)> {
+        core::mem::take(&mut self.trace.borrow_mut().inputs)
+            .into_iter()
+            .collect()
+    }
+
+    /// 取出最近一次完成的计算过程
+    pub fn take_trace(&self) -> Option<TraceNode> {
+        self.trace.borrow_mut().root.take()
     }
 }
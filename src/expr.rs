@@ -0,0 +1,134 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::ops::{Add, Div, Mul, Not, Sub};
+
+use crate::formula::{FormulaNode, OperatorNode};
+
+/// 用于在代码中构造表达式的构建器，支持使用 + - * / ! 等运算符组合表达式，
+/// 得到的 FormulaNode 与解析对应的公式文本所得到的节点相同，无需拼接字符串后再解析，如
+/// `Expr::var("A") + Expr::num(2.0) * Expr::call("Round", vec![Expr::var("B")])`
+#[derive(Debug, Clone)]
+pub struct Expr(FormulaNode);
+
+impl Expr {
+    /// 引用变量 name
+    pub fn var(name: &str) -> Self {
+        Expr(FormulaNode::Variant(name.to_string()))
+    }
+
+    /// 数值常量
+    pub fn num(value: f64) -> Self {
+        Expr(FormulaNode::Constant(value))
+    }
+
+    /// 布尔值常量
+    pub fn bool(value: bool) -> Self {
+        Expr(FormulaNode::Bool(value))
+    }
+
+    /// 以 args 为参数调用函数 name
+    pub fn call(name: &str, args: Vec<Expr>) -> Self {
+        Expr(FormulaNode::FunctionCall {
+            name: name.to_string(),
+            args: args.into_iter().map(|a| Rc::new(a.0)).collect(),
+        })
+    }
+
+    pub fn lt(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::Less { left, right })
+    }
+
+    pub fn le(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::LessEqual { left, right })
+    }
+
+    pub fn gt(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::Great { left, right })
+    }
+
+    pub fn ge(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::GreatEqual { left, right })
+    }
+
+    pub fn eq(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::Equal { left, right })
+    }
+
+    pub fn ne(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::NotEqual { left, right })
+    }
+
+    pub fn and(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::And { left, right })
+    }
+
+    pub fn or(self, rhs: Expr) -> Self {
+        self.binary(rhs, |left, right| OperatorNode::Or { left, right })
+    }
+
+    /// 获取构造得到的表达式节点
+    pub fn into_node(self) -> FormulaNode {
+        self.0
+    }
+
+    fn binary<F>(self, rhs: Expr, op: F) -> Self
+    where
+        F: FnOnce(Box<FormulaNode>, Box<FormulaNode>) -> OperatorNode,
+    {
+        Expr(FormulaNode::Operator(Box::new(op(
+            Box::new(self.0),
+            Box::new(rhs.0),
+        ))))
+    }
+}
+
+impl From<Expr> for FormulaNode {
+    fn from(expr: Expr) -> Self {
+        expr.0
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(value: f64) -> Self {
+        Expr::num(value)
+    }
+}
+
+impl From<bool> for Expr {
+    fn from(value: bool) -> Self {
+        Expr::bool(value)
+    }
+}
+
+macro_rules! impl_binary_op {
+    ($($trait:ident, $method:ident, $op:ident;)*) => {
+        $(
+            impl<T: Into<Expr>> $trait<T> for Expr {
+                type Output = Expr;
+
+                fn $method(self, rhs: T) -> Expr {
+                    self.binary(rhs.into(), |left, right| OperatorNode::$op { left, right })
+                }
+            }
+        )*
+    };
+}
+
+impl_binary_op! {
+    Add, add, Plus;
+    Sub, sub, Minus;
+    Mul, mul, Multiply;
+    Div, div, Divide;
+}
+
+impl Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr(FormulaNode::Operator(Box::new(OperatorNode::Not(
+            Box::new(self.0),
+        ))))
+    }
+}
@@ -0,0 +1,197 @@
+use crate::diagnostic::syntax_diagnostics;
+use crate::document::Document;
+use crate::formula::{FormulaNode, OperatorNode};
+use crate::locale::Locale;
+use crate::token::{self, TokenKind};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// format_formula 的排版配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    /// 每一层缩进的空格数，默认为 4
+    pub indent: usize,
+    /// 函数体及命名空间写在一行时的最大宽度，超过时每个表达式占一行，默认为 80
+    pub max_width: usize,
+    /// 顶层语句是否以 ; 结尾，默认为 false
+    pub semicolons: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: 4,
+            max_width: 80,
+            semicolons: false,
+        }
+    }
+}
+
+/// 将公式源码整理为统一的格式: 每个语句占一行，操作符两侧各有一个空格，逗号之后有一个空格,
+/// 操作符之间没有优先级，因此嵌套的运算总是加上括号，只有 a + b + c 等结合方式不影响结果的连续运算除外,
+/// 语句之间的注释及空行会被保留，包含注释或语法错误的语句原样输出，整理后的源码解析得到相同的语法树
+pub fn format_formula(source: &str, options: FormatOptions) -> String {
+    let document = Document::parse(source, |node| node);
+    let statements = document.statements();
+    let printer = Printer { options: &options };
+    let mut lines: Vec<String> = Vec::new();
+    let mut last = 0;
+    for (index, statement) in statements.iter().enumerate() {
+        gap(&mut lines, &source[last..statement.span.start], false);
+        let text = &source[statement.span.clone()];
+        let mut problems = Vec::new();
+        syntax_diagnostics(
+            &statement.node,
+            &statement.span,
+            Locale::default(),
+            &mut problems,
+        );
+        let verbatim = !problems.is_empty()
+            || token::tokenize(text)
+                .iter()
+                .any(|(kind, _)| *kind == TokenKind::Comment);
+        let mut text = match verbatim {
+            true => text.trim().to_string(),
+            false => printer.write(&statement.node, 0),
+        };
+        // 下一个语句以操作符开头时需要 ; 才能与当前语句分开
+        let next_is_operator = statements.get(index + 1).is_some_and(|next| {
+            token::tokenize(&source[next.span.clone()])
+                .first()
+                .is_some_and(|(kind, _)| *kind == TokenKind::Operator)
+        });
+        if !text.ends_with(';') && (options.semicolons || next_is_operator) {
+            text.push(';');
+        }
+        lines.extend(text.lines().map(|line| line.to_string()));
+        last = statement.span.end;
+    }
+    gap(&mut lines, &source[last..], true);
+    lines.join("\n")
+}
+
+/// 输出两个语句之间的注释，与上一个语句在同一行的注释保持在该行末尾,
+/// 连续的空行合并为一个，文档开头及末尾的空行被去除
+fn gap(lines: &mut Vec<String>, gap: &str, trailing: bool) {
+    let parts: Vec<&str> = gap.split('\n').collect();
+    let rest = parts[0].trim();
+    if !rest.is_empty() {
+        match lines.last_mut() {
+            Some(line) => {
+                line.push(' ');
+                line.push_str(rest);
+            }
+            None => lines.push(rest.to_string()),
+        }
+    }
+    // 最后一部分是下一个语句所在行在语句之前的内容，只有空白
+    let end = match trailing {
+        true => parts.len(),
+        false => parts.len().saturating_sub(1),
+    };
+    let mut blank = false;
+    for part in parts.iter().take(end).skip(1) {
+        match part.trim() {
+            "" => blank = true,
+            comment => {
+                if blank && !lines.is_empty() {
+                    lines.push(String::new());
+                }
+                blank = false;
+                lines.push(comment.to_string());
+            }
+        }
+    }
+    if blank && !trailing && !lines.is_empty() {
+        lines.push(String::new());
+    }
+}
+
+struct Printer<'a> {
+    options: &'a FormatOptions,
+}
+
+impl Printer<'_> {
+    /// 输出 node，除第一行以外的行都带有完整的缩进，level 为 node 所在的缩进层级
+    fn write(&self, node: &FormulaNode, level: usize) -> String {
+        let join = |nodes: &[alloc::rc::Rc<FormulaNode>]| {
+            nodes
+                .iter()
+                .map(|node| self.write(node, level))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match node {
+            FormulaNode::Operator(op) => self.operator(op, level),
+            FormulaNode::FunctionCall { name, args } => format!("{}({})", name, join(args)),
+            FormulaNode::Function {
+                name,
+                args,
+                expressions,
+            } => self.block(&format!("{}({})", name, join(args)), expressions, level),
+            FormulaNode::Namespace { name, definitions } => {
+                self.block(&format!("namespace {}", name), definitions, level)
+            }
+            FormulaNode::Formula { name, formula } => {
+                format!("{} := {}", name, self.write(formula, level))
+            }
+            FormulaNode::Arg { name, value } => format!("{} := {}", name, self.write(value, level)),
+            FormulaNode::Quote(node) => self.write(node, level),
+            node => node.to_string(),
+        }
+    }
+
+    /// 输出函数体及命名空间，宽度不超过 max_width 时写在一行
+    fn block(&self, head: &str, body: &[alloc::rc::Rc<FormulaNode>], level: usize) -> String {
+        if body.is_empty() {
+            return format!("{} {{}}", head);
+        }
+        let inner: Vec<String> = body.iter().map(|n| self.write(n, level + 1)).collect();
+        let line = format!("{} {{ {} }}", head, inner.join("; "));
+        let width = level * self.options.indent + line.chars().count();
+        if width <= self.options.max_width && !line.contains('\n') {
+            return line;
+        }
+        let indent = " ".repeat((level + 1) * self.options.indent);
+        let inner: Vec<String> = inner.iter().map(|n| format!("{}{}", indent, n)).collect();
+        let outer = " ".repeat(level * self.options.indent);
+        format!("{} {{\n{}\n{}}}", head, inner.join(";\n"), outer)
+    }
+
+    fn operator(&self, op: &OperatorNode, level: usize) -> String {
+        if let OperatorNode::Not(node) = op {
+            return format!("!{}", self.operand(node, false, level));
+        }
+        let operands = op.operands();
+        let (left, right) = (operands[0], operands[1]);
+        let kind = |node: &FormulaNode| match node.unquoted() {
+            FormulaNode::Operator(op) => Some(core::mem::discriminant(op.as_ref())),
+            _ => None,
+        };
+        let same = |node: &FormulaNode| kind(node) == Some(core::mem::discriminant(op));
+        // 逻辑运算从左向右结合，四则运算及乘方从右向左结合，相同的运算连续出现时不需要括号
+        let (left_bare, right_bare) = match op {
+            OperatorNode::And { .. } | OperatorNode::Or { .. } => (same(left), false),
+            OperatorNode::Plus { .. }
+            | OperatorNode::Multiply { .. }
+            | OperatorNode::Power { .. } => (false, same(right)),
+            _ => (false, false),
+        };
+        format!(
+            "{} {} {}",
+            self.operand(left, left_bare, level),
+            op.symbol(),
+            self.operand(right, right_bare, level)
+        )
+    }
+
+    /// 输出操作数，bare 为 false 时除逻辑非以外的运算加上括号
+    fn operand(&self, node: &FormulaNode, bare: bool, level: usize) -> String {
+        match node.unquoted() {
+            FormulaNode::Operator(op) if !bare && !matches!(op.as_ref(), OperatorNode::Not(_)) => {
+                format!("({})", self.operator(op, level))
+            }
+            node => self.write(node, level),
+        }
+    }
+}
@@ -1,28 +1,183 @@
-use crate::calculator::CalculateOption;
+use crate::calculator::{to_float, CalculateOption, FormulaCalc};
+use crate::complex::Complex;
 use crate::env::EnvType;
-use std::rc::Rc;
+use crate::matrix::Matrix;
+use crate::money::Money;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::hash::{Hash, Hasher};
 
 /// 内建函数的函数上下文，保存了传递给该函数的所有参数信息, 以及当前执行函数的环境信息, 可修改当前环境变量，
 pub struct FuncContext {
-    pub args: Vec<Rc<FormulaNode>>,
+    pub args: Rc<[Rc<FormulaNode>]>,
     pub env: EnvType,
 }
 
+/// 要求第 i 个参数的计算结果 value 为数值，与 FuncContext::arg_num 返回相同的错误,
+/// 也用于 calculate_par 在当前线程中计算并行调用的参数
+pub(crate) fn num_arg(i: usize, value: CalculateOption) -> Result<f64, CalcError> {
+    match to_float(value) {
+        CalculateOption::Num(n) => Ok(n),
+        CalculateOption::Err(e) => Err(CalcError::arg(i, e)),
+        v => Err(CalcError::arg(i, format!("应为数值，实际为 {:?}", v))),
+    }
+}
+
 impl FuncContext {
-    pub fn new(args: &Vec<Rc<FormulaNode>>, env: EnvType) -> Self {
-        FuncContext {
-            args: args.iter().map(|f| Rc::clone(f)).collect(),
-            env,
+    pub fn new(args: Rc<[Rc<FormulaNode>]>, env: EnvType) -> Self {
+        FuncContext { args, env }
+    }
+
+    /// 检查参数个数是否为 n
+    pub fn args_len_must(&self, n: usize) -> Result<(), CalcError> {
+        self.args_len_between(n, Some(n))
+    }
+
+    /// 检查参数个数是否在 [min, max] 之间，max 为 None 时不限制上限
+    pub fn args_len_between(&self, min: usize, max: Option<usize>) -> Result<(), CalcError> {
+        let len = self.args.len();
+        let expected = match max {
+            Some(max) if min == max => format!("{} 个", min),
+            Some(max) => format!("{} 到 {} 个", min, max),
+            None => format!("至少 {} 个", min),
+        };
+        match len >= min && max.is_none_or(|max| len <= max) {
+            true => Ok(()),
+            false => Err(CalcError::new(format!(
+                "需要 {}参数，实际为 {} 个",
+                expected, len
+            ))),
+        }
+    }
+
+    /// 计算第 i 个参数，参数不存在或计算结果为错误时返回错误，分数模式下的分数转换为数值
+    pub fn arg(&self, i: usize) -> Result<CalculateOption, CalcError> {
+        match self.args.get(i).map(|arg| to_float(arg.calc(&self.env))) {
+            Some(CalculateOption::Err(e)) => Err(CalcError::arg(i, e)),
+            Some(value) => Ok(value),
+            None => Err(CalcError::arg(i, "缺少该参数")),
+        }
+    }
+
+    /// 计算第 i 个参数并要求其结果为数值
+    pub fn arg_num(&self, i: usize) -> Result<f64, CalcError> {
+        self.arg(i).and_then(|value| num_arg(i, value))
+    }
+
+    /// 计算第 i 个参数并要求其结果为布尔值
+    pub fn arg_bool(&self, i: usize) -> Result<bool, CalcError> {
+        match self.arg(i)? {
+            CalculateOption::Bool(b) => Ok(b),
+            v => Err(CalcError::arg(i, format!("应为布尔值，实际为 {:?}", v))),
+        }
+    }
+
+    /// 计算第 i 个参数并要求其结果为文本
+    pub fn arg_text(&self, i: usize) -> Result<String, CalcError> {
+        match self.arg(i)? {
+            CalculateOption::Text(t) => Ok(t),
+            v => Err(CalcError::arg(i, format!("应为文本，实际为 {:?}", v))),
+        }
+    }
+
+    /// 计算第 i 个参数并要求其结果为货币金额
+    pub fn arg_money(&self, i: usize) -> Result<Money, CalcError> {
+        match self.arg(i)? {
+            CalculateOption::Money(m) => Ok(m),
+            v => Err(CalcError::arg(i, format!("应为货币金额，实际为 {:?}", v))),
+        }
+    }
+
+    /// 计算第 i 个参数并要求其结果为函数值
+    pub fn arg_func(&self, i: usize) -> Result<Rc<FormulaNode>, CalcError> {
+        match self.arg(i)? {
+            CalculateOption::Function(f) => Ok(f),
+            v => Err(CalcError::arg(i, format!("应为函数，实际为 {:?}", v))),
+        }
+    }
+
+    /// 计算第 i 个参数并要求其结果为 T 类型的宿主对象，即其他内建函数以 CalculateOption::opaque 返回的值
+    pub fn arg_opaque<T: Any>(&self, i: usize) -> Result<Rc<T>, CalcError> {
+        match self.arg(i)? {
+            CalculateOption::Opaque(v) => v.downcast::<T>().map_err(|_| {
+                CalcError::arg(
+                    i,
+                    format!("应为 {} 类型的宿主对象", core::any::type_name::<T>()),
+                )
+            }),
+            v => Err(CalcError::arg(i, format!("应为宿主对象，实际为 {:?}", v))),
+        }
+    }
+
+    /// 获取第 i 个参数书写的名称而不计算该参数，如 prev(A) 中的 A，参数不是变量名时返回错误
+    pub fn arg_str(&self, i: usize) -> Result<&str, CalcError> {
+        match self.args.get(i).map(|arg| arg.as_ref()) {
+            Some(FormulaNode::Variant(name)) => Ok(name),
+            Some(_) => Err(CalcError::arg(i, "应为变量名")),
+            None => Err(CalcError::arg(i, "缺少该参数")),
+        }
+    }
+}
+
+/// 内建函数返回的错误，arg 为出错的参数位置 (从 0 开始)，与参数无关的错误为 None
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalcError {
+    pub message: String,
+    pub arg: Option<usize>,
+}
+
+impl CalcError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        CalcError {
+            message: message.into(),
+            arg: None,
+        }
+    }
+
+    /// 第 index 个参数 (从 0 开始) 出错
+    pub fn arg<S: Into<String>>(index: usize, message: S) -> Self {
+        CalcError {
+            message: message.into(),
+            arg: Some(index),
+        }
+    }
+}
+
+impl core::fmt::Display for CalcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.arg {
+            Some(index) => write!(f, "第 {} 个参数错误: {}", index + 1, self.message),
+            None => write!(f, "{}", self.message),
         }
     }
 }
 
+impl core::error::Error for CalcError {}
+
+/// 内建函数的描述信息，用于交互式环境中的自动补全及帮助信息
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuiltinInfo {
+    pub name: String,
+    /// 函数的功能说明
+    pub description: String,
+    /// 参数名称
+    pub params: Vec<String>,
+    /// 调用示例
+    pub examples: Vec<String>,
+    /// 参数个数的范围 (min, max)，max 为 None 表示不限制上限，为 None 时表示未声明
+    pub arity: Option<(usize, Option<usize>)>,
+}
+
 /// 内建函数的声明
-pub type BuildInFunctionType = dyn Fn(&FuncContext) -> CalculateOption;
+pub type BuildInFunctionType = dyn Fn(&FuncContext) -> Result<CalculateOption, CalcError>;
 
 /// 解析公式的节点类型，可能有变量、常量、操作符、嵌套的公式类型等
 /// 2019-10-20 加入自定义函数，可以将 Rust 的函数注册到脚本中
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FormulaNode {
     /// 变量节点，可以是定义变量，也可能是引用变量,
     /// 变量可用于所有的计算场景，及作为函数的参数及返回值
@@ -30,16 +185,48 @@ pub enum FormulaNode {
     /// 常量节点
     /// 定义了在表达式中固定的值
     Constant(f64),
+    /// 复数常量节点
+    /// 由形如 `2i` 的虚数常量解析得到，或由函数参数绑定的复数计算结果生成
+    Complex(Complex),
+    /// 数值向量节点
+    /// 通常由宿主程序将一整列数据绑定到变量上，四则运算时会逐元素计算
+    Vector(Vec<f64>),
+    /// 矩阵常量节点
+    /// 由形如 `[[1, 2], [3, 4]]` 的矩阵常量解析得到，或由函数参数绑定的矩阵计算结果生成
+    Matrix(Matrix),
+    /// 文本常量节点
+    /// 由形如 `"0.00"` 的字符串常量解析得到，或由 Format 等函数的文本结果生成
+    Text(String),
     /// 布尔值节点
     /// 定义逻辑计算的结果
     Bool(bool),
+    /// 货币金额节点
+    /// 由函数参数绑定的金额计算结果生成，输出为 Money(amount, "currency") 的形式
+    Money(Money),
+    /// 宿主对象节点
+    /// 由函数参数绑定的 CalculateOption::Opaque 结果生成，公式中无法直接书写
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Opaque(Rc<dyn Any>),
+    /// 历史值节点，由 A@-1 的形式解析得到
+    /// 表示变量 @name 在之前的计算中得到的第 @back 个值
+    History {
+        name: String,
+        back: usize,
+    },
+    /// 下标访问节点，由 V[i] 或 V[start:end] 的形式解析得到
+    /// 从 @target 的计算结果中取出 @index 对应的元素或片段
+    Index {
+        target: Box<FormulaNode>,
+        index: Subscript,
+    },
     /// 操作符节点，定义了常用的数学及逻辑操作符
     Operator(Box<OperatorNode>),
     /// 函数调用
-    /// 用来描述当前需要调用的函数信息，包括 @param name 函数名， @args 调用该函数所传递的参数
+    /// 用来描述当前需要调用的函数信息，包括 @param name 函数名， @args 调用该函数所传递的参数,
+    /// 参数以共享的切片保存，调用时传递给内建函数及记录调用过程都只需增加引用计数
     FunctionCall {
         name: String,
-        args: Vec<Rc<FormulaNode>>,
+        args: Rc<[Rc<FormulaNode>]>,
     },
     /// 函数定义
     /// 定义了函数的 @name 名称， @args 函数的参数信息，以及 @expressions 函数体，
@@ -66,6 +253,14 @@ pub enum FormulaNode {
         name: String,
         formula: Rc<FormulaNode>,
     },
+    /// 命名空间节点，由 namespace 名称 { 定义; ... } 解析得到
+    /// 其中的命名表达式及函数会以 @name.定义名 的形式加入执行环境
+    Namespace {
+        name: String,
+        definitions: Vec<Rc<FormulaNode>>,
+    },
+    /// 引用其他公式文件，由 import "文件名"; 解析得到，解析时会加载该文件中的定义
+    Import(String),
     /// 未知节点，说明表达式出错
     UnKnow(String),
     /// 括号节点，用来明确表示表达式的优先级
@@ -73,7 +268,359 @@ pub enum FormulaNode {
     None,
 }
 
-#[derive(Debug, Clone)]
+impl FormulaNode {
+    /// 将节点中对 old 的所有引用重命名为 new，得到新的节点，
+    /// 如果函数的参数或函数体中的局部变量与 old 同名，该函数体中的引用不会被修改
+    pub fn rename(&self, old: &str, new: &str) -> FormulaNode {
+        let rename = |name: &String| match name == old {
+            true => new.to_string(),
+            false => name.clone(),
+        };
+        match self {
+            FormulaNode::Variant(name) => FormulaNode::Variant(rename(name)),
+            FormulaNode::History { name, back } => FormulaNode::History {
+                name: rename(name),
+                back: *back,
+            },
+            FormulaNode::Index { target, index } => FormulaNode::Index {
+                target: Box::new(target.rename(old, new)),
+                index: index.map_nodes(|n| n.rename(old, new)),
+            },
+            FormulaNode::Operator(op) => {
+                FormulaNode::Operator(Box::new(op.map_operands(|n| n.rename(old, new))))
+            }
+            FormulaNode::FunctionCall { name, args } => FormulaNode::FunctionCall {
+                name: rename(name),
+                args: args.iter().map(|a| Rc::new(a.rename(old, new))).collect(),
+            },
+            FormulaNode::Function {
+                name,
+                args,
+                expressions,
+            } => {
+                // 只有参数及函数体中的局部定义会遮蔽 old，函数体中对 old 的直接引用仍需重命名
+                let shadowed = args
+                    .iter()
+                    .any(|n| matches!(n.as_ref(), FormulaNode::Variant(a) if a == old))
+                    || expressions.iter().any(
+                        |n| matches!(n.as_ref(), FormulaNode::Formula { name, .. } if name == old),
+                    );
+                FormulaNode::Function {
+                    name: rename(name),
+                    args: args.clone(),
+                    expressions: match shadowed {
+                        true => expressions.clone(),
+                        false => expressions
+                            .iter()
+                            .map(|e| Rc::new(e.rename(old, new)))
+                            .collect(),
+                    },
+                }
+            }
+            FormulaNode::BuildInFunction { func } => {
+                FormulaNode::BuildInFunction { func: rename(func) }
+            }
+            FormulaNode::Arg { name, value } => FormulaNode::Arg {
+                name: name.clone(),
+                value: Box::new(value.rename(old, new)),
+            },
+            FormulaNode::Formula { name, formula } => FormulaNode::Formula {
+                name: rename(name),
+                formula: Rc::new(formula.rename(old, new)),
+            },
+            FormulaNode::Quote(node) => FormulaNode::Quote(Box::new(node.rename(old, new))),
+            FormulaNode::Namespace { name, definitions } => FormulaNode::Namespace {
+                name: name.clone(),
+                definitions: definitions
+                    .iter()
+                    .map(|d| Rc::new(d.rename(old, new)))
+                    .collect(),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// 修改命名节点 (命名表达式、函数及内建函数) 自身的名称，不修改其内部的引用
+    pub fn with_name(&self, new: &str) -> FormulaNode {
+        match self {
+            FormulaNode::Formula { formula, .. } => FormulaNode::Formula {
+                name: new.to_string(),
+                formula: Rc::clone(formula),
+            },
+            FormulaNode::Function {
+                args, expressions, ..
+            } => FormulaNode::Function {
+                name: new.to_string(),
+                args: args.clone(),
+                expressions: expressions.clone(),
+            },
+            FormulaNode::BuildInFunction { .. } => FormulaNode::BuildInFunction {
+                func: new.to_string(),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// 去掉外层的括号节点
+    pub fn unquoted(&self) -> &FormulaNode {
+        match self {
+            FormulaNode::Quote(node) => node.unquoted(),
+            node => node,
+        }
+    }
+
+    /// 是否为函数定义或内建函数，即可以被调用的节点
+    pub fn is_function(&self) -> bool {
+        matches!(
+            self,
+            FormulaNode::Function { .. } | FormulaNode::BuildInFunction { .. }
+        )
+    }
+
+    /// 是否为常量节点，即不依赖执行环境即可得到结果的字面量
+    pub fn is_constant(&self) -> bool {
+        matches!(
+            self,
+            FormulaNode::Constant(_)
+                | FormulaNode::Complex(_)
+                | FormulaNode::Vector(_)
+                | FormulaNode::Matrix(_)
+                | FormulaNode::Text(_)
+                | FormulaNode::Bool(_)
+                | FormulaNode::Money(_)
+                | FormulaNode::Opaque(_)
+        )
+    }
+}
+
+/// 将语法树输出为规范的公式文本，所有的操作符表达式都会加上括号以明确计算的优先级
+impl core::fmt::Display for FormulaNode {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let join = |nodes: &[Rc<FormulaNode>], sep: &str| {
+            nodes
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(sep)
+        };
+        match self {
+            FormulaNode::Variant(name) => write!(f, "{}", name),
+            FormulaNode::Constant(v) => write!(f, "{}", v),
+            FormulaNode::Complex(c) if c.re == 0.0 => write!(f, "{}i", c.im),
+            FormulaNode::Complex(c) => write!(f, "({} + {}i)", c.re, c.im),
+            FormulaNode::Vector(v) => write!(
+                f,
+                "[{}]",
+                v.iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            FormulaNode::Matrix(m) => write!(
+                f,
+                "[{}]",
+                (0..m.rows())
+                    .map(|i| FormulaNode::Vector(m.row(i).to_vec()).to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            FormulaNode::Text(t) => write!(f, "\"{}\"", t),
+            FormulaNode::Bool(b) => write!(f, "{}", b),
+            FormulaNode::Money(m) => write!(f, "Money({}, \"{}\")", m.amount, m.currency),
+            FormulaNode::Opaque(_) => write!(f, "<opaque>"),
+            FormulaNode::History { name, back } => write!(f, "{}@-{}", name, back),
+            FormulaNode::Index { target, index } => write!(f, "{}[{}]", target, index),
+            FormulaNode::Operator(op) => write!(f, "{}", op),
+            FormulaNode::FunctionCall { name, args } => {
+                write!(f, "{}({})", name, join(args, ", "))
+            }
+            FormulaNode::Function {
+                name,
+                args,
+                expressions,
+            } => write!(
+                f,
+                "{}({}) {{ {} }}",
+                name,
+                join(args, ", "),
+                join(expressions, "; ")
+            ),
+            FormulaNode::BuildInFunction { func } => write!(f, "{}", func),
+            FormulaNode::Arg { name, value } => write!(f, "{} := {}", name, value),
+            FormulaNode::Formula { name, formula } => write!(f, "{} := {}", name, formula),
+            FormulaNode::Namespace { name, definitions } => {
+                write!(f, "namespace {} {{ {} }}", name, join(definitions, "; "))
+            }
+            FormulaNode::Import(path) => write!(f, "import \"{}\"", path),
+            FormulaNode::UnKnow(msg) => write!(f, "<错误: {}>", msg),
+            // 操作符表达式已经带有括号
+            FormulaNode::Quote(node) => write!(f, "{}", node),
+            FormulaNode::None => Ok(()),
+        }
+    }
+}
+
+/// 语义上的相等，括号节点与其内部的节点相等，数值按其二进制表示比较，因此 NaN 与自身相等
+impl PartialEq for FormulaNode {
+    fn eq(&self, other: &Self) -> bool {
+        use FormulaNode::*;
+        let bits = |v: &[f64]| v.iter().map(|f| f.to_bits()).collect::<Vec<_>>();
+        match (self.unquoted(), other.unquoted()) {
+            (Variant(a), Variant(b)) => a == b,
+            (Constant(a), Constant(b)) => a.to_bits() == b.to_bits(),
+            (Complex(a), Complex(b)) => bits(&[a.re, a.im]) == bits(&[b.re, b.im]),
+            (Vector(a), Vector(b)) => bits(a) == bits(b),
+            (Matrix(a), Matrix(b)) => a.cols() == b.cols() && bits(a.data()) == bits(b.data()),
+            (Text(a), Text(b)) => a == b,
+            (Bool(a), Bool(b)) => a == b,
+            (Money(a), Money(b)) => {
+                a.amount.to_bits() == b.amount.to_bits() && a.currency == b.currency
+            }
+            (Opaque(a), Opaque(b)) => Rc::ptr_eq(a, b),
+            (
+                History { name, back },
+                History {
+                    name: name2,
+                    back: back2,
+                },
+            ) => name == name2 && back == back2,
+            (
+                Index { target, index },
+                Index {
+                    target: t,
+                    index: i,
+                },
+            ) => target == t && index == i,
+            (Operator(a), Operator(b)) => a == b,
+            (FunctionCall { name, args }, FunctionCall { name: n, args: a }) => {
+                name == n && args == a
+            }
+            (
+                Function {
+                    name,
+                    args,
+                    expressions,
+                },
+                Function {
+                    name: n,
+                    args: a,
+                    expressions: e,
+                },
+            ) => name == n && args == a && expressions == e,
+            (BuildInFunction { func }, BuildInFunction { func: f }) => func == f,
+            (Arg { name, value }, Arg { name: n, value: v }) => name == n && value == v,
+            (
+                Formula { name, formula },
+                Formula {
+                    name: n,
+                    formula: f,
+                },
+            ) => name == n && formula == f,
+            (
+                Namespace { name, definitions },
+                Namespace {
+                    name: n,
+                    definitions: d,
+                },
+            ) => name == n && definitions == d,
+            (Import(a), Import(b)) | (UnKnow(a), UnKnow(b)) => a == b,
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for FormulaNode {}
+
+/// 与 PartialEq 一致，括号节点的哈希值与其内部的节点相同
+impl Hash for FormulaNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let node = self.unquoted();
+        core::mem::discriminant(node).hash(state);
+        match node {
+            FormulaNode::Variant(name)
+            | FormulaNode::BuildInFunction { func: name }
+            | FormulaNode::Import(name)
+            | FormulaNode::Text(name)
+            | FormulaNode::UnKnow(name) => name.hash(state),
+            FormulaNode::Constant(f) => f.to_bits().hash(state),
+            FormulaNode::Complex(c) => (c.re.to_bits(), c.im.to_bits()).hash(state),
+            FormulaNode::Vector(v) => {
+                v.len().hash(state);
+                v.iter().for_each(|f| f.to_bits().hash(state));
+            }
+            FormulaNode::Matrix(m) => {
+                (m.rows(), m.cols()).hash(state);
+                m.data().iter().for_each(|f| f.to_bits().hash(state));
+            }
+            FormulaNode::Bool(b) => b.hash(state),
+            FormulaNode::Money(m) => (m.amount.to_bits(), &m.currency).hash(state),
+            FormulaNode::Opaque(v) => Rc::as_ptr(v).cast::<()>().hash(state),
+            FormulaNode::History { name, back } => (name, back).hash(state),
+            FormulaNode::Index { target, index } => (target, index).hash(state),
+            FormulaNode::Operator(op) => op.hash(state),
+            FormulaNode::FunctionCall { name, args } => (name, args).hash(state),
+            FormulaNode::Function {
+                name,
+                args,
+                expressions,
+            } => (name, args, expressions).hash(state),
+            FormulaNode::Arg { name, value } => (name, value).hash(state),
+            FormulaNode::Formula { name, formula } => (name, formula).hash(state),
+            FormulaNode::Namespace { name, definitions } => (name, definitions).hash(state),
+            FormulaNode::Quote(_) | FormulaNode::None => (),
+        }
+    }
+}
+
+/// 下标访问节点中的下标，下标从 0 开始，负数表示从末尾开始计数，如 V[-1] 为最后一个元素
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Subscript {
+    /// V[i] 取出第 i 个元素
+    At(Box<FormulaNode>),
+    /// V[start:end] 取出从 start 到 end (不包含 end) 的元素组成的向量，省略时分别为开头及末尾
+    Slice(Option<Box<FormulaNode>>, Option<Box<FormulaNode>>),
+}
+
+impl Subscript {
+    /// 下标中的表达式，省略的部分不包含在内
+    pub fn nodes(&self) -> Vec<&FormulaNode> {
+        match self {
+            Subscript::At(node) => vec![node],
+            Subscript::Slice(start, end) => start.iter().chain(end.iter()).map(|n| &**n).collect(),
+        }
+    }
+
+    /// 对下标中的每个表达式调用 f，得到新的下标
+    pub fn map_nodes<F>(&self, mut f: F) -> Subscript
+    where
+        F: FnMut(&FormulaNode) -> FormulaNode,
+    {
+        match self {
+            Subscript::At(node) => Subscript::At(Box::new(f(node))),
+            Subscript::Slice(start, end) => Subscript::Slice(
+                start.as_ref().map(|n| Box::new(f(n))),
+                end.as_ref().map(|n| Box::new(f(n))),
+            ),
+        }
+    }
+}
+
+impl core::fmt::Display for Subscript {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let text = |node: &Option<Box<FormulaNode>>| {
+            node.as_ref().map_or(String::new(), |n| n.to_string())
+        };
+        match self {
+            Subscript::At(node) => write!(f, "{}", node),
+            Subscript::Slice(start, end) => write!(f, "{}:{}", text(start), text(end)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// 数学及逻辑操作符节点,
 /// 其中包括了简单的算术操作：加减乘除，及逻辑操作：大于，大于等于，小于，小于等于，等于，不等于, 及取反
 pub enum OperatorNode {
@@ -97,6 +644,11 @@ pub enum OperatorNode {
         left: Box<FormulaNode>,
         right: Box<FormulaNode>,
     },
+    /// 乘方操作节点，如 2 ^ 3
+    Power {
+        left: Box<FormulaNode>,
+        right: Box<FormulaNode>,
+    },
     /// 小于操作节点
     Less {
         left: Box<FormulaNode>,
@@ -122,6 +674,11 @@ pub enum OperatorNode {
         left: Box<FormulaNode>,
         right: Box<FormulaNode>,
     },
+    /// 不等于操作节点
+    NotEqual {
+        left: Box<FormulaNode>,
+        right: Box<FormulaNode>,
+    },
     /// 取反操作节点
     Not(Box<FormulaNode>),
 
@@ -136,3 +693,77 @@ pub enum OperatorNode {
         right: Box<FormulaNode>,
     },
 }
+
+impl OperatorNode {
+    /// 获取操作符的所有操作数
+    pub fn operands(&self) -> Vec<&FormulaNode> {
+        match self {
+            OperatorNode::Not(node) => vec![node],
+            OperatorNode::Plus { left, right }
+            | OperatorNode::Minus { left, right }
+            | OperatorNode::Divide { left, right }
+            | OperatorNode::Multiply { left, right }
+            | OperatorNode::Power { left, right }
+            | OperatorNode::Less { left, right }
+            | OperatorNode::LessEqual { left, right }
+            | OperatorNode::Great { left, right }
+            | OperatorNode::GreatEqual { left, right }
+            | OperatorNode::Equal { left, right }
+            | OperatorNode::NotEqual { left, right }
+            | OperatorNode::And { left, right }
+            | OperatorNode::Or { left, right } => vec![left, right],
+        }
+    }
+
+    /// 操作符在公式中的写法，= 以 == 表示
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            OperatorNode::Plus { .. } => "+",
+            OperatorNode::Minus { .. } => "-",
+            OperatorNode::Divide { .. } => "/",
+            OperatorNode::Multiply { .. } => "*",
+            OperatorNode::Power { .. } => "^",
+            OperatorNode::Less { .. } => "<",
+            OperatorNode::LessEqual { .. } => "<=",
+            OperatorNode::Great { .. } => ">",
+            OperatorNode::GreatEqual { .. } => ">=",
+            OperatorNode::Equal { .. } => "==",
+            OperatorNode::NotEqual { .. } => "!=",
+            OperatorNode::And { .. } => "&&",
+            OperatorNode::Or { .. } => "||",
+            OperatorNode::Not(_) => "!",
+        }
+    }
+
+    /// 使用 f 转换所有的操作数，得到操作符相同的新节点
+    pub fn map_operands<F>(&self, mut f: F) -> OperatorNode
+    where
+        F: FnMut(&FormulaNode) -> FormulaNode,
+    {
+        macro_rules! map_binary {
+            ($($op:ident),*) => {
+                match self {
+                    OperatorNode::Not(node) => OperatorNode::Not(Box::new(f(node))),
+                    $(OperatorNode::$op { left, right } => OperatorNode::$op {
+                        left: Box::new(f(left)),
+                        right: Box::new(f(right)),
+                    },)*
+                }
+            };
+        }
+        map_binary!(
+            Plus, Minus, Divide, Multiply, Power, Less, LessEqual, Great, GreatEqual, Equal,
+            NotEqual, And, Or
+        )
+    }
+}
+
+impl core::fmt::Display for OperatorNode {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.operands().as_slice() {
+            [node] => write!(f, "{}{}", self.symbol(), node),
+            [left, right] => write!(f, "({} {} {})", left, self.symbol(), right),
+            _ => Ok(()),
+        }
+    }
+}
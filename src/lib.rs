@@ -1,12 +1,77 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[macro_use]
+extern crate alloc;
+
+pub mod analyze;
+pub mod arith;
+pub mod audit;
+pub mod build_in;
+pub mod cache;
 pub mod calculator;
+pub mod cell;
+pub mod clock;
+pub mod compat;
+pub mod compiled;
+pub mod complex;
+#[cfg(feature = "parallel")]
+pub mod concurrent;
+pub mod csv;
+pub mod debugger;
+pub mod diagnostic;
+pub mod dialect;
+pub mod diff;
+pub mod document;
 pub mod env;
+pub mod expr;
+pub mod format;
 pub mod formula;
+pub mod lint;
+pub mod locale;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod matrix;
+pub mod money;
+pub mod object;
 pub mod parser;
+pub mod plugin;
+pub mod policy;
+/// 常用类型的统一导出，`use formula_parser::prelude::*;` 即可引入解析及计算表达式所需的类型
+pub mod prelude;
+pub mod provenance;
+#[cfg(feature = "rational")]
+pub mod rational;
+pub mod rounding;
+pub mod scanner;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy;
+#[cfg(feature = "std")]
+pub mod stream;
+pub mod syntax;
+pub mod token;
+pub mod typecheck;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 #[cfg(test)]
+// 部分测试以 String 调用 parse 及 calculate，用于确认以 String 为参数的调用方式仍然可用
+#[allow(clippy::unnecessary_to_owned)]
 mod test {
-    use crate::calculator::{CalculateOption, FormulaCalc};
+    use crate::calculator::{CalculateOption, CalculateOptions, FormulaCalc};
+    use crate::compat::HashMap;
+    use crate::complex::Complex;
+    use crate::env::{Env, EnvChange, EnvEntryKind, EnvError};
+    use crate::formula;
+    use crate::formula::BuiltinInfo;
+    use crate::locale::{ErrorCode, Locale};
     use crate::parser;
+    use crate::plugin::FormulaPlugin;
+    use std::cell::RefCell;
+    use std::convert::TryFrom;
+    use std::rc::Rc;
+    use std::time::Duration;
 
     #[test]
     fn test_parser() {
@@ -27,11 +92,11 @@ mod test {
         parser.reg_build_in("Add", |c| {
             assert_eq!(c.args.len(), 2);
             match (
-                c.args.get(0).unwrap().calc(&c.env),
+                c.args.first().unwrap().calc(&c.env),
                 c.args.get(1).unwrap().calc(&c.env),
             ) {
                 (CalculateOption::Num(f1), CalculateOption::Num(f2)) => {
-                    return CalculateOption::Num(f1 + f2);
+                    CalculateOption::Num(f1 + f2)
                 }
                 _ => {
                     panic!("Add 函数接收了错误的参数信息");
@@ -39,9 +104,3636 @@ mod test {
             }
         });
         let result = parser.calculate("Add(A, B)".to_string());
-        assert_eq!(result.value, CalculateOption::Num(4.0));
+        assert_eq!(result.value, CalculateOption::Num(3.0));
+    }
+
+    #[test]
+    fn test_fallible_build_in() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 1; B := 2;".to_string());
+
+        parser
+            .reg_fallible_build_in("Div", |c| {
+                let divisor = c.arg_num(1)?;
+                if divisor == 0.0 {
+                    return Err(formula::CalcError::arg(1, "除数不能为 0"));
+                }
+                Ok(CalculateOption::Num(c.arg_num(0)? / divisor))
+            })
+            .unwrap();
+        assert_eq!(
+            parser.calculate("Div(B, A)").value,
+            CalculateOption::Num(2.0)
+        );
+
+        match parser.calculate("Div(A, 0)").value {
+            CalculateOption::Err(e) => {
+                assert!(
+                    e.contains("Div") && e.contains("第 2 个参数") && e.contains("除数不能为 0")
+                )
+            }
+            v => panic!("函数返回错误时应返回错误，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_arg_helpers() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 1; B := 2;".to_string());
+
+        parser
+            .reg_fallible_build_in("Add", |c| {
+                c.args_len_must(2)?;
+                Ok(CalculateOption::Num(c.arg_num(0)? + c.arg_num(1)?))
+            })
+            .unwrap();
+        parser
+            .reg_fallible_build_in("Pick", |c| {
+                c.args_len_between(2, Some(3))?;
+                match c.arg_bool(0)? {
+                    true => Ok(CalculateOption::Text(c.arg_text(1)?)),
+                    false => c.arg(2),
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            parser.calculate("Add(A, B)").value,
+            CalculateOption::Num(3.0)
+        );
+        assert_eq!(
+            parser.calculate("Pick(A > 0, \"是\")").value,
+            CalculateOption::Text("是".to_string())
+        );
+        assert_eq!(
+            parser.calculate("Pick(A < 0, \"是\", B)").value,
+            CalculateOption::Num(2.0)
+        );
+
+        for (formula, expected) in [
+            ("Add(A, 1 > 0)", "第 2 个参数"),
+            ("Add(A)", "需要 2 个参数，实际为 1 个"),
+            ("Pick(A)", "需要 2 到 3 个参数，实际为 1 个"),
+            ("Pick(A, \"是\")", "第 1 个参数"),
+            ("Pick(A < 0, \"是\")", "第 3 个参数"),
+        ] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(e) => assert!(e.contains(expected), "{}: {}", formula, e),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_complex() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Z := 3 + 2i".to_string());
+        assert_eq!(
+            parser.calculate("Z * 2i".to_string()).value,
+            CalculateOption::Complex(Complex::new(-4.0, 6.0))
+        );
+        assert_eq!(
+            parser.calculate("(1 + 1i) / 1i".to_string()).value,
+            CalculateOption::Complex(Complex::new(1.0, -1.0))
+        );
+        assert_eq!(
+            parser.calculate("Z == 3 + 2i".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+        match parser.calculate("Z > 1".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("复数比较大小应返回错误，实际返回 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_matrix() {
+        use crate::matrix::Matrix;
+
+        let m = |rows: Vec<Vec<f64>>| CalculateOption::Matrix(Matrix::from_rows(rows).unwrap());
+        let mut parser = parser::Parser::new();
+        parser.parse("A := [[1, 2], [3, 4]]; V := [1, -1]");
+        assert_eq!(parser.parse("A").to_string(), "A");
+        assert_eq!(
+            parser.calculate("A * [[0, 1], [1, 0]]").value,
+            m(vec![vec![2.0, 1.0], vec![4.0, 3.0]])
+        );
+        assert_eq!(
+            parser.calculate("A * V").value,
+            CalculateOption::Vector(vec![-1.0, -1.0])
+        );
+        assert_eq!(
+            parser.calculate("A + 1").value,
+            m(vec![vec![2.0, 3.0], vec![4.0, 5.0]])
+        );
+        assert_eq!(
+            parser.calculate("Transpose(A)").value,
+            m(vec![vec![1.0, 3.0], vec![2.0, 4.0]])
+        );
+        assert_eq!(parser.calculate("Det(A)").value.as_num(), Some(-2.0));
+        match parser.calculate("Inverse(A) * A").value {
+            CalculateOption::Matrix(i) => {
+                let identity = Matrix::identity(2);
+                let diff = i.data().iter().zip(identity.data());
+                assert!(diff.into_iter().all(|(a, b)| (a - b).abs() < 1e-12));
+            }
+            v => panic!("Inverse(A) * A 应为单位矩阵，实际为 {:?}", v),
+        }
+        // 求解线性方程组 A x = b
+        assert_eq!(
+            parser.calculate("Inverse([[2, 0], [0, 4]]) * [2, 8]").value,
+            CalculateOption::Vector(vec![1.0, 2.0])
+        );
+        assert_eq!(
+            parser.calculate("Transpose(A)").value.to_string(),
+            "[[1, 3], [2, 4]]"
+        );
+
+        for formula in [
+            "A * [[1, 2, 3]]",
+            "A + [[1, 2, 3]]",
+            "A / A",
+            "Inverse([[1, 2], [2, 4]])",
+            "Det([[1, 2, 3]])",
+        ] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+        assert!(parser
+            .calculate("A * [1, 2, 3]")
+            .value
+            .to_string()
+            .starts_with("E0307"));
+
+        // 只有一个表达式的 [ ] 与括号相同
+        assert_eq!(parser.calculate("[1 + 2] * 2").value.as_num(), Some(6.0));
+        assert_eq!(
+            parser.calculate("[]").value,
+            CalculateOption::Vector(vec![])
+        );
+        for formula in ["[[1, 2], [3]]", "[1, A]", "[-A]"] {
+            match parser.parse(formula).as_ref() {
+                formula::FormulaNode::UnKnow(_) => (),
+                n => panic!("{} 应为语法错误，实际为 {:?}", formula, n),
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rates := [[101, 0.05, 10], [205, 0.08, 20], [310, 0.12, 30]]");
+        assert_eq!(
+            parser.calculate("Lookup(205, Rates, 2)").value.as_num(),
+            Some(0.08)
+        );
+        assert_eq!(
+            parser.calculate("Lookup(250, Rates, 3, 1)").value.as_num(),
+            Some(20.0)
+        );
+        for formula in [
+            "Lookup(250, Rates, 2)",
+            "Lookup(1, Rates, 2, 1 > 0)",
+            "Lookup(101, Rates, 4)",
+            "Lookup(101, [1, 2], 1)",
+        ] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+
+        // 累进税率的档次
+        parser.parse("Rate(income) { MatchRange(income, [0, 5000, 20000], [0, 0.1, 0.2]) }");
+        for (income, rate) in [(0.0, 0.0), (4999.0, 0.0), (5000.0, 0.1), (80000.0, 0.2)] {
+            let value = parser.calculate(format!("Rate({})", income)).value;
+            assert_eq!(value.as_num(), Some(rate));
+        }
+        for formula in [
+            "MatchRange(0 - 1, [0, 10], [1, 2])",
+            "MatchRange(5, [10, 0], [1, 2])",
+            "MatchRange(5, [0, 10], [1])",
+        ] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_built_ins() {
+        let mut parser = parser::Parser::new();
+        let mut text = |formula: &str| {
+            let value = parser.calculate(formula).value;
+            String::try_from(value).unwrap()
+        };
+        assert_eq!(text("Format(3.14159, \"0.00\")"), "3.14");
+        assert_eq!(text("Format(1234567.891, \"#,##0.00\")"), "1,234,567.89");
+        assert_eq!(text("Format(0.5, \"#.##\")"), ".5");
+        assert_eq!(text("Format(7, \"000\")"), "007");
+        assert_eq!(text("Format(0.256, \"0.0%\")"), "25.6%");
+        assert_eq!(text("Format(0 - 42, \"$#,##0\")"), "-$42");
+        assert_eq!(text("Format(0 - 0.001, \"0.00\")"), "0.00");
+        assert_eq!(text("FormatPercent(0.125, 1)"), "12.5%");
+        assert_eq!(text("FormatPercent(0.5)"), "50%");
+        assert_eq!(text("FormatCurrency(0 - 1234.5, \"USD\")"), "-$1,234.50");
+        assert_eq!(text("FormatCurrency(1234.5, \"jpy\")"), "¥1,235");
+        assert_eq!(text("FormatCurrency(10, \"CHF\")"), "CHF 10.00");
+        assert_eq!(text("\"abc\""), "abc");
+
+        assert_eq!(
+            parser.calculate("Label := Format(2, \"0.0\"); Label").value,
+            CalculateOption::Text("2.0".to_string())
+        );
+        assert_eq!(
+            parser.parse("Format(X, \"0.00\")").to_string(),
+            "Format(X, \"0.00\")"
+        );
+        assert_eq!(
+            parser.calculate("Label == \"2.0\"").value,
+            CalculateOption::Bool(true)
+        );
+        for formula in [
+            "Format(1, \"abc\")",
+            "Format(\"1\", \"0\")",
+            "FormatPercent(1, 0.5)",
+            "FormatCurrency(1, \"dollar\")",
+            "\"a\" + 1",
+        ] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rounding() {
+        use crate::rounding::{Precision, RoundingMode};
+
+        let mut parser = parser::Parser::new();
+        let mut num = |formula: &str| match parser.calculate(formula).value {
+            CalculateOption::Num(n) => n,
+            v => panic!("{} 应返回数值，实际为 {:?}", formula, v),
+        };
+        assert_eq!(num("Round(2.5)"), 3.0);
+        assert_eq!(num("Round(0 - 2.5)"), -3.0);
+        assert_eq!(num("Round(1.005, 2)"), 1.01);
+        assert_eq!(num("Round(1234.5, 0 - 2)"), 1200.0);
+        assert_eq!(num("RoundHalfEven(2.5)"), 2.0);
+        assert_eq!(num("RoundHalfEven(0.125, 2)"), 0.12);
+        assert_eq!(num("RoundHalfEven(0.135, 2)"), 0.14);
+        assert_eq!(num("Floor(0 - 1.21, 1)"), -1.3);
+        assert_eq!(num("Ceiling(1.21, 1)"), 1.3);
+        assert_eq!(num("Ceiling(0 - 0.4)"), 0.0);
+        assert_eq!(
+            parser.calculate("Round([1.26, 2.5], 1)").value,
+            CalculateOption::Vector(vec![1.3, 2.5])
+        );
+        for formula in ["Round(1, 0.5)", "Round(\"1\")", "Floor()"] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+
+        // 默认精度只作用于最终结果
+        parser.parse("Price := 10 / 3");
+        parser.set_precision(Some(Precision::new(2, RoundingMode::HalfEven)));
+        assert_eq!(parser.calculate("Price").value, CalculateOption::Num(3.33));
+        assert_eq!(
+            parser.calculate("Price * 3").value,
+            CalculateOption::Num(10.0)
+        );
+        parser.set_precision(Some(Precision::new(1, RoundingMode::Ceiling)));
+        assert_eq!(parser.calculate("Price").value, CalculateOption::Num(3.4));
+        assert_eq!(
+            parser.compile("Price").eval().value,
+            CalculateOption::Num(3.4)
+        );
+        // 通过后缀指令计算的四则运算同样舍入
+        parser.set_var("A", 1.0);
+        let compiled = parser.compile("A / 3");
+        assert!(compiled.is_arithmetic());
+        assert_eq!(compiled.eval().value, CalculateOption::Num(0.4));
+        parser.set_precision(None);
+        assert_eq!(
+            parser.calculate("Price").value,
+            CalculateOption::Num(10.0 / 3.0)
+        );
+    }
+
+    #[test]
+    fn test_subscript() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Prices := [10, 20, 30, 40]");
+        let mut value = |formula: &str| parser.calculate(formula).value;
+        assert_eq!(value("Prices[0]"), CalculateOption::Num(10.0));
+        assert_eq!(value("Prices[-1]"), CalculateOption::Num(40.0));
+        assert_eq!(value("Prices[Len(Prices) - 2]"), CalculateOption::Num(30.0));
+        assert_eq!(value("Prices[1] + Prices[2]"), CalculateOption::Num(50.0));
+        assert_eq!(
+            value("Prices[1:3]"),
+            CalculateOption::Vector(vec![20.0, 30.0])
+        );
+        assert_eq!(
+            value("Prices[:2]"),
+            CalculateOption::Vector(vec![10.0, 20.0])
+        );
+        assert_eq!(
+            value("Prices[-2:]"),
+            CalculateOption::Vector(vec![30.0, 40.0])
+        );
+        assert_eq!(value("Prices[3:1]"), CalculateOption::Vector(vec![]));
+        assert_eq!(value("Sum(Prices[1:])"), CalculateOption::Num(90.0));
+        assert_eq!(value("[1, 2, 3][-1]"), CalculateOption::Num(3.0));
+        assert_eq!(value("Len(Prices)"), CalculateOption::Num(4.0));
+        assert_eq!(value("Len(\"价格\")"), CalculateOption::Num(2.0));
+
+        for formula in ["Prices[1:3]", "Prices[-1]", "Prices[:2]", "F(X)[0]"] {
+            assert_eq!(parser.parse(formula).to_string(), formula);
+        }
+        // [ 与前面的节点之间有空白时为新的语句
+        assert_eq!(parser.parse_statements("X := 1\n[1, 2]").len(), 2);
+
+        let err_code = |value: CalculateOption| match value {
+            CalculateOption::Err(msg) => ErrorCode::of(&msg),
+            _ => None,
+        };
+        for (formula, code) in [
+            ("Prices[4]", ErrorCode::IndexOutOfRange),
+            ("Prices[-5]", ErrorCode::IndexOutOfRange),
+            ("Prices[1:5]", ErrorCode::IndexOutOfRange),
+            ("Prices[1.5]", ErrorCode::InvalidIndex),
+            ("Prices[\"a\"]", ErrorCode::InvalidIndex),
+            ("Len(Prices)[0]", ErrorCode::NotIndexable),
+        ] {
+            assert_eq!(err_code(parser.calculate(formula).value), Some(code));
+        }
+    }
+
+    #[test]
+    fn test_group_by() {
+        use crate::matrix::Matrix;
+
+        let matrix =
+            |rows: Vec<Vec<f64>>| CalculateOption::Matrix(Matrix::from_rows(rows).unwrap());
+        let mut parser = parser::Parser::new();
+        // 每一行为一条订单记录: [品类, 金额]
+        parser.parse("Orders := [[1, 10], [2, 5], [1, 7], [3, 1]]");
+        assert_eq!(
+            parser.calculate("Orders[-1]").value,
+            CalculateOption::Vector(vec![3.0, 1.0])
+        );
+        assert_eq!(
+            parser.calculate("Orders[2][1]").value,
+            CalculateOption::Num(7.0)
+        );
+        assert_eq!(
+            parser.calculate("Orders[1:3]").value,
+            matrix(vec![vec![2.0, 5.0], vec![1.0, 7.0]])
+        );
+        assert_eq!(
+            parser.calculate("Len(Orders)").value,
+            CalculateOption::Num(4.0)
+        );
+
+        let totals = matrix(vec![vec![1.0, 17.0], vec![2.0, 5.0], vec![3.0, 1.0]]);
+        assert_eq!(
+            parser
+                .calculate(
+                    "GroupBy(Orders, Category(r) { r[0] }, Total(g) { Sum(Transpose(g)[1]) })"
+                )
+                .value,
+            totals
+        );
+        parser.parse("Category(r) { r[0] }");
+        parser.parse("Total(g) { Sum(Transpose(g)[1]) }");
+        assert_eq!(
+            parser.calculate("GroupBy(Orders, Category, Total)").value,
+            totals
+        );
+        assert_eq!(
+            parser
+                .calculate("Lookup(2, GroupBy(Orders, Category, Total), 2)")
+                .value,
+            CalculateOption::Num(5.0)
+        );
+        assert_eq!(
+            parser
+                .calculate("GroupBy([5, 12, 17, 3], Tier(x) { Floor(x, 0 - 1) }, Count)")
+                .value,
+            matrix(vec![vec![0.0, 2.0], vec![10.0, 2.0]])
+        );
+
+        for formula in [
+            "GroupBy(1, Category, Total)",
+            "GroupBy(Orders, Id(r) { r }, Total)",
+            "GroupBy(Orders, Category, Transpose)",
+            "GroupBy(Orders, 1, Total)",
+        ] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_number_locale() {
+        use crate::locale::NumberLocale;
+
+        let comma = NumberLocale::decimal_comma();
+        assert_eq!(comma.parse("1.234,56"), Some(1234.56));
+        assert_eq!(comma.parse("0,5"), Some(0.5));
+        assert_eq!(comma.parse("1.234.567"), Some(1234567.0));
+        assert_eq!(comma.parse("12.34"), None);
+        assert_eq!(comma.parse("1,2,3"), None);
+        assert_eq!(comma.format(-1234567.5), "-1.234.567,5");
+        assert_eq!(comma.format(999.0), "999");
+        assert_eq!(NumberLocale::default().format(1234.5), "1234.5");
+        assert_eq!(
+            NumberLocale::new('.', Some(',')).parse("1,234.5"),
+            Some(1234.5)
+        );
+
+        let mut parser = parser::Parser::new();
+        parser.set_number_locale(comma);
+        let value = parser.calculate("1.234,56 + 0,44").value;
+        assert_eq!(value, CalculateOption::Num(1235.0));
+        assert_eq!(parser.format_value(&value), "1.235");
+        // 参数及向量元素之间的 , 之后有空白
+        let value = parser.calculate("Sum([1,5, 2,5])").value;
+        assert_eq!(value, CalculateOption::Num(4.0));
+        let value = parser.calculate("[1,5, 1.000]").value;
+        assert_eq!(parser.format_value(&value), "[1,5, 1.000]");
+        assert_eq!(value.to_string(), "[1.5, 1000]");
+        assert!(matches!(
+            parser.calculate("12.34").value,
+            CalculateOption::Err(_)
+        ));
+
+        parser.set_number_locale(NumberLocale::default());
+        assert_eq!(
+            parser.calculate("Max(1,5)").value,
+            CalculateOption::Num(5.0)
+        );
+    }
+
+    #[test]
+    fn test_strict_logic() {
+        let mut parser = parser::Parser::new();
+        parser.parse("T := 1 > 0; F := 1 < 0");
+        let err_code = |value: CalculateOption| match value {
+            CalculateOption::Err(msg) => ErrorCode::of(&msg),
+            _ => None,
+        };
+        // 宽松模式下的转换规则，0 为假，其他数值为真
+        let permissive = [
+            ("T && F", CalculateOption::Bool(false)),
+            ("T || F", CalculateOption::Bool(true)),
+            ("T && 2", CalculateOption::Num(2.0)),
+            ("F && 2", CalculateOption::Bool(false)),
+            ("0 && T", CalculateOption::Num(0.0)),
+            ("3 && F", CalculateOption::Bool(false)),
+            ("3 && 0", CalculateOption::Num(0.0)),
+            ("0 && 3", CalculateOption::Num(0.0)),
+            ("T || 2", CalculateOption::Bool(true)),
+            ("F || 2", CalculateOption::Num(2.0)),
+            ("3 || F", CalculateOption::Num(3.0)),
+            ("0 || T", CalculateOption::Bool(true)),
+            ("0 || 3", CalculateOption::Num(3.0)),
+            ("!0", CalculateOption::Bool(true)),
+            ("!5", CalculateOption::Bool(false)),
+            ("!F", CalculateOption::Bool(true)),
+        ];
+        for (formula, expected) in permissive.iter() {
+            assert_eq!(&parser.calculate(formula).value, expected, "{}", formula);
+        }
+        assert_eq!(
+            err_code(parser.calculate("\"a\" && T").value),
+            Some(ErrorCode::NonNumericLogic)
+        );
+
+        parser.set_strict_logic(true);
+        assert_eq!(
+            parser.calculate("T && !F").value,
+            CalculateOption::Bool(true)
+        );
+        assert_eq!(
+            parser.calculate("F || T").value,
+            CalculateOption::Bool(true)
+        );
+        for formula in ["T && 2", "0 || T", "1 || 2"] {
+            assert_eq!(
+                err_code(parser.calculate(formula).value),
+                Some(ErrorCode::NonBoolLogic),
+                "{}",
+                formula
+            );
+        }
+        assert_eq!(
+            err_code(parser.calculate("!5").value),
+            Some(ErrorCode::NonBoolNot)
+        );
+    }
+
+    #[test]
+    fn test_opaque_value() {
+        /// 宿主程序中的曲线对象，公式中无法表示
+        struct Curve {
+            points: Vec<(f64, f64)>,
+        }
+
+        let mut parser = parser::Parser::new();
+        parser
+            .reg_fallible_build_in("Curve", |c| {
+                let rate = c.arg_num(0)?;
+                let points = (0..4).map(|t| (t as f64, rate * t as f64)).collect();
+                Ok(CalculateOption::opaque(Curve { points }))
+            })
+            .unwrap();
+        parser
+            .reg_fallible_build_in("At", |c| {
+                let curve = c.arg_opaque::<Curve>(0)?;
+                let t = c.arg_num(1)?;
+                let point = curve.points.iter().find(|(x, _)| *x == t);
+                Ok(CalculateOption::Num(point.map_or(f64::NAN, |(_, y)| *y)))
+            })
+            .unwrap();
+
+        assert_eq!(
+            parser.calculate("At(Curve(0.5), 2)").value,
+            CalculateOption::Num(1.0)
+        );
+        // 宿主对象可以绑定到变量及函数参数上
+        parser.parse("C := Curve(2); Shift(c, t) { At(c, t) + 1 }");
+        assert_eq!(
+            parser.calculate("Shift(C, 3)").value,
+            CalculateOption::Num(7.0)
+        );
+        let value = parser.calculate("C").value;
+        assert_eq!(value.type_name(), "opaque");
+        assert_eq!(value.as_opaque::<Curve>().map(|c| c.points.len()), Some(4));
+        assert!(value.as_opaque::<f64>().is_none());
+        assert!(parser.set_var("Handle", CalculateOption::opaque(Curve { points: vec![] })));
+        assert!(parser
+            .calculate("At(Handle, 0)")
+            .value
+            .as_num()
+            .unwrap()
+            .is_nan());
+
+        // 类型不符或参与运算时返回错误
+        for formula in ["At(2, 0)", "At(Len, 0)", "C + 1"] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+        parser.reg_build_in("Other", |_| CalculateOption::opaque(1u8));
+        match parser.calculate("At(Other(), 0)").value {
+            CalculateOption::Err(e) => assert!(e.contains("Curve"), "{}", e),
+            v => panic!("应返回错误，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_money() {
+        use crate::money::Money;
+
+        let mut parser = parser::Parser::new();
+        let eur = |amount: f64| CalculateOption::Money(Money::new(amount, "EUR").unwrap());
+        parser.parse("Price := Money(12.5, \"eur\"); Fee := Money(2, \"USD\")");
+        assert_eq!(parser.calculate("Price").value, eur(12.5));
+        assert_eq!(
+            parser.calculate("Price + Money(1.5, \"EUR\")").value,
+            eur(14.0)
+        );
+        assert_eq!(parser.calculate("Price * 2").value, eur(25.0));
+        assert_eq!(parser.calculate("2 * Price").value, eur(25.0));
+        assert_eq!(parser.calculate("Price / 5").value, eur(2.5));
+        assert_eq!(
+            parser.calculate("Price / Money(2.5, \"EUR\")").value,
+            CalculateOption::Num(5.0)
+        );
+        assert_eq!(
+            parser.calculate("Price > Money(10, \"EUR\")").value,
+            CalculateOption::Bool(true)
+        );
+        assert_eq!(
+            parser.calculate("Price == Price").value,
+            CalculateOption::Bool(true)
+        );
+        assert_eq!(
+            parser.calculate("Amount(Price)").value,
+            CalculateOption::Num(12.5)
+        );
+        assert_eq!(parser.calculate("Round(Price / 3, 2)").value, eur(4.17));
+        // 金额可以作为函数的参数及返回值
+        parser.parse("Net(p) { p - p * 0.2 }");
+        assert_eq!(parser.calculate("Net(Price)").value, eur(10.0));
+        assert_eq!(
+            parser.calculate("Price").value.to_string(),
+            "Money(12.5, \"EUR\")"
+        );
+
+        let err_code = |value: CalculateOption| match value {
+            CalculateOption::Err(msg) => ErrorCode::of(&msg),
+            _ => None,
+        };
+        for (formula, code) in [
+            ("Price + Fee", ErrorCode::CurrencyMismatch),
+            ("Price < Fee", ErrorCode::CurrencyMismatch),
+            ("Price == Fee", ErrorCode::CurrencyMismatch),
+            ("Price != Fee", ErrorCode::CurrencyMismatch),
+            ("Price + 1", ErrorCode::InvalidMoneyOperation),
+            ("Price * Price", ErrorCode::InvalidMoneyOperation),
+            ("1 / Price", ErrorCode::InvalidMoneyOperation),
+        ] {
+            assert_eq!(
+                err_code(parser.calculate(formula).value),
+                Some(code),
+                "{}",
+                formula
+            );
+        }
+
+        // 汇率可以是数值，也可以是宿主程序注册的查询汇率的函数
+        parser
+            .reg_fallible_build_in("Rate", |c| {
+                match (c.arg_text(0)?.as_str(), c.arg_text(1)?.as_str()) {
+                    ("USD", "EUR") => Ok(CalculateOption::Num(0.9)),
+                    (from, to) => Err(formula::CalcError::new(format!(
+                        "没有 {} 到 {} 的汇率",
+                        from, to
+                    ))),
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            parser.calculate("Convert(Fee, \"EUR\", Rate)").value,
+            eur(1.8)
+        );
+        assert_eq!(
+            parser.calculate("Convert(Fee, \"EUR\", 0.5) + Price").value,
+            eur(13.5)
+        );
+        assert_eq!(
+            parser.calculate("Convert(Price, \"EUR\", 0)").value,
+            eur(12.5)
+        );
+        for formula in [
+            "Convert(Price, \"USD\", Rate)",
+            "Convert(Fee, \"EUR\", 0)",
+            "Convert(2, \"EUR\", 1)",
+            "Money(1, \"dollar\")",
+        ] {
+            match parser.calculate(formula).value {
+                CalculateOption::Err(_) => (),
+                v => panic!("{} 应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+
+        let mut text = |formula: &str| parser.calculate(formula).value.as_text().map(String::from);
+        assert_eq!(
+            text("FormatCurrency(Money(1234.5, \"USD\"))").as_deref(),
+            Some("$1,234.50")
+        );
+        assert_eq!(
+            text("FormatCurrency(Price, \"eur\")").as_deref(),
+            Some("€12.50")
+        );
+        assert_eq!(text("FormatCurrency(Price, \"USD\")"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "rational")]
+    fn test_rational() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Third(x) { x / 3 }");
+        assert_eq!(
+            parser.calculate("(0.1 + 0.2) == 0.3").value,
+            CalculateOption::Bool(false)
+        );
+        parser.set_rational(true);
+        let exact = [
+            "((1 / 49) * 49) == 1",
+            "(0.1 + 0.2) == 0.3",
+            "(Third(1) * 3) == 1",
+            "(1 / 3) < 0.3334",
+            "(1 / 3) != 0.3333333333333333",
+        ];
+        for formula in exact.iter() {
+            assert_eq!(
+                parser.calculate(formula).value,
+                CalculateOption::Bool(true),
+                "{}",
+                formula
+            );
+        }
+        // 分数只在输出、调用内建函数及乘方时转换为数值
+        assert_eq!(parser.calculate("1 / 4").value, CalculateOption::Num(0.25));
+        assert_eq!(
+            parser.calculate("Max(1 / 4, 0)").value,
+            CalculateOption::Num(0.25)
+        );
+        assert_eq!(
+            parser.calculate("(1 / 2) ^ 2").value,
+            CalculateOption::Num(0.25)
+        );
+        assert_eq!(
+            parser.calculate("[1, 2, 3][2 / 2]").value,
+            CalculateOption::Num(2.0)
+        );
+        assert_eq!(
+            parser.calculate("Money(1.5, \"EUR\") * (1 / 2)").value,
+            CalculateOption::Money(crate::money::Money::new(0.75, "EUR").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_float_eq_epsilon() {
+        let mut parser = parser::Parser::new();
+        parser.parse("X := 0.1 + 0.2".to_string());
+        assert_eq!(
+            parser.calculate("X == 0.3".to_string()).value,
+            CalculateOption::Bool(false)
+        );
+        assert_eq!(
+            parser.calculate("(0.1 + 0.2) == 0.3".to_string()).value,
+            CalculateOption::Bool(false)
+        );
+
+        parser.set_options(CalculateOptions {
+            float_eq_epsilon: 1e-9,
+            ..Default::default()
+        });
+        assert_eq!(
+            parser.calculate("X == 0.3".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+        assert_eq!(
+            parser.calculate("X != 0.3".to_string()).value,
+            CalculateOption::Bool(false)
+        );
+        assert_eq!(
+            parser.calculate("0.3 == X == 0.3".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+        assert_eq!(
+            parser.calculate("(0.1 + 0.2) == 0.3".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_chained_compare() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 1; B := 2; C := 3;".to_string());
+        assert_eq!(
+            parser.calculate("A < B < C".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+        assert_eq!(
+            parser.calculate("A < C < B".to_string()).value,
+            CalculateOption::Bool(false)
+        );
+        assert_eq!(
+            parser.calculate("A != B".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_vector_broadcast() {
+        let mut parser = parser::Parser::new();
+        assert!(parser.set_column("Price", vec![1.0, 2.0, 3.0]));
+        assert!(parser.set_column("Qty", vec![2.0, 2.0, 4.0]));
+        assert_eq!(
+            parser.calculate("Price * Qty".to_string()).value,
+            CalculateOption::Vector(vec![2.0, 4.0, 12.0])
+        );
+        assert_eq!(
+            parser.calculate("Price * 2 + 1".to_string()).value,
+            CalculateOption::Vector(vec![3.0, 6.0, 9.0])
+        );
+
+        parser.set_column("Short", vec![1.0]);
+        match parser.calculate("Price - Short".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("向量长度不一致时应返回错误，实际返回 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_eval_batch() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.5".to_string());
+        let compiled = parser.compile("Qty * Price * Rate".to_string());
+
+        let rows: Vec<HashMap<String, f64>> = vec![(2.0, 3.0), (4.0, 5.0)]
+            .into_iter()
+            .map(|(qty, price)| {
+                let mut row = HashMap::new();
+                row.insert("Qty".to_string(), qty);
+                row.insert("Price".to_string(), price);
+                row
+            })
+            .collect();
+        let values: Vec<CalculateOption> = compiled
+            .eval_batch(&rows)
+            .into_iter()
+            .map(|r| r.value)
+            .collect();
+        assert_eq!(
+            values,
+            vec![CalculateOption::Num(3.0), CalculateOption::Num(10.0)]
+        );
+
+        #[cfg(feature = "parallel")]
+        assert_eq!(
+            crate::compiled::eval_batch_par(
+                || {
+                    let mut parser = parser::Parser::new();
+                    parser.parse("Rate := 0.5".to_string());
+                    parser
+                },
+                "Qty * Price * Rate",
+                &rows
+            ),
+            values
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut parser = parser::Parser::new();
+        parser.set_options(CalculateOptions {
+            history_limit: 10,
+            ..Default::default()
+        });
+        parser.parse("Avg := (Price + prev(Price)) / 2".to_string());
+
+        parser.parse("Price := 10".to_string());
+        // 第一次计算时没有历史值
+        match parser.calculate("prev(Price)".to_string()).value {
+            CalculateOption::None => (),
+            v => panic!("没有历史值时应返回 None，实际为 {:?}", v),
+        }
+        // 只有参与过计算的变量才会记录历史值
+        parser.calculate("Price".to_string());
+        parser.parse("Price := 20".to_string());
+        assert_eq!(
+            parser.calculate("Avg".to_string()).value,
+            CalculateOption::Num(15.0)
+        );
+        parser.parse("Price := 40".to_string());
+        assert_eq!(
+            parser.calculate("Avg@-1".to_string()).value,
+            CalculateOption::Num(15.0)
+        );
+        assert_eq!(
+            parser.calculate("Avg".to_string()).value,
+            CalculateOption::Num(30.0)
+        );
+        assert_eq!(
+            parser.calculate("Price@-2".to_string()).value,
+            CalculateOption::Num(20.0)
+        );
+        assert_eq!(
+            parser.history("Price"),
+            vec![
+                CalculateOption::Num(10.0),
+                CalculateOption::Num(20.0),
+                CalculateOption::Num(40.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut parser = parser::Parser::new();
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let recorder = Rc::clone(&changes);
+        let id = parser.subscribe("Total", move |name, change| {
+            recorder
+                .borrow_mut()
+                .push(format!("{} {:?}", name, change_value(change)));
+        });
+
+        parser.parse("Price := 2; Total := Price * 3".to_string());
+        parser.calculate("Total".to_string());
+        parser.calculate("Total".to_string());
+        parser.parse("Price := 4".to_string());
+        parser.calculate("Total".to_string());
+        assert_eq!(
+            *changes.borrow(),
+            vec![
+                "Total None",
+                "Total Some(Num(6.0))",
+                "Total Some(Num(12.0))"
+            ]
+        );
+
+        assert!(parser.unsubscribe("Total", id));
+        parser.parse("Total := 1".to_string());
+        assert_eq!(changes.borrow().len(), 3);
+    }
+
+    fn change_value(change: &EnvChange) -> Option<CalculateOption> {
+        match change {
+            EnvChange::Value { new, .. } => Some(new.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.1; Tax := Income * Rate".to_string());
+        parser.parse("Income := 100".to_string());
+        let snapshot = parser.snapshot();
+
+        parser.parse("Rate := 0.2; Bonus := 10".to_string());
+        assert_eq!(
+            parser.calculate("Tax".to_string()).value,
+            CalculateOption::Num(20.0)
+        );
+
+        parser.restore(snapshot);
+        assert_eq!(
+            parser.calculate("Tax".to_string()).value,
+            CalculateOption::Num(10.0)
+        );
+        match parser.calculate("Bonus".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("恢复快照后 Bonus 应不存在，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_env_info() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 1".to_string());
+        parser.parse("B := A + 1".to_string());
+        parser.parse("F(x) { x * 2 }".to_string());
+        parser.reg_build_in("Zero", |_| CalculateOption::Num(0.0));
+
+        // 只检查自行定义的内容及 prev，忽略其他默认加载的内建函数
+        let kinds: Vec<(String, EnvEntryKind)> = parser
+            .env_info()
+            .into_iter()
+            .filter(|info| {
+                info.kind != EnvEntryKind::BuildIn || ["Zero", "prev"].contains(&info.name.as_str())
+            })
+            .map(|info| (info.name, info.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("A".to_string(), EnvEntryKind::Constant),
+                ("B".to_string(), EnvEntryKind::Formula),
+                ("F".to_string(), EnvEntryKind::Function),
+                ("Zero".to_string(), EnvEntryKind::BuildIn),
+                ("prev".to_string(), EnvEntryKind::BuildIn),
+            ]
+        );
+
+        // 解析得到的定义保存了其源码，宿主程序绑定的值及内建函数没有源码
+        parser.parse("C := A + B;  G(x) {\n  x + 1\n}; namespace tax { Rate := 0.1 }");
+        parser.set_var("D", 4.0);
+        let infos = parser.env_info();
+        let source = |name: &str| {
+            let info = infos.iter().find(|info| info.name == name).unwrap();
+            info.source.clone()
+        };
+        assert_eq!(source("B").as_deref(), Some("B := A + 1"));
+        assert_eq!(source("C").as_deref(), Some("C := A + B"));
+        assert_eq!(source("G").as_deref(), Some("G(x) {\n  x + 1\n}"));
+        assert_eq!(
+            source("tax").as_deref(),
+            Some("namespace tax { Rate := 0.1 }")
+        );
+        assert_eq!(source("D"), None);
+        assert_eq!(source("Zero"), None);
+    }
+
+    #[test]
+    fn test_remove_and_rename() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.1".to_string());
+        parser.parse("Tax := Income * Rate".to_string());
+        parser.parse("Income := 100".to_string());
+        parser.parse("F(Rate) { Rate * 2 }".to_string());
+
+        assert!(parser.rename("Rate", "TaxRate", true));
+        assert_eq!(
+            parser.calculate("Tax".to_string()).value,
+            CalculateOption::Num(10.0)
+        );
+        // 函数参数与被重命名的变量同名时，函数体中的引用不会被改写
+        assert_eq!(
+            parser.calculate("F(3)".to_string()).value,
+            CalculateOption::Num(6.0)
+        );
+        // 函数体中直接引用被重命名的变量时同样会被改写
+        parser.parse("Direct(x) { TaxRate }");
+        assert!(parser.rename("TaxRate", "Rate", true));
+        assert_eq!(
+            parser.calculate("Direct(0)").value,
+            CalculateOption::Num(0.1)
+        );
+        assert!(parser.rename("Rate", "TaxRate", true));
+
+        assert!(parser.rename("Income", "Salary", false));
+        match parser.calculate("Tax".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("未改写引用时 Tax 仍应引用 Income，实际为 {:?}", v),
+        }
+
+        assert!(parser.remove("Tax"));
+        assert!(!parser.remove("Tax"));
+        assert!(parser.remove("prev"));
+        match parser.calculate("prev(Salary)".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("移除的内建函数不应再能调用，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_set_var() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Total := Qty * Price".to_string());
+        assert!(parser.set_var("Qty", 3.0));
+        let mut vars = HashMap::new();
+        vars.insert("Price".to_string(), CalculateOption::Num(2.5));
+        vars.insert("Vip".to_string(), CalculateOption::Bool(true));
+        assert!(parser.set_vars(vars));
+        assert_eq!(
+            parser.calculate("Total".to_string()).value,
+            CalculateOption::Num(7.5)
+        );
+        assert_eq!(
+            parser.calculate("Vip".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+        assert!(!parser.set_var("Bad", CalculateOption::Err("error".to_string())));
+    }
+
+    #[test]
+    fn test_resolver() {
+        let mut parser = parser::Parser::new();
+        parser.set_resolver(|name: &str| match name {
+            "DbRate" => Some(CalculateOption::Num(0.25)),
+            _ => None,
+        });
+        parser.parse("Rate := 0.5".to_string());
+        assert_eq!(
+            parser.calculate("DbRate * 4".to_string()).value,
+            CalculateOption::Num(1.0)
+        );
+        // 执行环境中已定义的变量优先于外部解析器
+        assert_eq!(
+            parser.calculate("Rate".to_string()).value,
+            CalculateOption::Num(0.5)
+        );
+        match parser.calculate("Missing".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("解析器中不存在的变量应返回错误，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_delay() {
+        use crate::clock::ManualClock;
+
+        let mut parser = parser::Parser::new();
+        let clock = Rc::new(ManualClock::default());
+        parser.set_clock(Rc::clone(&clock));
+        parser.parse("A := 1".to_string());
+        parser
+            .set_delayed("A", "2".to_string(), Duration::from_millis(50))
+            .unwrap();
+        parser
+            .set_delayed("B", "3".to_string(), Duration::from_millis(50))
+            .unwrap();
+
+        // 延迟时间未到，A 仍然是之前的值，B 之前未定义，得到 None
+        assert_eq!(
+            parser.calculate("A".to_string()).value,
+            CalculateOption::Num(1.0)
+        );
+        match parser.calculate("B".to_string()).value {
+            CalculateOption::None => (),
+            v => panic!("延迟生效前变量应为 None，实际为 {:?}", v),
+        }
+
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(
+            parser.calculate("A".to_string()).value,
+            CalculateOption::Num(1.0)
+        );
+
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(
+            parser.calculate("A".to_string()).value,
+            CalculateOption::Num(2.0)
+        );
+        assert_eq!(
+            parser.calculate("B".to_string()).value,
+            CalculateOption::Num(3.0)
+        );
+    }
+
+    #[test]
+    fn test_namespace() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.5".to_string());
+        parser.parse("namespace tax { Rate := 0.1; Calc(x) { x * Rate } }".to_string());
+
+        assert_eq!(
+            parser.calculate("tax.Calc(100)".to_string()).value,
+            CalculateOption::Num(10.0)
+        );
+        assert_eq!(
+            parser.calculate("tax.Rate".to_string()).value,
+            CalculateOption::Num(0.1)
+        );
+        assert_eq!(
+            parser.calculate("Rate".to_string()).value,
+            CalculateOption::Num(0.5)
+        );
+
+        // 命名空间是执行环境中单独的一类定义，只有 . 之前为命名空间时才在该命名空间中查找名称
+        let tax = parser
+            .env_info()
+            .into_iter()
+            .find(|i| i.name == "tax")
+            .unwrap();
+        assert_eq!(tax.kind, EnvEntryKind::Namespace);
+        parser.parse("cfg.Rate := 0.2; cfg.Total := Rate * 2");
+        assert_eq!(
+            parser.calculate("cfg.Total").value,
+            CalculateOption::Num(1.0)
+        );
+        match parser.parse("namespace Rate { X := 1 }").as_ref() {
+            formula::FormulaNode::UnKnow(msg) => assert!(msg.contains("Rate"), "{}", msg),
+            n => panic!("已定义的名称不能作为命名空间，实际为 {:?}", n),
+        }
+    }
+
+    #[test]
+    fn test_import() {
+        let mut parser = parser::Parser::new();
+        parser.set_importer(|path: &str| match path {
+            "common.fml" => Ok("Double(x) { x * 2 }".to_string()),
+            "loop.fml" => Ok("import \"loop.fml\";".to_string()),
+            _ => Err("not found".to_string()),
+        });
+
+        parser.parse("import \"common.fml\";".to_string());
+        assert_eq!(
+            parser.calculate("Double(21)".to_string()).value,
+            CalculateOption::Num(42.0)
+        );
+
+        for path in ["missing.fml", "loop.fml"] {
+            match parser.parse(format!("import \"{}\";", path)).as_ref() {
+                formula::FormulaNode::UnKnow(_) => (),
+                n => panic!("加载 {} 应当失败，实际为 {:?}", path, n),
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_env_json() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 2".to_string());
+        parser.parse("Double(x) { x * 2 }".to_string());
+        parser.set_column("C", vec![1.0, 2.0]);
+        let json = parser.to_json().unwrap();
+
+        let mut restored = parser::Parser::new();
+        restored.load_json(&json).unwrap();
+        assert_eq!(
+            restored.calculate("Double(A)".to_string()).value,
+            CalculateOption::Num(4.0)
+        );
+        assert_eq!(
+            restored.calculate("C * A".to_string()).value,
+            CalculateOption::Vector(vec![2.0, 4.0])
+        );
+        assert!(restored.load_json("{").is_err());
+    }
+
+    #[test]
+    fn test_readonly_system() {
+        let mut parser = parser::Parser::new();
+        parser.parse("PI := 3".to_string());
+        parser.seal();
+
+        match parser.parse("PI := 4".to_string()).as_ref() {
+            formula::FormulaNode::UnKnow(_) => (),
+            n => panic!("系统定义不应被覆盖，实际为 {:?}", n),
+        }
+        assert!(!parser.set_var("PI", 4.0));
+        assert!(!parser.set_column("PI", vec![4.0]));
+        assert_eq!(
+            parser.calculate("PI".to_string()).value,
+            CalculateOption::Num(3.0)
+        );
+        assert_eq!(
+            parser.reg_fallible_build_in("prev", |_| Ok(CalculateOption::None)),
+            Err(EnvError::Protected("prev".to_string()))
+        );
+        // seal 之后仍然可以注册新的内建函数，宿主程序通过 reg_build_in 也可以覆盖系统层中的函数
+        parser
+            .reg_fallible_build_in("Zero", |_| Ok(CalculateOption::Num(0.0)))
+            .unwrap();
+        assert_eq!(parser.calculate("Zero()").value, CalculateOption::Num(0.0));
+        parser.reg_build_in("Zero", |_| CalculateOption::Num(1.0));
+        assert_eq!(parser.calculate("Zero()").value, CalculateOption::Num(1.0));
+
+        parser.parse("R := 2".to_string());
+        assert_eq!(
+            parser.calculate("PI * R".to_string()).value,
+            CalculateOption::Num(6.0)
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_calculate_par() {
+        use crate::formula::CalcError;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let mut parser = parser::Parser::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        parser
+            .reg_concurrent_build_in("Fetch", move |args| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                match args[0] >= 0.0 {
+                    true => Ok(args[0] * 10.0),
+                    false => Err(CalcError::arg(0, "不能为负数")),
+                }
+            })
+            .unwrap();
+        parser.parse("X := 2");
+
+        let result = parser.calculate_par("(Fetch(1) + Fetch(X)) * Fetch(X + 1)");
+        assert_eq!(result.value, CalculateOption::Num(900.0));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            parser
+                .calculate("(Fetch(1) + Fetch(X)) * Fetch(X + 1)")
+                .value,
+            CalculateOption::Num(900.0)
+        );
+
+        // 脚本函数的参数中的调用同样并行执行
+        parser.parse("Add(a, b) { a + b }");
+        calls.store(0, Ordering::SeqCst);
+        assert_eq!(
+            parser.calculate_par("Add(Fetch(1), Fetch(2)) + 1").value,
+            CalculateOption::Num(31.0)
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // 出错的调用不会再次调用，得到与 calculate 相同的错误信息
+        for &(formula, count) in &[
+            ("Fetch(1) + Fetch(0 - 1)", 2),
+            ("Fetch(1) + Fetch(\"a\")", 1),
+        ] {
+            let expected = parser.calculate(formula).value;
+            calls.store(0, Ordering::SeqCst);
+            match (parser.calculate_par(formula).value, expected) {
+                (CalculateOption::Err(e), CalculateOption::Err(expected)) => {
+                    assert_eq!(e, expected);
+                }
+                v => panic!("调用出错时应返回错误，实际返回 {:?}", v),
+            }
+            assert_eq!(calls.load(Ordering::SeqCst), count);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_parse_reader() {
+        let source = "Rate := 0.5; # 注释中的 ; 不会结束语句\n\
+                      Tax(x) {\n  y := x * Rate;\n  y\n};\n\
+                      Tax(4) + 1;\n\
+                      \"a;b\"; Tax(2)";
+        let mut parser = parser::Parser::new();
+        let nodes: Vec<Rc<formula::FormulaNode>> = parser
+            .parse_reader(std::io::Cursor::new(source))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(nodes.len(), 5);
+        assert_eq!(nodes[3].to_string(), "\"a;b\"");
+        assert_eq!(
+            parser.calculate("Tax(4) + 1").value,
+            CalculateOption::Num(3.0)
+        );
+        assert_eq!(nodes[4].to_string(), "Tax(2)");
+
+        // 不以 ; 分隔的语句在读到下一条语句时即被解析，而不是等到读取结束
+        struct Broken;
+        impl std::io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("broken"))
+            }
+        }
+        let source = "A := 1\nB := A +\n  1\nC := B * 2\n";
+        let reader = std::io::BufReader::new(std::io::Read::chain(source.as_bytes(), Broken));
+        let mut parser = parser::Parser::new();
+        let items: Vec<_> = parser.parse_reader(reader).collect();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].as_ref().unwrap().to_string(), "A := 1");
+        assert_eq!(items[1].as_ref().unwrap().to_string(), "B := (A + 1)");
+        assert!(items[2].is_err());
+    }
+
+    #[test]
+    fn test_reparse() {
+        use crate::dialect::Dialect;
+        use crate::document::{EditError, TextEdit};
+
+        let parser = parser::Parser::new();
+        let source = "A := 1; B := A + 2; C := B * 3";
+        let old = parser.parse_document(source);
+        assert_eq!(old.statements().len(), 3);
+        assert_eq!(&source[old.statements()[1].span.clone()], "B := A + 2;");
+
+        // 只修改第二条语句，其他语句的语法树直接复用
+        let edit = TextEdit {
+            range: 13..18,
+            text: "A + 20".to_string(),
+        };
+        let new = parser.reparse(&old, &edit).unwrap();
+        assert_eq!(new.source(), "A := 1; B := A + 20; C := B * 3");
+        assert!(Rc::ptr_eq(
+            &old.statements()[0].node,
+            &new.statements()[0].node
+        ));
+        assert!(Rc::ptr_eq(
+            &old.statements()[2].node,
+            &new.statements()[2].node
+        ));
+        assert_eq!(new.statements()[2].span, 21..31);
+        let fresh = parser.parse_document(new.source());
+        for (a, b) in new.statements().iter().zip(fresh.statements()) {
+            assert_eq!(a.span, b.span);
+            assert_eq!(a.node.to_string(), b.node.to_string());
+        }
+
+        // 删除 ; 后之后的语句需要与被修改的语句一起重新扫描
+        let edit = TextEdit {
+            range: 19..20,
+            text: String::new(),
+        };
+        let merged = parser.reparse(&new, &edit).unwrap();
+        let fresh = parser.parse_document(merged.source());
+        assert_eq!(merged.statements().len(), fresh.statements().len());
+        for (a, b) in merged.statements().iter().zip(fresh.statements()) {
+            assert_eq!(a.span, b.span);
+            assert_eq!(a.node.to_string(), b.node.to_string());
+        }
+
+        // 超出文档或不在字符边界上的修改返回错误
+        let edit = |range| TextEdit {
+            range,
+            text: String::new(),
+        };
+        let reversed = edit(core::ops::Range { start: 5, end: 3 });
+        assert_eq!(
+            parser.reparse(&merged, &edit(0..100)).unwrap_err(),
+            EditError::OutOfBounds {
+                range: 0..100,
+                len: merged.source().len()
+            }
+        );
+        assert!(parser.reparse(&merged, &reversed).is_err());
+        let text = parser.parse_document("T := \"税率\"");
+        assert_eq!(
+            parser.reparse(&text, &edit(7..8)).unwrap_err(),
+            EditError::NotCharBoundary(7)
+        );
+        let excel = parser::Parser::with_dialect(Dialect::Excel);
+        assert!(excel.reparse(&merged, &edit(0..100)).is_err());
+    }
+
+    #[test]
+    fn test_parse_resilient() {
+        let parser = parser::Parser::new();
+        let (nodes, diagnostics) = parser.parse_resilient("1 +; B := (2); f(x) { x");
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].code, ErrorCode::MissingOperand);
+        assert_eq!(diagnostics[0].span, 0..4);
+        // 到源码末尾仍未闭合的括号作为语法错误
+        assert_eq!(diagnostics[1].code, ErrorCode::SyntaxError);
+        assert!(diagnostics[1].message.contains("{ 没有闭合"));
+        for source in ["(1 + 2", "F(a) { a", "[1, 2", "V[1"] {
+            let (_, diagnostics) = parser.parse_resilient(source);
+            assert_eq!(diagnostics.len(), 1, "{}", source);
+            assert_eq!(diagnostics[0].code, ErrorCode::SyntaxError);
+        }
+
+        // 嵌套过深的语句作为语法错误，之后的语句继续解析
+        let deep = format!("{}1{}; 2", "(".repeat(100_000), ")".repeat(100_000));
+        let (nodes, diagnostics) = parser.parse_resilient(&deep);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(diagnostics[0].code, ErrorCode::SyntaxError);
+        assert_eq!(nodes[1].to_string(), "2");
+        for source in [
+            "1+".repeat(100_000),
+            "!".repeat(100_000),
+            "a(x){".repeat(50_000),
+        ] {
+            let (_, diagnostics) = parser.parse_resilient(&source);
+            assert_eq!(diagnostics[0].code, ErrorCode::SyntaxError);
+        }
+        // 不含括号的运算链不受嵌套层数的限制，超过限制时 calculate 返回对应的语法错误
+        let mut parser = parser::Parser::new();
+        let flat = format!("1{}", " + 1".repeat(300));
+        assert_eq!(parser.calculate(&flat).value, CalculateOption::Num(301.0));
+        match parser.calculate(&deep).value {
+            CalculateOption::Err(msg) => {
+                assert!(msg.starts_with("E0405") && msg.contains("256"), "{}", msg)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // 由记号片段随机组成的源码，解析时不应 panic
+        let pieces = [
+            "(",
+            ")",
+            "[",
+            "]",
+            "{",
+            "}",
+            ",",
+            ";",
+            ":",
+            ":=",
+            "=",
+            "+",
+            "-",
+            "*",
+            "/",
+            "<",
+            "<>",
+            "&",
+            "&&",
+            "|",
+            "!",
+            "^",
+            "@",
+            "@-",
+            "\"",
+            "#",
+            "//",
+            "\n",
+            "1",
+            "2.",
+            ".5i",
+            "a",
+            "B2",
+            "B2:C3",
+            "import",
+            "namespace",
+            "f",
+            "é",
+            " ",
+        ];
+        let excel = parser::Parser::with_dialect(crate::dialect::Dialect::Excel);
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..2000 {
+            let mut source = String::new();
+            for _ in 0..(seed % 24) {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                source.push_str(pieces[(seed % pieces.len() as u64) as usize]);
+            }
+            parser.parse_resilient(&source);
+            excel.parse_resilient(&source);
+        }
+    }
+
+    #[test]
+    fn test_syntax_tables() {
+        use crate::syntax::{self, OperatorKind};
+        use crate::token::{tokenize, TokenKind};
+
+        for op in syntax::OPERATORS {
+            assert_eq!(
+                tokenize(op.symbol),
+                vec![(TokenKind::Operator, 0..op.symbol.len())]
+            );
+            assert!(syntax::GRAMMAR.contains(&format!("\"{}\"", op.symbol)));
+        }
+        for keyword in syntax::KEYWORDS {
+            assert_eq!(tokenize(keyword)[0].0, TokenKind::Keyword);
+        }
+        for punctuation in syntax::PUNCTUATION {
+            assert_eq!(tokenize(punctuation)[0].0, TokenKind::Punctuation);
+        }
+        assert_eq!(syntax::operator("==").unwrap().kind, OperatorKind::Compare);
+        assert!(syntax::operator("<>").is_none());
     }
 
+    /// 逐个验证 tests/cases 中的语法用例，用例的格式见 tests/cases/arithmetic.fml
     #[test]
-    fn test_delay() {}
+    fn test_conformance() {
+        use crate::dialect::Dialect;
+
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cases");
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "fml"))
+            .collect();
+        files.sort();
+
+        let mut count = 0;
+        let mut failures = Vec::new();
+        for file in files {
+            let text = std::fs::read_to_string(&file).unwrap();
+            let file = file.file_name().unwrap().to_string_lossy().to_string();
+            // 每个用例为 (名称, 各部分的内容)，第一部分为源码
+            type Sections = Vec<(String, Vec<String>)>;
+            let mut cases: Vec<(String, Sections)> = Vec::new();
+            for line in text.lines() {
+                if let Some(name) = line.strip_prefix("=== ") {
+                    cases.push((name.to_string(), vec![(String::new(), Vec::new())]));
+                } else if let Some((_, sections)) = cases.last_mut() {
+                    match line.strip_prefix("--- ") {
+                        Some(section) => sections.push((section.to_string(), Vec::new())),
+                        None => sections.last_mut().unwrap().1.push(line.to_string()),
+                    }
+                }
+            }
+
+            for (name, sections) in cases {
+                count += 1;
+                let mut parser = match name.strip_suffix(" [excel]") {
+                    Some(_) => parser::Parser::with_dialect(Dialect::Excel),
+                    None => parser::Parser::new(),
+                };
+                let mut fail = |msg: String| failures.push(format!("{} / {}: {}", file, name, msg));
+                let source = sections[0].1.join("\n");
+                for (section, mut lines) in sections.into_iter().skip(1) {
+                    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+                        lines.pop();
+                    }
+                    match section.as_str() {
+                        "ast" => {
+                            let ast: Vec<String> = parser
+                                .parse_document(&source)
+                                .statements()
+                                .iter()
+                                .map(|s| s.node.to_string())
+                                .collect();
+                            if ast != lines {
+                                fail(format!("语法树为 {:?}，应为 {:?}", ast, lines));
+                            }
+                        }
+                        "result" => {
+                            let value = parser.calculate(&source).value.to_string();
+                            if value != lines.join("\n") {
+                                fail(format!("结果为 {}，应为 {}", value, lines.join("\n")));
+                            }
+                        }
+                        "error" => match parser.try_calculate(&source) {
+                            Err(e) if e.message().contains(&lines.join("\n")) => (),
+                            other => fail(format!("结果为 {:?}，应出错", other)),
+                        },
+                        _ => fail(format!("未知的部分: {}", section)),
+                    }
+                }
+            }
+        }
+        assert!(count > 0);
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    proptest::proptest! {
+        /// 四则运算的语法树逐节点计算、通过后缀指令计算及输出为源码后重新解析计算的结果一致
+        #[test]
+        fn prop_arith_evaluators_agree(
+            node in crate::strategy::arith_node(&["A", "B"]),
+            a in 0.0..100.0f64,
+            b in 0.0..100.0f64,
+        ) {
+            let same = |l: &CalculateOption, r: f64| match l {
+                CalculateOption::Num(l) => l.to_bits() == r.to_bits() || (l.is_nan() && r.is_nan()),
+                _ => false,
+            };
+            let env = Env::new();
+            env.borrow_mut().insert("A", Rc::new(formula::FormulaNode::Constant(a)));
+            env.borrow_mut().insert("B", Rc::new(formula::FormulaNode::Constant(b)));
+            let walked = match node.calc(&env) {
+                CalculateOption::Num(n) => n,
+                other => panic!("四则运算的结果应为数值: {:?}", other),
+            };
+
+            let compiled = crate::compiled::CompiledFormula::new(Rc::new(node.clone()), env);
+            proptest::prop_assert!(compiled.is_arithmetic());
+            let fast = compiled.eval().value;
+            proptest::prop_assert!(same(&fast, walked), "{} = {:?}, 应为 {}", node, fast, walked);
+
+            let mut parser = parser::Parser::new();
+            parser.set_var("A", a);
+            parser.set_var("B", b);
+            let reparsed = parser.calculate(node.to_string()).value;
+            proptest::prop_assert!(same(&reparsed, walked), "{} = {:?}, 应为 {}", node, reparsed, walked);
+
+            // 设置了默认精度时，编译后的快速路径与逐节点计算同样舍入最终结果
+            parser.set_precision(Some(crate::rounding::Precision::new(2, crate::rounding::RoundingMode::HalfUp)));
+            let rounded = parser.calculate(node.to_string()).value;
+            let fast = parser.compile(node.to_string()).eval().value;
+            proptest::prop_assert!(
+                rounded == fast || rounded.as_num().zip(fast.as_num()).is_some_and(|(l, r)| l.is_nan() && r.is_nan()),
+                "{} = {:?}, 应为 {:?}", node, fast, rounded
+            );
+        }
+
+        /// 四则运算的语法树经 format_formula 整理后重新解析得到相同的语法树
+        #[test]
+        fn prop_format_round_trip(node in crate::strategy::arith_node(&["A", "B"])) {
+            let options = crate::format::FormatOptions::default();
+            let formatted = crate::format::format_formula(&node.to_string(), options);
+            let reparsed = parser::Parser::new().parse(&formatted);
+            proptest::prop_assert_eq!(reparsed.as_ref(), &node, "{}", formatted);
+        }
+
+        /// 自定义的内建函数在任意参数下都只会得到数值或错误
+        #[test]
+        fn prop_fuzz_build_in(call in crate::strategy::function_call("Ratio", 2, &["A"])) {
+            let parser = parser::Parser::new();
+            parser.set_var("A", 2.0);
+            parser
+                .reg_fallible_build_in("Ratio", |c| {
+                    c.args_len_must(2)?;
+                    match c.arg_num(1)? {
+                        0.0 => Err(formula::CalcError::new("除数不能为 0")),
+                        d => Ok(CalculateOption::Num(c.arg_num(0)? / d)),
+                    }
+                })
+                .unwrap();
+            let value = parser.compile_node(Rc::new(call)).eval().value;
+            proptest::prop_assert!(
+                matches!(value, CalculateOption::Num(_) | CalculateOption::Err(_)),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_cache() {
+        let mut parser = parser::Parser::new();
+        parser.calculate("1 + 2");
+        assert_eq!(parser.parse_cache_len(), 0);
+
+        parser.set_parse_cache(2);
+        parser.parse("X := 1");
+        assert_eq!(parser.calculate("X + 1").value, CalculateOption::Num(2.0));
+        let node = parser.parse("X + 1");
+        assert!(Rc::ptr_eq(&node, &parser.parse("X + 1")));
+        // 包含定义的公式不会被缓存
+        assert_eq!(parser.parse_cache_len(), 1);
+        parser.parse("X := 5");
+        assert_eq!(parser.calculate("X + 1").value, CalculateOption::Num(6.0));
+
+        // 超过容量时淘汰最久未使用的公式
+        parser.parse("X * 2");
+        parser.parse("X + 1");
+        parser.parse("X * 3");
+        assert_eq!(parser.parse_cache_len(), 2);
+        assert!(Rc::ptr_eq(&node, &parser.parse("X + 1")));
+
+        parser.set_parse_cache(0);
+        assert_eq!(parser.parse_cache_len(), 0);
+    }
+
+    #[test]
+    fn test_env_lookup_cache() {
+        let constant = |n: f64| Rc::new(formula::FormulaNode::Constant(n));
+        let root = Env::new();
+        root.borrow_mut().insert("A", constant(1.0));
+        let mut chain = vec![Rc::clone(&root)];
+        for _ in 0..4 {
+            let child = Env::extend(chain.last().unwrap());
+            chain.push(child);
+        }
+        let leaf = chain.last().unwrap();
+        let value = |env: &crate::env::EnvType| match env.borrow().get("A").as_deref() {
+            Some(formula::FormulaNode::Constant(n)) => Some(*n),
+            _ => None,
+        };
+        assert_eq!(value(leaf), Some(1.0));
+        assert_eq!(value(leaf), Some(1.0));
+
+        // 重新定义、被中间层级遮蔽及移除后，缓存的查找结果都应失效
+        root.borrow_mut().insert("A", constant(2.0));
+        assert_eq!(value(leaf), Some(2.0));
+        chain[2].borrow_mut().insert("A", constant(3.0));
+        assert_eq!(value(leaf), Some(3.0));
+        chain[2].borrow_mut().remove("A");
+        root.borrow_mut().remove("A");
+        assert_eq!(value(leaf), None);
+    }
+
+    #[test]
+    fn test_env_pool() {
+        let root = Env::new();
+        let child = Env::extend(&root);
+        child
+            .borrow_mut()
+            .insert("A", Rc::new(formula::FormulaNode::Constant(1.0)));
+        Env::release(child);
+        assert_eq!(root.borrow().pooled(), 1);
+
+        let reused = Env::extend(&root);
+        assert_eq!(root.borrow().pooled(), 0);
+        assert!(reused.borrow().get("A").is_none());
+
+        // 仍被引用的环境不会被回收
+        let held = Rc::clone(&reused);
+        Env::release(reused);
+        assert_eq!(root.borrow().pooled(), 0);
+        drop(held);
+
+        let mut parser = parser::Parser::new();
+        parser.parse("Inc(x) { x + 1 }".to_string());
+        for i in 0..10 {
+            assert_eq!(
+                parser.calculate(format!("Inc(Inc({}))", i)).value,
+                CalculateOption::Num(i as f64 + 2.0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lexical_scope() {
+        let mut parser = parser::Parser::new();
+        parser.parse("x := 100".to_string());
+        parser.parse("Double(x) { x * 2 }".to_string());
+        parser.parse("AddX(y) { y + x }".to_string());
+        parser.parse("Outer(x) { AddX(x) }".to_string());
+        parser.parse("Local(y) { x := y + 1; x * 2 }".to_string());
+
+        // 参数遮蔽同名的全局变量
+        assert_eq!(
+            parser.calculate("Double(3)".to_string()).value,
+            CalculateOption::Num(6.0)
+        );
+        // 被调用的函数看不到调用者的参数，只能看到定义处的全局变量
+        assert_eq!(
+            parser.calculate("Outer(1)".to_string()).value,
+            CalculateOption::Num(101.0)
+        );
+        // 函数体中的局部变量不会修改全局变量
+        assert_eq!(
+            parser.calculate("Local(1)".to_string()).value,
+            CalculateOption::Num(4.0)
+        );
+        assert_eq!(
+            parser.calculate("x".to_string()).value,
+            CalculateOption::Num(100.0)
+        );
+
+        // 函数引用的全局变量在调用时读取
+        parser.parse("x := 10".to_string());
+        assert_eq!(
+            parser.calculate("AddX(1)".to_string()).value,
+            CalculateOption::Num(11.0)
+        );
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 1".to_string());
+        parser.parse("B := 2".to_string());
+        parser.parse("C := 6".to_string());
+        parser.set_column("V", vec![4.0, 5.0]);
+
+        let cases = [
+            ("Sum(A, B, C)", 9.0),
+            ("Avg(A, B, C)", 3.0),
+            ("Min(A, B, C)", 1.0),
+            ("Max(A, V)", 5.0),
+            ("Count(A, V)", 3.0),
+            ("Product(B, C, V)", 240.0),
+            ("Sum()", 0.0),
+        ];
+        for (formula, expected) in cases.iter() {
+            assert_eq!(
+                parser.calculate(formula.to_string()).value,
+                CalculateOption::Num(*expected),
+                "{}",
+                formula
+            );
+        }
+
+        match parser.calculate("Avg()".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("没有参数时 Avg 应返回错误，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut parser = parser::Parser::new();
+        parser.set_column("X", vec![1.0, 2.0, 3.0, 4.0]);
+        parser.set_column("Y", vec![2.0, 4.0, 6.0, 8.0]);
+        parser.set_column("Z", vec![8.0, 6.0, 4.0, 2.0]);
+
+        let cases = [
+            ("Median(X)", 2.5),
+            ("Median(X, 10)", 3.0),
+            ("Variance(X)", 5.0 / 3.0),
+            ("StdDev(Y)", (20.0f64 / 3.0).sqrt()),
+            ("Percentile(X, 0.5)", 2.5),
+            ("Percentile(X, 1)", 4.0),
+            ("Correl(X, Y)", 1.0),
+            ("Correl(X, Z)", -1.0),
+        ];
+        for (formula, expected) in cases.iter() {
+            match parser.calculate(formula.to_string()).value {
+                CalculateOption::Num(n) => assert!((n - expected).abs() < 1e-9, "{}", formula),
+                v => panic!("{} 的计算结果应为数值，实际为 {:?}", formula, v),
+            }
+        }
+
+        match parser.calculate("Percentile(X, 2)".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("k 超出范围时 Percentile 应返回错误，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_if_error() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 2".to_string());
+
+        assert_eq!(
+            parser.calculate("IfError(Missing, 0)".to_string()).value,
+            CalculateOption::Num(0.0)
+        );
+        assert_eq!(
+            parser.calculate("IfError(Avg(), A)".to_string()).value,
+            CalculateOption::Num(2.0)
+        );
+        assert_eq!(
+            parser.calculate("IfError(A * 3, 0)".to_string()).value,
+            CalculateOption::Num(6.0)
+        );
+    }
+
+    #[test]
+    fn test_stateful_build_in() {
+        let mut parser = parser::Parser::new();
+        let mut count = 0.0;
+        parser
+            .reg_stateful_build_in("Counter", move |ctx| {
+                for i in 0..ctx.args.len() {
+                    ctx.arg(i)?;
+                }
+                count += 1.0;
+                Ok(CalculateOption::Num(count))
+            })
+            .unwrap();
+
+        assert_eq!(
+            parser.calculate("Counter()".to_string()).value,
+            CalculateOption::Num(1.0)
+        );
+        assert_eq!(
+            parser.calculate("Counter()".to_string()).value,
+            CalculateOption::Num(2.0)
+        );
+        match parser.calculate("Counter(Counter())".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("执行过程中再次调用应返回错误，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_build_in_arity() {
+        let mut parser = parser::Parser::new();
+        parser
+            .reg_build_in_with_arity("Clamp", 1, Some(3), |ctx| {
+                let v = ctx.arg_num(0)?;
+                let lo = ctx.arg_num(1).unwrap_or(0.0);
+                let hi = ctx.arg_num(2).unwrap_or(1.0);
+                Ok(CalculateOption::Num(v.max(lo).min(hi)))
+            })
+            .unwrap();
+
+        assert_eq!(
+            parser.calculate("Clamp(5)".to_string()).value,
+            CalculateOption::Num(1.0)
+        );
+        assert_eq!(
+            parser.calculate("Clamp(5, 0, 10)".to_string()).value,
+            CalculateOption::Num(5.0)
+        );
+        for formula in ["Clamp()", "Clamp(1, 2, 3, 4)", "Percentile(1)"] {
+            match parser.calculate(formula.to_string()).value {
+                CalculateOption::Err(e) => assert!(e.contains("参数"), "{}", e),
+                v => panic!("{} 参数个数错误时应返回错误，实际为 {:?}", formula, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_in_ns() {
+        let mut parser = parser::Parser::new();
+        parser
+            .reg_build_in_ns("math", "sqrt", |ctx| {
+                Ok(CalculateOption::Num(ctx.arg_num(0)?.sqrt()))
+            })
+            .unwrap();
+        parser
+            .reg_build_in_ns("stats", "sqrt", |_| Ok(CalculateOption::Num(0.0)))
+            .unwrap();
+
+        assert_eq!(
+            parser.calculate("math.sqrt(16)".to_string()).value,
+            CalculateOption::Num(4.0)
+        );
+        assert_eq!(
+            parser.calculate("stats.sqrt(16)".to_string()).value,
+            CalculateOption::Num(0.0)
+        );
+        assert_eq!(
+            parser.reg_build_in_ns("math", "sqrt", |_| Ok(CalculateOption::None)),
+            Err(EnvError::Duplicate("math.sqrt".to_string()))
+        );
+        assert_eq!(
+            parser.try_reg_build_in("Sum", |_| Ok(CalculateOption::None)),
+            Err(EnvError::Duplicate("Sum".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_builtins_info() {
+        let parser = parser::Parser::new();
+        parser
+            .reg_build_in_with_info(
+                BuiltinInfo {
+                    name: "Twice".to_string(),
+                    description: "将数值乘以 2".to_string(),
+                    params: vec!["x".to_string()],
+                    examples: vec!["Twice(2)".to_string()],
+                    arity: Some((1, Some(1))),
+                },
+                |ctx| Ok(CalculateOption::Num(ctx.arg_num(0)? * 2.0)),
+            )
+            .unwrap();
+        parser.reg_build_in("Plain", |_| CalculateOption::None);
+
+        let builtins = parser.builtins();
+        let twice = builtins.iter().find(|b| b.name == "Twice").unwrap();
+        assert_eq!(twice.params, vec!["x".to_string()]);
+        assert_eq!(twice.arity, Some((1, Some(1))));
+
+        let plain = builtins.iter().find(|b| b.name == "Plain").unwrap();
+        assert!(plain.description.is_empty());
+
+        let sum = builtins.iter().find(|b| b.name == "Sum").unwrap();
+        assert_eq!(sum.arity, Some((0, None)));
+    }
+
+    #[test]
+    fn test_plugin() {
+        struct Finance;
+
+        impl FormulaPlugin for Finance {
+            fn name(&self) -> &str {
+                "finance"
+            }
+
+            fn install(&self, parser: &mut parser::Parser) -> Result<(), EnvError> {
+                parser.set_var("finance.Rate", 0.05);
+                parser.reg_build_in_ns("finance", "Interest", |ctx| {
+                    Ok(CalculateOption::Num(ctx.arg_num(0)? * 0.05))
+                })
+            }
+        }
+
+        let mut parser = parser::Parser::new();
+        parser.load_plugin(&Finance).unwrap();
+        // 同名的函数包不会被重复加载
+        parser.load_plugin(&Finance).unwrap();
+        assert_eq!(
+            parser.plugins(),
+            &["std".to_string(), "finance".to_string()]
+        );
+        assert_eq!(
+            parser.calculate("finance.Interest(200)".to_string()).value,
+            CalculateOption::Num(10.0)
+        );
+    }
+
+    #[test]
+    fn test_bind_object() {
+        struct Order {
+            qty: f64,
+            price: f64,
+            vip: bool,
+        }
+        crate::formula_bind!(Order { qty, price, vip });
+
+        let mut parser = parser::Parser::new();
+        assert!(parser.bind(
+            "Order",
+            &Order {
+                qty: 2.0,
+                price: 3.0,
+                vip: true,
+            },
+        ));
+        assert_eq!(
+            parser
+                .calculate("Order.qty * Order.price".to_string())
+                .value,
+            CalculateOption::Num(6.0)
+        );
+        assert_eq!(
+            parser.calculate("Order.vip".to_string()).value,
+            CalculateOption::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_function_value() {
+        let mut parser = parser::Parser::new();
+        parser.parse("F(a, b) { (a + b) * 2 }".to_string());
+        parser.parse("Twice(f, x) { f(f(x, 0), 0) }".to_string());
+        parser.parse("Call(g, x) { Apply(g, x, 1) }".to_string());
+
+        assert_eq!(
+            parser.calculate("Twice(F, 1)".to_string()).value,
+            CalculateOption::Num(4.0)
+        );
+        assert_eq!(
+            parser.calculate("Call(F, 1)".to_string()).value,
+            CalculateOption::Num(4.0)
+        );
+        assert_eq!(
+            parser.calculate("Apply(Sum, 1, 2, 3)".to_string()).value,
+            CalculateOption::Num(6.0)
+        );
+        match parser.calculate("F".to_string()).value {
+            CalculateOption::Function(f) => assert!(f.is_function()),
+            v => panic!("F 的值应为函数，实际为 {:?}", v),
+        }
+        // 函数定义的值与引用函数名得到的是执行环境中的同一个定义
+        match (
+            parser.calculate("G(x) { x }").value,
+            parser.calculate("G").value,
+        ) {
+            (CalculateOption::Function(defined), CalculateOption::Function(referenced)) => {
+                assert!(Rc::ptr_eq(&defined, &referenced))
+            }
+            v => panic!("G 的值应为函数，实际为 {:?}", v),
+        }
+        match parser.calculate("Apply(1, 2)".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("非函数值无法被调用，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_map_filter_reduce() {
+        let mut parser = parser::Parser::new();
+        parser.set_column("V", vec![1.0, -2.0, 3.0]);
+        parser.parse("Double(x) { x * 2 }".to_string());
+        parser.parse("IsPositive(x) { x > 0 }".to_string());
+        parser.parse("Add(acc, x) { acc + x }".to_string());
+
+        assert_eq!(
+            parser.calculate("Map(V, Double)".to_string()).value,
+            CalculateOption::Vector(vec![2.0, -4.0, 6.0])
+        );
+        assert_eq!(
+            parser.calculate("Filter(V, IsPositive)".to_string()).value,
+            CalculateOption::Vector(vec![1.0, 3.0])
+        );
+        assert_eq!(
+            parser.calculate("Reduce(V, Add, 10)".to_string()).value,
+            CalculateOption::Num(12.0)
+        );
+        assert_eq!(
+            parser
+                .calculate("Reduce(Filter(Map(V, Double), IsPositive), Add, 0)".to_string())
+                .value,
+            CalculateOption::Num(8.0)
+        );
+        match parser.calculate("Filter(V, Double)".to_string()).value {
+            CalculateOption::Err(_) => (),
+            v => panic!("断言函数应返回布尔值，实际为 {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let mut parser = parser::Parser::new();
+        let node = parser.parse("X := A + B * (C - 1)".to_string());
+        assert_eq!(node.to_string(), "X := (A + (B * (C - 1)))");
+
+        assert_eq!(
+            parser::format_source("F(a, b) { c := a + b; c * 2 }"),
+            "F(a, b) { c := (a + b); (c * 2) }"
+        );
+        assert_eq!(
+            parser::format_source("(F(1, 2i) >= 3) && !B@-1"),
+            "((F(1, 2i) >= 3) && !B@-1)"
+        );
+        assert_eq!(parser::format_source("A := 1;\nB := 2"), "A := 1\nB := 2");
+
+        // 规范化后的文本重新解析应得到相同的文本
+        let formatted = parser::format_source("A+B/C");
+        assert_eq!(parser::format_source(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_format_formula() {
+        use crate::format::{format_formula, FormatOptions};
+
+        let format = |source: &str| format_formula(source, FormatOptions::default());
+        assert_eq!(format("X:=A+B*(C-1)"), "X := A + (B * (C - 1))");
+        assert_eq!(format("A+B+C ; (A-B)-C"), "A + B + C\n(A - B) - C");
+        assert_eq!(format("a<b<c"), "(a < b) && (b < c)");
+        assert_eq!(format("a&&b&&!(c||d)+1"), "(a && b && !(c || d)) + 1");
+        assert_eq!(
+            format("F( a,b ){c:=a+b;c*2}"),
+            "F(a, b) { c := a + b; c * 2 }"
+        );
+
+        // 注释及空行被保留，包含注释或语法错误的语句原样输出
+        let source =
+            "# 税率\nRate := 0.1 ; // 默认值\n\n\nTax := Price*Rate\nA + # 注释\n B\nC +\n";
+        assert_eq!(
+            format(source),
+            "# 税率\nRate := 0.1 // 默认值\n\nTax := Price * Rate\nA + # 注释\n B\nC +"
+        );
+
+        // 超过宽度的函数体每个表达式占一行
+        let options = FormatOptions {
+            indent: 2,
+            max_width: 24,
+            semicolons: true,
+        };
+        assert_eq!(
+            format_formula(
+                "namespace tax { Rate := 0.1; Calc(x) { x * Rate } }",
+                options
+            ),
+            "namespace tax {\n  Rate := 0.1;\n  Calc(x) { x * Rate }\n};"
+        );
+
+        // 整理后的源码解析得到相同的语法树，再次整理不会发生变化
+        let source =
+            "A := (B - C) / D * E; F(x) { y := !x && x > 1; If(y, x - 1 - 2, 0) }; G@-1 >= 2";
+        let formatted = format(source);
+        assert_eq!(format(&formatted), formatted);
+        assert_eq!(
+            parser::format_source(&formatted),
+            parser::format_source(source)
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        use crate::diff::{diff, AstChange};
+
+        let mut parser = parser::Parser::new();
+        let old = parser.parse("Tax := (Price * 0.2) + Sum(A, B)");
+        let new = parser.parse("Tax := (Price / 0.3) + Sum(A, B, C)");
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                AstChange::OperatorChanged {
+                    path: "left".to_string(),
+                    old: "*",
+                    new: "/",
+                },
+                AstChange::ConstantChanged {
+                    path: "left.right".to_string(),
+                    old: 0.2,
+                    new: 0.3,
+                },
+                AstChange::ArgumentAdded {
+                    path: "right".to_string(),
+                    index: 2,
+                    node: "C".to_string(),
+                },
+            ]
+        );
+        assert!(diff(&old, &parser.parse("Tax := ((Price*0.2)) + (Sum(A,B))")).is_empty());
+
+        // 按名称对应定义，不修改执行环境
+        let changes = parser.diff(
+            "Rate := 0.1; F(x) { x * Rate }; Old := 1",
+            "# 调整税率\nF(x) { x * Rate * 2 }; Rate := 0.12; New := 2",
+        );
+        let lines: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Rate: 常量 0.1 改为 0.12",
+                "F.[0].right: Rate 改为 (Rate * 2)",
+                "删除了 Old := 1",
+                "增加了 New := 2",
+            ]
+        );
+        assert_eq!(parser.calculate("Rate").value.as_num(), None);
+    }
+
+    #[test]
+    fn test_provenance() {
+        use crate::provenance::Provenance;
+
+        let mut parser = parser::Parser::new();
+        parser.set_importer(|path: &str| match path {
+            "tax.fml" => Ok("## 增值税率\n## 依据 2024 年的政策\n## @author alice\n\
+                 ## @version 2.1\nnamespace tax { Rate := 0.13 }"
+                .to_string()),
+            _ => Err("not found".to_string()),
+        });
+        parser.parse("import \"tax.fml\";\nA := 1 ## 不属于 B\n# 普通注释\nB := 2");
+        assert_eq!(
+            parser.provenance("tax.Rate").as_deref(),
+            Some(&Provenance {
+                source: Some("tax.fml".to_string()),
+                author: Some("alice".to_string()),
+                version: Some("2.1".to_string()),
+                description: Some("增值税率\n依据 2024 年的政策".to_string()),
+            })
+        );
+        assert!(parser.provenance("A").is_none() && parser.provenance("B").is_none());
+
+        parser.parse("## 折扣\nDiscount(x) { x * 0.9 }");
+        let info = parser.env_info();
+        let discount = info.iter().find(|i| i.name == "Discount").unwrap();
+        let provenance = discount.provenance.as_ref().unwrap();
+        assert_eq!(provenance.description.as_deref(), Some("折扣"));
+        assert_eq!(provenance.source, None);
+
+        // 重新定义时清除原来的来源信息
+        parser.parse("Discount(x) { x * 0.8 }");
+        assert!(parser.provenance("Discount").is_none());
+
+        parser.set_var("Price", 10.0);
+        assert!(parser.set_provenance("Price", Provenance::from_doc(["@author erp"])));
+        assert_eq!(
+            parser.provenance("Price").unwrap().author.as_deref(),
+            Some("erp")
+        );
+        assert!(!parser.set_provenance("Missing", Provenance::default()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_ast_serde() {
+        let mut parser = parser::Parser::new();
+        let node = parser.parse("Price * (Qty + 1) > 10 && !Closed".to_string());
+        let json = serde_json::to_string(node.as_ref()).unwrap();
+
+        let cached: formula::FormulaNode = serde_json::from_str(&json).unwrap();
+        assert_eq!(cached.to_string(), node.to_string());
+
+        // 缓存的语法树无需重新解析即可计算
+        let cached: formula::FormulaNode = serde_json::from_str(
+            &serde_json::to_string(&*parser.parse("Price * Qty".to_string())).unwrap(),
+        )
+        .unwrap();
+        let compiled = parser.compile_node(Rc::new(cached));
+        let mut row = HashMap::new();
+        row.insert("Price".to_string(), 2.0);
+        row.insert("Qty".to_string(), 3.0);
+        assert_eq!(compiled.eval_with(&row).value, CalculateOption::Num(6.0));
+
+        let json = serde_json::to_string(&CalculateOption::Vector(vec![1.0, 2.0])).unwrap();
+        let value: CalculateOption = serde_json::from_str(&json).unwrap();
+        assert_eq!(value, CalculateOption::Vector(vec![1.0, 2.0]));
+    }
+
+    #[test]
+    fn test_expr_builder() {
+        use crate::expr::Expr;
+
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 4".to_string());
+        parser.parse("B := 3".to_string());
+
+        let expr =
+            Expr::var("A") + Expr::num(2.0) * Expr::call("Max", vec![Expr::var("B"), 1.0.into()]);
+        let node = expr.into_node();
+        assert_eq!(node.to_string(), "(A + (2 * Max(B, 1)))");
+        assert_eq!(
+            parser.compile_node(Rc::new(node)).eval().value,
+            CalculateOption::Num(10.0)
+        );
+
+        let cond = (Expr::var("A") - 1.0)
+            .gt(Expr::var("B"))
+            .and(!Expr::bool(false));
+        assert_eq!(
+            parser.compile_node(Rc::new(cond.into())).eval().value,
+            CalculateOption::Bool(false)
+        );
+
+        parser.define("Half", Expr::var("A") / 2.0).unwrap();
+        assert_eq!(
+            parser.calculate("Half".to_string()).value,
+            CalculateOption::Num(2.0)
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        use crate::diagnostic::Severity;
+
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 1".to_string());
+        parser.parse("F(a, b) { a + b }".to_string());
+
+        assert!(parser.check("F(A, 2) + Sum(A, 1)").is_empty());
+        assert!(parser.check("G(x) { x * F(x, A) }").is_empty());
+        assert!(parser.check(String::from("F(A, 2)")).is_empty());
+
+        let source = "F(A) + B * Percentile(A)";
+        let diagnostics = parser.check(source);
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+        let spans: Vec<&str> = diagnostics
+            .iter()
+            .map(|d| &source[d.span.clone()])
+            .collect();
+        assert_eq!(spans, vec!["F", "B", "Percentile"]);
+
+        // 覆盖已有的定义只是警告，检查不会修改执行环境
+        let diagnostics = parser.check("A := 2");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(
+            parser.calculate("A".to_string()).value,
+            CalculateOption::Num(1.0)
+        );
+
+        assert_eq!(parser.check("A +").len(), 1);
+    }
+
+    #[test]
+    fn test_unresolved_names() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.1".to_string());
+
+        assert_eq!(
+            parser.unresolved_names("Price * Qty * (1 + Rate) + Discount(Price)"),
+            vec!["Price", "Qty", "Discount"]
+        );
+        assert!(parser
+            .unresolved_names("Net(x) { x * (1 - Rate) }")
+            .is_empty());
+
+        parser.set_var("Price", 10.0);
+        assert_eq!(parser.unresolved_names("Price * Qty"), vec!["Qty"]);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 3".to_string());
+        parser.parse("F(a, b) { a * b }".to_string());
+        assert!(parser.calculate("F(A, 2)".to_string()).trace.is_none());
+
+        parser.set_options(CalculateOptions {
+            trace: true,
+            ..Default::default()
+        });
+        let trace = parser.calculate("F(A, 2) + 1".to_string()).trace.unwrap();
+        assert_eq!(trace.expr, "(F(A, 2) + 1)");
+        assert_eq!(trace.value, CalculateOption::Num(7.0));
+
+        let call = &trace.children[0];
+        assert_eq!(call.expr, "F(A, 2)");
+        let exprs: Vec<&str> = call.children.iter().map(|c| c.expr.as_str()).collect();
+        assert_eq!(exprs, vec!["A", "2", "(a * b)"]);
+        assert_eq!(
+            call.children[0].children[0].value,
+            CalculateOption::Num(3.0)
+        );
+        assert!(trace
+            .to_string()
+            .starts_with("(F(A, 2) + 1) => Num(7.0)\n  F(A, 2) => Num(6.0)"));
+    }
+
+    #[test]
+    fn test_observer() {
+        use crate::env::EvalObserver;
+        use crate::formula::FormulaNode;
+
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl EvalObserver for Recorder {
+            fn on_enter_node(&self, node: &FormulaNode, _env: &Env) {
+                self.0.borrow_mut().push(format!("enter {}", node));
+            }
+
+            fn on_function_call(&self, name: &str, args: &[Rc<FormulaNode>], _env: &Env) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("call {}/{}", name, args.len()));
+            }
+
+            fn on_error(&self, node: &FormulaNode, _message: &str, _env: &Env) {
+                self.0.borrow_mut().push(format!("error {}", node));
+            }
+        }
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut parser = parser::Parser::new();
+        parser.parse("F(a) { a * 2 }".to_string());
+        parser.set_observer(Recorder(Rc::clone(&events)));
+
+        parser.calculate("F(1) + 1".to_string());
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "enter (F(1) + 1)",
+                "enter F(1)",
+                "call F/1",
+                "enter 1",
+                "enter (a * 2)",
+                "enter a",
+                "enter 1",
+                "enter 2",
+                "enter 1",
+            ]
+        );
+
+        // 错误只在最先产生该错误的节点上通知一次
+        events.borrow_mut().clear();
+        parser.calculate("(X + 1) * 2".to_string());
+        let errors: Vec<String> = events
+            .borrow()
+            .iter()
+            .filter(|e| e.starts_with("error"))
+            .cloned()
+            .collect();
+        assert_eq!(errors, vec!["error X"]);
+
+        parser.clear_observer();
+        events.borrow_mut().clear();
+        parser.calculate("F(1)".to_string());
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_debugger() {
+        use crate::debugger::{Breakpoint, Debugger, StepKind};
+
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 2".to_string());
+        parser.parse("F(a) { b := a + 1; b * Rate }".to_string());
+
+        let mut debugger = Debugger::new(&mut parser);
+        debugger.add_breakpoint(Breakpoint::FunctionCall("F".to_string()));
+        debugger.add_breakpoint(Breakpoint::Variable("Rate".to_string()));
+        debugger.start("F(3) + 1".to_string());
+        assert_eq!(debugger.value(), Some(&CalculateOption::Num(9.0)));
+
+        let step = debugger.step().unwrap();
+        assert_eq!(
+            (step.kind, step.expr.as_str()),
+            (StepKind::Enter, "(F(3) + 1)")
+        );
+
+        let step = debugger.resume().unwrap();
+        assert_eq!((step.kind, step.expr.as_str()), (StepKind::Call, "F"));
+
+        // 在函数体中引用 Rate 时，可以看到参数及局部变量的值
+        let step = debugger.resume().unwrap();
+        assert_eq!(step.name.as_deref(), Some("Rate"));
+        assert_eq!(
+            debugger.frame(),
+            &[
+                ("a".to_string(), "3".to_string()),
+                ("b".to_string(), "4".to_string())
+            ]
+        );
+
+        let step = debugger.step().unwrap();
+        assert_eq!(step.expr, "Rate := 2");
+        assert!(debugger.resume().is_none());
+        assert!(debugger.is_finished());
+    }
+
+    #[test]
+    fn test_call_frames() {
+        let mut parser = parser::Parser::new();
+        parser.parse("G(x) { x * 2 }".to_string());
+        parser.parse("F(a) { G(a + 1) + 1 }".to_string());
+
+        let result = parser.calculate("F(3) + Sum(1, 2)".to_string());
+        assert_eq!(result.value, CalculateOption::Num(12.0));
+
+        let calls: Vec<&str> = result.more.iter().map(|f| f.func.as_str()).collect();
+        assert_eq!(calls, vec!["F", "Sum"]);
+
+        let f = &result.more[0];
+        assert_eq!(f.arg_values, vec![CalculateOption::Num(3.0)]);
+        assert_eq!(f.value, CalculateOption::Num(9.0));
+        assert_eq!(f.children.len(), 1);
+
+        let g = &f.children[0];
+        assert_eq!(g.func, "G");
+        assert_eq!(g.args[0].to_string(), "(a + 1)");
+        assert_eq!(g.arg_values, vec![CalculateOption::Num(4.0)]);
+        assert_eq!(g.value, CalculateOption::Num(8.0));
+        assert!(g.children.is_empty());
+    }
+
+    #[test]
+    fn test_tail_call() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Loop(n, acc) { If(n > 0, Loop(n - 1, acc + n), acc) }");
+        assert_eq!(
+            parser.calculate("Loop(100000, 0)").value,
+            CalculateOption::Num(5000050000.0)
+        );
+
+        // 之后的尾调用合并在第一次调用的记录中
+        let result = parser.calculate("Loop(2, 0)");
+        assert_eq!(result.value, CalculateOption::Num(3.0));
+        assert_eq!(result.more.len(), 1);
+        assert_eq!(result.more[0].value, CalculateOption::Num(3.0));
+        assert_eq!(
+            result.more[0].arg_values,
+            vec![CalculateOption::Num(2.0), CalculateOption::Num(0.0)]
+        );
+
+        // 尾部位置的 If 与普通调用一样受安全策略限制
+        parser.parse("Count(n) { If(n > 0, Count(n - 1), 7) }");
+        parser.set_security_policy(crate::policy::SecurityPolicy {
+            allowed_build_ins: Some(Default::default()),
+            ..Default::default()
+        });
+        match parser.calculate("Count(3)").value {
+            CalculateOption::Err(msg) => assert!(msg.starts_with("E0211"), "{}", msg),
+            other => panic!("unexpected {:?}", other),
+        }
+        parser.set_security_policy(crate::policy::SecurityPolicy::default());
+        assert_eq!(
+            parser.calculate("Count(3)").value,
+            CalculateOption::Num(7.0)
+        );
+
+        // 宿主程序重新注册的 If 在尾部位置同样生效
+        parser
+            .reg_fallible_build_in("If", |_| Ok(CalculateOption::Num(42.0)))
+            .unwrap();
+        assert_eq!(
+            parser.calculate("Count(3)").value,
+            CalculateOption::Num(42.0)
+        );
+    }
+
+    #[test]
+    fn test_record_stack() {
+        let mut parser = parser::Parser::new();
+        parser.parse("G(x) { x * 2 }");
+        assert_eq!(parser.calculate("G(1) + Sum(1, 2)").more.len(), 2);
+
+        parser.set_options(CalculateOptions {
+            record_stack: false,
+            ..Default::default()
+        });
+        let result = parser.calculate("G(1) + Sum(1, 2)");
+        assert_eq!(result.value, CalculateOption::Num(5.0));
+        assert!(result.more.is_empty());
+    }
+
+    #[test]
+    fn test_deterministic() {
+        use crate::audit::Deterministic;
+
+        let mut parser = parser::Parser::new();
+        parser
+            .reg_nondeterministic_build_in("Rand", |_| Ok(CalculateOption::Num(0.25)))
+            .unwrap();
+        parser.parse("A := 0.1");
+        parser.set_var("B", 0.2);
+        let result = parser.calculate("A + B");
+        assert_eq!(result.value, CalculateOption::Num(0.1 + 0.2));
+        assert!(result.audit.is_none());
+        assert_eq!(parser.calculate("Rand()").value, CalculateOption::Num(0.25));
+
+        parser.set_options(CalculateOptions {
+            deterministic: Some(Deterministic::default()),
+            ..Default::default()
+        });
+        let result = parser.calculate("A + B");
+        assert_eq!(result.value, CalculateOption::Num(0.3));
+        let audit = result.audit.unwrap();
+        assert_eq!(audit.formula, "(A + B)");
+        assert_eq!(audit.result, CalculateOption::Num(0.3));
+        assert_eq!(
+            audit.inputs,
+            vec![
+                ("A".to_string(), CalculateOption::Num(0.1)),
+                ("B".to_string(), CalculateOption::Num(0.2)),
+            ]
+        );
+        // 哈希值只与表达式的规范形式有关
+        let compiled = parser.compile("(A) + B");
+        assert_eq!(
+            compiled.eval().audit.unwrap().formula_hash,
+            audit.formula_hash
+        );
+
+        match parser.calculate("Rand() + 1").value {
+            CalculateOption::Err(msg) => assert!(msg.contains("E0210")),
+            other => panic!("unexpected {:?}", other),
+        }
+        parser.set_options(CalculateOptions {
+            deterministic: Some(Deterministic {
+                decimals: 1,
+                allowed: vec!["Rand".to_string()],
+            }),
+            ..Default::default()
+        });
+        assert_eq!(
+            parser.calculate("Rand() + 1").value,
+            CalculateOption::Num(1.2)
+        );
+    }
+
+    #[test]
+    fn test_security_policy() {
+        use crate::policy::SecurityPolicy;
+
+        let err_code = |value: CalculateOption| match value {
+            CalculateOption::Err(msg) => ErrorCode::of(&msg),
+            _ => None,
+        };
+        let mut parser = parser::Parser::new();
+        parser.parse("Loop(n) { Loop(n + 1) }");
+        parser.parse("Deep(n) { If(n > 0, 1 + Deep(n - 1), 0) }");
+        parser.parse("Rate := 0.1");
+        parser.set_security_policy(SecurityPolicy {
+            allowed_build_ins: Some(std::iter::once("If".to_string()).collect()),
+            max_formula_len: Some(32),
+            max_call_depth: Some(10),
+            fuel: Some(10_000),
+            allow_override: false,
+        });
+
+        // 内建函数的调用同样计入嵌套深度，Deep(3) 共嵌套 8 层
+        assert_eq!(parser.calculate("Deep(3)").value, CalculateOption::Num(3.0));
+        assert_eq!(
+            err_code(parser.calculate("Deep(20)").value),
+            Some(ErrorCode::CallDepthExceeded)
+        );
+        // 尾调用不会增加嵌套深度，由 fuel 限制
+        match parser.calculate("Loop(0)").value {
+            // 在参数的计算中耗尽，错误被包装在参数错误中
+            CalculateOption::Err(msg) => assert!(msg.contains("E0213"), "{}", msg),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            err_code(parser.calculate("Sum(1, 2)").value),
+            Some(ErrorCode::BuildInNotAllowed)
+        );
+        match parser.parse(format!("1{}", " + 1".repeat(10))).as_ref() {
+            formula::FormulaNode::UnKnow(msg) => assert!(msg.starts_with("E0407")),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parser.parse("Rate := 0.5").as_ref() {
+            formula::FormulaNode::UnKnow(msg) => assert!(msg.starts_with("E0408")),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parser.parse("If(x) { x }").as_ref() {
+            formula::FormulaNode::UnKnow(msg) => assert!(msg.starts_with("E0408")),
+            other => panic!("unexpected {:?}", other),
+        }
+        // 宿主程序的修改不受限制，新的名称可以定义
+        assert!(parser.set_var("Rate", 0.2));
+        parser.parse("Fee := Rate * 2");
+        assert_eq!(parser.calculate("Fee").value, CalculateOption::Num(0.4));
+
+        // 具名表达式的引用同样计入嵌套深度，循环引用返回错误而不是耗尽宿主的栈
+        parser.set_security_policy(SecurityPolicy {
+            max_call_depth: Some(50),
+            fuel: Some(100_000),
+            ..SecurityPolicy::default()
+        });
+        parser.parse("x := x + 1");
+        parser.parse("a := b; b := a");
+        assert_eq!(
+            err_code(parser.calculate("x").value),
+            Some(ErrorCode::CircularReference)
+        );
+        assert_eq!(
+            err_code(parser.calculate("a").value),
+            Some(ErrorCode::CircularReference)
+        );
+        parser.parse("d0 := 1");
+        for i in 1..60 {
+            parser.parse(format!("d{} := d{} + 1", i, i - 1));
+        }
+        assert_eq!(parser.calculate("d40").value, CalculateOption::Num(41.0));
+        assert_eq!(
+            err_code(parser.calculate("d59").value),
+            Some(ErrorCode::CallDepthExceeded)
+        );
+
+        parser.set_security_policy(SecurityPolicy::default());
+        assert_eq!(
+            parser.calculate("Sum(1, 2)").value,
+            CalculateOption::Num(3.0)
+        );
+        assert_eq!(
+            err_code(parser.calculate("x").value),
+            Some(ErrorCode::CircularReference)
+        );
+    }
+
+    #[test]
+    fn test_typecheck() {
+        use crate::typecheck::{Schema, Type};
+
+        let mut parser = parser::Parser::new();
+        parser
+            .schema_mut()
+            .declare("qty", Type::Number)
+            .declare("flag", Type::Bool)
+            .declare_function("Discount", &[Type::Number, Type::Bool], Type::Number);
+        parser.parse("Price := 2.5");
+
+        let ast = parser.parse("qty * Price + flag");
+        let diagnostics = parser.typecheck(&ast);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, ErrorCode::OperandTypeMismatch);
+        assert_eq!(diagnostics[0].expression, "flag");
+        assert_eq!(
+            (diagnostics[0].expected, diagnostics[0].found),
+            (Type::Number, Type::Bool)
+        );
+        assert!(diagnostics[0].message.starts_with("E0409"));
+
+        let ast = parser.parse("Discount(flag, qty > 1)");
+        let diagnostics = parser.typecheck(&ast);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, ErrorCode::ArgumentTypeMismatch);
+        assert_eq!(diagnostics[0].expression, "flag");
+
+        // 函数的返回值类型参与推导，比较的结果为逻辑值
+        let ast = parser.parse("(Discount(qty, flag) > 1) && !(qty < 2)");
+        assert!(parser.typecheck(&ast).is_empty());
+        // 操作符之间没有优先级，1 && flag 先作为比较的右侧
+        let ast = parser.parse("qty > 1 && flag");
+        assert_eq!(parser.typecheck(&ast)[0].found, Type::Bool);
+
+        // 函数的参数遮蔽同名的输入变量，未声明的名称不做检查
+        let ast = parser.parse("F(flag) { flag + 1 }");
+        assert!(parser.typecheck(&ast).is_empty());
+        let ast = parser.parse("unknown + 1");
+        assert!(parser.typecheck(&ast).is_empty());
+
+        // 不依赖解析器时只使用 schema 中的声明
+        let mut schema = Schema::new();
+        schema.declare("v", Type::Vector);
+        let ast = parser.parse("v * 2i");
+        assert_eq!(schema.typecheck(&ast).len(), 1);
+        let ast = parser.parse("v * 2");
+        assert!(schema.typecheck(&ast).is_empty());
+    }
+
+    #[test]
+    fn test_lint() {
+        use crate::diagnostic::{Diagnostic, Severity};
+        use crate::lint::{LintOptions, LintRule};
+
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.1");
+        let codes = |diagnostics: &[Diagnostic]| -> Vec<ErrorCode> {
+            diagnostics.iter().map(|d| d.code).collect()
+        };
+
+        let source = "F(x, Rate) { y := x * 2; x + 1; y }";
+        let diagnostics = parser.lint(source);
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+        assert_eq!(
+            codes(&diagnostics),
+            vec![
+                ErrorCode::ShadowedName,
+                ErrorCode::MagicNumber,
+                ErrorCode::UnusedExpression,
+                ErrorCode::UnusedDefinition,
+            ]
+        );
+        let spans: Vec<&str> = diagnostics
+            .iter()
+            .map(|d| &source[d.span.clone()])
+            .collect();
+        assert_eq!(spans, vec!["Rate", "2", "x", "Rate"]);
+
+        let diagnostics = parser.lint("If(1 > 0, Rate, 0) + (Rate * 3 == 0.3)");
+        assert_eq!(
+            codes(&diagnostics),
+            vec![
+                ErrorCode::ConstantCondition,
+                ErrorCode::MagicNumber,
+                ErrorCode::MagicNumber,
+                ErrorCode::FloatEquality,
+            ]
+        );
+        assert!(parser.lint("Tax := 0.07; Price * Tax").is_empty());
+
+        // 只启用部分规则，check 的结果中同时包含 lint 的问题
+        assert!(parser.check("Rate * 3").is_empty());
+        parser.set_lint(Some(LintOptions {
+            rules: vec![LintRule::MagicNumber],
+            allowed_numbers: vec![3.0],
+        }));
+        assert!(parser.check("Rate * 3").is_empty());
+        assert_eq!(
+            codes(&parser.check("Rate * 4 + B")),
+            vec![ErrorCode::UndefinedVariable, ErrorCode::MagicNumber]
+        );
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Base := Price * 2".to_string());
+        parser.parse("Tax(x) { x * 0.1 }".to_string());
+        assert!(parser.calculate("1".to_string()).profile.is_none());
+
+        parser.set_options(CalculateOptions {
+            profile: true,
+            ..Default::default()
+        });
+        let compiled = parser.compile("Tax(Base) + Tax(Base)".to_string());
+        let rows: Vec<HashMap<String, f64>> = (0..3)
+            .map(|i| {
+                let mut row = HashMap::new();
+                row.insert("Price".to_string(), i as f64);
+                row
+            })
+            .collect();
+
+        let mut report = crate::calculator::ProfileReport::default();
+        for result in compiled.eval_batch(&rows) {
+            report.merge(&result.profile.unwrap());
+        }
+        assert_eq!(report.entries["Tax"].calls, 6);
+        assert_eq!(report.entries["Base"].calls, 6);
+        assert!(!report.entries.contains_key("Price"));
+        assert_eq!(report.slowest().len(), 2);
+    }
+
+    #[test]
+    fn test_arithmetic_fast_path() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.5");
+        parser.parse("Base := Price * 2");
+        parser
+            .reg_fallible_build_in("Double", |c| Ok(CalculateOption::Num(c.arg_num(0)? * 2.0)))
+            .unwrap();
+
+        let mut row = HashMap::new();
+        row.insert("Qty".to_string(), 4.0);
+        row.insert("Price".to_string(), 3.0);
+
+        let compiled = parser.compile("((Qty - 1) * Price / 2) + (Rate * Qty)");
+        assert!(compiled.is_arithmetic());
+        assert_eq!(compiled.eval_with(&row).value, CalculateOption::Num(6.5));
+
+        // 变量为非常量的表达式时退回到逐节点计算，结果保持一致
+        let compiled = parser.compile("Base + Qty");
+        assert!(compiled.is_arithmetic());
+        assert_eq!(compiled.eval_with(&row).value, CalculateOption::Num(10.0));
+        match compiled.eval().value {
+            CalculateOption::Err(_) => (),
+            v => panic!("缺少变量时应返回错误，实际返回 {:?}", v),
+        }
+
+        assert!(!parser.compile("Double(Qty) + 1").is_arithmetic());
+        assert!(!parser.compile("Qty > 1").is_arithmetic());
+
+        parser.set_options(CalculateOptions {
+            trace: true,
+            ..Default::default()
+        });
+        let result = parser.compile("Qty * Price").eval_with(&row);
+        assert_eq!(result.value, CalculateOption::Num(12.0));
+        assert!(result.trace.is_some());
+    }
+
+    #[test]
+    fn test_locale() {
+        let mut parser = parser::Parser::new();
+        let result = parser.calculate("X + 1".to_string());
+        match result.value {
+            CalculateOption::Err(ref e) => {
+                assert!(e.starts_with("E0101: 无法从执行环境中获取指定的变量名 X"));
+                assert_eq!(ErrorCode::of(e), Some(ErrorCode::UndefinedVariable));
+            }
+            ref v => panic!("unexpected value {:?}", v),
+        }
+
+        parser.set_locale(Locale::En);
+        match parser.calculate("X + 1".to_string()).value {
+            CalculateOption::Err(e) => assert_eq!(e, "E0101: variable X is not defined"),
+            v => panic!("unexpected value {:?}", v),
+        }
+
+        let diagnostics = parser.check("Y * 2");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, ErrorCode::UndefinedVariable);
+        assert_eq!(diagnostics[0].message, "variable Y is not defined");
+    }
+
+    #[test]
+    fn test_calculate_option_conversion() {
+        let mut parser = parser::Parser::new();
+        let value = parser.calculate("1 + 2".to_string()).value;
+        assert_eq!(value.as_num(), Some(3.0));
+        assert_eq!(value.as_bool(), None);
+        assert_eq!(value.to_string(), "3");
+        assert_eq!(f64::try_from(value), Ok(3.0));
+
+        let value = parser.calculate("1 < 2".to_string()).value;
+        assert_eq!(bool::try_from(value.clone()), Ok(true));
+        assert_eq!(String::try_from(value), Ok("true".to_string()));
+
+        assert_eq!(CalculateOption::from(1.5).to_string(), "1.5");
+        assert_eq!(
+            CalculateOption::Vector(vec![1.0, 2.0]).to_string(),
+            "[1, 2]"
+        );
+        assert_eq!(CalculateOption::None.to_string(), "None");
+
+        let value = parser.calculate("X".to_string()).value;
+        assert!(value.to_string().starts_with("E0101"));
+        match f64::try_from(value) {
+            Err(CalculateOption::Err(e)) => assert!(e.starts_with("E0101")),
+            v => panic!("unexpected value {:?}", v),
+        }
+        assert!(String::try_from(CalculateOption::None).is_err());
+    }
+
+    #[test]
+    fn test_calculate_option_ops() {
+        let a = CalculateOption::Num(6.0);
+        let b = CalculateOption::Num(2.0);
+        assert_eq!(a.clone() + b.clone(), CalculateOption::Num(8.0));
+        assert_eq!(a.clone() - b.clone(), CalculateOption::Num(4.0));
+        assert_eq!(a.clone() * b.clone(), CalculateOption::Num(12.0));
+        assert_eq!(a.clone() / b.clone(), CalculateOption::Num(3.0));
+        assert_eq!(
+            CalculateOption::Vector(vec![1.0, 2.0]) * b.clone(),
+            CalculateOption::Vector(vec![2.0, 4.0])
+        );
+        assert_eq!(
+            CalculateOption::Complex(Complex::new(1.0, 1.0)) + b.clone(),
+            CalculateOption::Complex(Complex::new(3.0, 1.0))
+        );
+
+        match a.clone() + CalculateOption::Err("E0101: X".to_string()) {
+            CalculateOption::Err(e) => assert_eq!(e, "E0101: X"),
+            v => panic!("unexpected value {:?}", v),
+        }
+        match a.clone() + CalculateOption::Bool(true) {
+            CalculateOption::Err(e) => assert!(e.starts_with("E0302")),
+            v => panic!("unexpected value {:?}", v),
+        }
+
+        assert!(a > b);
+        assert!(CalculateOption::Bool(false) < CalculateOption::Bool(true));
+        assert_eq!(a.partial_cmp(&CalculateOption::Bool(true)), None);
+        assert_eq!(
+            CalculateOption::None.partial_cmp(&CalculateOption::None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prelude() {
+        use crate::prelude::*;
+
+        let mut parser = Parser::new();
+        parser
+            .reg_fallible_build_in("Half", |ctx: &FuncContext| {
+                ctx.args_len_must(1)?;
+                match ctx.arg_num(0)? {
+                    n if n >= 0.0 => Ok(CalculateOption::Num(n / 2.0)),
+                    _ => Err(CalcError::arg(0, "参数不能为负数")),
+                }
+            })
+            .unwrap();
+        let result: CalculateResult = parser.calculate("Half(5)");
+        assert_eq!(result.value.as_num(), Some(2.5));
+    }
+
+    #[test]
+    fn test_try_calculate() {
+        use crate::prelude::*;
+
+        fn total(parser: &mut Parser) -> Result<f64, FormulaError> {
+            let price = parser.try_calculate("Price * 2")?;
+            Ok(price.as_num().unwrap_or_default())
+        }
+
+        let mut parser = Parser::new();
+        parser.set_var("Price", 1.5);
+        assert_eq!(parser.try_calculate("Price + 1"), Ok(TypedValue::Num(2.5)));
+        assert_eq!(
+            parser.try_calculate(String::from("Price + 1")),
+            Ok(TypedValue::Num(2.5))
+        );
+        assert_eq!(total(&mut parser), Ok(3.0));
+
+        match parser.try_calculate("Price + Qty") {
+            Err(FormulaError::Eval { code, span, .. }) => {
+                assert_eq!(code, Some(ErrorCode::UndefinedVariable));
+                assert_eq!(span, Some(8..11));
+            }
+            v => panic!("unexpected value {:?}", v),
+        }
+        match parser.try_calculate("import \"rates.f") {
+            Err(e @ FormulaError::Parse { .. }) => assert!(!e.message().is_empty()),
+            v => panic!("unexpected value {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        use crate::clock::ManualClock;
+
+        let mut parser = parser::Parser::new();
+        let clock = Rc::new(ManualClock::default());
+        parser.set_clock(Rc::clone(&clock));
+        parser.parse("A := 1");
+        parser
+            .set_delayed("A", "2", Duration::from_secs(10))
+            .unwrap();
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(parser.calculate("A").value, CalculateOption::Num(1.0));
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(parser.calculate("A").value, CalculateOption::Num(2.0));
+
+        // 语法错误不会被保存为延迟的值，其中的定义也不会立即加入执行环境
+        assert!(parser
+            .set_delayed("A", "(1 +", Duration::from_secs(1))
+            .is_err());
+        assert!(parser
+            .set_delayed("B", "C := 3; C + 1", Duration::from_secs(1))
+            .is_ok());
+        assert!(matches!(
+            parser.calculate("C").value,
+            CalculateOption::Err(_)
+        ));
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(parser.calculate("A").value, CalculateOption::Num(2.0));
+
+        parser.seal();
+        assert!(parser
+            .set_delayed("A", "3", Duration::from_secs(1))
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn test_wasm_parser() {
+        let mut parser = crate::wasm::WasmParser::new();
+        assert!(parser.set_var("A", 2.0));
+        assert!(parser.set_var_json("V", r#"{"Vector":[1.0,2.0]}"#));
+        assert!(!parser.set_var_json("B", "not json"));
+
+        parser.parse("F(x) { x * A }");
+        assert_eq!(parser.calculate("F(3)"), r#"{"Num":6.0}"#);
+        assert_eq!(parser.calculate("V + A"), r#"{"Vector":[3.0,4.0]}"#);
+
+        let json = parser.to_json().unwrap();
+        let restored = crate::wasm::WasmParser::new();
+        assert_eq!(restored.load_json(&json), None);
+    }
+
+    #[test]
+    fn test_excel_dialect() {
+        use crate::dialect::Dialect;
+
+        let mut parser = parser::Parser::with_dialect(Dialect::Excel);
+        parser.set_var("A1", 3.0);
+        parser.set_column("V", vec![1.0, 2.0]);
+
+        assert_eq!(
+            parser.calculate("=IF(A1>0, A1*2, 0)").value,
+            CalculateOption::Num(6.0)
+        );
+        assert_eq!(
+            parser.calculate("=A1<>3").value,
+            CalculateOption::Bool(false)
+        );
+        assert_eq!(
+            parser.calculate("=sum(A1, 2)").value,
+            CalculateOption::Num(5.0)
+        );
+        assert_eq!(
+            parser.calculate("=AVERAGE(2, 4)").value,
+            CalculateOption::Num(3.0)
+        );
+        assert_eq!(
+            parser.calculate("=V & A1").value,
+            CalculateOption::Vector(vec![1.0, 2.0, 3.0])
+        );
+        assert_eq!(
+            parser.calculate("=AND(TRUE, A1 > 1, NOT(FALSE))").value,
+            CalculateOption::Bool(true)
+        );
+        assert!(parser.check("=IF(A1>0, B1, 0)").len() == 1);
+
+        // 与 Excel 相同的运算优先级，编译后的公式及文档的语法树保持一致
+        parser.set_var("A1", 5.0);
+        assert_eq!(
+            parser.calculate("=A1 * 2 + 1").value,
+            CalculateOption::Num(11.0)
+        );
+        assert_eq!(
+            parser.compile("=A1 * 2 + 1").eval().value,
+            CalculateOption::Num(11.0)
+        );
+        let document = parser.parse_document("=A1 - 1 - 1");
+        assert_eq!(document.statements()[0].node.to_string(), "((A1 - 1) - 1)");
+    }
+
+    #[test]
+    fn test_cell_resolver() {
+        let mut parser = parser::Parser::new();
+        let grid = [vec![1.0, 10.0], vec![2.0, 20.0], vec![3.0, 30.0]];
+        parser.set_cell_resolver(move |column: usize, row: usize| {
+            grid.get(row).and_then(|r| r.get(column)).cloned()
+        });
+
+        assert_eq!(
+            parser.calculate("A1 + B3").value,
+            CalculateOption::Num(31.0)
+        );
+        assert_eq!(
+            parser.calculate("B1:B3").value,
+            CalculateOption::Vector(vec![10.0, 20.0, 30.0])
+        );
+        assert_eq!(
+            parser.calculate("Sum(A1:B2)").value,
+            CalculateOption::Num(33.0)
+        );
+        match parser.calculate("C1").value {
+            CalculateOption::Err(_) => (),
+            v => panic!("unexpected value {:?}", v),
+        }
+
+        parser.parse("Total := Sum(A2:A3) * 2");
+        assert_eq!(parser.calculate("Total").value, CalculateOption::Num(10.0));
+        assert_eq!(parser.unresolved_names("Sum(A1:A3) + D1"), vec!["D1"]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_calculate_with_json() {
+        let mut parser = parser::Parser::new();
+        let payload = serde_json::json!({
+            "user": { "age": 20, "vip": true, "name": "Tom" },
+            "items": [{ "price": 2.5 }, { "price": 4 }],
+            "scores": [1, 2, 3]
+        });
+
+        assert_eq!(
+            parser
+                .calculate_with_json("(payload.user.age > 18) && payload.user.vip", &payload)
+                .value,
+            CalculateOption::Bool(true)
+        );
+        assert_eq!(
+            parser
+                .calculate_with_json("payload.items.0.price + payload.items.1.price", &payload)
+                .value,
+            CalculateOption::Num(6.5)
+        );
+        assert_eq!(
+            parser
+                .calculate_with_json("Sum(payload.scores)", &payload)
+                .value,
+            CalculateOption::Num(6.0)
+        );
+
+        // payload 只在本次计算中生效
+        match parser.calculate("payload.user.age").value {
+            CalculateOption::Err(_) => (),
+            v => panic!("unexpected value {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_calculate_csv() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.1");
+        let csv = "name,price,qty\nApple,2.5,4\n\"Pear, green\",3,x\n";
+        let out = parser
+            .calculate_csv(csv, &[("total", "price * qty"), ("tax", "price * Rate")])
+            .unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows[0], "name,price,qty,total,tax");
+        assert_eq!(rows[1], "Apple,2.5,4,10,0.25");
+        assert!(rows[2].starts_with("\"Pear, green\",3,x,E0101"));
+        assert!(rows[2].ends_with(",0.30000000000000004"));
+
+        assert!(parser.calculate_csv("a,b\n1\n", &[("c", "a")]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "msgpack")]
+    fn test_compiled_bytes() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Rate := 0.5");
+        let bytes = parser
+            .compile("Price * Rate + Sum(1, 2)")
+            .to_bytes()
+            .unwrap();
+        assert_eq!(&bytes[..4], b"FRML");
+
+        let mut edge = parser::Parser::new();
+        edge.parse("Rate := 2");
+        let compiled = edge.compile_bytes(&bytes).unwrap();
+        let mut row = HashMap::new();
+        row.insert("Price".to_string(), 10.0);
+        assert_eq!(compiled.eval_with(&row).value, CalculateOption::Num(50.0));
+
+        let mut future = bytes.clone();
+        future[5] += 1;
+        assert!(edge.compile_bytes(&future).is_err());
+        assert!(edge.compile_bytes(b"not a formula").is_err());
+    }
+
+    #[test]
+    fn test_sensitivity() {
+        use crate::analyze::{sensitivity, SweepRange};
+
+        assert_eq!(
+            SweepRange::new(0.0, 1.0, 5).values(),
+            [0.0, 0.25, 0.5, 0.75, 1.0]
+        );
+        assert!(SweepRange::new(0.0, 1.0, 0).values().is_empty());
+
+        let mut parser = parser::Parser::new();
+        parser.parse("Cost := 30");
+        let compiled = parser.compile("(Qty * (Price - Cost)) - Fixed");
+        let mut base = HashMap::new();
+        base.insert("Fixed".to_string(), 100.0);
+        base.insert("Qty".to_string(), 1.0);
+        let table = sensitivity(
+            &compiled,
+            &base,
+            &[
+                ("Qty", SweepRange::new(10.0, 20.0, 2)),
+                ("Price", SweepRange::new(40.0, 50.0, 3)),
+            ],
+        );
+        assert_eq!(table.inputs, ["Qty", "Price"]);
+        let results: Vec<(Vec<f64>, Option<f64>)> = table
+            .rows
+            .iter()
+            .map(|row| (row.inputs.clone(), row.value.as_num()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (vec![10.0, 40.0], Some(0.0)),
+                (vec![10.0, 45.0], Some(50.0)),
+                (vec![10.0, 50.0], Some(100.0)),
+                (vec![20.0, 40.0], Some(100.0)),
+                (vec![20.0, 45.0], Some(200.0)),
+                (vec![20.0, 50.0], Some(300.0)),
+            ]
+        );
+        assert!(table.to_csv().starts_with("Qty,Price,result\n10,40,0\n"));
+        assert_eq!(parser.calculate("Qty").value.as_num(), None);
+
+        let table = parser.sensitivity("Price * 2", &HashMap::new(), &[]);
+        assert_eq!(table.rows.len(), 1);
+        assert!(table.rows[0].value.as_num().is_none());
+    }
+
+    #[test]
+    fn test_goal_seek() {
+        use crate::analyze::goal_seek;
+
+        let mut parser = parser::Parser::new();
+        parser.parse("Cost := 30");
+        let mut base = HashMap::new();
+        base.insert("Price".to_string(), 50.0);
+        base.insert("Fixed".to_string(), 100.0);
+        // 盈亏平衡的销量
+        let compiled = parser.compile("(Qty * (Price - Cost)) - Fixed");
+        let seek = goal_seek(&compiled, &base, 0.0, "Qty", (0.0, 1000.0)).unwrap();
+        assert!((seek.value - 5.0).abs() < 1e-9);
+
+        let seek = parser
+            .goal_seek("x * x", &HashMap::new(), 2.0, "x", (2.0, 0.0))
+            .unwrap();
+        assert!((seek.value - 2f64.sqrt()).abs() < 1e-9);
+        assert!((seek.result - 2.0).abs() < 1e-9 && seek.iterations > 0);
+
+        // 范围两端在同一侧时从中点开始迭代
+        let seek = parser
+            .goal_seek("x * x", &HashMap::new(), 4.0, "x", (1.0, 3.0))
+            .unwrap();
+        assert!((seek.value - 2.0).abs() < 1e-9);
+
+        let empty = HashMap::new();
+        assert!(parser
+            .goal_seek("x * x", &empty, -1.0, "x", (-1.0, 1.0))
+            .is_err());
+        assert!(parser
+            .goal_seek("x > 1", &empty, 1.0, "x", (0.0, 2.0))
+            .is_err());
+        assert!(parser
+            .goal_seek("If(x > 1, 1, -1)", &empty, 0.0, "x", (0.0, 2.0))
+            .is_err());
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let mut parser = parser::Parser::new();
+        let hash = parser.canonical_hash("A+B*2");
+        assert_eq!(hash, parser.canonical_hash("  A + (B * 2) "));
+        assert_ne!(hash, parser.canonical_hash("A + B * 3"));
+        assert_ne!(hash, parser.canonical_hash("(A + B) * 2"));
+
+        let node = parser.parse("F(x) { x * 2 }");
+        assert_eq!(*node, *parser.parse("F( x ) { (x) * 2 }"));
+        assert_ne!(*node, *parser.parse("F(y) { y * 2 }"));
+        assert_eq!(
+            *parser.parse("A + 1"),
+            formula::FormulaNode::from(crate::expr::Expr::var("A") + 1.0)
+        );
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_server_handle() {
+        let value = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+
+        let (status, body) =
+            crate::server::handle("/evaluate", r#"{"formula": "A * 2", "vars": {"A": 21}}"#);
+        assert_eq!(status, 200);
+        assert_eq!(value(&body)["value"]["Num"], 42.0);
+
+        let (status, body) = crate::server::handle("/evaluate", r#"{"formula": "A * 2"}"#);
+        assert_eq!(status, 422);
+        assert_eq!(value(&body)["error"]["kind"], "eval");
+
+        let (status, body) = crate::server::handle("/validate", r#"{"formula": "B + 1"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(
+            value(&body)["diagnostics"][0]["span"],
+            serde_json::json!([0, 1])
+        );
+
+        let (status, body) = crate::server::handle("/parse", r#"{"formula": "1 + 2"}"#);
+        assert_eq!(status, 200);
+        assert!(value(&body)["ast"].is_object());
+
+        assert_eq!(crate::server::handle("/parse", "{}").0, 400);
+        assert_eq!(
+            crate::server::handle("/unknown", r#"{"formula": "1"}"#).0,
+            404
+        );
+    }
+
+    #[cfg(feature = "lsp")]
+    #[test]
+    fn test_lsp_document() {
+        use lsp_types::Position;
+
+        let source = "Double(x) { x * 2 };\nRate := 0.5;\nTotal := Double(Rate) + Missing";
+        let mut doc = crate::lsp::Document::new(source);
+        let at = |line, character| Position { line, character };
+
+        let diagnostics = doc.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, at(2, 24));
+
+        let definition = doc.definition(at(2, 18)).unwrap();
+        assert_eq!((definition.start, definition.end), (at(1, 0), at(1, 4)));
+        assert_eq!(doc.definition(at(2, 10)).unwrap().start, at(0, 0));
+
+        match doc.hover(at(1, 2)).unwrap().contents {
+            lsp_types::HoverContents::Markup(content) => assert!(content.value.contains("0.5")),
+            contents => panic!("悬停提示应为 Markdown，实际为 {:?}", contents),
+        }
+        assert!(doc.hover(at(0, 14)).is_none());
+
+        let labels: Vec<String> = doc.completion().into_iter().map(|i| i.label).collect();
+        assert!(labels.iter().any(|l| l == "Double") && labels.iter().any(|l| l == "Sum"));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        use crate::token::TokenKind::*;
+
+        let mut parser = parser::Parser::new();
+        let source = "import \"a.f\"; # 汇率\nTotal := tax.Rate@-1 * 2i >= F(x) // 注释";
+        let tokens: Vec<_> = parser
+            .tokenize(source)
+            .into_iter()
+            .map(|(kind, span)| (kind, &source[span]))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Keyword, "import"),
+                (String, "\"a.f\""),
+                (Punctuation, ";"),
+                (Comment, "# 汇率"),
+                (Identifier, "Total"),
+                (Operator, ":="),
+                (Identifier, "tax.Rate"),
+                (Operator, "@"),
+                (Operator, "-"),
+                (Number, "1"),
+                (Operator, "*"),
+                (Number, "2i"),
+                (Operator, ">="),
+                (Identifier, "F"),
+                (Punctuation, "("),
+                (Identifier, "x"),
+                (Punctuation, ")"),
+                (Comment, "// 注释"),
+            ]
+        );
+
+        // 注释在解析时会被忽略
+        parser.parse("# 税率\nRate := 0.5 // 一半");
+        assert_eq!(
+            parser.calculate("Rate * 4").value,
+            CalculateOption::Num(2.0)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_result_to_json() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Double(x) { x * 2 }");
+        let json = parser.calculate("Double(3) + 1").to_json();
+        assert_eq!(json["value"], 7.0);
+        assert_eq!(json["type"], "num");
+        assert_eq!(json["trace"][0]["func"], "Double");
+        assert_eq!(json["trace"][0]["arg_values"][0], 3.0);
+
+        let json = parser.calculate("Missing").to_json();
+        assert_eq!(json["type"], "error");
+        assert!(json["value"].as_str().unwrap().starts_with("E0101"));
+        assert_eq!(
+            CalculateOption::Complex(Complex::new(1.0, 2.0)).to_json_value(),
+            serde_json::json!({ "re": 1.0, "im": 2.0 })
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_load_dir() {
+        let dir = std::env::temp_dir().join(format!("formula_load_dir_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tax")).unwrap();
+        // a.fml 引用了之后才加载的 tax/rates.fml 中的定义
+        std::fs::write(dir.join("a.fml"), "Total := Price * (1 + tax.Rate);").unwrap();
+        std::fs::write(dir.join("tax/rates.fml"), "namespace tax { Rate := 0.5 }").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a formula").unwrap();
+
+        let mut parser = parser::Parser::new();
+        let files = parser.load_dir(&dir).unwrap();
+        assert_eq!(files.len(), 2);
+        let source = parser.provenance("tax.Rate").unwrap().source.clone();
+        assert!(source.unwrap().ends_with("rates.fml"));
+        parser.set_var("Price", 100.0);
+        assert_eq!(parser.calculate("Total").value, CalculateOption::Num(150.0));
+
+        std::fs::write(dir.join("b.fml"), "Total := 1;").unwrap();
+        let e = parser::Parser::new().load_dir(&dir).unwrap_err();
+        assert!(e.contains("Total") && e.contains("a.fml") && e.contains("b.fml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_import_relative() {
+        let dir = std::env::temp_dir().join(format!("formula_import_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        // 相对路径相对于 import 语句所在的文件，而不是当前工作目录
+        std::fs::write(
+            dir.join("main.fml"),
+            "import \"lib/tax.fml\"; Total := Net * 2;",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("lib/tax.fml"),
+            "import \"rate.fml\"; Net := 100 * Rate;",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/rate.fml"), "Rate := 0.5;").unwrap();
+
+        let mut parser = parser::Parser::new();
+        let main = dir.join("main.fml").display().to_string();
+        if let formula::FormulaNode::UnKnow(msg) =
+            parser.parse(format!("import \"{}\";", main)).as_ref()
+        {
+            panic!("加载失败: {}", msg);
+        }
+        assert_eq!(parser.calculate("Total").value, CalculateOption::Num(100.0));
+        let source = parser.provenance("Rate").unwrap().source.clone().unwrap();
+        assert!(
+            std::path::Path::new(&source).ends_with("lib/rate.fml"),
+            "{}",
+            source
+        );
+
+        // 通过 ../ 及 ./ 再次引用正在加载的文件时同样是循环引用
+        std::fs::create_dir_all(dir.join("loop")).unwrap();
+        std::fs::write(dir.join("lib/c.fml"), "import \"../loop/b.fml\";").unwrap();
+        std::fs::write(dir.join("loop/b.fml"), "import \"../lib/c.fml\";").unwrap();
+        std::fs::write(dir.join("self.fml"), "import \"./self.fml\";").unwrap();
+        for file in ["lib/c.fml", "self.fml"] {
+            let path = dir.join(file).display().to_string();
+            match parser.parse(format!("import \"{}\";", path)).as_ref() {
+                formula::FormulaNode::UnKnow(msg) => assert!(msg.contains("循环引用"), "{}", msg),
+                n => panic!("{} 应存在循环引用，实际为 {:?}", file, n),
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_messages() {
+        use crate::locale::Message;
+
+        assert_eq!("en".parse::<Locale>(), Ok(Locale::En));
+        assert!("fr".parse::<Locale>().is_err());
+        assert_eq!(
+            Message::Saved.text(Locale::En, &[&2, &"a.fml"]),
+            "saved 2 expressions to a.fml"
+        );
+        assert_eq!(
+            Message::Saved.text(Locale::Zh, &[&2, &"a.fml"]),
+            "已将 2 条表达式保存到 a.fml"
+        );
+    }
+
+    #[test]
+    fn test_result_display() {
+        let mut parser = parser::Parser::new();
+        parser.parse("Double(x) { x * 2 }");
+        parser.parse("Quad(x) { Double(Double(x)) }");
+        assert_eq!(
+            parser.calculate("Quad(1) + Max(1, 0)").to_string(),
+            "5 (num)\n  Quad(1) => 4\n    Double(2) => 4\n      Double(1) => 2\n  Max(1, 0) => 1"
+        );
+        assert_eq!(parser.calculate("1 > 0").to_string(), "true (bool)");
+    }
+
+    #[test]
+    fn test_scanner() {
+        use crate::scanner::Scanner;
+
+        let mut parser = parser::Parser::new();
+        parser.parse("A := 1\nB := A + 2 # 注释\nF(x) { y := x * 2; y + B }");
+        assert_eq!(parser.calculate("F(A)").value, CalculateOption::Num(5.0));
+
+        let depth = 200;
+        let nested = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(parser.calculate(&nested).value, CalculateOption::Num(1.0));
+
+        let nodes: Vec<_> = Scanner::new("A := 1 & 2; B := 3").collect();
+        assert_eq!(nodes.len(), 2);
+        assert!(matches!(&nodes[0], formula::FormulaNode::UnKnow(msg) if msg.contains("&&")));
+        assert_eq!(nodes[1].to_string(), "B := 3");
+    }
 }
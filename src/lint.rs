@@ -0,0 +1,328 @@
+#[cfg(not(any(feature = "std", test)))]
+use crate::compat::Float;
+use crate::compat::HashSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::ops::Range;
+
+use crate::diagnostic::{Diagnostic, Severity, SpanFinder};
+use crate::env::Env;
+use crate::formula::{FormulaNode, OperatorNode};
+use crate::locale::{ErrorCode, Locale};
+
+/// lint 检查的规则，发现的问题都是警告，不影响表达式的计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// 函数中从未使用的参数及局部变量，顶层的定义可能被其他公式引用，不作检查
+    UnusedDefinition,
+    /// 函数的参数或局部变量与已有的定义同名
+    ShadowedName,
+    /// 使用 == 或 != 比较小数常量或四则运算的结果
+    FloatEquality,
+    /// If 的条件、逻辑运算的操作数不依赖任何变量
+    ConstantCondition,
+    /// 函数体中最后一个表达式之前不是定义的表达式，其结果会被丢弃
+    UnusedExpression,
+    /// 未命名的数值常量，直接作为具名表达式的值的常量除外
+    MagicNumber,
+}
+
+impl LintRule {
+    pub const ALL: &'static [LintRule] = &[
+        LintRule::UnusedDefinition,
+        LintRule::ShadowedName,
+        LintRule::FloatEquality,
+        LintRule::ConstantCondition,
+        LintRule::UnusedExpression,
+        LintRule::MagicNumber,
+    ];
+
+    /// 规则对应的错误码
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            LintRule::UnusedDefinition => ErrorCode::UnusedDefinition,
+            LintRule::ShadowedName => ErrorCode::ShadowedName,
+            LintRule::FloatEquality => ErrorCode::FloatEquality,
+            LintRule::ConstantCondition => ErrorCode::ConstantCondition,
+            LintRule::UnusedExpression => ErrorCode::UnusedExpression,
+            LintRule::MagicNumber => ErrorCode::MagicNumber,
+        }
+    }
+}
+
+/// lint 检查的配置，通过 Parser::set_lint 设置
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintOptions {
+    /// 启用的规则，默认为全部规则
+    pub rules: Vec<LintRule>,
+    /// 不作为 MagicNumber 报告的数值，默认为 0 和 1
+    pub allowed_numbers: Vec<f64>,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        LintOptions {
+            rules: LintRule::ALL.to_vec(),
+            allowed_numbers: vec![0.0, 1.0],
+        }
+    }
+}
+
+impl LintOptions {
+    pub fn enabled(&self, rule: LintRule) -> bool {
+        self.rules.contains(&rule)
+    }
+}
+
+/// 函数的参数及局部变量
+struct Scope {
+    declared: Vec<(String, Range<usize>)>,
+    used: HashSet<String>,
+}
+
+/// 按源码的顺序遍历语法树，检查其中可疑的写法
+pub(crate) struct Linter<'a> {
+    options: &'a LintOptions,
+    env: &'a Env,
+    locale: Locale,
+    len: usize,
+    spans: SpanFinder<'a>,
+    /// 数值常量在源码中的位置，与名称分开查找
+    numbers: SpanFinder<'a>,
+    /// 源码顶层定义的名称
+    defined: HashSet<String>,
+    scopes: Vec<Scope>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    pub fn new(source: &'a str, env: &'a Env, options: &'a LintOptions) -> Self {
+        Linter {
+            options,
+            env,
+            locale: env.options().locale,
+            len: source.len(),
+            spans: SpanFinder::new(source),
+            numbers: SpanFinder::new(source),
+            defined: HashSet::new(),
+            scopes: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// 检查源码中的所有语句
+    pub fn lint(&mut self, nodes: &[FormulaNode]) {
+        for node in nodes {
+            if let FormulaNode::Formula { name, .. } | FormulaNode::Function { name, .. } = node {
+                self.defined.insert(name.clone());
+            }
+        }
+        for node in nodes {
+            self.visit(node);
+        }
+    }
+
+    /// 检查 node 并返回其中第一个名称在源码中的位置，用作该表达式的位置
+    fn visit(&mut self, node: &FormulaNode) -> Option<Range<usize>> {
+        match node {
+            FormulaNode::Variant(name) | FormulaNode::History { name, .. } => {
+                self.use_name(name);
+                Some(self.spans.find(name))
+            }
+            FormulaNode::Constant(n) => {
+                if self.options.enabled(LintRule::MagicNumber)
+                    && !self.options.allowed_numbers.contains(n)
+                {
+                    let span = self.numbers.find(&n.to_string());
+                    self.warn(LintRule::MagicNumber, span, &[n]);
+                }
+                None
+            }
+            FormulaNode::Index { target, index } => {
+                let span = self.visit(target);
+                for node in index.nodes() {
+                    match node.unquoted() {
+                        // 下标中的数字表示位置，不作为未命名的数字
+                        FormulaNode::Constant(n) => {
+                            self.numbers.find(&n.to_string());
+                        }
+                        node => {
+                            self.visit(node);
+                        }
+                    }
+                }
+                span
+            }
+            FormulaNode::Operator(op) => self.operator(op),
+            FormulaNode::FunctionCall { name, args } => {
+                self.use_name(name);
+                let span = self.spans.find(name);
+                for (index, arg) in args.iter().enumerate() {
+                    let arg_span = self.visit(arg);
+                    if index == 0 && name == "If" && is_constant(arg) {
+                        let span = arg_span.unwrap_or_else(|| span.clone());
+                        self.warn(LintRule::ConstantCondition, span, &[arg]);
+                    }
+                }
+                Some(span)
+            }
+            FormulaNode::Function {
+                name,
+                args,
+                expressions,
+            } => {
+                let span = self.spans.find(name);
+                let mut scope = Scope {
+                    declared: Vec::new(),
+                    used: HashSet::new(),
+                };
+                for arg in args {
+                    if let FormulaNode::Variant(arg) = arg.as_ref() {
+                        let span = self.spans.find(arg);
+                        self.shadow(arg, &span);
+                        scope.declared.push((arg.clone(), span));
+                    }
+                }
+                self.scopes.push(scope);
+                for (index, exp) in expressions.iter().enumerate() {
+                    let exp_span = self.visit(exp);
+                    let discarded = !matches!(
+                        exp.as_ref(),
+                        FormulaNode::Formula { .. } | FormulaNode::Function { .. }
+                    );
+                    if index + 1 < expressions.len() && discarded {
+                        let exp_span = exp_span.unwrap_or(0..self.len);
+                        self.warn(LintRule::UnusedExpression, exp_span, &[name, exp]);
+                    }
+                }
+                if let Some(scope) = self.scopes.pop() {
+                    for (name, span) in scope.declared {
+                        if !scope.used.contains(&name) {
+                            self.warn(LintRule::UnusedDefinition, span, &[&name]);
+                        }
+                    }
+                }
+                Some(span)
+            }
+            FormulaNode::Formula { name, formula } => {
+                let span = self.spans.find(name);
+                let is_new = self
+                    .scopes
+                    .last()
+                    .is_some_and(|scope| !scope.declared.iter().any(|(n, _)| n == name));
+                if is_new {
+                    self.shadow(name, &span);
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.declared.push((name.clone(), span.clone()));
+                    }
+                }
+                match formula.unquoted() {
+                    // 直接作为具名表达式的值即为对该数字的命名
+                    FormulaNode::Constant(n) => {
+                        self.numbers.find(&n.to_string());
+                    }
+                    _ => {
+                        self.visit(formula);
+                    }
+                }
+                Some(span)
+            }
+            FormulaNode::Namespace { definitions, .. } => {
+                for definition in definitions {
+                    self.visit(definition);
+                }
+                None
+            }
+            FormulaNode::Arg { value, .. } => self.visit(value),
+            FormulaNode::Quote(node) => self.visit(node),
+            _ => None,
+        }
+    }
+
+    fn operator(&mut self, op: &OperatorNode) -> Option<Range<usize>> {
+        let operands = op.operands();
+        let spans: Vec<Option<Range<usize>>> = operands.iter().map(|o| self.visit(o)).collect();
+        let first = spans.iter().flatten().next().cloned();
+        match op {
+            OperatorNode::Equal { left, right } | OperatorNode::NotEqual { left, right }
+                if is_float(left) || is_float(right) =>
+            {
+                let span = first.clone().unwrap_or(0..self.len);
+                self.warn(LintRule::FloatEquality, span, &[&op.symbol()]);
+            }
+            OperatorNode::And { .. } | OperatorNode::Or { .. } | OperatorNode::Not(_) => {
+                for (operand, span) in operands.iter().zip(spans) {
+                    if is_constant(operand) {
+                        let span = span.or_else(|| first.clone()).unwrap_or(0..self.len);
+                        self.warn(LintRule::ConstantCondition, span, &[operand]);
+                    }
+                }
+            }
+            _ => (),
+        }
+        first
+    }
+
+    /// 将 name 记录为最内层声明了该名称的作用域中已使用的名称
+    fn use_name(&mut self, name: &str) {
+        let scope = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find(|scope| scope.declared.iter().any(|(n, _)| n == name));
+        if let Some(scope) = scope {
+            scope.used.insert(name.to_string());
+        }
+    }
+
+    /// 函数中声明的 name 与源码顶层或执行环境中已有的定义同名时报告问题
+    fn shadow(&mut self, name: &str, span: &Range<usize>) {
+        if self.defined.contains(name) || self.env.get(name).is_some() {
+            self.warn(LintRule::ShadowedName, span.clone(), &[&name]);
+        }
+    }
+
+    fn warn(&mut self, rule: LintRule, span: Range<usize>, args: &[&dyn Display]) {
+        if self.options.enabled(rule) {
+            let code = rule.code();
+            self.diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                code,
+                message: code.text(self.locale, args),
+                span,
+            });
+        }
+    }
+}
+
+/// 表达式中是否不包含变量及函数调用，即计算结果总是相同
+fn is_constant(node: &FormulaNode) -> bool {
+    match node {
+        FormulaNode::Constant(_)
+        | FormulaNode::Bool(_)
+        | FormulaNode::Complex(_)
+        | FormulaNode::Vector(_)
+        | FormulaNode::Matrix(_)
+        | FormulaNode::Text(_) => true,
+        FormulaNode::Operator(op) => op.operands().into_iter().all(is_constant),
+        FormulaNode::Quote(node) => is_constant(node),
+        _ => false,
+    }
+}
+
+/// 表达式是否为小数常量或四则运算及乘方的结果，这类数值使用 == 比较时可能受精度误差的影响
+fn is_float(node: &FormulaNode) -> bool {
+    match node.unquoted() {
+        FormulaNode::Constant(n) => n.fract() != 0.0,
+        FormulaNode::Operator(op) => matches!(
+            op.as_ref(),
+            OperatorNode::Plus { .. }
+                | OperatorNode::Minus { .. }
+                | OperatorNode::Multiply { .. }
+                | OperatorNode::Divide { .. }
+                | OperatorNode::Power { .. }
+        ),
+        _ => false,
+    }
+}
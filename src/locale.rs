@@ -0,0 +1,295 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+/// 错误信息所使用的语言，默认为中文
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    Zh,
+    En,
+}
+
+/// 数值的书写习惯，决定了公式中数值常量的解析方式及计算结果输出时数值的格式,
+/// 默认以 . 为小数点且不使用千位分隔符，如 1234.56
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberLocale {
+    /// 小数点
+    pub decimal: char,
+    /// 整数部分的千位分隔符，None 表示不分组
+    pub grouping: Option<char>,
+}
+
+impl Default for NumberLocale {
+    fn default() -> Self {
+        NumberLocale {
+            decimal: '.',
+            grouping: None,
+        }
+    }
+}
+
+impl NumberLocale {
+    pub fn new(decimal: char, grouping: Option<char>) -> Self {
+        NumberLocale { decimal, grouping }
+    }
+
+    /// 欧洲常用的写法，以 , 为小数点，以 . 为千位分隔符，如 1.234,56
+    pub fn decimal_comma() -> Self {
+        NumberLocale::new(',', Some('.'))
+    }
+
+    /// 是否以 , 作为小数点或千位分隔符，此时紧跟在数值之后且之后紧跟着数值的 , 属于该数值,
+    /// 函数参数及向量元素之间的 , 之后需要有空白，如 F(1, 2)
+    pub fn uses_comma(&self) -> bool {
+        self.decimal == ',' || self.grouping == Some(',')
+    }
+
+    /// 按该写法解析数值文本，千位分隔符只能出现在整数部分且除第一组外每组均为 3 位数字,
+    /// 整数部分及小数部分都可以为空但不能同时为空，格式不符时返回 None
+    pub fn parse(&self, text: &str) -> Option<f64> {
+        let (integer, fraction) = text.split_once(self.decimal).unwrap_or((text, ""));
+        let integer = match self.grouping {
+            Some(grouping) if integer.contains(grouping) => {
+                let groups: Vec<&str> = integer.split(grouping).collect();
+                let valid = (1..=3).contains(&groups[0].len())
+                    && groups[1..].iter().all(|group| group.len() == 3);
+                if !valid {
+                    return None;
+                }
+                groups.concat()
+            }
+            _ => integer.to_string(),
+        };
+        let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if !digits(&integer) || !digits(fraction) || integer.len() + fraction.len() == 0 {
+            return None;
+        }
+        format!("{}.{}", integer, fraction).parse().ok()
+    }
+
+    /// 按该写法输出数值，非有限的数值与 Display 的输出相同
+    pub fn format(&self, n: f64) -> String {
+        let text = n.to_string();
+        if !n.is_finite() {
+            return text;
+        }
+        let (sign, text) = match text.strip_prefix('-') {
+            Some(text) => ("-", text),
+            None => ("", text.as_str()),
+        };
+        let (integer, fraction) = text.split_once('.').unwrap_or((text, ""));
+        let mut out = sign.to_string();
+        for (i, c) in integer.chars().enumerate() {
+            if let Some(grouping) = self.grouping {
+                if i > 0 && (integer.len() - i) % 3 == 0 {
+                    out.push(grouping);
+                }
+            }
+            out.push(c);
+        }
+        if !fraction.is_empty() {
+            out.push(self.decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+}
+
+macro_rules! error_codes {
+    ($($name:ident = $code:literal, $zh:literal, $en:literal;)*) => {
+        /// 计算及检查表达式时产生的错误码，错误信息以 "错误码: " 开头，
+        /// 便于日志系统等程序在不依赖具体语言的情况下识别错误的类型
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum ErrorCode {
+            $($name,)*
+        }
+
+        impl ErrorCode {
+            const ALL: &'static [ErrorCode] = &[$(ErrorCode::$name,)*];
+
+            /// 错误码，如 E0101
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $(ErrorCode::$name => $code,)*
+                }
+            }
+
+            /// 错误信息的模板，其中的 {0}、{1} 等会被依次替换为对应位置的参数
+            pub fn template(&self, locale: Locale) -> &'static str {
+                match (self, locale) {
+                    $(
+                        (ErrorCode::$name, Locale::Zh) => $zh,
+                        (ErrorCode::$name, Locale::En) => $en,
+                    )*
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    UndefinedVariable = "E0101", "无法从执行环境中获取指定的变量名 {0}", "variable {0} is not defined";
+    UndefinedFunction = "E0102", "从执行环境中获取函数 {0} 时出错，对应的函数不存在环境变量中，是否未定义该函数", "function {0} is not defined";
+    InvalidExpression = "E0103", "无法计算该表达式，格式出错？", "cannot evaluate the expression, is it malformed?";
+    ArityMismatch = "E0201", "函数 {0} 定义的参数个数为 {1}，与函数调用的参数个数 {2} 不匹配", "function {0} takes {1} arguments but {2} were given";
+    BuildInArityMismatch = "E0202", "内建函数 {0} 需要 {1} 个参数，实际传入了 {2} 个参数", "built-in function {0} takes {1} arguments but {2} were given";
+    InvalidParameter = "E0203", "为函数 {0} 获取执行变量时出错，错误变量位置为 {1}", "parameter {1} of function {0} is not a variable name";
+    ArgumentError = "E0204", "为函数 {0} 计算参数值时出错，错误信息为 {1}", "failed to evaluate an argument of function {0}: {1}";
+    ArgumentNone = "E0205", "为函数 {0} 计算参数值时出错，错误信息为该参数返回结果为 None", "an argument of function {0} evaluated to None";
+    FunctionBodyError = "E0206", "计算函数体时出错", "failed to evaluate the function body";
+    BuildInError = "E0207", "调用内建函数 {0} 时出错，{1}", "built-in function {0} failed: {1}";
+    BuildInNotFound = "E0208", "获取内建函数 {0} 时出错，运行环境中不存在该函数", "built-in function {0} is not registered";
+    NotCallable = "E0209", "{0} 不是函数，无法被调用", "{0} is not a function and cannot be called";
+    NondeterministicCall = "E0210", "确定性计算模式下不允许调用结果不确定的内建函数 {0}", "built-in function {0} is nondeterministic and not allowed in deterministic mode";
+    BuildInNotAllowed = "E0211", "安全策略不允许调用内建函数 {0}", "built-in function {0} is not allowed by the security policy";
+    CallDepthExceeded = "E0212", "函数调用的嵌套深度超过了安全策略限制的 {0} 层", "call depth exceeds the limit of {0} set by the security policy";
+    FuelExhausted = "E0213", "计算的节点个数超过了安全策略限制的 {0} 个", "evaluation exceeds the fuel budget of {0} set by the security policy";
+    CircularReference = "E0214", "变量 {0} 的定义直接或间接地引用了自身", "the definition of {0} refers to itself";
+    VectorLengthMismatch = "E0301", "参与计算的两个向量长度不一致，分别为 {0} 与 {1}", "vector lengths differ: {0} and {1}";
+    NonNumericArithmetic = "E0302", "尝试使用 {0} 计算非数值类型", "cannot apply {0} to non-numeric values";
+    NonNumericCompare = "E0303", "尝试用 {0} 比较两个非数值类型", "cannot compare non-numeric values with {0}";
+    ComplexCompare = "E0304", "复数之间无法使用 {0} 比较大小", "complex numbers cannot be ordered with {0}";
+    NonBoolNot = "E0305", "尝试对非逻辑结果取反", "cannot negate a non-boolean value";
+    NonNumericLogic = "E0306", "尝试对两个非数值类型使用 {0} 操作", "cannot apply {0} to non-numeric values";
+    MatrixDimensionMismatch = "E0307", "矩阵的维度 {0}x{1} 与 {2}x{3} 不匹配，无法使用 {4} 计算", "cannot apply {4} to matrices of size {0}x{1} and {2}x{3}";
+    IndexOutOfRange = "E0308", "下标 {0} 超出了长度为 {1} 的范围", "index {0} is out of range for length {1}";
+    InvalidIndex = "E0309", "下标应为整数，实际为 {0}", "index must be an integer but is {0}";
+    NotIndexable = "E0310", "{0} 类型的值不支持下标访问", "values of type {0} cannot be indexed";
+    NonBoolLogic = "E0311", "严格逻辑模式下 {0} 的操作数应为逻辑值", "operands of {0} must be boolean in strict logic mode";
+    ComplexPower = "E0312", "复数不支持 ^ 乘方运算", "complex numbers cannot be raised with ^";
+    CurrencyMismatch = "E0313", "货币 {0} 与 {1} 的金额无法使用 {2} 计算，请先通过 Convert 换算", "cannot apply {2} to amounts in {0} and {1}, use Convert first";
+    InvalidMoneyOperation = "E0314", "{1} 与 {2} 之间不支持 {0} 运算", "cannot apply {0} to {1} and {2}";
+    MissingOperand = "E0401", "操作符缺少操作数", "operator is missing an operand";
+    InvalidParameterName = "E0402", "函数 {0} 的参数应为变量名", "parameters of function {0} must be variable names";
+    Redefinition = "E0403", "{0} 已定义，将覆盖原有的定义", "{0} is already defined and will be overwritten";
+    MaybeImported = "E0404", "{0}，该名称可能由 import 引入", "{0}, it may be provided by an import";
+    SyntaxError = "E0405", "{0}", "{0}";
+    ReadOnly = "E0406", "{0}", "{0}";
+    FormulaTooLong = "E0407", "公式的长度 {0} 超过了安全策略限制的 {1} 个字节", "formula length {0} exceeds the limit of {1} bytes set by the security policy";
+    OverrideForbidden = "E0408", "安全策略不允许覆盖已有的定义 {0}", "the security policy does not allow redefining {0}";
+    OperandTypeMismatch = "E0409", "{0} 的操作数应为 {1} 类型，实际为 {2}", "operand of {0} should be {1} but is {2}";
+    ArgumentTypeMismatch = "E0410", "函数 {0} 的第 {1} 个参数应为 {2} 类型，实际为 {3}", "argument {1} of function {0} should be {2} but is {3}";
+    UnusedDefinition = "E0501", "{0} 已定义但从未使用", "{0} is defined but never used";
+    ShadowedName = "E0502", "{0} 遮蔽了同名的已有定义", "{0} shadows an existing definition";
+    FloatEquality = "E0503", "使用 {0} 比较浮点数可能因精度误差得到意外的结果", "comparing floating-point values with {0} may be affected by rounding errors";
+    ConstantCondition = "E0504", "条件 {0} 不依赖任何变量，结果总是相同", "condition {0} does not depend on any variable and always has the same result";
+    UnusedExpression = "E0505", "函数 {0} 中的表达式 {1} 的结果未被使用，只有最后一个表达式作为返回值", "the result of {1} in function {0} is discarded, only the last expression is returned";
+    MagicNumber = "E0506", "数字 {0} 没有命名，建议定义为具名的表达式", "magic number {0}, consider giving it a name";
+}
+
+macro_rules! messages {
+    ($($name:ident, $zh:literal, $en:literal;)*) => {
+        /// 命令行工具的提示信息，与错误信息使用相同的模板格式
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Message {
+            $($name,)*
+        }
+
+        impl Message {
+            /// 提示信息的模板，其中的 {0}、{1} 等会被依次替换为对应位置的参数
+            pub fn template(&self, locale: Locale) -> &'static str {
+                match (self, locale) {
+                    $(
+                        (Message::$name, Locale::Zh) => $zh,
+                        (Message::$name, Locale::En) => $en,
+                    )*
+                }
+            }
+        }
+    };
+}
+
+messages! {
+    Options, "Options: --output json|text  以 JSON 或文本输出计算结果\n         --lang zh|en       提示信息的语言", "Options: --output json|text  print results as JSON or text\n         --lang zh|en       language of messages";
+    InvalidOutput, "--output 的取值应为 json 或 text", "--output must be json or text";
+    InvalidLang, "--lang 的取值应为 zh 或 en", "--lang must be zh or en";
+    MissingFormula, "--eval 之后缺少表达式", "--eval requires a formula";
+    InvalidColumn, "{0} 的格式应为 列名=表达式", "{0} should be written as column=formula";
+    ReadFile, "读取文件 {0} 时出错: {1}", "failed to read {0}: {1}";
+    WriteFile, "保存到 {0} 时出错: {1}", "failed to write {0}: {1}";
+    ReadInput, "读取输入时出错: {0}", "failed to read input: {0}";
+    ParsedFile, "成功解析表达式 {0}", "parsed {0}";
+    LoadedDir, "成功加载目录 {0} 中的 {1} 个公式文件", "loaded {1} formula files from {0}";
+    EnterRepl, "进入交互式环境？(yes/no) (default: yes)", "enter the interactive environment? (yes/no) (default: yes)";
+    EmptyInput, "输入的表达式为空!", "the expression is empty!";
+    InputDiscarded, "已放弃当前输入", "input discarded";
+    EnvCleared, "已清空执行环境", "environment cleared";
+    Saved, "已将 {0} 条表达式保存到 {1}", "saved {0} expressions to {1}";
+    Unformatted, "{0} 的格式不规范", "{0} is not formatted";
+    ReplHelp, "可用的命令:
+    :vars           列出变量及其当前的值
+    :ast <表达式>   输出表达式的语法树
+    :funcs          列出内建函数及脚本中定义的函数
+    :clear          清空交互式环境中定义的变量及函数
+    :load <文件名>  加载并计算公式文件
+    :save <文件名>  将本次会话中输入的表达式保存到文件", "Commands:
+    :vars           list variables and their current values
+    :ast <formula>  print the syntax tree of a formula
+    :funcs          list built-in and script functions
+    :clear          remove everything defined in this session
+    :load <file>    load and evaluate a formula file
+    :save <file>    save the formulas entered in this session to a file";
+}
+
+impl Message {
+    /// 生成 locale 语言的提示信息，args 依次替换模板中的 {0}、{1} 等参数
+    pub fn text(&self, locale: Locale, args: &[&dyn Display]) -> String {
+        fill(self.template(locale), args)
+    }
+}
+
+impl Locale {
+    /// 根据 LC_ALL、LC_MESSAGES 及 LANG 环境变量确定语言，以 zh 开头时为中文，
+    /// 设置为其他语言时为英文，均未设置时使用默认的中文
+    #[cfg(feature = "std")]
+    pub fn from_env() -> Locale {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|key| std::env::var(key).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Locale::default(), |value| match value.starts_with("zh") {
+                true => Locale::Zh,
+                false => Locale::En,
+            })
+    }
+}
+
+impl core::str::FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zh" => Ok(Locale::Zh),
+            "en" => Ok(Locale::En),
+            _ => Err(Message::InvalidLang.text(Locale::default(), &[])),
+        }
+    }
+}
+
+/// 依次将模板中的 {0}、{1} 等替换为 args 中对应位置的参数
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut text = template.to_string();
+    for (i, arg) in args.iter().enumerate() {
+        text = text.replace(&format!("{{{}}}", i), &arg.to_string());
+    }
+    text
+}
+
+impl ErrorCode {
+    /// 生成 locale 语言的错误描述，args 依次替换模板中的 {0}、{1} 等参数
+    pub fn text(&self, locale: Locale, args: &[&dyn Display]) -> String {
+        fill(self.template(locale), args)
+    }
+
+    /// 生成以错误码开头的错误信息，如 E0101: variable A is not defined
+    pub fn message(&self, locale: Locale, args: &[&dyn Display]) -> String {
+        format!("{}: {}", self.code(), self.text(locale, args))
+    }
+
+    /// 从错误信息中识别错误码，错误信息不以错误码开头时返回 None
+    pub fn of(message: &str) -> Option<ErrorCode> {
+        let code = message.split(':').next()?;
+        ErrorCode::ALL.iter().find(|c| c.code() == code).copied()
+    }
+}
@@ -0,0 +1,195 @@
+use std::ops::Range;
+
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, Hover, HoverContents,
+    MarkupContent, MarkupKind, NumberOrString, Position,
+};
+
+use crate::diagnostic::Severity;
+use crate::env::EnvEntryKind;
+use crate::parser::Parser;
+
+/// formula-lsp 中对单个文档的分析，每次分析都在新的执行环境中加载文档中的定义,
+/// 其中的位置均为 LSP 使用的 行号及 UTF-16 列号
+pub struct Document<'a> {
+    source: &'a str,
+    parser: Parser,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let mut parser = Parser::new();
+        parser.parse(source);
+        Document { source, parser }
+    }
+
+    /// 文档中的语法错误、未定义的名称及参数个数不匹配等问题
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        Parser::new()
+            .check(self.source)
+            .into_iter()
+            .map(|d| Diagnostic {
+                range: self.range(d.span),
+                severity: Some(match d.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                code: Some(NumberOrString::String(d.code.code().to_string())),
+                source: Some("formula".to_string()),
+                message: d.message,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// position 处名称的说明，表达式显示其定义及当前的计算结果，内建函数显示其参数及功能说明
+    pub fn hover(&mut self, position: Position) -> Option<Hover> {
+        let span = self.word_at(position)?;
+        let name = &self.source[span.clone()];
+        let value = match self.parser.builtins().into_iter().find(|b| b.name == name) {
+            Some(info) => format!(
+                "```\n{}({})\n```\n{}",
+                info.name,
+                info.params.join(", "),
+                info.description
+            ),
+            None => {
+                let info = self
+                    .parser
+                    .env_info()
+                    .into_iter()
+                    .find(|i| i.name == name)?;
+                match info.kind {
+                    EnvEntryKind::Function => format!("```\n{}\n```", info.node),
+                    _ => format!(
+                        "```\n{}\n```\n= {}",
+                        info.node,
+                        self.parser.calculate(name).value
+                    ),
+                }
+            }
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(self.range(span)),
+        })
+    }
+
+    /// position 处名称在文档中的定义位置，即 Name := ... 或 Name(args) { ... } 中的 Name
+    pub fn definition(&self, position: Position) -> Option<lsp_types::Range> {
+        let span = self.word_at(position)?;
+        let name = &self.source[span];
+        // 命名空间中的定义在源码中只写了最后一段名称
+        let short = name.rsplit('.').next().unwrap_or(name);
+        let found =
+            definition_span(self.source, name).or_else(|| definition_span(self.source, short))?;
+        Some(self.range(found))
+    }
+
+    /// 执行环境中的变量、函数及内建函数名
+    pub fn completion(&self) -> Vec<CompletionItem> {
+        self.parser
+            .env_info()
+            .into_iter()
+            .map(|info| CompletionItem {
+                kind: Some(match info.kind {
+                    EnvEntryKind::Function | EnvEntryKind::BuildIn => CompletionItemKind::FUNCTION,
+                    EnvEntryKind::Constant => CompletionItemKind::CONSTANT,
+                    EnvEntryKind::Formula => CompletionItemKind::VARIABLE,
+                    EnvEntryKind::Namespace => CompletionItemKind::MODULE,
+                }),
+                detail: match info.kind {
+                    EnvEntryKind::BuildIn | EnvEntryKind::Namespace => None,
+                    _ => Some(info.node.to_string()),
+                },
+                label: info.name,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// position 所在的名称在源码中的字节范围
+    fn word_at(&self, position: Position) -> Option<Range<usize>> {
+        let offset = self.offset(position);
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+        let start = self.source[..offset]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_name_char(*c))
+            .last()
+            .map_or(offset, |(i, _)| i);
+        let end = self.source[offset..]
+            .char_indices()
+            .find(|(_, c)| !is_name_char(*c))
+            .map_or(self.source.len(), |(i, _)| offset + i);
+        match self.source[start..end].chars().next() {
+            Some(c) if !c.is_ascii_digit() => Some(start..end),
+            _ => None,
+        }
+    }
+
+    /// 将 LSP 的位置转换为源码中的字节位置，超出范围时取最接近的位置
+    fn offset(&self, position: Position) -> usize {
+        let mut offset = 0;
+        for (row, line) in self.source.split_inclusive('\n').enumerate() {
+            if row as u32 == position.line {
+                let mut column = 0;
+                for (i, c) in line.char_indices() {
+                    if column >= position.character || c == '\n' {
+                        return offset + i;
+                    }
+                    column += c.len_utf16() as u32;
+                }
+                return offset + line.len();
+            }
+            offset += line.len();
+        }
+        self.source.len()
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let before = &self.source[..offset];
+        let line = before.matches('\n').count() as u32;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let character = before[line_start..].encode_utf16().count() as u32;
+        Position { line, character }
+    }
+
+    fn range(&self, span: Range<usize>) -> lsp_types::Range {
+        lsp_types::Range {
+            start: self.position(span.start),
+            end: self.position(span.end),
+        }
+    }
+}
+
+/// 查找 name 作为表达式或函数定义出现的位置
+fn definition_span(source: &str, name: &str) -> Option<Range<usize>> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+    let mut start = 0;
+    while let Some(offset) = source[start..].find(name) {
+        let (begin, end) = (start + offset, start + offset + name.len());
+        start = end;
+        if source[..begin]
+            .chars()
+            .next_back()
+            .is_some_and(is_name_char)
+        {
+            continue;
+        }
+        let rest = source[end..].trim_start();
+        if rest.starts_with(":=") {
+            return Some(begin..end);
+        }
+        if rest.starts_with('(') {
+            let after_args = rest.find(')').map(|i| rest[i + 1..].trim_start());
+            if after_args.is_some_and(|s| s.starts_with('{')) {
+                return Some(begin..end);
+            }
+        }
+    }
+    None
+}
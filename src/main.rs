@@ -1,14 +1,54 @@
 use std::env as Env;
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::process;
 
-pub mod calculator;
-pub mod env;
-pub mod formula;
-pub mod parser;
-
+use formula_parser::calculator::{CalculateOption, CalculateResult};
+use formula_parser::diagnostic::Severity;
+use formula_parser::env::{EnvEntryKind, EnvSnapshot};
+use formula_parser::format::{format_formula, FormatOptions};
+use formula_parser::locale::{Locale, Message};
 use formula_parser::parser::Parser;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
 
 fn main() {
-    let args: Vec<String> = Env::args().collect();
+    let mut args: Vec<String> = Env::args().collect();
+    // --lang 指定提示信息及错误信息的语言，未指定时根据系统的语言环境确定
+    let locale = match take_option(&mut args, "--lang") {
+        Some(lang) => lang.parse().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }),
+        None => Locale::from_env(),
+    };
+    // --output json 以 JSON 输出每次计算的结果，可出现在任意位置
+    let json = match take_option(&mut args, "--output").as_deref() {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(_) => {
+            eprintln!("{}", Message::InvalidOutput.text(locale, &[]));
+            process::exit(1);
+        }
+    };
+    let mut parser = Parser::new();
+    parser.set_locale(locale);
+
+    // 通过管道传入的表达式直接计算，不进入交互式环境
+    if args.len() == 1 && !std::io::stdin().is_terminal() {
+        let mut source = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("{}", Message::ReadInput.text(locale, &[&e]));
+            process::exit(1);
+        }
+        process::exit(eval(&mut parser, &source, json));
+    }
+
     if args.len() == 1 {
         println!(
             "Formula syntax:
@@ -16,9 +56,10 @@ fn main() {
     Constant: Number,
     Value: Variant, Constant, Formula
     Name: Constant := Formula
-    BinaryOp: +, -, *, /
-    UnaryOp: ^
-    LogicOp: >, >=, <, <=, !=
+    BinaryOp: +, -, *, /, ^
+    UnaryOp: !
+    CompareOp: >, >=, <, <=, ==, !=
+    LogicOp: &&, ||
     Function Definition: Variant(Variant, ...) {{ Exp; ... }}
     Function Call: Variant(Variant|Constant, ...)
     Exp: UnaryOp Value
@@ -26,63 +67,392 @@ fn main() {
          (Exp)
         "
         );
-        println!("Usage: {}  <filename> [--from_std]", args[0]);
+        println!("Usage: {}  <filename|directory>... [--from_std]", args[0]);
+        println!("       {}  --eval <formula>", args[0]);
+        println!("       echo <formula> | {}", args[0]);
+        println!("       {}  fmt [--check] <filename>...", args[0]);
+        println!("       {}  lint <filename>...", args[0]);
+        println!("       {}  csv <filename> <column>=<formula>...", args[0]);
+        println!("{}", Message::Options.text(locale, &[]));
         return;
     }
 
-    let mut parser = Parser::new();
+    // fmt 输出整理后的源码，--check 只列出格式不规范的文件，存在这样的文件时退出码为 1
+    if &args[1] == "fmt" {
+        let check = match args.iter().position(|arg| arg == "--check") {
+            Some(i) => {
+                args.remove(i);
+                true
+            }
+            None => false,
+        };
+        let mut unformatted = false;
+        for arg in args.iter().skip(2) {
+            let source = std::fs::read_to_string(arg).unwrap_or_else(|e| {
+                eprintln!("{}", Message::ReadFile.text(locale, &[arg, &e]));
+                process::exit(1);
+            });
+            let formatted = format_formula(&source, FormatOptions::default());
+            match check {
+                true if source.trim_end_matches('\n') != formatted => {
+                    println!("{}", Message::Unformatted.text(locale, &[arg]));
+                    unformatted = true;
+                }
+                true => (),
+                false => println!("{}", formatted),
+            }
+        }
+        process::exit(unformatted as i32);
+    }
+
+    // lint 输出每个文件中发现的问题，存在问题时退出码为 1
+    if &args[1] == "lint" {
+        let mut found = false;
+        for arg in args.iter().skip(2) {
+            let source = std::fs::read_to_string(arg).unwrap_or_else(|e| {
+                eprintln!("{}", Message::ReadFile.text(locale, &[arg, &e]));
+                process::exit(1);
+            });
+            for diagnostic in parser.lint(&source) {
+                let (line, column) = line_column(&source, diagnostic.span.start);
+                println!(
+                    "{}:{}:{}: {}: {}",
+                    arg,
+                    line,
+                    column,
+                    diagnostic.code.code(),
+                    diagnostic.message
+                );
+                found = true;
+            }
+        }
+        process::exit(found as i32);
+    }
+
+    if &args[1] == "--eval" {
+        match args.get(2) {
+            Some(formula) => process::exit(eval(&mut parser, formula, json)),
+            None => {
+                eprintln!("{}", Message::MissingFormula.text(locale, &[]));
+                process::exit(1);
+            }
+        }
+    }
+
+    if &args[1] == "csv" && args.len() > 2 {
+        let csv = std::fs::read_to_string(&args[2]).unwrap_or_else(|e| {
+            eprintln!("{}", Message::ReadFile.text(locale, &[&args[2], &e]));
+            process::exit(1);
+        });
+        let formulas: Vec<(&str, &str)> = args[3..]
+            .iter()
+            .map(|arg| match arg.split_once('=') {
+                Some(formula) => formula,
+                None => {
+                    eprintln!("{}", Message::InvalidColumn.text(locale, &[arg]));
+                    process::exit(1);
+                }
+            })
+            .collect();
+        match parser.calculate_csv(&csv, &formulas) {
+            Ok(out) => print!("{}", out),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
 
     if &args[1] == "--from_std" {
-        exec_cmd(&mut parser);
+        exec_cmd(&mut parser, json);
     } else {
         for arg in args.iter().skip(1) {
-            std::fs::read_to_string(arg)
-                .and_then(|formula| {
-                    parser.calculate(formula.clone());
-                    println!("成功解析表达式 {}", formula);
-                    Ok(1)
-                })
-                .expect(&format!("处理表达式 {} 时出错", arg));
+            if std::path::Path::new(arg).is_dir() {
+                match parser.load_dir(arg) {
+                    Ok(files) => {
+                        println!("{}", Message::LoadedDir.text(locale, &[arg, &files.len()]))
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+                continue;
+            }
+            match std::fs::read_to_string(arg) {
+                Ok(formula) => {
+                    let result = parser.calculate(&formula);
+                    match json {
+                        true => println!("{}", result.to_json()),
+                        false => println!("{}", Message::ParsedFile.text(locale, &[&formula])),
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", Message::ReadFile.text(locale, &[arg, &e]));
+                    process::exit(1);
+                }
+            }
         }
 
-        println!("进入交互式环境？(yes/no) (default: yes)");
+        println!("{}", Message::EnterRepl.text(locale, &[]));
         let mut get_into = String::new();
-        std::io::stdin()
-            .read_line(&mut get_into)
-            .expect("读取进入交互式环境的命令出错");
-        if get_into.trim() == "yes" || get_into.trim().len() == 0 {
-            exec_cmd(&mut parser);
+        if let Err(e) = std::io::stdin().read_line(&mut get_into) {
+            eprintln!("{}", Message::ReadInput.text(locale, &[&e]));
+            process::exit(1);
+        }
+        if get_into.trim() == "yes" || get_into.trim().is_empty() {
+            exec_cmd(&mut parser, json);
         }
     }
 }
 
-fn exec_cmd(parser: &mut Parser) {
+/// 源码中字节位置 offset 所在的行号及列号，均从 1 开始
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |s| s.chars().count()) + 1;
+    (line, column)
+}
+
+/// 取出命令行参数中的选项 name 及其取值，选项之后没有取值时返回空字符串
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    let mut option = args.drain(i..(i + 2).min(args.len())).skip(1);
+    Some(option.next().unwrap_or_default())
+}
+
+/// 依次计算 source 中的每条语句，输出出错语句的错误信息及最后一条语句的结果,
+/// json 为 true 时输出 CalculateResult::to_json 的格式,
+/// 返回进程的退出码：成功为 0，存在语法错误时为 1 且不计算任何语句，有语句计算出错时为 2
+fn eval(parser: &mut Parser, source: &str, json: bool) -> i32 {
+    let (_, diagnostics) = parser.parse_resilient(source);
+    let mut code = 0;
+    for diagnostic in diagnostics {
+        if diagnostic.severity != Severity::Error {
+            continue;
+        }
+        let message = format!("{}: {}", diagnostic.code.code(), diagnostic.message);
+        print_result(&error_result(message), json);
+        code = 1;
+    }
+    if code != 0 {
+        return code;
+    }
+
+    let statements: Vec<_> = parser.parse_reader(source.as_bytes()).collect();
+    let mut last = None;
+    for statement in statements {
+        let result = match statement {
+            Ok(node) => parser.compile_node(node).eval(),
+            Err(message) => error_result(message),
+        };
+        if let CalculateOption::Err(_) = result.value {
+            print_result(&result, json);
+            code = 2;
+            last = None;
+        } else {
+            last = Some(result);
+        }
+    }
+    if let Some(result) = last {
+        print_result(&result, json);
+    }
+    code
+}
+
+/// 只包含错误信息 message 的计算结果
+fn error_result(message: String) -> CalculateResult {
+    CalculateResult {
+        value: CalculateOption::Err(message),
+        trace: None,
+        more: Vec::new(),
+        profile: None,
+        audit: None,
+    }
+}
+
+/// 输出一次计算的结果，出错时以文本格式输出到标准错误
+fn print_result(result: &CalculateResult, json: bool) {
+    match (json, &result.value) {
+        (true, _) => println!("{}", result.to_json()),
+        (false, CalculateOption::Err(message)) => eprintln!("{}", message),
+        (false, value) => println!("{}", value),
+    }
+}
+
+/// 交互式环境，空行表示表达式输入结束，Ctrl-C 放弃当前输入，Ctrl-D 退出
+fn exec_cmd(parser: &mut Parser, json: bool) {
+    let locale = parser.options().locale;
+    let mut editor: Editor<NameCompleter, DefaultHistory> =
+        Editor::new().expect("初始化交互式环境时出错");
+    editor.set_helper(Some(NameCompleter::default()));
+    let history = history_path();
+    if let Some(ref path) = history {
+        // 首次运行时历史记录文件不存在
+        let _ = editor.load_history(path);
+    }
+
+    let initial = parser.snapshot();
+    let mut session = Vec::new();
     let mut lines = String::new();
     loop {
-        println!("输入表达式:");
-        loop {
-            let mut formula = String::new();
-            std::io::stdin()
-                .read_line(&mut formula)
-                .expect("从标准输入中读取数据时出错");
-            if formula.trim().len() == 0 && lines.len() == 0 {
-                println!("输入的表达式为空!");
-                continue;
+        if let Some(helper) = editor.helper_mut() {
+            helper.names = parser
+                .env_info()
+                .into_iter()
+                .map(|info| info.name)
+                .collect();
+        }
+        let prompt = if lines.is_empty() { ">> " } else { ".. " };
+        match editor.readline(prompt) {
+            Ok(line) if line.trim().is_empty() && lines.is_empty() => {
+                println!("{}", Message::EmptyInput.text(locale, &[]));
             }
-
-            if formula.trim().len() == 0 {
+            Ok(line) if line.starts_with(':') && lines.is_empty() => {
+                let _ = editor.add_history_entry(line.trim_end());
+                meta_command(parser, line.trim(), &mut session, &initial);
+            }
+            Ok(line) if line.trim().is_empty() => {
+                let _ = editor.add_history_entry(lines.trim_end());
+                let result = parser.calculate(&lines);
+                match json {
+                    true => println!("{}", result.to_json()),
+                    false => println!("{}", result),
+                }
+                session.push(lines.trim_end().to_string());
+                lines.clear();
+            }
+            Ok(line) => {
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("{}", Message::InputDiscarded.text(locale, &[]));
+                lines.clear();
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", Message::ReadInput.text(locale, &[&e]));
                 break;
             }
+        }
+    }
 
-            lines.push_str(&formula);
-            formula.clear();
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!(
+                "{}",
+                Message::WriteFile.text(locale, &[&path.display(), &e])
+            );
         }
+    }
+}
 
-        println!("{:?}", parser.calculate(lines.clone()));
-        lines.clear();
+/// 交互式环境中以 : 开始的命令，session 记录了本次会话中输入的表达式，用于 :save
+fn meta_command(parser: &mut Parser, line: &str, session: &mut Vec<String>, initial: &EnvSnapshot) {
+    let locale = parser.options().locale;
+    let (command, arg) = match line.split_once(' ') {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    };
+    match command {
+        ":vars" => {
+            for info in parser.env_info() {
+                if matches!(info.kind, EnvEntryKind::Formula | EnvEntryKind::Constant) {
+                    println!("{} = {}", info.name, parser.calculate(&info.name).value);
+                }
+            }
+        }
+        ":ast" => {
+            // 只查看语法树，不保留表达式中的定义
+            let snapshot = parser.snapshot();
+            println!("{:#?}", parser.parse(arg));
+            parser.restore(snapshot);
+        }
+        ":funcs" => {
+            for info in parser.builtins() {
+                println!(
+                    "{}({})  {}",
+                    info.name,
+                    info.params.join(", "),
+                    info.description
+                );
+            }
+            for info in parser.env_info() {
+                if info.kind == EnvEntryKind::Function {
+                    println!("{}", info.node);
+                }
+            }
+        }
+        ":clear" => {
+            parser.restore(initial.clone());
+            session.clear();
+            println!("{}", Message::EnvCleared.text(locale, &[]));
+        }
+        ":load" if !arg.is_empty() => match std::fs::read_to_string(arg) {
+            Ok(source) => {
+                println!("{}", parser.calculate(&source));
+                session.push(source.trim_end().to_string());
+            }
+            Err(e) => eprintln!("{}", Message::ReadFile.text(locale, &[&arg, &e])),
+        },
+        ":save" if !arg.is_empty() => {
+            let mut script = session.join(";\n");
+            script.push('\n');
+            match std::fs::write(arg, script) {
+                Ok(_) => println!("{}", Message::Saved.text(locale, &[&session.len(), &arg])),
+                Err(e) => eprintln!("{}", Message::WriteFile.text(locale, &[&arg, &e])),
+            }
+        }
+        _ => println!("{}", Message::ReplHelp.text(locale, &[])),
+    }
+}
+
+/// 历史记录保存在用户目录下的 .formula_history 中
+fn history_path() -> Option<PathBuf> {
+    Env::var_os("HOME")
+        .or_else(|| Env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".formula_history"))
+}
+
+/// 使用执行环境中的变量、函数及内建函数名补全光标前的名称
+#[derive(Default)]
+struct NameCompleter {
+    names: Vec<String>,
+}
+
+impl Completer for NameCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        let mut candidates: Vec<String> = self
+            .names
+            .iter()
+            .filter(|name| !prefix.is_empty() && name.starts_with(prefix))
+            .cloned()
+            .collect();
+        candidates.sort();
+        Ok((start, candidates))
     }
 }
 
+impl Hinter for NameCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for NameCompleter {}
+
+impl Validator for NameCompleter {}
+
+impl Helper for NameCompleter {}
+
 fn _test_formula() {
     /*
 
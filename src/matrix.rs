@@ -0,0 +1,227 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// 数值矩阵，元素按行优先的顺序保存
+/// 脚本中通过 `[[1, 2], [3, 4]]` 的形式书写矩阵常量，`*` 为矩阵乘法，
+/// 与数值进行四则运算时逐元素计算，加减运算要求两个矩阵的行列数相同
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+/// 判断主元为 0 时使用的误差，避免因精度误差将奇异矩阵当作可逆矩阵
+const PIVOT_EPSILON: f64 = 1e-12;
+
+impl Matrix {
+    /// 由按行优先排列的 data 创建 rows 行 cols 列的矩阵，data 的长度不等于 rows * cols 时返回 None
+    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Option<Self> {
+        match data.len() == rows * cols {
+            true => Some(Matrix { rows, cols, data }),
+            false => None,
+        }
+    }
+
+    /// 由每一行的元素创建矩阵，各行的长度不一致或没有任何元素时返回 None
+    pub fn from_rows(rows: Vec<Vec<f64>>) -> Option<Self> {
+        let cols = rows.first().map_or(0, |row| row.len());
+        if cols == 0 || rows.iter().any(|row| row.len() != cols) {
+            return None;
+        }
+        let count = rows.len();
+        Matrix::new(count, cols, rows.into_iter().flatten().collect())
+    }
+
+    /// n 阶单位矩阵
+    pub fn identity(n: usize) -> Self {
+        let mut data = vec![0.0; n * n];
+        for i in 0..n {
+            data[i * n + i] = 1.0;
+        }
+        Matrix {
+            rows: n,
+            cols: n,
+            data,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<f64> {
+        match row < self.rows && col < self.cols {
+            true => Some(self.data[row * self.cols + col]),
+            false => None,
+        }
+    }
+
+    /// 第 row 行的元素
+    pub fn row(&self, row: usize) -> &[f64] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    /// 第 range 范围内的行组成的矩阵
+    pub fn slice_rows(&self, range: Range<usize>) -> Matrix {
+        Matrix {
+            rows: range.len(),
+            cols: self.cols,
+            data: self.data[range.start * self.cols..range.end * self.cols].to_vec(),
+        }
+    }
+
+    /// 按行优先排列的全部元素
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn is_square(&self) -> bool {
+        self.rows == self.cols
+    }
+
+    /// 转置矩阵
+    pub fn transpose(&self) -> Matrix {
+        let mut data = Vec::with_capacity(self.data.len());
+        for col in 0..self.cols {
+            for row in 0..self.rows {
+                data.push(self.data[row * self.cols + col]);
+            }
+        }
+        Matrix {
+            rows: self.cols,
+            cols: self.rows,
+            data,
+        }
+    }
+
+    /// 对每个元素计算 f
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Matrix {
+        Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().map(|x| f(*x)).collect(),
+        }
+    }
+
+    /// 与行列数相同的矩阵 other 逐元素计算 f，行列数不同时返回 None
+    pub fn zip_with(&self, other: &Matrix, f: impl Fn(f64, f64) -> f64) -> Option<Matrix> {
+        if self.rows != other.rows || self.cols != other.cols {
+            return None;
+        }
+        let data = self
+            .data
+            .iter()
+            .zip(other.data.iter())
+            .map(|(l, r)| f(*l, *r))
+            .collect();
+        Matrix::new(self.rows, self.cols, data)
+    }
+
+    /// 矩阵乘法，self 的列数与 other 的行数不同时返回 None
+    pub fn matmul(&self, other: &Matrix) -> Option<Matrix> {
+        if self.cols != other.rows {
+            return None;
+        }
+        let mut data = vec![0.0; self.rows * other.cols];
+        for i in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self.data[i * self.cols + k];
+                for j in 0..other.cols {
+                    data[i * other.cols + j] += a * other.data[k * other.cols + j];
+                }
+            }
+        }
+        Matrix::new(self.rows, other.cols, data)
+    }
+
+    /// 矩阵与列向量相乘，向量的长度与矩阵的列数不同时返回 None
+    pub fn mul_vector(&self, vector: &[f64]) -> Option<Vec<f64>> {
+        if self.cols != vector.len() {
+            return None;
+        }
+        Some(
+            (0..self.rows)
+                .map(|i| self.row(i).iter().zip(vector).map(|(a, b)| a * b).sum())
+                .collect(),
+        )
+    }
+
+    /// 行列式，通过部分主元的高斯消元计算，不是方阵时返回 None
+    pub fn determinant(&self) -> Option<f64> {
+        if !self.is_square() {
+            return None;
+        }
+        let n = self.rows;
+        let mut m = self.data.clone();
+        let mut det = 1.0;
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|a, b| m[a * n + col].abs().total_cmp(&m[b * n + col].abs()))
+                .unwrap_or(col);
+            if m[pivot * n + col] == 0.0 {
+                return Some(0.0);
+            }
+            if pivot != col {
+                swap_rows(&mut m, n, pivot, col);
+                det = -det;
+            }
+            let p = m[col * n + col];
+            det *= p;
+            for row in col + 1..n {
+                let factor = m[row * n + col] / p;
+                for k in col..n {
+                    m[row * n + k] -= factor * m[col * n + k];
+                }
+            }
+        }
+        Some(det)
+    }
+
+    /// 逆矩阵，通过高斯-约当消元计算，不是方阵或为奇异矩阵时返回 None
+    pub fn inverse(&self) -> Option<Matrix> {
+        if !self.is_square() {
+            return None;
+        }
+        let n = self.rows;
+        let mut m = self.data.clone();
+        let mut inv = Matrix::identity(n).data;
+        let scale = self.data.iter().fold(0.0f64, |max, x| max.max(x.abs()));
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|a, b| m[a * n + col].abs().total_cmp(&m[b * n + col].abs()))
+                .unwrap_or(col);
+            if m[pivot * n + col].abs() <= PIVOT_EPSILON * scale.max(1.0) {
+                return None;
+            }
+            swap_rows(&mut m, n, pivot, col);
+            swap_rows(&mut inv, n, pivot, col);
+            let p = m[col * n + col];
+            for k in 0..n {
+                m[col * n + k] /= p;
+                inv[col * n + k] /= p;
+            }
+            for row in (0..n).filter(|row| *row != col) {
+                let factor = m[row * n + col];
+                for k in 0..n {
+                    m[row * n + k] -= factor * m[col * n + k];
+                    inv[row * n + k] -= factor * inv[col * n + k];
+                }
+            }
+        }
+        Matrix::new(n, n, inv)
+    }
+}
+
+fn swap_rows(data: &mut [f64], cols: usize, a: usize, b: usize) {
+    if a != b {
+        for k in 0..cols {
+            data.swap(a * cols + k, b * cols + k);
+        }
+    }
+}
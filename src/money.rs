@@ -0,0 +1,51 @@
+use alloc::string::{String, ToString};
+
+/// 货币金额，由 Money(amount, currency) 得到
+/// 相同货币的金额之间可以加减及相除，金额可以乘以或除以数值，不同货币的金额之间需先通过 Convert 换算
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Money {
+    pub amount: f64,
+    /// USD、EUR 等三个字母的大写货币代码
+    pub currency: String,
+}
+
+impl Money {
+    /// currency 不是三个字母的货币代码时返回 None，货币代码统一转换为大写
+    pub fn new(amount: f64, currency: &str) -> Option<Self> {
+        match currency.len() == 3 && currency.chars().all(|c| c.is_ascii_alphabetic()) {
+            true => Some(Money {
+                amount,
+                currency: currency.to_ascii_uppercase(),
+            }),
+            false => None,
+        }
+    }
+
+    /// 货币相同、金额为 amount 的新金额
+    pub fn with_amount(&self, amount: f64) -> Self {
+        Money {
+            amount,
+            currency: self.currency.clone(),
+        }
+    }
+}
+
+/// 货币代码 code 显示时的前缀及保留的小数位数，常用的货币使用对应的符号，如 USD 为 $,
+/// JPY 等没有辅币的货币不保留小数，其他货币以 "代码 " 作为前缀，code 不是三个字母的货币代码时返回 None
+pub fn currency_style(code: &str) -> Option<(String, usize)> {
+    let code = code.to_uppercase();
+    let style = match code.as_str() {
+        "USD" => ("$".to_string(), 2),
+        "EUR" => ("€".to_string(), 2),
+        "GBP" => ("£".to_string(), 2),
+        "CNY" | "RMB" => ("¥".to_string(), 2),
+        "JPY" => ("¥".to_string(), 0),
+        "KRW" => ("₩".to_string(), 0),
+        code if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) => {
+            (format!("{} ", code), 2)
+        }
+        _ => return None,
+    };
+    Some(style)
+}
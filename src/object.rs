@@ -0,0 +1,88 @@
+use crate::calculator::CalculateOption;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// 可以绑定到表达式中的宿主对象，对象的每个字段会以 对象名.字段名 的形式绑定为变量,
+/// 如将 Order 绑定为 Order 后，表达式中可通过 Order.qty 访问其 qty 字段，
+/// 一般通过 formula_bind! 宏实现该 trait
+pub trait IntoFormulaValue {
+    /// 获取对象所有字段的名称及值
+    fn formula_fields(&self) -> Vec<(String, CalculateOption)>;
+}
+
+/// 为结构体实现 IntoFormulaValue，列出的字段需要能转换为 CalculateOption，如 f64、bool
+///
+/// ```
+/// use formula_parser::formula_bind;
+///
+/// struct Order {
+///     qty: f64,
+///     price: f64,
+/// }
+/// formula_bind!(Order { qty, price });
+///
+/// let mut parser = formula_parser::parser::Parser::new();
+/// parser.bind("Order", &Order { qty: 2.0, price: 3.5 });
+/// let result = parser.calculate("Order.qty * Order.price");
+/// ```
+#[macro_export]
+macro_rules! formula_bind {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::object::IntoFormulaValue for $ty {
+            fn formula_fields(&self) -> Vec<(String, $crate::calculator::CalculateOption)> {
+                vec![$((
+                    stringify!($field).to_string(),
+                    $crate::calculator::CalculateOption::from(self.$field.clone()),
+                )),*]
+            }
+        }
+    };
+}
+
+/// JSON 文档按路径展开为字段，嵌套对象的字段以 . 连接，数组元素以下标作为字段名,
+/// 只包含数值的数组同时作为一个向量字段，字符串及 null 没有对应的计算结果，不会被展开
+#[cfg(feature = "serde")]
+impl IntoFormulaValue for serde_json::Value {
+    fn formula_fields(&self) -> Vec<(String, CalculateOption)> {
+        let mut fields = Vec::new();
+        flatten_json("", self, &mut fields);
+        fields
+    }
+}
+
+#[cfg(feature = "serde")]
+fn flatten_json(
+    path: &str,
+    value: &serde_json::Value,
+    fields: &mut Vec<(String, CalculateOption)>,
+) {
+    use serde_json::Value;
+
+    let child = |key: &str| match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    };
+    match value {
+        Value::Bool(b) => fields.push((path.to_string(), CalculateOption::Bool(*b))),
+        Value::Number(n) => {
+            if let Some(n) = n.as_f64() {
+                fields.push((path.to_string(), CalculateOption::Num(n)))
+            }
+        }
+        Value::Array(items) => {
+            let numbers: Option<Vec<f64>> = items.iter().map(|v| v.as_f64()).collect();
+            if let (Some(numbers), false) = (numbers, path.is_empty()) {
+                fields.push((path.to_string(), CalculateOption::Vector(numbers)));
+            }
+            for (index, item) in items.iter().enumerate() {
+                flatten_json(&child(&index.to_string()), item, fields);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                flatten_json(&child(key), item, fields);
+            }
+        }
+        Value::String(_) | Value::Null => (),
+    }
+}
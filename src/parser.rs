@@ -1,461 +1,1372 @@
-use crate::env::{Env, EnvType};
-use std::iter::Peekable;
-use std::rc::Rc;
-use std::str::Chars;
+use crate::analyze::{self, GoalSeekResult, SensitivityTable, SweepRange};
+use crate::audit;
+use crate::build_in;
+use crate::cache::ParseCache;
+use crate::cell::{CellReferences, CellResolver};
+use crate::clock::Clock;
+use crate::compat::HashMap;
+use crate::compiled::CompiledFormula;
+#[cfg(feature = "parallel")]
+use crate::concurrent::{self, ConcurrentBuildInType};
+use crate::csv::{read_csv, write_row};
+use crate::diagnostic::{syntax_diagnostics, Analyzer, Diagnostic, FormulaError, Severity};
+use crate::dialect::Dialect;
+use crate::diff::{self, AstChange};
+use crate::document::{Document, EditError, TextEdit};
+use crate::env::{
+    Env, EnvChange, EnvEntryInfo, EnvEntryKind, EnvError, EnvSnapshot, EnvType, EvalObserver,
+    VariableResolver,
+};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+#[cfg(feature = "parallel")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::convert::TryFrom;
+use core::hash::{Hash, Hasher};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io::BufRead;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
-use crate::calculator::{CalculateOption, CalculateResult, FormulaCalc};
-use crate::formula::{FormulaNode, FuncContext, OperatorNode};
+use crate::calculator::{
+    CalculateOption, CalculateOptions, CalculateResult, FormulaCalc, TypedValue,
+};
+#[cfg(feature = "parallel")]
+use crate::formula::num_arg;
+use crate::formula::{BuiltinInfo, CalcError, FormulaNode, FuncContext};
+use crate::lint::{LintOptions, Linter};
+use crate::locale::{ErrorCode, Locale, NumberLocale};
+use crate::object::IntoFormulaValue;
+use crate::plugin::FormulaPlugin;
+use crate::policy::SecurityPolicy;
+use crate::provenance::Provenance;
+use crate::rounding::Precision;
+use crate::scanner::Scanner;
+#[cfg(feature = "std")]
+use crate::stream::StatementReader;
+use crate::token::{self, Span, TokenKind};
+use crate::typecheck::{Schema, Type, TypeChecker, TypeDiagnostic};
 
 /// 表达式解析器
 /// 表达式解析器内部包含一个环境变量，用于记录该解析器中所产生的各种表达式节点，
 /// 已记录的表达式节点可以在其他的表达式中引用
 pub struct Parser {
     env: EnvType,
+    /// 加载 import 语句所引用的公式文件，未设置时从文件系统中读取
+    importer: Option<Box<dyn ImportResolver>>,
+    /// 正在加载的公式文件，用于检查循环引用
+    importing: Vec<String>,
+    /// 内建函数的描述信息，key 为函数名
+    build_in_info: RefCell<HashMap<String, BuiltinInfo>>,
+    /// 已加载的函数包名称
+    plugins: Vec<String>,
+    /// 解析时接受的公式写法
+    dialect: Dialect,
+    /// 不包含定义的表达式的解析结果，默认容量为 0，即不缓存
+    parse_cache: ParseCache,
+    /// 输入变量及函数的类型声明，供 typecheck 使用
+    schema: Schema,
+    /// 设置后 check 的结果中包含 lint 发现的问题
+    lint: Option<LintOptions>,
+    /// 可以并行调用的内建函数，key 为函数名
+    #[cfg(feature = "parallel")]
+    concurrent: RefCell<HashMap<String, Arc<ConcurrentBuildInType>>>,
+}
+
+/// 公式文件加载器，由宿主程序控制 import "文件名"; 语句如何获取被引用的公式内容,
+/// 加载失败时返回错误信息
+pub trait ImportResolver {
+    fn load(&self, path: &str) -> Result<String, String>;
+}
+
+impl<F> ImportResolver for F
+where
+    F: Fn(&str) -> Result<String, String>,
+{
+    fn load(&self, path: &str) -> Result<String, String> {
+        self(path)
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parser {
     /// 创建一个新的表达式解析器
     pub fn new() -> Self {
-        Self { env: Env::new() }
+        let mut parser = Self {
+            env: Env::new(),
+            importer: None,
+            importing: Vec::new(),
+            build_in_info: RefCell::new(HashMap::new()),
+            plugins: Vec::new(),
+            dialect: Dialect::Native,
+            parse_cache: ParseCache::default(),
+            schema: Schema::new(),
+            lint: None,
+            #[cfg(feature = "parallel")]
+            concurrent: RefCell::new(HashMap::new()),
+        };
+        parser
+            .load_plugin(&build_in::StandardLibrary)
+            .expect("新创建的解析器环境不应为只读");
+        parser.env.borrow_mut().mark_std_if();
+        parser
+    }
+
+    /// 创建接受 dialect 写法的解析器，如 Dialect::Excel 可以直接解析 =IF(A1>0, A1*2, 0)
+    pub fn with_dialect(dialect: Dialect) -> Self {
+        let mut parser = Self::new();
+        parser.dialect = dialect;
+        parser
     }
 
-    /// 注册内建函数到执行环境中
+    /// 加载函数包，同名的函数包已加载时直接返回，不会重复注册
+    pub fn load_plugin<P: FormulaPlugin + ?Sized>(&mut self, plugin: &P) -> Result<(), EnvError> {
+        if self.plugins.iter().any(|name| name == plugin.name()) {
+            return Ok(());
+        }
+        plugin.install(self)?;
+        self.plugins.push(plugin.name().to_string());
+        Ok(())
+    }
+
+    /// 已加载的函数包名称，按加载的顺序排列
+    pub fn plugins(&self) -> &[String] {
+        &self.plugins
+    }
+
+    /// 注册内建函数到执行环境中，同名的函数已存在时会被覆盖，包括 seal 之后系统层中的函数,
+    /// 函数需要报告错误时返回 CalculateOption::Err，或使用 reg_fallible_build_in 注册
     pub fn reg_build_in<F>(&self, fun_name: &str, f: F)
     where
         F: Fn(&FuncContext) -> CalculateOption + 'static,
     {
-        self.env.borrow_mut().set_build_in(fun_name, Rc::new(f))
+        #[cfg(feature = "parallel")]
+        self.concurrent.borrow_mut().remove(fun_name);
+        self.env
+            .borrow_mut()
+            .set_build_in(fun_name, Rc::new(move |ctx: &FuncContext| Ok(f(ctx))));
+    }
+
+    /// 注册可能出错的内建函数，函数返回的 CalcError 会连同函数名及出错的参数位置一起
+    /// 作为 CalculateOption::Err 返回给调用者，fun_name 为 seal 之后的系统定义时返回错误
+    pub fn reg_fallible_build_in<F>(&self, fun_name: &str, f: F) -> Result<(), EnvError>
+    where
+        F: Fn(&FuncContext) -> Result<CalculateOption, CalcError> + 'static,
+    {
+        self.env
+            .borrow_mut()
+            .try_set_build_in(fun_name, Rc::new(f))?;
+        #[cfg(feature = "parallel")]
+        self.concurrent.borrow_mut().remove(fun_name);
+        Ok(())
+    }
+
+    /// 注册计算结果不确定的内建函数 (如 Rand、Now)，开启确定性计算模式时,
+    /// 除非列在 Deterministic::allowed 中，否则调用该函数会返回错误
+    pub fn reg_nondeterministic_build_in<F>(&self, fun_name: &str, f: F) -> Result<(), EnvError>
+    where
+        F: Fn(&FuncContext) -> Result<CalculateOption, CalcError> + 'static,
+    {
+        self.reg_fallible_build_in(fun_name, f)?;
+        self.env.borrow_mut().set_nondeterministic(fun_name);
+        Ok(())
+    }
+
+    /// 注册可以在多个线程中同时调用的内建函数，函数的参数及返回值都是数值,
+    /// 通过 calculate 计算时与普通的内建函数相同，通过 calculate_par 计算时,
+    /// 表达式中彼此独立的调用会通过 rayon 并行执行
+    #[cfg(feature = "parallel")]
+    pub fn reg_concurrent_build_in<F>(&self, fun_name: &str, f: F) -> Result<(), EnvError>
+    where
+        F: Fn(&[f64]) -> Result<f64, CalcError> + Send + Sync + 'static,
+    {
+        let f: Arc<ConcurrentBuildInType> = Arc::new(f);
+        let call = Arc::clone(&f);
+        self.reg_fallible_build_in(fun_name, move |ctx: &FuncContext| {
+            let args = (0..ctx.args.len())
+                .map(|i| ctx.arg_num(i))
+                .collect::<Result<Vec<f64>, CalcError>>()?;
+            call(&args).map(CalculateOption::Num)
+        })?;
+        self.concurrent.borrow_mut().insert(fun_name.to_string(), f);
+        Ok(())
+    }
+
+    /// 注册内建函数，与 reg_fallible_build_in 不同的是 fun_name 已被注册时返回错误，而不是覆盖原有的函数
+    pub fn try_reg_build_in<F>(&self, fun_name: &str, f: F) -> Result<(), EnvError>
+    where
+        F: Fn(&FuncContext) -> Result<CalculateOption, CalcError> + 'static,
+    {
+        self.env.borrow_mut().add_build_in(fun_name, Rc::new(f))
+    }
+
+    /// 将内建函数注册到命名空间 ns 中，脚本中通过 ns.fun_name(...) 调用,
+    /// 用于避免不同来源的内建函数之间的命名冲突，同名的函数已被注册时返回错误
+    pub fn reg_build_in_ns<F>(&self, ns: &str, fun_name: &str, f: F) -> Result<(), EnvError>
+    where
+        F: Fn(&FuncContext) -> Result<CalculateOption, CalcError> + 'static,
+    {
+        self.try_reg_build_in(&format!("{}.{}", ns, fun_name), f)
+    }
+
+    /// 注册声明了参数个数的内建函数，调用前会检查参数个数是否在 [min, max] 之间,
+    /// max 为 None 时表示不限制参数个数的上限，如 Sum(...) 这样的可变参数函数
+    pub fn reg_build_in_with_arity<F>(
+        &self,
+        fun_name: &str,
+        min: usize,
+        max: Option<usize>,
+        f: F,
+    ) -> Result<(), EnvError>
+    where
+        F: Fn(&FuncContext) -> Result<CalculateOption, CalcError> + 'static,
+    {
+        let info = BuiltinInfo {
+            name: fun_name.to_string(),
+            arity: Some((min, max)),
+            ..Default::default()
+        };
+        self.reg_build_in_with_info(info, f)
+    }
+
+    /// 注册内建函数并附带其描述信息，描述信息可通过 builtins 获取，用于自动补全及帮助信息,
+    /// info.arity 不为 None 时调用前会检查参数个数
+    pub fn reg_build_in_with_info<F>(&self, info: BuiltinInfo, f: F) -> Result<(), EnvError>
+    where
+        F: Fn(&FuncContext) -> Result<CalculateOption, CalcError> + 'static,
+    {
+        let arity = info.arity;
+        self.reg_fallible_build_in(&info.name, move |ctx: &FuncContext| {
+            if let Some((min, max)) = arity {
+                ctx.args_len_between(min, max)?;
+            }
+            f(ctx)
+        })?;
+        self.build_in_info
+            .borrow_mut()
+            .insert(info.name.clone(), info);
+        Ok(())
+    }
+
+    /// 列出所有已注册的内建函数及其描述信息，按名称排序，未提供描述信息的函数只包含名称
+    pub fn builtins(&self) -> Vec<BuiltinInfo> {
+        let docs = self.build_in_info.borrow();
+        self.env_info()
+            .into_iter()
+            .filter(|info| info.kind == EnvEntryKind::BuildIn)
+            .map(|info| match docs.get(&info.name) {
+                Some(doc) => doc.clone(),
+                None => BuiltinInfo {
+                    name: info.name,
+                    ..Default::default()
+                },
+            })
+            .collect()
+    }
+
+    /// 注册带有可变状态的内建函数，如计数器、缓存等，函数可以直接修改其捕获的数据,
+    /// 执行环境只在单线程中使用，因此无需加锁，但函数在执行过程中不能被再次调用，
+    /// 如 Counter(Counter()) 中参数的计算发生在函数执行期间，此时内层的调用会返回错误
+    pub fn reg_stateful_build_in<F>(&self, fun_name: &str, f: F) -> Result<(), EnvError>
+    where
+        F: FnMut(&FuncContext) -> Result<CalculateOption, CalcError> + 'static,
+    {
+        let f = RefCell::new(f);
+        self.reg_fallible_build_in(fun_name, move |ctx: &FuncContext| {
+            match f.try_borrow_mut() {
+                Ok(mut f) => f(ctx),
+                Err(_) => Err(CalcError::new(
+                    "带有状态的内建函数不能在执行过程中被再次调用",
+                )),
+            }
+        })
+    }
+
+    /// 将当前环境中已有的定义及内建函数标记为只读的系统层，之后的定义都会写入新的用户层,
+    /// 用户层中的表达式无法重新定义系统层中的常量及函数
+    pub fn seal(&mut self) {
+        self.env.borrow_mut().set_readonly(true);
+        self.env = Env::extend(&self.env);
+    }
+
+    /// 直接将值绑定到变量 name 上，无需解析表达式，value 可以是 f64、bool 或 CalculateOption,
+    /// Err、None 等非值类型的结果无法绑定，name 为只读的系统定义时也无法绑定，此时返回 false
+    pub fn set_var<V: Into<CalculateOption>>(&self, name: &str, value: V) -> bool {
+        match value.into().to_node() {
+            Some(node) => self.env.borrow_mut().try_set(name, Rc::new(node)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// 批量绑定变量，返回所有的变量是否都绑定成功
+    pub fn set_vars<V: Into<CalculateOption>>(&self, vars: HashMap<String, V>) -> bool {
+        vars.into_iter()
+            .fold(true, |ok, (name, value)| self.set_var(&name, value) && ok)
+    }
+
+    /// 将宿主对象绑定到变量 name 上，对象的字段以 name.字段名 的形式绑定为变量,
+    /// 返回所有的字段是否都绑定成功
+    pub fn bind<T: IntoFormulaValue + ?Sized>(&self, name: &str, object: &T) -> bool {
+        object
+            .formula_fields()
+            .into_iter()
+            .fold(true, |ok, (field, value)| {
+                self.set_var(&format!("{}.{}", name, field), value) && ok
+            })
+    }
+
+    /// 设置外部变量解析器，表达式中引用的变量在解析器环境中不存在时，会通过 resolver 获取
+    pub fn set_resolver<R: VariableResolver + 'static>(&self, resolver: R) {
+        self.env.borrow_mut().set_resolver(Rc::new(resolver))
+    }
+
+    /// 设置电子表格的单元格数据源，表达式中的 A1、B2:B10 等单元格引用会通过 cells 获取,
+    /// 会替换通过 set_resolver 设置的外部变量解析器
+    pub fn set_cell_resolver<R: CellResolver + 'static>(&self, cells: R) {
+        self.set_resolver(CellReferences::new(cells))
+    }
+
+    /// 设置错误信息及诊断信息所使用的语言
+    pub fn set_locale(&self, locale: Locale) {
+        let mut options = self.options();
+        options.locale = locale;
+        self.set_options(options);
+    }
+
+    /// 设置计算结果的默认精度，之后每次计算的最终结果都按 precision 舍入，None 表示不舍入
+    pub fn set_precision(&self, precision: Option<Precision>) {
+        let mut options = self.options();
+        options.precision = precision;
+        self.set_options(options);
+    }
+
+    /// 设置数值的书写习惯，之后解析的公式中的数值常量按 number_locale 解析，
+    /// 如 NumberLocale::decimal_comma 时 1.234,56 为 1234.56，此时函数参数之间的 , 之后需要有空白
+    pub fn set_number_locale(&self, number_locale: NumberLocale) {
+        let mut options = self.options();
+        options.number_locale = number_locale;
+        self.set_options(options);
+    }
+
+    /// 设置是否为严格逻辑模式，开启后 &&、|| 及 ! 的操作数不是逻辑值时返回错误,
+    /// 见 CalculateOptions::strict_logic
+    pub fn set_strict_logic(&self, strict: bool) {
+        let mut options = self.options();
+        options.strict_logic = strict;
+        self.set_options(options);
+    }
+
+    /// 设置是否为分数模式，开启后数值常量及其四则运算以分数精确计算，只在输出时转换为 f64,
+    /// 见 CalculateOptions::rational
+    #[cfg(feature = "rational")]
+    pub fn set_rational(&self, rational: bool) {
+        let mut options = self.options();
+        options.rational = rational;
+        self.set_options(options);
+    }
+
+    /// 按当前数值的书写习惯输出计算结果 value
+    pub fn format_value(&self, value: &CalculateOption) -> String {
+        value.to_string_with(&self.env.borrow().options().number_locale)
+    }
+
+    /// 设置计算过程的观察者，计算表达式时会在进入、退出节点，调用函数及出错时通知观察者
+    pub fn set_observer<O: EvalObserver + 'static>(&self, observer: O) {
+        self.env.borrow().set_observer(Some(Rc::new(observer)))
+    }
+
+    /// 移除已设置的计算过程观察者
+    pub fn clear_observer(&self) {
+        self.env.borrow().set_observer(None)
+    }
+
+    /// 设置公式文件加载器，用于加载 import 语句所引用的公式文件
+    pub fn set_importer<R: ImportResolver + 'static>(&mut self, importer: R) {
+        self.importer = Some(Box::new(importer))
+    }
+
+    /// 将一整列数据绑定到变量 name 上，表达式中的四则运算会对该列逐元素计算，
+    /// 与标量计算时标量会被广播到每一个元素，name 为只读的系统定义时无法绑定，此时返回 false
+    pub fn set_column(&self, name: &str, values: Vec<f64>) -> bool {
+        self.env
+            .borrow_mut()
+            .try_set(name, Rc::new(FormulaNode::Vector(values)))
+            .is_ok()
+    }
+
+    /// 设置判断延迟变量是否到期及统计耗时所用的时钟，调用方可保留 clock 以便手动推进时间
+    pub fn set_clock<C: Clock + 'static>(&self, clock: Rc<C>) {
+        self.env.borrow_mut().set_clock(clock);
+    }
+
+    /// 将 formula 的解析结果绑定到变量 name 上，但需要经过 delay 的时间后才会生效，
+    /// 生效之前读取该变量得到的是它之前的值，之前未定义过则得到 None。
+    /// formula 中的定义不会加入执行环境，存在语法错误或 name 为只读的系统定义时返回错误
+    pub fn set_delayed(
+        &self,
+        name: &str,
+        formula: impl AsRef<str>,
+        delay: Duration,
+    ) -> Result<(), String> {
+        let node = self.parse_value(formula.as_ref())?;
+        let mut env = self.env.borrow_mut();
+        env.check_write(name).map_err(|e| e.to_string())?;
+        env.set_delayed(name, node, delay);
+        Ok(())
+    }
+
+    /// 获取变量 name 记录的所有历史值，需通过 CalculateOptions::history_limit 开启历史值的记录
+    pub fn history(&self, name: &str) -> Vec<CalculateOption> {
+        self.env.borrow().history(name)
+    }
+
+    /// 监听变量 name 的变化，变量被重新定义或其计算结果发生变化时会调用 listener,
+    /// 返回的 id 可用于通过 unsubscribe 取消监听，listener 中不能再访问该解析器
+    pub fn subscribe<F>(&self, name: &str, listener: F) -> usize
+    where
+        F: Fn(&str, &EnvChange) + 'static,
+    {
+        self.env.borrow_mut().subscribe(name, listener)
+    }
+
+    /// 取消对变量 name 的监听
+    pub fn unsubscribe(&self, name: &str, id: usize) -> bool {
+        self.env.borrow_mut().unsubscribe(name, id)
+    }
+
+    /// 列出解析器环境中所有已定义的变量、函数及内建函数的信息，按名称排序
+    pub fn env_info(&self) -> Vec<EnvEntryInfo> {
+        let env = self.env.borrow();
+        env.names()
+            .iter()
+            .filter_map(|name| env.get_typed(name))
+            .collect()
+    }
+
+    /// 从解析器环境中移除变量、函数或内建函数 name，返回是否存在该定义
+    pub fn remove(&self, name: &str) -> bool {
+        self.env.borrow_mut().remove(name).is_some()
+    }
+
+    /// 将变量、函数或内建函数 old 重命名为 new，rewrite_references 为 true 时同时改写
+    /// 其他定义中对 old 的引用，返回是否存在 old 的定义，new 为只读的系统定义时返回 false
+    pub fn rename(&self, old: &str, new: &str, rewrite_references: bool) -> bool {
+        let mut env = self.env.borrow_mut();
+        env.check_write(new).is_ok() && env.rename(old, new, rewrite_references)
+    }
+
+    /// 创建解析器环境的快照，之后可以通过 restore 撤销对变量及函数的修改
+    pub fn snapshot(&self) -> EnvSnapshot {
+        self.env.borrow().snapshot()
+    }
+
+    /// 将解析器环境恢复到 snapshot 创建时的状态
+    pub fn restore(&self, snapshot: EnvSnapshot) {
+        self.env.borrow_mut().restore(snapshot)
+    }
+
+    /// 将解析器环境中的变量及函数序列化为 JSON，内建函数不会被序列化
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, String> {
+        self.env.borrow().to_json()
+    }
+
+    /// 将 to_json 得到的变量及函数加载到解析器环境中
+    #[cfg(feature = "serde")]
+    pub fn load_json(&self, json: &str) -> Result<(), String> {
+        self.env.borrow_mut().load_json(json)
+    }
+
+    /// 设置表达式计算时使用的配置
+    pub fn set_options(&self, options: CalculateOptions) {
+        self.env.borrow().set_options(options)
+    }
+
+    /// 获取表达式计算时使用的配置
+    pub fn options(&self) -> CalculateOptions {
+        self.env.borrow().options().clone()
     }
 
     /// 解析 formula 对应的表达式，并返回其解析后的表达式节点，该节点可直接调用 calc
     /// 用来计算表达式的结果，但需要自己提供执行环境 env, 所以一般是交由 parser 的
-    /// calculate 方法来触发表达式的计算
-    pub fn parse(&mut self, formula: String) -> Rc<FormulaNode> {
-        let mut iter = formula.chars().peekable();
-        skip_space(&mut iter);
-        if let None = iter.peek() {
-            return Rc::new(FormulaNode::None);
+    /// calculate 方法来触发表达式的计算，formula 可以是 &str 或 String
+    pub fn parse(&mut self, formula: impl AsRef<str>) -> Rc<FormulaNode> {
+        let formula = formula.as_ref();
+        if let Some(node) = self.check_length(formula) {
+            return node;
+        }
+        // 其他写法的转换结果与当前环境中定义的函数有关，因此只缓存原生写法的解析结果，
+        // 缓存的结果同样只对应默认的数值写法
+        let number_locale = self.env.borrow().options().number_locale;
+        let cacheable = self.dialect == Dialect::Native
+            && number_locale == NumberLocale::default()
+            && self.parse_cache.capacity() > 0;
+        if cacheable {
+            if let Some(node) = self.parse_cache.get(formula) {
+                return node;
+            }
         }
 
+        let source = formula;
+        let formula = self.dialect.translate(formula);
         let mut node = Rc::new(FormulaNode::None);
-        while iter.peek().is_some() {
-            let inner_node = scan_node(&mut iter, false);
-
-            node = Rc::new(inner_node);
-            match node.as_ref() {
-                FormulaNode::Function {
-                    name,
-                    args: _,
-                    expressions: _,
-                } => {
-                    self.env.borrow_mut().insert(&name, node.clone());
-                }
-                FormulaNode::Formula { name, formula: _ } => {
-                    self.env.borrow_mut().insert(&name, node.clone());
-                }
-                FormulaNode::UnKnow(msg) => return Rc::new(FormulaNode::UnKnow(msg.clone())),
-                _ => (),
-            };
+        let mut pure = true;
+        let mut scanner = self
+            .dialect
+            .scanner(&formula)
+            .with_number_locale(number_locale);
+        let mut last = None;
+        while let Some((span, statement)) = scanner.next_statement_span() {
+            node = self.apply_statement(statement);
+            self.attach_provenance(&node, &formula, last, &span);
+            self.attach_source(&node, &formula, &span);
+            last = Some(span.end);
+            pure = pure
+                && !matches!(
+                    node.as_ref(),
+                    FormulaNode::Function { .. }
+                        | FormulaNode::Formula { .. }
+                        | FormulaNode::Namespace { .. }
+                        | FormulaNode::Import(_)
+                        | FormulaNode::UnKnow(_)
+                );
+            if let FormulaNode::UnKnow(_) = node.as_ref() {
+                return node;
+            }
+        }
+        if cacheable && pure {
+            self.parse_cache.insert(source, Rc::clone(&node));
         }
         node
     }
 
-    /// 执行 formula 表达式，表达式所需的各种变量及函数需要在执行前 parse,
-    /// 以加入环境变量, 加入
-    pub fn calculate(&mut self, formula: String) -> CalculateResult {
-        let node = self.parse(formula);
-        let env = Env::extend(&self.env);
-        let value = node.as_ref().calc(&env);
-        let more = env.borrow().call_stack();
-        CalculateResult { value, more }
+    /// 将扫描得到的一条语句转换为解析器所使用的写法，并将其中的定义加入执行环境,
+    /// 定义失败时返回包含错误信息的 UnKnow 节点
+    fn apply_statement(&mut self, statement: FormulaNode) -> Rc<FormulaNode> {
+        let node = Rc::new(self.rewrite(statement));
+        let result = match node.as_ref() {
+            FormulaNode::Function { name, .. } | FormulaNode::Formula { name, .. } => {
+                self.check_override(name).and_then(|_| {
+                    self.env
+                        .borrow_mut()
+                        .try_set(name, node.clone())
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+            }
+            FormulaNode::Namespace { .. } => self.define_namespace(&node, ""),
+            FormulaNode::Import(path) => self.import(path),
+            _ => Ok(()),
+        };
+        match result {
+            Ok(()) => node,
+            Err(msg) => Rc::new(FormulaNode::UnKnow(msg)),
+        }
     }
-}
 
-/// 解析 formula，并返回该公式的预解析结果，即将公式解析为各种算子
-/// 同时会将具有名称的节点加入 env
-fn parse_formula(formula: String) -> FormulaNode {
-    let mut iter = formula.chars().peekable();
-    skip_space(&mut iter);
+    /// 只解析 formula 而不将其中的定义加入执行环境，返回最后一条语句的节点,
+    /// 存在语法错误时返回错误信息
+    fn parse_value(&self, formula: &str) -> Result<Rc<FormulaNode>, String> {
+        if let Some(node) = self.check_length(formula) {
+            if let FormulaNode::UnKnow(msg) = node.as_ref() {
+                return Err(msg.clone());
+            }
+        }
+        let formula = self.dialect.translate(formula);
+        let number_locale = self.env.borrow().options().number_locale;
+        let mut scanner = self
+            .dialect
+            .scanner(&formula)
+            .with_number_locale(number_locale);
+        let mut node = FormulaNode::None;
+        while let Some((_, statement)) = scanner.next_statement_span() {
+            node = self.rewrite(statement);
+            if let FormulaNode::UnKnow(msg) = node {
+                return Err(msg);
+            }
+        }
+        Ok(Rc::new(node))
+    }
 
-    let mut node = FormulaNode::None;
-    while iter.peek().is_some() {
-        node = scan_node(&mut iter, false);
+    /// 从 reader 中逐条读取并解析语句，返回按顺序得到每条语句节点的迭代器，
+    /// 语句中的定义在读取到该语句时加入执行环境，与 parse 不同的是出错的语句不会中断读取,
+    /// 而是得到 UnKnow 节点后继续读取之后的语句。
+    /// 读取时只缓存尚未结束的语句，语句以 ; 或换行后开始的下一条语句结束，因此适合解析体积很大的公式文件
+    #[cfg(feature = "std")]
+    pub fn parse_reader<R: BufRead>(&mut self, reader: R) -> StatementReader<'_, R> {
+        StatementReader::new(self, reader)
     }
-    node
-}
 
-/// 删除无用的空格
-fn skip_space(iter: &mut Peekable<Chars>) {
-    loop {
-        match iter.peek() {
-            Some(c) => match c {
-                ' ' | '\r' | '\n' => {
-                    iter.next();
-                }
-                _ => break,
-            },
-            None => {
-                break;
-            }
+    /// 解析一段完整的语句，依次返回每条语句的节点，供 StatementReader 使用
+    #[cfg(any(feature = "std", test))]
+    pub(crate) fn parse_statements(&mut self, source: &str) -> Vec<Rc<FormulaNode>> {
+        if let Some(node) = self.check_length(source) {
+            return vec![node];
+        }
+        let source = self.dialect.translate(source);
+        let number_locale = self.env.borrow().options().number_locale;
+        let mut scanner = self
+            .dialect
+            .scanner(&source)
+            .with_number_locale(number_locale);
+        let mut nodes = Vec::new();
+        let mut last = None;
+        while let Some((span, statement)) = scanner.next_statement_span() {
+            let node = self.apply_statement(statement);
+            self.attach_provenance(&node, &source, last, &span);
+            self.attach_source(&node, &source, &span);
+            last = Some(span.end);
+            nodes.push(node);
         }
+        nodes
+    }
+
+    /// source 中已经完整的语句的结束位置，即最后一条语句所在行的开头，供 StatementReader 使用。
+    /// 语句在 ; 或下一条语句开始的位置结束，之后追加的源码只可能延续最后一条语句,
+    /// 因此之前的语句都已完整，source 中少于两条语句或最后一条语句与之前的语句在同一行时返回 None
+    #[cfg(feature = "std")]
+    pub(crate) fn complete_statements(&self, source: &str) -> Option<usize> {
+        let translated = self.dialect.translate(source);
+        let number_locale = self.env.borrow().options().number_locale;
+        let mut scanner = self
+            .dialect
+            .scanner(&translated)
+            .with_number_locale(number_locale);
+        let mut spans = Vec::new();
+        while let Some((span, _)) = scanner.next_statement_span() {
+            spans.push(span);
+        }
+        let [.., prev, last] = spans.as_slice() else {
+            return None;
+        };
+        // translate 不改变换行符，按行号换算回 source 中的位置
+        let line_start = translated[..last.start].rfind('\n')? + 1;
+        if prev.end > line_start {
+            return None;
+        }
+        let line = translated[..line_start].matches('\n').count();
+        source.match_indices('\n').nth(line - 1).map(|(i, _)| i + 1)
+    }
+
+    /// 为语句 node 中成功加入执行环境的定义记录来源信息，包括正在加载的公式文件及
+    /// 语句之前的 ## 文档注释，last 为上一条语句在 source 中的结束位置
+    fn attach_provenance(
+        &mut self,
+        node: &FormulaNode,
+        source: &str,
+        last: Option<usize>,
+        span: &Span,
+    ) {
+        let gap = &source[last.unwrap_or(0)..span.start];
+        let provenance = Provenance {
+            source: self.importing.last().cloned(),
+            ..Provenance::from_gap(gap, last.is_some())
+        };
+        if provenance.is_empty() {
+            return;
+        }
+        let provenance = Rc::new(provenance);
+        let mut names = Vec::new();
+        defined_names(node, "", &mut names);
+        let mut env = self.env.borrow_mut();
+        for name in names {
+            env.set_provenance(&name, Rc::clone(&provenance));
+        }
+    }
+
+    /// 记录语句 node 直接定义的变量、函数或命名空间的源码，span 为语句在 source 中的范围
+    fn attach_source(&self, node: &FormulaNode, source: &str, span: &Span) {
+        let name = match node {
+            FormulaNode::Formula { name, .. }
+            | FormulaNode::Function { name, .. }
+            | FormulaNode::Namespace { name, .. } => name,
+            _ => return,
+        };
+        let text = source[span.clone()].trim_end_matches(';').trim_end();
+        self.env.borrow_mut().set_source(name, Rc::from(text));
     }
-}
 
-/// 扫描当前公式，尝试得到一个节点
-fn scan_node(iter: &mut Peekable<Chars>, limit: bool) -> FormulaNode {
-    if iter.peek().is_none() {
-        return FormulaNode::None;
+    /// 获取变量或函数 name 的来源信息，未记录来源信息时返回 None
+    pub fn provenance(&self, name: &str) -> Option<Rc<Provenance>> {
+        self.env
+            .borrow()
+            .get_typed(name)
+            .and_then(|info| info.provenance)
     }
 
-    let mut node = None;
-    while iter.peek().is_some() {
-        skip_space(iter);
+    /// 为宿主程序定义的变量或函数 name 设置来源信息，name 未定义时返回 false
+    pub fn set_provenance(&self, name: &str, provenance: Provenance) -> bool {
+        self.env
+            .borrow_mut()
+            .set_provenance(name, Rc::new(provenance))
+    }
+
+    /// 设置解析结果缓存的容量，calculate 等接口重复计算同一个公式时可以跳过解析，
+    /// 只有原生写法中不包含定义 (命名表达式、函数、命名空间及 import) 的公式会被缓存,
+    /// 超过容量时淘汰最久未使用的公式，capacity 为 0 时关闭缓存
+    pub fn set_parse_cache(&mut self, capacity: usize) {
+        self.parse_cache.set_capacity(capacity);
+    }
+
+    /// 解析结果缓存中的公式个数
+    pub fn parse_cache_len(&self) -> usize {
+        self.parse_cache.len()
+    }
 
-        if iter.peek().is_none() {
-            break;
+    /// 加载并解析 path 对应的公式文件，其中的定义会加入解析器的环境中
+    fn import(&mut self, path: &str) -> Result<(), String> {
+        let path = self.import_path(path);
+        let path = path.as_str();
+        if self.importing.iter().any(|p| p == path) {
+            return Err(format!("公式文件 {} 存在循环引用", path));
         }
 
-        match iter.peek().unwrap() {
-            // 公式定义: 命名
-            ':' => {
-                return scan_naming_node(iter, node);
-            }
-            // 处理一元计算
-            '^' | '!' => {
-                // 处理一元计算节点，一元计算节点需要用到该节点之后的后置节点
-                iter.next();
-                let next_node = scan_node(iter, true);
-                node = Some(FormulaNode::Operator(Box::new(OperatorNode::Not(
-                    Box::new(next_node),
-                ))))
-            }
-            '(' | '[' => {
-                // 开始处理嵌套的 Brace
-                node = Some(find_end_brace(iter));
-            }
-            'A'..='Z' | 'a'..='z' | '_' => {
-                // 可能是 Variant 也可能是 Formula
-                let var_node = scan_variant(iter);
-
-                // 如果一个变量后续是括号，则说明它是一个函数
-                skip_space(iter);
-                let n = match iter.peek() {
-                    Some(c) if c == &'(' => {
-                        let sub_formula = find_end_brace_without_parse(iter);
-                        // 处理函数的参数
-                        let args = scan_split_node(sub_formula, '(', ')', ',');
-                        let func_node = match var_node {
-                            FormulaNode::Variant(name) => FormulaNode::FunctionCall { name, args },
-                            _ => {
-                                FormulaNode::UnKnow("当前节点类型错误，该错误不应发生!".to_string())
-                                //                                panic!("当前节点类型错误，该错误不应发生！")
-                            }
-                        };
-
-                        func_node
-                    }
-                    _ => var_node,
-                };
-
-                // 检查是否函数定义, 如果是函数定义，则需要确认 args 中的元素必须都是 Variant 类型
-                skip_space(iter);
-                let n = match iter.peek() {
-                    Some(c) if c == &'{' => {
-                        let sub_formula = find_end_brace_without_parse(iter);
-                        // 解析出函数体中的多个表达式，每个表达式之间使用 ; 进行分割
-                        let expressions = scan_split_node(sub_formula, '{', '}', ';');
-                        match n {
-                            FormulaNode::FunctionCall { name, args } => FormulaNode::Function {
-                                name,
-                                args,
-                                expressions,
-                            },
-                            _ => {
-                                FormulaNode::UnKnow(
-                                    "当前节点类型不为 FunctionCall， 该错误不应发生".to_string(),
-                                )
-                                //                                panic!("当前节点类型不为 FunctionCall， 该错误不应发生")
-                            }
-                        }
-                    }
-                    _ => n,
-                };
+        let formula = match self.importer {
+            Some(ref importer) => importer.load(path),
+            #[cfg(feature = "std")]
+            None => std::fs::read_to_string(path).map_err(|e| e.to_string()),
+            #[cfg(not(feature = "std"))]
+            None => Err("未设置 ImportResolver".to_string()),
+        }
+        .map_err(|e| format!("加载公式文件 {} 时出错，错误信息为 {}", path, e))?;
 
-                node = Some(n);
-            }
-            '0'..='9' | '.' => {
-                node = Some(scan_const(iter));
-            }
-            '+' | '-' | '*' | '/' => {
-                // 处理二元计算节点，计算节点的话可能会需要用到前置节点以及后置节点
-                node = Some(scan_math(iter, node));
-            }
-            '>' | '<' | '=' => {
-                node = Some(scan_compare(iter, node));
-            }
-            ';' => {
-                // 结束当前语句
-                iter.next();
-                break;
-                //                iter.next();
-            }
-            '&' => {
-                iter.next(); // skip first &
-                match iter.peek() {
-                    Some('&') => {
-                        iter.next(); // skip second &
-                        node = Some(scan_logic_and(iter, node));
-                    }
-                    _ => {
-                        // maybe mathematical &, but not support yet
-                        return FormulaNode::UnKnow(format!(
-                            "逻辑与的关键符号为 &&， 缺少了第二个 &"
-                        ));
-                    }
-                }
+        self.importing.push(path.to_string());
+        let node = self.parse(&formula);
+        self.importing.pop();
+
+        match node.as_ref() {
+            FormulaNode::UnKnow(msg) => Err(msg.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    /// import 语句中的 path 所指的文件，从文件系统中读取时相对路径相对于正在加载的公式文件所在的目录,
+    /// 不在加载公式文件时相对于当前工作目录，并转换为规范的绝对路径，以便通过 ../ 等形式
+    /// 引用同一文件时也能发现循环引用，通过 ImportResolver 加载时原样使用 path
+    fn import_path(&self, path: &str) -> String {
+        #[cfg(feature = "std")]
+        if self.importer.is_none() {
+            let file = match self
+                .importing
+                .last()
+                .and_then(|file| Path::new(file).parent())
+            {
+                Some(dir) => dir.join(path),
+                None => PathBuf::from(path),
+            };
+            // 文件不存在时无法规范化，读取时会报告相应的错误
+            return std::fs::canonicalize(&file)
+                .unwrap_or(file)
+                .display()
+                .to_string();
+        }
+        path.to_string()
+    }
+
+    /// 加载目录 dir 及其子目录中所有的 .fml 公式文件，按路径排序后依次解析，返回加载的文件,
+    /// 表达式在计算时才查找其引用的名称，因此文件之间可以互相引用而不受加载顺序的影响,
+    /// 多个文件定义了同一个名称时不会加载任何文件，并返回所有重复的定义
+    #[cfg(feature = "std")]
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<Vec<PathBuf>, String> {
+        let mut files = Vec::new();
+        collect_files(dir.as_ref(), "fml", &mut files)?;
+        files.sort();
+
+        let mut sources = Vec::new();
+        let mut defined: HashMap<String, &Path> = HashMap::new();
+        let mut duplicates = Vec::new();
+        for file in files.iter() {
+            let source = std::fs::read_to_string(file)
+                .map_err(|e| format!("读取公式文件 {} 时出错: {}", file.display(), e))?;
+            let mut names = Vec::new();
+            for node in scan_statements(&source) {
+                defined_names(&node, "", &mut names);
             }
-            '|' => {
-                iter.next(); // skip first &
-                match iter.peek() {
-                    Some('|') => {
-                        iter.next(); // skip second &
-                        node = Some(scan_logic_or(iter, node));
-                    }
-                    _ => {
-                        // maybe mathematical &, but not support yet
-                        return FormulaNode::UnKnow(format!(
-                            "逻辑与的关键符号为 ||， 缺少了第二个 |"
-                        ));
+            for name in names {
+                match defined.get(&name) {
+                    Some(first) => duplicates.push(format!(
+                        "{} 同时定义在 {} 与 {} 中",
+                        name,
+                        first.display(),
+                        file.display()
+                    )),
+                    None => {
+                        defined.insert(name, file);
                     }
                 }
             }
-            _ => {
-                return FormulaNode::UnKnow(format!(
-                    "扫描公式时遇到非法符号: {}！",
-                    iter.peek().unwrap()
-                ));
-                //                panic!("扫描公式时遇到非法符号: {}！", iter.peek().unwrap())
-            }
+            sources.push(source);
+        }
+        if !duplicates.is_empty() {
+            return Err(duplicates.join("\n"));
         }
 
-        if node.is_some() & &limit {
-            return node.unwrap();
+        for (file, source) in files.iter().zip(sources) {
+            // 与 import_path 一致使用规范的路径，以便文件中的 import 能发现循环引用
+            let canonical = std::fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+            self.importing.push(canonical.display().to_string());
+            let node = self.parse(&source);
+            self.importing.pop();
+            if let FormulaNode::UnKnow(msg) = node.as_ref() {
+                return Err(format!("解析公式文件 {} 时出错: {}", file.display(), msg));
+            }
         }
+        Ok(files)
     }
 
-    node.unwrap()
-}
+    /// 将解析得到的节点转换为解析器所使用的写法对应的语义
+    fn rewrite(&self, node: FormulaNode) -> FormulaNode {
+        if self.dialect == Dialect::Native {
+            return node;
+        }
+        let functions: Vec<String> = self
+            .env_info()
+            .into_iter()
+            .filter(|info| matches!(info.kind, EnvEntryKind::BuildIn | EnvEntryKind::Function))
+            .map(|info| info.name)
+            .collect();
+        self.dialect.rewrite(node, &functions)
+    }
 
-/// 获取括号中的表达式，支持获取嵌套的表达式
-fn find_end_brace_without_parse(iter: &mut Peekable<Chars>) -> String {
-    let mut sub_formula = String::new();
-    let mut brace_count = 1;
-
-    iter.next(); // skip the first brace
-    while let Some(c) = iter.next() {
-        match c {
-            ')' | ']' | '}' => {
-                brace_count -= 1;
-                if brace_count != 0 {
-                    sub_formula.push(c);
-                    continue;
+    /// 将命名空间本身及其中的定义以 命名空间.名称 的形式加入环境，嵌套的命名空间以 . 连接
+    fn define_namespace(&self, node: &Rc<FormulaNode>, prefix: &str) -> Result<(), String> {
+        if let FormulaNode::Namespace { name, definitions } = node.as_ref() {
+            let name = format!("{}{}", prefix, name);
+            let defined = self.env.borrow().get(&name);
+            if defined.is_some_and(|n| !matches!(n.as_ref(), FormulaNode::Namespace { .. })) {
+                return Err(format!("{} 已被定义，无法再作为命名空间的名称", name));
+            }
+            self.env
+                .borrow_mut()
+                .try_set(&name, Rc::clone(node))
+                .map_err(|e| e.to_string())?;
+            let prefix = format!("{}.", name);
+            for definition in definitions {
+                match definition.as_ref() {
+                    FormulaNode::Formula { name, .. } | FormulaNode::Function { name, .. } => {
+                        let name = format!("{}{}", prefix, name);
+                        self.check_override(&name)?;
+                        let definition = Rc::new(definition.with_name(&name));
+                        self.env
+                            .borrow_mut()
+                            .try_set(&name, definition)
+                            .map_err(|e| e.to_string())?;
+                    }
+                    FormulaNode::Namespace { .. } => self.define_namespace(definition, &prefix)?,
+                    _ => (),
                 }
-                return sub_formula;
             }
-            '(' | '[' | '{' => {
-                // 找到了嵌套的 Quote
-                brace_count += 1;
-                sub_formula.push(c);
+        }
+        Ok(())
+    }
+
+    /// 源码超过安全策略限制的长度时返回包含错误信息的 UnKnow 节点
+    fn check_length(&self, source: &str) -> Option<Rc<FormulaNode>> {
+        let env = self.env.borrow();
+        let max = env.policy().max_formula_len?;
+        match source.len() > max {
+            true => {
+                let locale = env.options().locale;
+                let message = ErrorCode::FormulaTooLong.message(locale, &[&source.len(), &max]);
+                Some(Rc::new(FormulaNode::UnKnow(message)))
             }
-            _ => {
-                sub_formula.push(c);
+            false => None,
+        }
+    }
+
+    /// 安全策略不允许覆盖已有的定义时，检查 name 是否已经定义
+    fn check_override(&self, name: &str) -> Result<(), String> {
+        let env = self.env.borrow();
+        match !env.policy().allow_override && env.get(name).is_some() {
+            true => {
+                let locale = env.options().locale;
+                Err(ErrorCode::OverrideForbidden.message(locale, &[&name]))
             }
+            false => Ok(()),
         }
     }
 
-    sub_formula
-}
+    /// 执行不可信公式时的安全策略，限制可以调用的内建函数、公式的长度、函数调用的嵌套深度、
+    /// 每次计算的节点个数及公式中的定义能否覆盖已有的名称，对之后的解析及计算生效
+    pub fn set_security_policy(&self, policy: SecurityPolicy) {
+        self.env.borrow().set_policy(policy);
+    }
 
-/// 处理括号中的表达式, 并将表达式的字符串解析为 表达式节点
-fn find_end_brace(iter: &mut Peekable<Chars>) -> FormulaNode {
-    let sub_formula = find_end_brace_without_parse(iter);
-    if sub_formula.len() != 0 {
-        parse_formula(sub_formula)
-    } else {
-        FormulaNode::None
+    pub fn security_policy(&self) -> SecurityPolicy {
+        self.env.borrow().policy().clone()
     }
-}
 
-/// 处理公式命名
-fn scan_naming_node(iter: &mut Peekable<Chars>, node: Option<FormulaNode>) -> FormulaNode {
-    // 处理公式的命名, 前置节点应为一个 Variant 节点
-    if node.is_none() {
-        panic!("公式的格式出错，命名公式的格式为 公式名 := 表达式");
+    /// 解析 formula 并返回预解析的表达式，该表达式可以在不同的输入数据上重复计算，
+    /// 与 parse 一样，formula 中具名的表达式及函数会被加入解析器的环境中
+    pub fn compile(&mut self, formula: impl AsRef<str>) -> CompiledFormula {
+        CompiledFormula::new(self.parse(formula), Rc::clone(&self.env))
     }
 
-    iter.next();
+    /// 编译 formula 并进行敏感性分析，参见 analyze::sensitivity
+    pub fn sensitivity(
+        &mut self,
+        formula: &str,
+        base: &HashMap<String, f64>,
+        inputs: &[(&str, SweepRange)],
+    ) -> SensitivityTable {
+        analyze::sensitivity(&self.compile(formula), base, inputs)
+    }
 
-    match iter.peek() {
-        None => panic!("公式格式出错，等号后没有后续的表达式"),
-        Some(c) if c != &'=' => panic!("公式格式出错，命名公式时缺少了 : 之后的 = 号"),
-        // c is =
-        _ => iter.next(),
-    };
+    /// 编译 formula 并求解使其结果等于 target 的 variable 的取值，参见 analyze::goal_seek
+    pub fn goal_seek(
+        &mut self,
+        formula: &str,
+        base: &HashMap<String, f64>,
+        target: f64,
+        variable: &str,
+        bounds: (f64, f64),
+    ) -> Result<GoalSeekResult, String> {
+        analyze::goal_seek(&self.compile(formula), base, target, variable, bounds)
+    }
 
-    match node.unwrap() {
-        FormulaNode::Variant(name) => {
-            if iter.peek().is_none() {
-                panic!("公式格式出错，公式名称之后没有任何表达式");
-            }
-            let formula = Rc::new(scan_node(iter, false));
-            return FormulaNode::Formula { name, formula };
+    /// 检查 source 中的所有问题而不计算表达式，也不会将其中的定义加入执行环境，
+    /// 包括语法错误、未定义的变量及函数、函数的参数个数不匹配、覆盖已有的定义等
+    /// 通过 set_lint 启用 lint 检查后，结果中还包含 lint 发现的问题
+    pub fn check(&self, source: impl AsRef<str>) -> Vec<Diagnostic> {
+        let source = source.as_ref();
+        let mut diagnostics = self.analyze(source, |analyzer| analyzer.diagnostics);
+        if let Some(ref options) = self.lint {
+            diagnostics.extend(self.lint_with(source, options));
         }
-        _ => {
-            panic!("公式的格式出错，命名的节点应为 Variant 类型，命名公式的格式为 公式名 = 表达式")
+        diagnostics
+    }
+
+    /// 设置 check 使用的 lint 规则，为 None 时 check 不进行 lint 检查
+    pub fn set_lint(&mut self, options: Option<LintOptions>) {
+        self.lint = options;
+    }
+
+    /// 检查 source 中可疑的写法，如函数中未使用的参数、遮蔽已有定义的名称、用 == 比较浮点数、
+    /// 不依赖变量的条件、结果被丢弃的表达式及未命名的数值常量，未通过 set_lint 设置时使用全部规则
+    pub fn lint(&self, source: impl AsRef<str>) -> Vec<Diagnostic> {
+        let source = source.as_ref();
+        match self.lint {
+            Some(ref options) => self.lint_with(source, options),
+            None => self.lint_with(source, &LintOptions::default()),
         }
     }
-}
 
-/// 处理公式的数学运算
-fn scan_math(iter: &mut Peekable<Chars>, left: Option<FormulaNode>) -> FormulaNode {
-    if left.is_none() {
-        panic!("公式的格式错误，二元操作符前没有合法的计算节点");
+    fn lint_with(&self, source: &str, options: &LintOptions) -> Vec<Diagnostic> {
+        let source = self.dialect.translate(source);
+        let source = source.as_ref();
+        let nodes: Vec<FormulaNode> = scan_statements(source)
+            .into_iter()
+            .map(|node| self.rewrite(node))
+            .collect();
+        let env = self.env.borrow();
+        let mut linter = Linter::new(source, &env, options);
+        linter.lint(&nodes);
+        linter.diagnostics
     }
 
-    let op = iter.next().unwrap();
-    let left = Box::new(left.unwrap());
-    let right = Box::new(scan_node(iter, false));
-    let op_node = match op {
-        '+' => OperatorNode::Plus { left, right },
-        '-' => OperatorNode::Minus { left, right },
-        '*' => OperatorNode::Multiply { left, right },
-        '/' => OperatorNode::Divide { left, right },
-        _ => panic!("扫描公式时遇到未知的操作符"),
-    };
+    /// 列出 source 中引用了但执行环境中不存在的变量及函数名，按首次出现的顺序排列，
+    /// source 中自身定义的名称及函数的参数不会被列出，可用于在计算前提示用户补充缺少的输入
+    pub fn unresolved_names(&self, source: &str) -> Vec<String> {
+        self.analyze(source, |analyzer| analyzer.unresolved)
+    }
 
-    FormulaNode::Operator(Box::new(op_node))
-}
+    /// 计算 source 语法树的哈希值，只与表达式的结构有关，与空格、多余的括号无关，
+    /// 可用于缓存中识别相同的表达式，source 中的定义不会加入执行环境,
+    /// 哈希算法在不同的 Rust 版本之间可能不同，不适合持久化保存
+    pub fn canonical_hash(&self, source: &str) -> u64 {
+        let source = self.dialect.translate(source);
+        let mut hasher = crate::compat::hasher();
+        for node in scan_statements(&source) {
+            self.rewrite(node).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
 
-fn scan_compare(iter: &mut Peekable<Chars>, node: Option<FormulaNode>) -> FormulaNode {
-    let op = iter.next().unwrap();
-    let next_op = *iter.peek().unwrap();
-    if next_op == '=' {
-        iter.next();
-    }
-    skip_space(iter);
-
-    let left = Box::new(node.unwrap());
-    let right = Box::new(scan_node(iter, false));
-    let op_node = match op {
-        '>' => match next_op {
-            '=' => OperatorNode::GreatEqual { left, right },
-            _ => OperatorNode::Great { left, right },
-        },
-        '<' => match next_op {
-            '=' => OperatorNode::LessEqual { left, right },
-            _ => OperatorNode::Less { left, right },
-        },
-        '=' => OperatorNode::Equal { left, right },
-        _ => panic!("扫描公式时遇到未知的操作符"),
-    };
-
-    FormulaNode::Operator(Box::new(op_node))
-}
+    /// 解析 source 中的所有语句并保留每条语句的位置，不会修改执行环境，
+    /// 供编辑器在文档被修改后通过 reparse 增量地重新解析,
+    /// 其他写法的文档中语句的位置对应于转换为原生写法之后的源码
+    pub fn parse_document(&self, source: &str) -> Document {
+        match self.dialect {
+            Dialect::Native => Document::parse(source, |node| node),
+            dialect => Document::parse_dialect(&dialect.translate(source), dialect, |node| {
+                self.rewrite(node)
+            })
+            .with_source(source),
+        }
+    }
 
-/// 处理公式的变量
-fn scan_variant(iter: &mut Peekable<Chars>) -> FormulaNode {
-    let mut node = String::new();
-    while let Some(c) = iter.peek() {
-        match c {
-            'A'..='Z' | 'a'..='z' | ' ' | '_' | '0'..='9' => {
-                node.push(*c);
-                iter.next();
-            }
-            _ => break,
+    /// 解析 source 中的所有语句，对任意输入都不会 panic 或陷入死循环，可直接用于处理用户提交的公式,
+    /// 出错的语句得到 UnKnow 节点后继续解析之后的语句，返回所有语句的节点及其中的语法问题,
+    /// 嵌套超过 scanner::MAX_DEPTH 层、连续的运算超过 scanner::MAX_CHAIN 个及到源码末尾仍未闭合括号的语句
+    /// 同样作为语法错误，不会将其中的定义加入执行环境
+    pub fn parse_resilient(&self, source: &str) -> (Vec<Rc<FormulaNode>>, Vec<Diagnostic>) {
+        let locale = self.env.borrow().options().locale;
+        let document = self.parse_document(source);
+        let mut diagnostics = Vec::new();
+        for statement in document.statements() {
+            syntax_diagnostics(&statement.node, &statement.span, locale, &mut diagnostics);
         }
+        let nodes = document.statements().iter().map(|s| Rc::clone(&s.node));
+        (nodes.collect(), diagnostics)
     }
 
-    if node.len() == 0 {
-        return FormulaNode::None;
+    /// 在文档 old 上应用修改 edit 并重新解析，与修改范围无关的语句直接复用 old 中的语法树,
+    /// 只有原生写法支持增量解析，其他写法的转换可能改变语句的位置，因此会重新解析整个文档,
+    /// edit 的范围超出文档或不在字符边界上时返回错误
+    pub fn reparse(&self, old: &Document, edit: &TextEdit) -> Result<Document, EditError> {
+        match self.dialect {
+            Dialect::Native => old.apply(edit, |node| node),
+            _ => Ok(self.parse_document(&edit.apply_to(old.source())?)),
+        }
     }
 
-    FormulaNode::Variant(node.trim().to_string())
-}
+    /// 设置输入变量及函数的类型声明，替换之前的声明
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.schema = schema;
+    }
 
-/// 处理公式的常量
-fn scan_const(iter: &mut Peekable<Chars>) -> FormulaNode {
-    let mut node = String::new();
+    /// 类型声明，可以通过 declare 及 declare_function 继续添加声明
+    pub fn schema_mut(&mut self) -> &mut Schema {
+        &mut self.schema
+    }
 
-    while let Some(c) = iter.peek() {
-        match c {
-            '0'..='9' | '.' => {
-                node.push(*c);
-                iter.next();
-            }
-            _ => {
-                break;
+    /// 在不计算表达式的前提下检查 ast 中的类型错误，如数值与逻辑值相加、调用函数时参数的类型与声明不符,
+    /// 变量的类型优先使用 schema 中的声明，未声明时使用执行环境中值为常量的定义，其余视为任意类型
+    pub fn typecheck(&self, ast: &FormulaNode) -> Vec<TypeDiagnostic> {
+        let env = self.env.borrow();
+        let locale = env.options().locale;
+        let lookup = |name: &str| env.get(name).map(|node| Type::of_node(&node));
+        TypeChecker::new(&self.schema, &lookup, locale).check(ast)
+    }
+
+    /// 比较同一组公式的两个版本 old 与 new，报告其中的语义变化，如操作符或常量被修改、增加了参数,
+    /// 具名的表达式及函数按名称对应，只有空白、注释及括号不同时没有变化，不会修改执行环境
+    pub fn diff(&self, old: &str, new: &str) -> Vec<AstChange> {
+        let scan = |source: &str| -> Vec<Rc<FormulaNode>> {
+            scan_statements(&self.dialect.translate(source))
+                .into_iter()
+                .map(|node| Rc::new(self.rewrite(node)))
+                .collect()
+        };
+        diff::diff_statements(&scan(old), &scan(new))
+    }
+
+    /// 将 source 切分为带有类别及位置的记号，供编辑器进行语法高亮，不会修改执行环境
+    pub fn tokenize(&self, source: &str) -> Vec<(TokenKind, Span)> {
+        token::tokenize(source)
+    }
+
+    /// 在当前的执行环境中检查 source，并通过 f 获取检查的结果
+    fn analyze<R, F: FnOnce(Analyzer) -> R>(&self, source: &str, f: F) -> R {
+        let source = self.dialect.translate(source);
+        let source = source.as_ref();
+        let nodes: Vec<FormulaNode> = scan_statements(source)
+            .into_iter()
+            .map(|node| self.rewrite(node))
+            .collect();
+        let env = self.env.borrow();
+        let docs = self.build_in_info.borrow();
+        let arity_of = |name: &str| docs.get(name).and_then(|info| info.arity);
+        let mut analyzer = Analyzer::new(source, &env, &arity_of);
+        analyzer.analyze(&nodes);
+        f(analyzer)
+    }
+
+    /// 将表达式节点定义为具名的表达式 name，与解析 name := 表达式 的效果相同，
+    /// 通常用于定义由 Expr 构造得到的表达式
+    pub fn define<N: Into<FormulaNode>>(&self, name: &str, node: N) -> Result<(), EnvError> {
+        let formula = FormulaNode::Formula {
+            name: name.to_string(),
+            formula: Rc::new(node.into()),
+        };
+        self.env.borrow_mut().try_set(name, Rc::new(formula))?;
+        Ok(())
+    }
+
+    /// 使用已经解析好的表达式节点创建预解析的表达式，如从缓存中反序列化得到的语法树，
+    /// 节点不会被加入解析器的环境中
+    pub fn compile_node(&self, node: Rc<FormulaNode>) -> CompiledFormula {
+        CompiledFormula::new(node, Rc::clone(&self.env))
+    }
+
+    /// 从 CompiledFormula::to_bytes 得到的二进制数据中恢复预解析的表达式，
+    /// 表达式在当前解析器的环境中计算，格式不兼容时返回错误
+    #[cfg(feature = "msgpack")]
+    pub fn compile_bytes(&self, bytes: &[u8]) -> Result<CompiledFormula, String> {
+        let node = CompiledFormula::node_from_bytes(bytes)?;
+        Ok(self.compile_node(Rc::new(node)))
+    }
+
+    /// 执行 formula 表达式，表达式所需的各种变量及函数需要在执行前 parse,
+    /// 以加入环境变量, 加入
+    pub fn calculate(&mut self, formula: impl AsRef<str>) -> CalculateResult {
+        let node = self.parse(formula);
+        self.evaluate(&node)
+    }
+
+    /// 与 calculate 相同，但表达式中通过 reg_concurrent_build_in 注册的函数调用会并行执行,
+    /// 只有位于操作符的操作数、脚本函数或此类函数的参数中，且参数中不再包含此类调用的调用会被并行执行,
+    /// 这些调用的参数先在当前线程中依次计算，参数不是数值或调用出错时直接得到与 calculate 相同的错误,
+    /// 不会再次调用，被安全策略禁止的函数仍按顺序计算，
+    /// 并行执行的调用不会出现在 CalculateResult::more 中，确定性计算模式下总是按顺序计算
+    #[cfg(feature = "parallel")]
+    pub fn calculate_par(&mut self, formula: impl AsRef<str>) -> CalculateResult {
+        use rayon::prelude::*;
+
+        let node = self.parse(formula);
+        if self.env.borrow().is_auditing() {
+            return self.evaluate(&node);
+        }
+        let concurrent = self.concurrent.borrow().clone();
+        let env = Env::extend(&self.env);
+        let lookup = |name: &str| RefCell::borrow(&env).get(name);
+        let names = concurrent
+            .keys()
+            .filter(|name| {
+                let e = RefCell::borrow(&env);
+                matches!(
+                    lookup(name).as_deref(),
+                    Some(FormulaNode::BuildInFunction { .. })
+                ) && !e.forbids(name)
+                    && e.policy().allows(name)
+            })
+            .cloned()
+            .collect();
+        let is_function =
+            |name: &str| matches!(lookup(name).as_deref(), Some(FormulaNode::Function { .. }));
+        let mut calls = Vec::new();
+        concurrent::collect_calls(&node, &names, &is_function, &mut calls);
+        if calls.len() < 2 {
+            Env::release(env);
+            return self.evaluate(&node);
+        }
+
+        let mut values = HashMap::new();
+        let mut sites = Vec::new();
+        let mut jobs = Vec::new();
+        for call in calls {
+            if let FormulaNode::FunctionCall { name, args } = call {
+                let args = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| num_arg(i, arg.calc(&env)))
+                    .collect::<Result<Vec<f64>, CalcError>>();
+                match args {
+                    Ok(args) => {
+                        sites.push((call as *const FormulaNode, name));
+                        jobs.push((Arc::clone(&concurrent[name]), args));
+                    }
+                    Err(e) => {
+                        values.insert(call as *const FormulaNode, self.call_error(name, &e));
+                    }
+                }
             }
         }
+        Env::release(env);
+
+        let results: Vec<Result<f64, CalcError>> =
+            jobs.par_iter().map(|(f, args)| f(args)).collect();
+        for ((site, name), result) in sites.into_iter().zip(results) {
+            let value = match result {
+                Ok(n) => FormulaNode::Constant(n),
+                Err(e) => self.call_error(name, &e),
+            };
+            values.insert(site, value);
+        }
+        let node = Rc::new(concurrent::substitute(&node, &values));
+        self.evaluate(&node)
     }
-    if node.ends_with('.') {
-        node.pop();
+
+    /// 调用内建函数 name 出错时的结果，与按顺序计算时返回的错误相同
+    #[cfg(feature = "parallel")]
+    fn call_error(&self, name: &str, e: &CalcError) -> FormulaNode {
+        let locale = self.env.borrow().options().locale;
+        FormulaNode::UnKnow(ErrorCode::BuildInError.message(locale, &[&name, e]))
+    }
+
+    /// 与 calculate 相同，但将成功的结果与错误分开返回，以便宿主代码使用 ? 处理错误，
+    /// 错误的位置通过 check 得到，只有能在检查时发现的错误 (如未定义的变量及函数) 才有位置
+    pub fn try_calculate(&mut self, formula: impl AsRef<str>) -> Result<TypedValue, FormulaError> {
+        let formula = formula.as_ref();
+        let span_of = |parser: &Self, code: Option<ErrorCode>| {
+            parser
+                .check(formula)
+                .into_iter()
+                .find(|d| d.severity == Severity::Error && Some(d.code) == code)
+                .map(|d| d.span)
+        };
+
+        let node = self.parse(formula);
+        if let FormulaNode::UnKnow(message) = node.as_ref() {
+            return Err(FormulaError::Parse {
+                message: message.clone(),
+                span: span_of(self, Some(ErrorCode::SyntaxError))
+                    .or_else(|| span_of(self, Some(ErrorCode::ReadOnly))),
+            });
+        }
+
+        TypedValue::try_from(self.evaluate(&node).value).map_err(|message| {
+            let code = ErrorCode::of(&message);
+            FormulaError::Eval {
+                code,
+                span: span_of(self, code),
+                message,
+            }
+        })
     }
 
-    if node.len() == 0 {
-        return FormulaNode::None;
+    /// 在新的计算层级中计算解析得到的表达式节点
+    fn evaluate(&self, node: &Rc<FormulaNode>) -> CalculateResult {
+        self.evaluate_with(node, Vec::new())
     }
-    FormulaNode::Constant(node.parse::<f64>().unwrap())
-}
 
-/// 处理函数的参数
-/// 通过扫描字符串并根据 , 分割，把分割后的字符串再次处理为 公式的节点类型
-fn scan_split_node(
-    formula_str: String,
-    begin_brace: char,
-    end_brace: char,
-    splitter: char,
-) -> Vec<Rc<FormulaNode>> {
-    let mut args = vec![];
-    let mut arg = String::new();
-    let mut iter = formula_str.chars();
-    let mut brace_count = 0;
-    while let Some(c) = iter.next() {
-        match c {
-            n if n == begin_brace => brace_count += 1,
-            n if n == end_brace => brace_count -= 1,
-            n if n == splitter => {
-                if brace_count == 0 {
-                    let formula = parse_formula(arg.clone());
-                    args.push(Rc::new(formula));
-                    arg.clear();
-                }
+    /// 在新的计算层级中计算表达式节点，vars 只在本次计算中生效，不会加入解析器的环境
+    fn evaluate_with(
+        &self,
+        node: &Rc<FormulaNode>,
+        vars: Vec<(String, CalculateOption)>,
+    ) -> CalculateResult {
+        self.env.borrow().next_epoch();
+        let env = Env::extend(&self.env);
+        for (name, value) in vars {
+            if let Some(node) = value.to_node() {
+                env.borrow_mut().insert(&name, Rc::new(node));
             }
-            _ => arg.push(c),
+        }
+        let value = node.as_ref().calc(&env);
+        let (value, audit) = audit::finish(node, &env.borrow(), value);
+        let trace = env.borrow().take_trace();
+        let more = env.borrow().take_calls();
+        let profile = env.borrow().take_profile();
+        Env::release(env);
+        CalculateResult {
+            value,
+            trace,
+            more,
+            profile,
+            audit,
         }
     }
 
-    if arg.len() != 0 {
-        let formula = parse_formula(arg);
-        args.push(Rc::new(formula));
+    /// 以 JSON 文档 payload 为输入计算 formula，表达式中通过 payload.字段.字段 访问文档中的值,
+    /// 如 payload.user.age > 18，数组元素通过下标访问，如 payload.items.0.price，
+    /// 数值数组同时可作为向量整体访问，字符串及 null 不会被绑定，payload 只在本次计算中生效
+    #[cfg(feature = "serde")]
+    pub fn calculate_with_json(
+        &mut self,
+        formula: &str,
+        payload: &serde_json::Value,
+    ) -> CalculateResult {
+        let node = self.parse(formula);
+        let vars = payload
+            .formula_fields()
+            .into_iter()
+            .map(|(path, value)| (format!("payload.{}", path), value))
+            .collect();
+        self.evaluate_with(&node, vars)
     }
 
-    args
+    /// 对 CSV 的每一行依次计算 formulas，返回在末尾增加了结果列的 CSV 文本,
+    /// formulas 为 (结果列名, 表达式)，第一行为列名，每一行中的数值及 true/false 会以列名
+    /// 绑定为变量，只在该行的计算中生效，计算出错的单元格输出其错误信息
+    pub fn calculate_csv(
+        &mut self,
+        csv: &str,
+        formulas: &[(&str, &str)],
+    ) -> Result<String, String> {
+        let mut rows = read_csv(csv)?.into_iter();
+        let header = rows.next().ok_or_else(|| "CSV 中缺少列名".to_string())?;
+        let nodes: Vec<Rc<FormulaNode>> = formulas.iter().map(|(_, f)| self.parse(f)).collect();
+
+        let mut columns = header.clone();
+        columns.extend(formulas.iter().map(|(name, _)| name.to_string()));
+        let mut out = vec![write_row(&columns)];
+        for (index, mut row) in rows.enumerate() {
+            if row.len() != header.len() {
+                return Err(format!(
+                    "CSV 第 {} 行有 {} 列，与列名的个数 {} 不一致",
+                    index + 2,
+                    row.len(),
+                    header.len()
+                ));
+            }
+            let vars: Vec<(String, CalculateOption)> = header
+                .iter()
+                .zip(row.iter())
+                .filter_map(|(name, field)| {
+                    let field = field.trim();
+                    let value = match field.parse::<f64>() {
+                        Ok(n) => CalculateOption::Num(n),
+                        Err(_) if field.eq_ignore_ascii_case("true") => CalculateOption::Bool(true),
+                        Err(_) if field.eq_ignore_ascii_case("false") => {
+                            CalculateOption::Bool(false)
+                        }
+                        Err(_) => return None,
+                    };
+                    Some((name.trim().to_string(), value))
+                })
+                .collect();
+            for node in nodes.iter() {
+                let value = self.evaluate_with(node, vars.clone()).value;
+                row.push(value.to_string());
+            }
+            out.push(write_row(&row));
+        }
+        Ok(out.join("\n") + "\n")
+    }
+}
+
+/// 将公式源码解析后重新输出为规范的、完全加括号的公式文本，每个语句占一行
+pub fn format_source(source: &str) -> String {
+    scan_statements(source)
+        .iter()
+        .map(|node| node.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
-fn scan_logic_and(iter: &mut Peekable<Chars>, left: Option<FormulaNode>) -> FormulaNode {
-    let right = scan_node(iter, true);
-    let left = left.unwrap();
-    return FormulaNode::Operator(Box::new(OperatorNode::And {
-        left: Box::new(left),
-        right: Box::new(right),
-    }));
+/// 递归地查找 dir 中扩展名为 extension 的文件
+#[cfg(feature = "std")]
+fn collect_files(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("读取目录 {} 时出错: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_dir() {
+            collect_files(&path, extension, files)?;
+        } else if path.extension().is_some_and(|ext| ext == extension) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// 语句中定义的表达式及函数名，命名空间中的名称以 命名空间.名称 的形式记录
+fn defined_names(node: &FormulaNode, prefix: &str, names: &mut Vec<String>) {
+    match node {
+        FormulaNode::Formula { name, .. } | FormulaNode::Function { name, .. } => {
+            names.push(format!("{}{}", prefix, name))
+        }
+        FormulaNode::Namespace { name, definitions } => {
+            let prefix = format!("{}{}.", prefix, name);
+            for definition in definitions {
+                defined_names(definition, &prefix, names);
+            }
+        }
+        _ => (),
+    }
 }
 
-fn scan_logic_or(iter: &mut Peekable<Chars>, left: Option<FormulaNode>) -> FormulaNode {
-    let right = scan_node(iter, true);
-    let left = left.unwrap();
-    return FormulaNode::Operator(Box::new(OperatorNode::Or {
-        left: Box::new(left),
-        right: Box::new(right),
-    }));
+/// 只解析 source 中的语句而不将其中的定义加入执行环境
+fn scan_statements(source: &str) -> Vec<FormulaNode> {
+    Scanner::new(source).collect()
 }
@@ -0,0 +1,12 @@
+use crate::env::EnvError;
+use crate::parser::Parser;
+
+/// 内建函数包，如数学、金融、字符串或特定业务领域的函数，
+/// 可以作为独立的 crate 发布，再由使用者通过 Parser::load_plugin 组合到解析器中
+pub trait FormulaPlugin {
+    /// 函数包的名称，同一个解析器中不能重复加载同名的函数包
+    fn name(&self) -> &str;
+
+    /// 将函数包中的内建函数及变量注册到解析器中，注册失败 (如函数名冲突) 时返回错误
+    fn install(&self, parser: &mut Parser) -> Result<(), EnvError>;
+}
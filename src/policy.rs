@@ -0,0 +1,40 @@
+use crate::compat::HashSet;
+use alloc::string::String;
+
+/// 执行不可信公式 (如租户编写的公式) 时的安全策略，通过 Parser::set_security_policy 设置,
+/// 默认不做任何限制
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityPolicy {
+    /// 允许调用的内建函数，为 None 时不限制
+    pub allowed_build_ins: Option<HashSet<String>>,
+    /// 公式源码的最大字节数，超过时解析得到错误，为 None 时不限制
+    pub max_formula_len: Option<usize>,
+    /// 函数调用的最大嵌套深度，引用具名表达式同样计为一层调用，尾调用自身不会增加深度，为 None 时不限制
+    pub max_call_depth: Option<usize>,
+    /// 每次计算最多可以计算的节点个数，用完时计算得到错误，为 None 时不限制
+    pub fuel: Option<u64>,
+    /// 公式中的定义是否可以覆盖已有的变量及函数，默认为 true，
+    /// 不影响宿主程序通过 set_var 及 define 等方法进行的修改
+    pub allow_override: bool,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        SecurityPolicy {
+            allowed_build_ins: None,
+            max_formula_len: None,
+            max_call_depth: None,
+            fuel: None,
+            allow_override: true,
+        }
+    }
+}
+
+impl SecurityPolicy {
+    /// 是否允许调用内建函数 name
+    pub fn allows(&self, name: &str) -> bool {
+        self.allowed_build_ins
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(name))
+    }
+}
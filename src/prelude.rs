@@ -0,0 +1,19 @@
+pub use crate::analyze::{GoalSeekResult, SensitivityTable, SweepRange};
+pub use crate::audit::{AuditRecord, Deterministic};
+pub use crate::calculator::{
+    CalculateOption, CalculateOptions, CalculateResult, FormulaCalc, TypedValue,
+};
+pub use crate::diagnostic::{Diagnostic, FormulaError, Severity};
+pub use crate::diff::AstChange;
+pub use crate::env::EnvError;
+pub use crate::expr::Expr;
+pub use crate::format::{format_formula, FormatOptions};
+pub use crate::formula::{CalcError, FormulaNode, FuncContext, Subscript};
+pub use crate::lint::{LintOptions, LintRule};
+pub use crate::locale::{ErrorCode, Locale, NumberLocale};
+pub use crate::money::Money;
+pub use crate::parser::Parser;
+pub use crate::policy::SecurityPolicy;
+pub use crate::provenance::Provenance;
+pub use crate::rounding::{Precision, RoundingMode};
+pub use crate::typecheck::{Schema, Type, TypeDiagnostic};
@@ -0,0 +1,68 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// 定义的来源信息，解析时从定义之前以 ## 开始的文档注释中读取，例如:
+///
+/// ```text
+/// ## 增值税率，依据 2024 年的政策调整
+/// ## @author alice
+/// ## @version 2.1
+/// Rate := 0.13
+/// ```
+///
+/// 以 @author、@version 开始的行分别为作者及版本，其余的行作为说明文字
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Provenance {
+    /// 定义所在的公式文件，通过 import 或 load_dir 加载时为文件路径
+    pub source: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Provenance {
+    /// 从文档注释中读取来源信息，doc 为去掉开头的 ## 之后的每一行
+    pub fn from_doc<'a, I: IntoIterator<Item = &'a str>>(doc: I) -> Self {
+        let mut provenance = Provenance::default();
+        let mut description = Vec::new();
+        for line in doc {
+            let line = line.trim();
+            if let Some(author) = line.strip_prefix("@author") {
+                provenance.author = Some(author.trim().to_string());
+            } else if let Some(version) = line.strip_prefix("@version") {
+                provenance.version = Some(version.trim().to_string());
+            } else {
+                description.push(line);
+            }
+        }
+        let description = description.join("\n");
+        if !description.trim().is_empty() {
+            provenance.description = Some(description.trim().to_string());
+        }
+        provenance
+    }
+
+    /// 读取 gap 末尾紧邻下一条语句的 ## 文档注释，gap 为两条语句之间的源码,
+    /// after_statement 为 true 时 gap 的第一行与上一条语句在同一行，不属于下一条语句
+    pub(crate) fn from_gap(gap: &str, after_statement: bool) -> Self {
+        let gap = match after_statement {
+            true => gap.split_once('\n').map_or("", |(_, rest)| rest),
+            false => gap,
+        };
+        let mut doc: Vec<&str> = gap
+            .trim_end()
+            .lines()
+            .rev()
+            .map(|line| line.trim_start())
+            .take_while(|line| line.starts_with("##"))
+            .map(|line| &line[2..])
+            .collect();
+        doc.reverse();
+        Provenance::from_doc(doc)
+    }
+
+    /// 是否不包含任何信息
+    pub fn is_empty(&self) -> bool {
+        *self == Provenance::default()
+    }
+}
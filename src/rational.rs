@@ -0,0 +1,47 @@
+use crate::formula::{FormulaNode, OperatorNode};
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub};
+
+/// 数值 n 按十进制书写所表示的分数，如 0.1 为 1/10 而不是其二进制近似值,
+/// 非有限的数值及分子、分母超出 i64 范围时返回 None
+pub fn from_f64(n: f64) -> Option<Rational64> {
+    if !n.is_finite() {
+        return None;
+    }
+    // f64 的 Display 输出能还原该数值的最短十进制表示，且不使用科学计数法
+    let text = n.abs().to_string();
+    let (int, frac) = text.split_once('.').unwrap_or((&text, ""));
+    let denom = 10i64.checked_pow(frac.len() as u32)?;
+    let numer: i64 = format!("{}{}", int, frac).parse().ok()?;
+    let sign = if n < 0.0 { -1 } else { 1 };
+    Some(Rational64::new(sign * numer, denom))
+}
+
+/// 分数对应的 f64 数值
+pub fn to_f64(r: Rational64) -> f64 {
+    *r.numer() as f64 / *r.denom() as f64
+}
+
+/// 两个分数的四则运算，op 为 +、-、* 或 /，溢出、除以 0 及其他运算返回 None
+pub fn arithmetic(l: Rational64, r: Rational64, op: &str) -> Option<Rational64> {
+    match op {
+        "+" => l.checked_add(&r),
+        "-" => l.checked_sub(&r),
+        "*" => l.checked_mul(&r),
+        "/" if *r.numer() != 0 => l.checked_div(&r),
+        _ => None,
+    }
+}
+
+/// 分数对应的表达式节点，整数为常量，其余为 分子 / 分母，用于将分数绑定到函数参数上而不损失精度
+pub fn to_node(r: Rational64) -> FormulaNode {
+    match r.is_integer() {
+        true => FormulaNode::Constant(*r.numer() as f64),
+        false => FormulaNode::Operator(Box::new(OperatorNode::Divide {
+            left: Box::new(FormulaNode::Constant(*r.numer() as f64)),
+            right: Box::new(FormulaNode::Constant(*r.denom() as f64)),
+        })),
+    }
+}
@@ -0,0 +1,82 @@
+use crate::calculator::CalculateOption;
+#[cfg(not(any(feature = "std", test)))]
+use crate::compat::Float;
+use crate::complex::Complex;
+
+/// 舍入方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// 四舍五入，恰好为 5 时远离 0 舍入，与表格软件的 ROUND 一致
+    #[default]
+    HalfUp,
+    /// 四舍六入五成双，即银行家舍入，恰好为 5 时舍入到偶数
+    HalfEven,
+    /// 向负无穷方向舍入
+    Floor,
+    /// 向正无穷方向舍入
+    Ceiling,
+}
+
+impl RoundingMode {
+    /// 将 n 按 digits 位小数舍入，digits 为负数时舍入到整数部分的对应位，如 -2 为舍入到百位,
+    /// -0 统一为 0，非有限的数值保持不变
+    pub fn round(self, n: f64, digits: i32) -> f64 {
+        if !n.is_finite() {
+            return n;
+        }
+        // 通过十进制文本移动小数点，避免 1.005 * 100 得到 100.49999999999999 之类的误差
+        let scaled = shift(n, digits);
+        // 超出 f64 的精确整数范围时已没有可舍入的小数部分
+        if !scaled.is_finite() || scaled.abs() >= 2f64.powi(52) {
+            return n + 0.0;
+        }
+        let rounded = match self {
+            RoundingMode::HalfUp => scaled.round(),
+            RoundingMode::HalfEven => scaled.round_ties_even(),
+            RoundingMode::Floor => scaled.floor(),
+            RoundingMode::Ceiling => scaled.ceil(),
+        };
+        shift(rounded, -digits) + 0.0
+    }
+
+    /// 对数值、复数、向量、矩阵中的每个数值及金额按 digits 位小数舍入，其他类型的值保持不变
+    pub fn round_value(self, value: CalculateOption, digits: i32) -> CalculateOption {
+        let round = |n: f64| self.round(n, digits);
+        match value {
+            CalculateOption::Num(n) => CalculateOption::Num(round(n)),
+            CalculateOption::Complex(c) => {
+                CalculateOption::Complex(Complex::new(round(c.re), round(c.im)))
+            }
+            CalculateOption::Vector(v) => {
+                CalculateOption::Vector(v.into_iter().map(round).collect())
+            }
+            CalculateOption::Matrix(m) => CalculateOption::Matrix(m.map(round)),
+            CalculateOption::Money(m) => CalculateOption::Money(m.with_amount(round(m.amount))),
+            value => value,
+        }
+    }
+}
+
+/// n * 10^digits，结果与按十进制书写时移动小数点得到的数值一致
+fn shift(n: f64, digits: i32) -> f64 {
+    format!("{}e{}", n, digits).parse().unwrap_or(f64::NAN)
+}
+
+/// 计算结果的默认精度，通过 CalculateOptions::precision 或 Parser::set_precision 设置,
+/// 设置后每次计算的最终结果按 decimals 位小数及 mode 舍入，计算的中间结果不受影响
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    pub decimals: u32,
+    pub mode: RoundingMode,
+}
+
+impl Precision {
+    pub fn new(decimals: u32, mode: RoundingMode) -> Self {
+        Precision { decimals, mode }
+    }
+
+    /// 将计算结果按该精度舍入
+    pub fn apply(&self, value: CalculateOption) -> CalculateOption {
+        self.mode.round_value(value, self.decimals as i32)
+    }
+}
@@ -0,0 +1,774 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::cell::parse_cell;
+use crate::complex::Complex;
+use crate::formula::{FormulaNode, OperatorNode, Subscript};
+use crate::locale::NumberLocale;
+use crate::matrix::Matrix;
+use crate::syntax::{self, OperatorKind};
+use crate::token::{tokenize, Span, TokenKind};
+
+/// 当前所在的括号结构，决定了其中 , 及右括号的含义
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Nesting {
+    /// 以 ( 包围的表达式
+    Group,
+    /// 函数调用或定义的参数列表，以及 [ ] 中的向量及矩阵常量
+    Args,
+    /// 函数体及命名空间中以 { } 包围的语句
+    Block,
+    /// 下标访问 V[i] 及 V[start:end] 中的下标
+    Index,
+}
+
+type Scan = Result<FormulaNode, String>;
+
+/// 表达式允许嵌套的最大层数，每个括号、函数调用、逻辑非及命名表达式的右侧都会增加一层,
+/// 超过时该语句作为语法错误处理，避免任意输入导致扫描时栈溢出
+pub const MAX_DEPTH: usize = 256;
+
+/// 同一层中连续的四则运算及比较运算的最大个数，如 1 + 1 + ... + 1，
+/// 这样的运算链在扫描时逐个读取而不会增加嵌套的层数，但得到的语法树仍随其长度加深
+pub const MAX_CHAIN: usize = 5_000;
+
+/// 在 tokenize 得到的记号流上单次扫描源码，逐条得到语句的表达式节点，
+/// 嵌套的括号通过 nesting 记录而不再截取子串重新扫描，扫描的耗时与源码长度成正比。
+/// 遇到语法错误时该语句对应 UnKnow 节点，之后会跳过到下一个 ; 继续扫描
+pub struct Scanner<'a> {
+    source: &'a str,
+    tokens: Vec<(TokenKind, Span)>,
+    pos: usize,
+    nesting: Vec<Nesting>,
+    /// 当前 node 的递归层数
+    depth: usize,
+    /// 数值常量的书写习惯
+    number_locale: NumberLocale,
+    /// 是否按运算符的优先级组合运算，而不是原生语法中的全部右结合
+    precedence: bool,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let tokens = tokenize(source)
+            .into_iter()
+            .filter(|(kind, _)| *kind != TokenKind::Comment)
+            .collect();
+        Scanner {
+            source,
+            tokens,
+            pos: 0,
+            nesting: Vec::new(),
+            depth: 0,
+            number_locale: NumberLocale::default(),
+            precedence: false,
+        }
+    }
+
+    /// 按 number_locale 的书写习惯解析数值常量，如 NumberLocale::decimal_comma 时 1.234,5 为 1234.5
+    pub fn with_number_locale(mut self, number_locale: NumberLocale) -> Self {
+        self.number_locale = number_locale;
+        self
+    }
+
+    /// 按运算符的优先级组合四则运算及比较运算，用于 Excel 写法，如 A1 * 2 + 1 为 (A1 * 2) + 1
+    pub fn with_precedence(mut self, precedence: bool) -> Self {
+        self.precedence = precedence;
+        self
+    }
+
+    /// 扫描下一条语句，没有更多语句时返回 None，空语句会被跳过
+    pub fn next_statement(&mut self) -> Option<FormulaNode> {
+        self.next_statement_span().map(|(_, node)| node)
+    }
+
+    /// 与 next_statement 相同，同时返回语句在源码中的字节范围，包括结束该语句的 ;
+    pub fn next_statement_span(&mut self) -> Option<(Span, FormulaNode)> {
+        while self.pos < self.tokens.len() {
+            let start = self.pos;
+            let node = match self.node(false) {
+                Ok(FormulaNode::None) => continue,
+                Ok(node) => node,
+                Err(msg) => {
+                    self.recover(start);
+                    FormulaNode::UnKnow(msg)
+                }
+            };
+            let end = self.tokens[self.pos.max(start + 1) - 1].1.end;
+            return Some((self.tokens[start].1.start..end, node));
+        }
+        None
+    }
+
+    /// 扫描出错后从语句的开头 start 查找不在括号中的 ; 并从其之后继续扫描
+    fn recover(&mut self, start: usize) {
+        self.nesting.clear();
+        self.depth = 0;
+        let mut depth = 0usize;
+        for i in start..self.tokens.len() {
+            match self.text(i) {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                ";" if depth == 0 && i >= self.pos => {
+                    self.pos = i + 1;
+                    return;
+                }
+                _ => (),
+            }
+        }
+        self.pos = self.tokens.len();
+    }
+
+    fn text(&self, index: usize) -> &'a str {
+        let source: &'a str = self.source;
+        &source[self.tokens[index].1.clone()]
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<(TokenKind, &'a str)> {
+        let index = self.pos + offset;
+        self.tokens
+            .get(index)
+            .map(|(kind, _)| (*kind, self.text(index)))
+    }
+
+    fn peek(&self) -> Option<(TokenKind, &'a str)> {
+        self.peek_at(0)
+    }
+
+    fn peek_text(&self) -> Option<&'a str> {
+        self.peek().map(|(_, text)| text)
+    }
+
+    fn bump(&mut self) -> &'a str {
+        let text = self.text(self.pos);
+        self.pos += 1;
+        text
+    }
+
+    /// 下一个记号是否为紧跟在上一个记号之后的 [，即下标访问的开始，如 V[1]，
+    /// 与上一个节点之间有空白时 [ 为新语句中的向量常量
+    fn at_subscript(&self) -> bool {
+        self.pos > 0 && self.peek_text() == Some("[") && self.adjacent(self.pos)
+    }
+
+    /// 第 index 个记号是否紧跟在上一个记号之后，之间没有空白
+    fn adjacent(&self, index: usize) -> bool {
+        self.tokens[index - 1].1.end == self.tokens[index].1.start
+    }
+
+    /// 扫描一个节点，limit 为 true 时只扫描一个基本节点，用作一元运算及逻辑运算的后置节点。
+    /// 运算符之间没有优先级，二元运算的右侧会扫描到语句结束，
+    /// 已得到节点时再遇到名称、数值或括号等新节点的开头也视为当前语句结束，紧跟在节点之后的 [ 为下标访问
+    fn node(&mut self, limit: bool) -> Scan {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("公式嵌套的层数超过了 {} 层", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let result = self.scan_node(limit);
+        self.depth -= 1;
+        result
+    }
+
+    fn scan_node(&mut self, limit: bool) -> Scan {
+        let mut node = None;
+        // 尚未得到右侧操作数的二元运算，在扫描结束时从右向左组合，以免运算链的长度消耗调用栈
+        let mut chain = Vec::new();
+        while let Some((kind, text)) = self.peek() {
+            let starts_node = matches!(
+                (kind, text),
+                (
+                    TokenKind::Identifier
+                        | TokenKind::Keyword
+                        | TokenKind::Number
+                        | TokenKind::String,
+                    _
+                ) | (_, "(" | "[" | "!")
+            );
+            if starts_node && node.is_some() && !self.at_subscript() {
+                break;
+            }
+
+            match (kind, text) {
+                (TokenKind::Identifier | TokenKind::Keyword, _) => {
+                    self.operand(&mut node, Self::name)?
+                }
+                (TokenKind::Number, _) => self.operand(&mut node, Self::number)?,
+                (TokenKind::String, _) => self.operand(&mut node, Self::string)?,
+                (_, "(") => {
+                    self.pos += 1;
+                    self.operand(&mut node, |s| s.nested(Nesting::Group, Self::sequence))?;
+                }
+                // 已得到节点时紧跟的 [ 为下标访问
+                (_, "[") if node.is_some() => self.index(&mut node)?,
+                (_, "[") => {
+                    self.pos += 1;
+                    self.operand(&mut node, |s| s.nested(Nesting::Args, Self::bracket))?;
+                }
+                (_, ")" | "]" | "}") if !self.nesting.is_empty() => break,
+                (_, ",") if self.nesting.last() == Some(&Nesting::Args) => break,
+                (_, ";") => {
+                    self.pos += 1;
+                    break;
+                }
+                (_, ":") if self.nesting.last() == Some(&Nesting::Index) => break,
+                (_, ":") => return Err("公式格式出错，命名公式时缺少了 : 之后的 = 号".to_string()),
+                (_, "&") => return Err("逻辑与的关键符号为 &&， 缺少了第二个 &".to_string()),
+                (_, "|") => return Err("逻辑或的关键符号为 ||， 缺少了第二个 |".to_string()),
+                (TokenKind::Operator, _) => match syntax::operator(text).map(|op| op.kind) {
+                    Some(OperatorKind::Naming) => {
+                        let named = self.naming(node)?;
+                        return Ok(self.combine(chain, named));
+                    }
+                    Some(OperatorKind::Unary) => self.operand(&mut node, Self::not)?,
+                    Some(OperatorKind::Math) => self.math(&mut node, &mut chain)?,
+                    Some(OperatorKind::Compare) => self.compare(&mut node, &mut chain)?,
+                    Some(OperatorKind::Logic) => self.logic(&mut node)?,
+                    _ => return Err(format!("扫描公式时遇到非法符号: {}！", text)),
+                },
+                _ => return Err(format!("扫描公式时遇到非法符号: {}！", text)),
+            }
+
+            if limit && node.is_some() && !self.at_subscript() {
+                break;
+            }
+        }
+        Ok(self.combine(chain, node.unwrap_or(FormulaNode::None)))
+    }
+
+    /// 通过 scan 扫描一个操作数并替换 node，scan_node 位于递归路径上,
+    /// 在单独的函数中保存扫描结果以减少 scan_node 每一层占用的调用栈
+    fn operand(
+        &mut self,
+        node: &mut Option<FormulaNode>,
+        scan: fn(&mut Self) -> Scan,
+    ) -> Result<(), String> {
+        *node = Some(scan(self)?);
+        Ok(())
+    }
+
+    /// 组合一条运算链，right 为最右侧的操作数
+    fn combine(&self, chain: Chain, right: FormulaNode) -> FormulaNode {
+        match self.precedence {
+            true => fold_precedence(chain, right),
+            false => fold_chain(chain, right),
+        }
+    }
+
+    /// 扫描逻辑非，! 之后只扫描一个基本节点
+    fn not(&mut self) -> Scan {
+        self.pos += 1;
+        let operand = self.node(true)?;
+        Ok(FormulaNode::Operator(Box::new(OperatorNode::Not(
+            Box::new(operand),
+        ))))
+    }
+
+    /// 在 nesting 结构中调用 scan，并读取结尾的右括号，扫描到源码末尾仍缺少右括号时返回错误
+    fn nested<T>(
+        &mut self,
+        nesting: Nesting,
+        scan: fn(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let open = self.text(self.pos - 1);
+        self.nesting.push(nesting);
+        let result = scan(self);
+        self.nesting.pop();
+        let result = result?;
+        match self.peek_text() {
+            Some(")" | "]" | "}") => self.pos += 1,
+            None => {
+                return Err(format!(
+                    "公式的格式错误，{} 没有闭合，缺少了对应的右括号",
+                    open
+                ))
+            }
+            _ => (),
+        }
+        Ok(result)
+    }
+
+    /// 扫描括号中以 ; 分隔的多条语句，结果为最后一条语句
+    fn sequence(&mut self) -> Scan {
+        let mut last = FormulaNode::None;
+        loop {
+            match self.node(false)? {
+                FormulaNode::None => (),
+                node => last = node,
+            }
+            if matches!(self.peek_text(), None | Some(")" | "]" | "}" | ",")) {
+                return Ok(last);
+            }
+        }
+    }
+
+    /// 扫描 [ ] 中的内容，以 , 分隔的数值为向量常量，如 [1, -2]，以 , 分隔的向量为矩阵常量,
+    /// 如 [[1, 2], [3, 4]]，[] 为空向量，只有一个表达式时与 ( ) 相同
+    fn bracket(&mut self) -> Scan {
+        let mut items = Vec::new();
+        let mut comma = false;
+        while self
+            .peek_text()
+            .is_some_and(|text| !matches!(text, ")" | "]" | "}"))
+        {
+            items.push(self.signed(Self::sequence)?);
+            if self.peek_text() == Some(",") {
+                self.pos += 1;
+                comma = true;
+            }
+        }
+
+        bracket_node(items, comma)
+    }
+
+    /// 通过 scan 扫描 [ ] 中的一项，开头的 - 之后紧跟数值时为负数常量，如 [1, -2] 及 V[-1]
+    fn signed(&mut self, scan: fn(&mut Self) -> Scan) -> Scan {
+        // 常量中的负数，- 之前没有操作数
+        let negative = self.peek_text() == Some("-")
+            && matches!(self.peek_at(1), Some((TokenKind::Number, _)));
+        if negative {
+            self.pos += 1;
+        }
+        match (negative, scan(self)?) {
+            (true, FormulaNode::Constant(n)) => Ok(FormulaNode::Constant(-n)),
+            (true, _) => Err("公式的格式错误，二元操作符前没有合法的计算节点".to_string()),
+            (false, item) => Ok(item),
+        }
+    }
+
+    /// 扫描 node 之后的下标访问，如 V[1]，并将 node 替换为下标访问节点
+    fn index(&mut self, node: &mut Option<FormulaNode>) -> Result<(), String> {
+        self.pos += 1;
+        let target = Box::new(node.take().unwrap_or(FormulaNode::None));
+        let index = self.nested(Nesting::Index, Self::subscript)?;
+        *node = Some(FormulaNode::Index { target, index });
+        Ok(())
+    }
+
+    /// 扫描下标访问 [ ] 中的下标，如 V[1]、V[-1]、V[1:3] 及 V[:2]
+    fn subscript(&mut self) -> Result<Subscript, String> {
+        let scan = |s: &mut Self| s.node(false);
+        let start = self.signed(scan)?;
+        if self.peek_text() != Some(":") {
+            return match start {
+                FormulaNode::None => {
+                    Err("下标访问的格式为 V[i] 或 V[start:end]，缺少了下标".to_string())
+                }
+                start => Ok(Subscript::At(Box::new(start))),
+            };
+        }
+        self.pos += 1;
+        let end = self.signed(scan)?;
+        let bound = |node| match node {
+            FormulaNode::None => None,
+            node => Some(Box::new(node)),
+        };
+        Ok(Subscript::Slice(bound(start), bound(end)))
+    }
+
+    /// 扫描以 , 分隔的参数列表，空的参数为 None 节点，结尾多余的 , 会被忽略
+    fn args(&mut self) -> Result<Vec<Rc<FormulaNode>>, String> {
+        let mut args = Vec::new();
+        while self
+            .peek_text()
+            .is_some_and(|text| !matches!(text, ")" | "]" | "}"))
+        {
+            let arg = self.sequence()?;
+            let more = self.peek_text() == Some(",");
+            if more {
+                self.pos += 1;
+            }
+            if more || arg != FormulaNode::None {
+                args.push(Rc::new(arg));
+            }
+        }
+        Ok(args)
+    }
+
+    /// 扫描 { } 中以 ; 分隔的语句，空语句会被忽略
+    fn block(&mut self) -> Result<Vec<Rc<FormulaNode>>, String> {
+        let mut statements = Vec::new();
+        while self
+            .peek_text()
+            .is_some_and(|text| !matches!(text, ")" | "]" | "}"))
+        {
+            match self.node(false)? {
+                FormulaNode::None => (),
+                node => statements.push(Rc::new(node)),
+            }
+        }
+        Ok(statements)
+    }
+
+    /// 扫描以名称开头的节点：变量、单元格区域、历史值、函数调用及定义、import 与命名空间
+    fn name(&mut self) -> Scan {
+        let name_at = self.pos;
+        let name = self.bump();
+
+        match (name, self.peek()) {
+            // 引用其他公式文件: import "文件名";
+            ("import", Some((TokenKind::String, path))) => {
+                self.pos += 1;
+                return match path.len() >= 2 && path.ends_with('"') {
+                    true => Ok(FormulaNode::Import(path[1..path.len() - 1].to_string())),
+                    false => Err("import 的格式为 import \"文件名\";，缺少了结尾的 \"".to_string()),
+                };
+            }
+            // 命名空间的定义: namespace 名称 { 定义; ... }
+            ("namespace", Some((TokenKind::Identifier, ns)))
+                if self.peek_at(1).map(|t| t.1) == Some("{") =>
+            {
+                self.pos += 2;
+                let definitions = self.nested(Nesting::Block, Self::block)?;
+                return Ok(FormulaNode::Namespace {
+                    name: ns.to_string(),
+                    definitions,
+                });
+            }
+            _ => (),
+        }
+
+        match self.peek_text() {
+            // 单元格区域如 B2:B10 作为一个整体的变量名，以便通过 CellResolver 获取
+            Some(":") if parse_cell(name).is_some() => Ok(self.cell_range(name_at)),
+            // 变量后续是 @-N 时表示读取该变量的历史值
+            Some("@") => self.history(name),
+            // 如果一个变量后续是括号，则说明它是一个函数调用，之后再跟随 { } 时为函数定义
+            Some("(") => self.call(name),
+            Some("{") => Err(format!(
+                "{} 之后的 {{ 应为函数体，函数定义的格式为 函数名(参数, ...) {{ 表达式; ... }}",
+                name
+            )),
+            _ => Ok(FormulaNode::Variant(name.to_string())),
+        }
+    }
+
+    // name 位于扫描的递归路径上，各个分支拆分为单独的函数以减少 name 每一层占用的调用栈
+
+    /// 扫描 name_at 处的单元格之后的 :单元格，如 B2:B10，不是单元格区域时为普通的变量名
+    fn cell_range(&mut self, name_at: usize) -> FormulaNode {
+        let colon_end = self.tokens[self.pos].1.end;
+        match self.tokens.get(self.pos + 1) {
+            Some((TokenKind::Identifier, end))
+                if end.start == colon_end && parse_cell(self.text(self.pos + 1)).is_some() =>
+            {
+                let range = self.tokens[name_at].1.start..end.end;
+                self.pos += 2;
+                FormulaNode::Variant(self.source[range].to_string())
+            }
+            _ => FormulaNode::Variant(self.text(name_at).to_string()),
+        }
+    }
+
+    /// 扫描变量 name 之后的 @-N
+    fn history(&mut self, name: &str) -> Scan {
+        self.pos += 1;
+        if self.peek_text() != Some("-") {
+            return Err("读取历史值的格式为 变量名@-N，缺少了 @ 之后的 -".to_string());
+        }
+        self.pos += 1;
+        match self.peek() {
+            Some((TokenKind::Number, back)) => {
+                self.pos += 1;
+                back.parse::<usize>()
+                    .map(|back| FormulaNode::History {
+                        name: name.to_string(),
+                        back,
+                    })
+                    .map_err(|_| "读取历史值的格式为 变量名@-N，N 应为正整数".to_string())
+            }
+            _ => Err("读取历史值的格式为 变量名@-N，N 应为正整数".to_string()),
+        }
+    }
+
+    /// 扫描 name 之后的参数列表，之后再跟随 { } 时为函数定义
+    fn call(&mut self, name: &str) -> Scan {
+        self.pos += 1;
+        let args = self.nested(Nesting::Args, Self::args)?;
+        if self.peek_text() != Some("{") {
+            return Ok(FormulaNode::FunctionCall {
+                name: name.to_string(),
+                args: args.into(),
+            });
+        }
+        self.pos += 1;
+        let expressions = self.nested(Nesting::Block, Self::block)?;
+        Ok(FormulaNode::Function {
+            name: name.to_string(),
+            args,
+            expressions,
+        })
+    }
+
+    /// 处理公式的常量，紧跟在数值之后的 i 表示虚数常量，如 2i
+    /// 数值的写法以 , 作为小数点或千位分隔符时，紧跟着的 , 及数值都属于该数值，如 1.234,56
+    fn number(&mut self) -> Scan {
+        let start = self.tokens[self.pos].1.start;
+        self.pos += 1;
+        while self.number_locale.uses_comma()
+            && self.peek_text() == Some(",")
+            && matches!(self.peek_at(1), Some((TokenKind::Number, _)))
+            && self.adjacent(self.pos)
+            && self.adjacent(self.pos + 1)
+        {
+            self.pos += 2;
+        }
+        let text = &self.source[start..self.tokens[self.pos - 1].1.end];
+        let (digits, imaginary) = match text.strip_suffix('i') {
+            Some(digits) => (digits, true),
+            None => (text, false),
+        };
+        let value = self
+            .number_locale
+            .parse(digits)
+            .ok_or_else(|| format!("无法识别的数值: {}", text))?;
+        Ok(match imaginary {
+            true => FormulaNode::Complex(Complex::new(0.0, value)),
+            false => FormulaNode::Constant(value),
+        })
+    }
+
+    /// 处理文本常量，如 "0.00"，文本中不支持转义字符
+    fn string(&mut self) -> Scan {
+        let text = self.bump();
+        match text.len() >= 2 && text.ends_with('"') {
+            true => Ok(FormulaNode::Text(text[1..text.len() - 1].to_string())),
+            false => Err("文本常量缺少了结尾的 \"".to_string()),
+        }
+    }
+
+    /// 处理公式命名, 前置节点应为一个 Variant 节点
+    fn naming(&mut self, node: Option<FormulaNode>) -> Scan {
+        let name = match node {
+            Some(FormulaNode::Variant(name)) => name,
+            Some(_) => return Err(
+                "公式的格式出错，命名的节点应为 Variant 类型，命名公式的格式为 公式名 := 表达式"
+                    .to_string(),
+            ),
+            None => return Err("公式的格式出错，命名公式的格式为 公式名 := 表达式".to_string()),
+        };
+        self.pos += 1;
+
+        match self.node(false)? {
+            FormulaNode::None => Err("公式格式出错，公式名称之后没有任何表达式".to_string()),
+            formula => Ok(FormulaNode::Formula {
+                name,
+                formula: Rc::new(formula),
+            }),
+        }
+    }
+
+    /// 处理公式的数学运算，将左侧的节点 node 及操作符加入 chain，右侧的节点在之后继续扫描
+    /// ^ 为乘方，早期版本中作为逻辑非的前置 ^ 会提示改用 !
+    fn math(
+        &mut self,
+        node: &mut Option<FormulaNode>,
+        chain: &mut Chain<'a>,
+    ) -> Result<(), String> {
+        let op = self.bump();
+        let left = match (node.take(), op) {
+            (Some(left), _) => left,
+            (None, "^") => return Err("^ 为乘方运算，逻辑非请使用 !，如 !A".to_string()),
+            (None, _) => return Err("公式的格式错误，二元操作符前没有合法的计算节点".to_string()),
+        };
+        push_chain(chain, op, left)
+    }
+
+    /// 处理比较操作符，与数学运算一样加入 chain，
+    /// 连续的比较如 a < b < c 会被展开为 (a < b) && (b < c)，展开后中间的操作数会被计算两次
+    fn compare(
+        &mut self,
+        node: &mut Option<FormulaNode>,
+        chain: &mut Chain<'a>,
+    ) -> Result<(), String> {
+        let op = self.bump();
+        let left = node
+            .take()
+            .ok_or("公式的格式错误，比较操作符前没有合法的计算节点")?;
+        push_chain(chain, op, left)
+    }
+
+    /// 处理逻辑运算，右侧只取一个基本节点
+    fn logic(&mut self, node: &mut Option<FormulaNode>) -> Result<(), String> {
+        let op = self.bump();
+        let left = Box::new(
+            node.take()
+                .ok_or("公式的格式错误，逻辑操作符前没有合法的计算节点")?,
+        );
+        let right = Box::new(self.node(true)?);
+        let op_node = match op {
+            "&&" => OperatorNode::And { left, right },
+            _ => OperatorNode::Or { left, right },
+        };
+        *node = Some(FormulaNode::Operator(Box::new(op_node)));
+        Ok(())
+    }
+}
+
+impl Iterator for Scanner<'_> {
+    type Item = FormulaNode;
+
+    fn next(&mut self) -> Option<FormulaNode> {
+        self.next_statement()
+    }
+}
+
+/// [ ] 中以 , 分隔的各项组成的节点，comma 表示其中是否出现过 ,
+fn bracket_node(mut items: Vec<FormulaNode>, comma: bool) -> Scan {
+    match items.as_slice() {
+        [] => Ok(FormulaNode::Vector(Vec::new())),
+        [item] if !comma && !matches!(item, FormulaNode::Vector(_)) => Ok(items.remove(0)),
+        _ if items
+            .iter()
+            .all(|item| matches!(item, FormulaNode::Constant(_))) =>
+        {
+            Ok(FormulaNode::Vector(
+                items
+                    .iter()
+                    .filter_map(|item| match item {
+                        FormulaNode::Constant(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect(),
+            ))
+        }
+        _ if items
+            .iter()
+            .all(|item| matches!(item, FormulaNode::Vector(_))) =>
+        {
+            let rows = items
+                .into_iter()
+                .filter_map(|item| match item {
+                    FormulaNode::Vector(row) => Some(row),
+                    _ => None,
+                })
+                .collect();
+            Matrix::from_rows(rows)
+                .map(FormulaNode::Matrix)
+                .ok_or_else(|| "矩阵常量中每一行的元素个数应相同且不为空".to_string())
+        }
+        _ => Err(
+            "向量常量 [a, b] 中只能包含数值，矩阵常量 [[a, b], [c, d]] 中只能包含数值向量"
+                .to_string(),
+        ),
+    }
+}
+
+/// 一条语句中等待右侧操作数的二元运算，每项为操作符及其左侧的节点
+type Chain<'a> = Vec<(&'a str, FormulaNode)>;
+
+fn push_chain<'a>(chain: &mut Chain<'a>, op: &'a str, left: FormulaNode) -> Result<(), String> {
+    if chain.len() >= MAX_CHAIN {
+        return Err(format!("公式中连续的运算超过了 {} 个", MAX_CHAIN));
+    }
+    chain.push((op, left));
+    Ok(())
+}
+
+/// 从右向左组合 chain 中的二元运算，right 为最右侧的操作数，所有运算都是右结合的,
+/// 即 a - b - c 为 a - (b - c)，右侧为比较表达式的比较会展开为连续比较
+fn fold_chain(chain: Chain, right: FormulaNode) -> FormulaNode {
+    chain.into_iter().rev().fold(right, |right, (op, left)| {
+        if precedence(op) > 1 {
+            return binary_node(op, left, right);
+        }
+        match compare_chain_head(&right).cloned() {
+            Some(head) => FormulaNode::Operator(Box::new(OperatorNode::And {
+                left: Box::new(binary_node(op, left, head)),
+                right: Box::new(right),
+            })),
+            None => binary_node(op, left, right),
+        }
+    })
+}
+
+/// 按运算符的优先级组合 chain 中的二元运算，同一优先级的运算从左向右结合，与 Excel 的规则一致:
+/// ^ 高于 * 及 /，再高于 + 及 -，比较运算最低，如 A1 * 2 + 1 为 (A1 * 2) + 1，2 ^ 3 ^ 2 为 (2 ^ 3) ^ 2
+fn fold_precedence(chain: Chain, right: FormulaNode) -> FormulaNode {
+    let mut operands = Vec::with_capacity(chain.len() + 1);
+    let mut ops: Vec<&str> = Vec::new();
+    let reduce = |operands: &mut Vec<FormulaNode>, op: &str| {
+        let right = operands.pop().unwrap_or(FormulaNode::None);
+        let left = operands.pop().unwrap_or(FormulaNode::None);
+        operands.push(binary_node(op, left, right));
+    };
+    for (op, left) in chain {
+        operands.push(left);
+        while let Some(&top) = ops.last().filter(|top| precedence(top) >= precedence(op)) {
+            ops.pop();
+            reduce(&mut operands, top);
+        }
+        ops.push(op);
+    }
+    operands.push(right);
+    while let Some(op) = ops.pop() {
+        reduce(&mut operands, op);
+    }
+    operands.pop().unwrap_or(FormulaNode::None)
+}
+
+/// 二元运算的优先级，比较运算为 1
+fn precedence(op: &str) -> u8 {
+    match op {
+        "^" => 4,
+        "*" | "/" => 3,
+        "+" | "-" => 2,
+        _ => 1,
+    }
+}
+
+/// 根据操作符生成四则运算或比较节点，比较不会展开为连续比较
+fn binary_node(op: &str, left: FormulaNode, right: FormulaNode) -> FormulaNode {
+    let (left, right) = (Box::new(left), Box::new(right));
+    let op_node = match op {
+        "+" => OperatorNode::Plus { left, right },
+        "-" => OperatorNode::Minus { left, right },
+        "*" => OperatorNode::Multiply { left, right },
+        "^" => OperatorNode::Power { left, right },
+        "/" => OperatorNode::Divide { left, right },
+        _ => return compare_node(op, left, right),
+    };
+    FormulaNode::Operator(Box::new(op_node))
+}
+
+/// 根据操作符生成比较节点
+fn compare_node(op: &str, left: Box<FormulaNode>, right: Box<FormulaNode>) -> FormulaNode {
+    let op_node = match op {
+        ">" => OperatorNode::Great { left, right },
+        ">=" => OperatorNode::GreatEqual { left, right },
+        "<" => OperatorNode::Less { left, right },
+        "<=" => OperatorNode::LessEqual { left, right },
+        "!=" => OperatorNode::NotEqual { left, right },
+        _ => OperatorNode::Equal { left, right },
+    };
+    FormulaNode::Operator(Box::new(op_node))
+}
+
+/// 如果 node 是比较表达式（或已展开的连续比较），返回其最左侧的操作数,
+/// 最左侧的操作数为逻辑运算时，如 a > b || c > b 的右侧 (b || c) > b，node 不是连续比较的一部分，返回 None
+fn compare_chain_head(node: &FormulaNode) -> Option<&FormulaNode> {
+    match node {
+        FormulaNode::Operator(op) => match op.as_ref() {
+            OperatorNode::Less { left, .. }
+            | OperatorNode::LessEqual { left, .. }
+            | OperatorNode::Great { left, .. }
+            | OperatorNode::GreatEqual { left, .. }
+            | OperatorNode::Equal { left, .. }
+            | OperatorNode::NotEqual { left, .. } => match left.as_ref() {
+                FormulaNode::Operator(op)
+                    if matches!(
+                        op.as_ref(),
+                        OperatorNode::And { .. } | OperatorNode::Or { .. }
+                    ) =>
+                {
+                    None
+                }
+                _ => Some(left),
+            },
+            OperatorNode::And { left, .. } => compare_chain_head(left),
+            _ => None,
+        },
+        _ => None,
+    }
+}
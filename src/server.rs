@@ -0,0 +1,96 @@
+use serde_json::{json, Map, Value};
+
+use crate::calculator::CalculateOption;
+use crate::diagnostic::{FormulaError, Severity};
+use crate::parser::Parser;
+
+/// 处理 formula-server 收到的请求，path 为请求的路径，body 为 JSON 格式的请求体,
+/// 返回 HTTP 状态码及 JSON 格式的响应体
+///
+/// 请求体的格式为 {"formula": "A + 1", "vars": {"A": 1}}，vars 可省略，
+/// 其中的值可以是数值、逻辑值或数值数组，每个请求都在独立的执行环境中处理
+pub fn handle(path: &str, body: &str) -> (u16, String) {
+    let (status, value) = match request(body) {
+        Ok((formula, parser)) => route(path, &formula, parser),
+        Err(message) => (400, json!({ "error": { "message": message } })),
+    };
+    (status, value.to_string())
+}
+
+fn route(path: &str, formula: &str, mut parser: Parser) -> (u16, Value) {
+    match path {
+        "/parse" => match serde_json::to_value(parser.parse(formula).as_ref()) {
+            Ok(ast) => (200, json!({ "ast": ast })),
+            Err(e) => (500, json!({ "error": { "message": e.to_string() } })),
+        },
+        "/evaluate" => match parser.try_calculate(formula) {
+            Ok(value) => (200, json!({ "value": CalculateOption::from(value) })),
+            Err(e) => (422, json!({ "error": error(&e) })),
+        },
+        "/validate" => {
+            let diagnostics: Vec<Value> = parser
+                .check(formula)
+                .into_iter()
+                .map(|d| {
+                    json!({
+                        "severity": match d.severity {
+                            Severity::Error => "error",
+                            Severity::Warning => "warning",
+                        },
+                        "code": d.code.code(),
+                        "message": d.message,
+                        "span": [d.span.start, d.span.end],
+                    })
+                })
+                .collect();
+            (200, json!({ "diagnostics": diagnostics }))
+        }
+        _ => (
+            404,
+            json!({ "error": { "message": format!("未知的路径 {}", path) } }),
+        ),
+    }
+}
+
+/// 解析请求体，并创建绑定了 vars 的解析器
+fn request(body: &str) -> Result<(String, Parser), String> {
+    let body: Map<String, Value> =
+        serde_json::from_str(body).map_err(|e| format!("请求体不是合法的 JSON 对象: {}", e))?;
+    let formula = match body.get("formula") {
+        Some(Value::String(formula)) => formula.clone(),
+        _ => return Err("请求体中缺少字符串类型的 formula 字段".to_string()),
+    };
+
+    let parser = Parser::new();
+    if let Some(vars) = body.get("vars") {
+        let vars = vars.as_object().ok_or("vars 字段应为 JSON 对象")?;
+        for (name, value) in vars {
+            let value = match value {
+                Value::Number(n) => n.as_f64().map(CalculateOption::Num),
+                Value::Bool(b) => Some(CalculateOption::Bool(*b)),
+                Value::Array(items) => items
+                    .iter()
+                    .map(Value::as_f64)
+                    .collect::<Option<Vec<_>>>()
+                    .map(CalculateOption::Vector),
+                _ => None,
+            };
+            if !value.is_some_and(|value| parser.set_var(name, value)) {
+                return Err(format!("无法绑定变量 {}", name));
+            }
+        }
+    }
+    Ok((formula, parser))
+}
+
+fn error(e: &FormulaError) -> Value {
+    let kind = match e {
+        FormulaError::Parse { .. } => "parse",
+        FormulaError::Eval { .. } => "eval",
+    };
+    json!({
+        "kind": kind,
+        "message": e.message(),
+        "span": e.span().map(|span| [span.start, span.end]),
+    })
+}
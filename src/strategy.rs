@@ -0,0 +1,103 @@
+use std::rc::Rc;
+
+use proptest::prelude::*;
+
+use crate::formula::{FormulaNode, OperatorNode};
+
+/// 随机语法树的最大深度
+const MAX_DEPTH: u32 = 6;
+/// 随机语法树中节点的期望数量上限
+const MAX_NODES: u32 = 64;
+
+/// 生成非负的数值常量，包括 0 以覆盖除以零的情况,
+/// 公式语法中没有负数常量，因此生成的语法树都可以通过 Display 输出后再解析
+pub fn constant() -> impl Strategy<Value = FormulaNode> {
+    prop_oneof![
+        Just(FormulaNode::Constant(0.0)),
+        (0u32..1000).prop_map(|n| FormulaNode::Constant(n as f64)),
+        (0.0..1000.0f64).prop_map(FormulaNode::Constant),
+    ]
+}
+
+/// 生成常量或引用 names 中变量的叶子节点，names 为空时只生成常量
+fn leaf(names: &[&str]) -> BoxedStrategy<FormulaNode> {
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    match names.is_empty() {
+        true => constant().boxed(),
+        false => prop_oneof![
+            constant(),
+            prop::sample::select(names).prop_map(FormulaNode::Variant),
+        ]
+        .boxed(),
+    }
+}
+
+fn operator(op: OperatorNode) -> FormulaNode {
+    FormulaNode::Operator(Box::new(op))
+}
+
+/// 生成只由常量、names 中的变量及 + - * / 组成的语法树，即 CompiledFormula 可以直接计算的表达式
+pub fn arith_node(names: &[&str]) -> impl Strategy<Value = FormulaNode> {
+    leaf(names).prop_recursive(MAX_DEPTH, MAX_NODES, 2, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone(), 0..4u8).prop_map(|(l, r, op)| {
+                let (left, right) = (Box::new(l), Box::new(r));
+                operator(match op {
+                    0 => OperatorNode::Plus { left, right },
+                    1 => OperatorNode::Minus { left, right },
+                    2 => OperatorNode::Multiply { left, right },
+                    _ => OperatorNode::Divide { left, right },
+                })
+            }),
+            inner.prop_map(|node| FormulaNode::Quote(Box::new(node))),
+        ]
+    })
+}
+
+/// 生成包含四则运算、比较、逻辑运算及 If 调用的语法树，叶子节点为常量及 names 中的变量
+pub fn formula_node(names: &[&str]) -> impl Strategy<Value = FormulaNode> {
+    leaf(names).prop_recursive(MAX_DEPTH, MAX_NODES, 3, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone(), 0..10u8).prop_map(|(l, r, op)| {
+                let (left, right) = (Box::new(l), Box::new(r));
+                operator(match op {
+                    0 => OperatorNode::Plus { left, right },
+                    1 => OperatorNode::Minus { left, right },
+                    2 => OperatorNode::Multiply { left, right },
+                    3 => OperatorNode::Divide { left, right },
+                    4 => OperatorNode::Less { left, right },
+                    5 => OperatorNode::GreatEqual { left, right },
+                    6 => OperatorNode::Equal { left, right },
+                    7 => OperatorNode::NotEqual { left, right },
+                    8 => OperatorNode::And { left, right },
+                    _ => OperatorNode::Or { left, right },
+                })
+            }),
+            inner
+                .clone()
+                .prop_map(|node| operator(OperatorNode::Not(Box::new(node)))),
+            (inner.clone(), inner.clone(), inner).prop_map(|(cond, then, otherwise)| {
+                FormulaNode::FunctionCall {
+                    name: "If".to_string(),
+                    args: vec![Rc::new(cond), Rc::new(then), Rc::new(otherwise)].into(),
+                }
+            }),
+        ]
+    })
+}
+
+/// 生成对函数 name 的调用，参数为 arity 个由 formula_node 生成的表达式,
+/// 可用于对自定义的内建函数进行模糊测试，检查其在任意参数下都不会 panic
+pub fn function_call(
+    name: &str,
+    arity: usize,
+    names: &[&str],
+) -> impl Strategy<Value = FormulaNode> {
+    let name = name.to_string();
+    prop::collection::vec(formula_node(names), arity).prop_map(move |args| {
+        FormulaNode::FunctionCall {
+            name: name.clone(),
+            args: args.into_iter().map(Rc::new).collect(),
+        }
+    })
+}
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::rc::Rc;
+
+use crate::formula::FormulaNode;
+use crate::parser::Parser;
+
+/// 从 BufRead 中逐条读取语句的迭代器，由 Parser::parse_reader 创建,
+/// 按行读取源码，每当读到不在括号、字符串及注释中的 ; 时解析此前缓存的语句,
+/// 不以 ; 分隔的语句在读到下一条语句所在的行时解析，读取出错时得到错误信息并结束迭代
+pub struct StatementReader<'p, R> {
+    parser: &'p mut Parser,
+    reader: R,
+    /// 尚未结束的语句的源码
+    buffer: String,
+    /// 已经扫描过的 buffer 中的字节数
+    scanned: usize,
+    depth: usize,
+    in_string: bool,
+    /// 已经解析但尚未返回的语句
+    pending: VecDeque<Rc<FormulaNode>>,
+    finished: bool,
+}
+
+impl<'p, R: BufRead> StatementReader<'p, R> {
+    pub fn new(parser: &'p mut Parser, reader: R) -> Self {
+        StatementReader {
+            parser,
+            reader,
+            buffer: String::new(),
+            scanned: 0,
+            depth: 0,
+            in_string: false,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// 扫描 buffer 中新读入的部分，返回最后一个位于最外层的 ; 之后的位置
+    fn scan(&mut self) -> Option<usize> {
+        let mut end = None;
+        let mut chars = self.buffer[self.scanned..].char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => self.in_string = !self.in_string,
+                _ if self.in_string => (),
+                // 注释直到行尾，而每次读入的都是完整的一行，因此跳过该行剩余的部分即可
+                '#' => break,
+                '/' if matches!(chars.peek(), Some((_, '/'))) => break,
+                '(' | '[' | '{' => self.depth += 1,
+                ')' | ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                ';' if self.depth == 0 => end = Some(self.scanned + i + 1),
+                _ => (),
+            }
+        }
+        self.scanned = self.buffer.len();
+        end
+    }
+
+    /// 解析 buffer 中 end 之前的语句，剩余的部分保留在 buffer 中
+    fn flush(&mut self, end: usize) {
+        let rest = self.buffer.split_off(end);
+        let source = std::mem::replace(&mut self.buffer, rest);
+        self.scanned = self.buffer.len();
+        let nodes = self.parser.parse_statements(&source);
+        self.pending.extend(nodes);
+    }
+}
+
+impl<R: BufRead> Iterator for StatementReader<'_, R> {
+    type Item = Result<Rc<FormulaNode>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(node) = self.pending.pop_front() {
+                return Some(Ok(node));
+            }
+            if self.finished {
+                return None;
+            }
+            match self.reader.read_line(&mut self.buffer) {
+                Ok(0) => {
+                    self.finished = true;
+                    let end = self.buffer.len();
+                    self.flush(end);
+                }
+                Ok(_) => {
+                    let end = match self.scan() {
+                        Some(end) => Some(end),
+                        None if self.depth == 0 && !self.in_string => {
+                            self.parser.complete_statements(&self.buffer)
+                        }
+                        None => None,
+                    };
+                    if let Some(end) = end {
+                        self.flush(end);
+                    }
+                }
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(format!("读取公式时出错: {}", e)));
+                }
+            }
+        }
+    }
+}
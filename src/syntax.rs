@@ -0,0 +1,72 @@
+/// 公式语法的 EBNF 描述
+pub const GRAMMAR: &str = include_str!("grammar.ebnf");
+
+/// 关键字，不能作为变量或函数的名称
+pub const KEYWORDS: &[&str] = &["import", "namespace"];
+
+/// 括号、逗号及分号
+pub const PUNCTUATION: &[&str] = &["(", ")", "[", "]", "{", "}", ",", ";"];
+
+/// 操作符的类别，决定了扫描器处理该操作符的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperatorKind {
+    /// 四则运算，右侧扫描到语句结束
+    Math,
+    /// 比较运算，连续的比较会被展开为逻辑与
+    Compare,
+    /// 逻辑运算，右侧只取一个基本节点
+    Logic,
+    /// 一元的逻辑非
+    Unary,
+    /// 命名表达式的 :=
+    Naming,
+    /// 读取历史值的 @
+    History,
+}
+
+/// 一个操作符的定义
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatorSpec {
+    pub symbol: &'static str,
+    pub kind: OperatorKind,
+    pub description: &'static str,
+}
+
+const fn op(symbol: &'static str, kind: OperatorKind, description: &'static str) -> OperatorSpec {
+    OperatorSpec {
+        symbol,
+        kind,
+        description,
+    }
+}
+
+/// 所有的操作符，= 与 == 含义相同
+pub const OPERATORS: &[OperatorSpec] = &[
+    op("+", OperatorKind::Math, "加"),
+    op("-", OperatorKind::Math, "减"),
+    op("*", OperatorKind::Math, "乘"),
+    op("/", OperatorKind::Math, "除"),
+    op("^", OperatorKind::Math, "乘方"),
+    op(">", OperatorKind::Compare, "大于"),
+    op(">=", OperatorKind::Compare, "大于等于"),
+    op("<", OperatorKind::Compare, "小于"),
+    op("<=", OperatorKind::Compare, "小于等于"),
+    op("=", OperatorKind::Compare, "等于"),
+    op("==", OperatorKind::Compare, "等于"),
+    op("!=", OperatorKind::Compare, "不等于"),
+    op("&&", OperatorKind::Logic, "逻辑与"),
+    op("||", OperatorKind::Logic, "逻辑或"),
+    op("!", OperatorKind::Unary, "逻辑非"),
+    op(":=", OperatorKind::Naming, "命名表达式"),
+    op("@", OperatorKind::History, "读取历史值，如 A@-1"),
+];
+
+/// 查找 symbol 对应的操作符
+pub fn operator(symbol: &str) -> Option<&'static OperatorSpec> {
+    OPERATORS.iter().find(|op| op.symbol == symbol)
+}
+
+/// name 是否为关键字
+pub fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
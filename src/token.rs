@@ -0,0 +1,103 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::syntax;
+
+/// 记号在源码中的字节范围
+pub type Span = Range<usize>;
+
+/// 记号的类别，用于语法高亮
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// 变量、函数及命名空间的名称，命名空间中的名称包含 .，如 tax.Rate
+    Identifier,
+    /// import、namespace 等关键字
+    Keyword,
+    /// 数值常量及虚数常量，如 1.5、2i
+    Number,
+    /// import 语句中以双引号包围的文件名
+    String,
+    /// 运算符，包括 :=、&&、!= 及读取历史值的 @
+    Operator,
+    /// 括号、逗号及分号
+    Punctuation,
+    /// 以 # 或 // 开始直到行尾的注释
+    Comment,
+    /// 无法识别的字符
+    Unknown,
+}
+
+/// 将 source 切分为记号，空白字符不会生成记号，所有字符都会被归入某个记号中，
+/// 即使 source 中存在语法错误也不会中断
+pub fn tokenize(source: &str) -> Vec<(TokenKind, Span)> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    let end_of = |chars: &mut core::iter::Peekable<core::str::CharIndices>| {
+        chars.peek().map_or(source.len(), |(i, _)| *i)
+    };
+
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            ' ' | '\t' | '\r' | '\n' => continue,
+            '#' => {
+                skip_line(&mut chars);
+                TokenKind::Comment
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                skip_line(&mut chars);
+                TokenKind::Comment
+            }
+            'A'..='Z' | 'a'..='z' | '_' => {
+                while let Some((_, 'A'..='Z' | 'a'..='z' | '_' | '0'..='9' | '.')) = chars.peek() {
+                    chars.next();
+                }
+                match syntax::is_keyword(&source[start..end_of(&mut chars)]) {
+                    true => TokenKind::Keyword,
+                    false => TokenKind::Identifier,
+                }
+            }
+            '0'..='9' | '.' => {
+                while let Some((_, '0'..='9' | '.')) = chars.peek() {
+                    chars.next();
+                }
+                if let Some((_, 'i')) = chars.peek() {
+                    chars.next();
+                }
+                TokenKind::Number
+            }
+            '"' => {
+                for (_, c) in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                }
+                TokenKind::String
+            }
+            ':' | '<' | '>' | '=' | '!' | '&' | '|' => {
+                if let Some((_, next)) = chars.peek() {
+                    if matches!(
+                        (c, next),
+                        (':' | '<' | '>' | '=' | '!', '=') | ('&', '&') | ('|', '|')
+                    ) {
+                        chars.next();
+                    }
+                }
+                TokenKind::Operator
+            }
+            '+' | '-' | '*' | '/' | '^' | '@' => TokenKind::Operator,
+            '(' | ')' | '[' | ']' | '{' | '}' | ',' | ';' => TokenKind::Punctuation,
+            _ => TokenKind::Unknown,
+        };
+        tokens.push((kind, start..end_of(&mut chars)));
+    }
+    tokens
+}
+
+fn skip_line(chars: &mut core::iter::Peekable<core::str::CharIndices>) {
+    while let Some((_, c)) = chars.peek() {
+        if *c == '\n' {
+            break;
+        }
+        chars.next();
+    }
+}
@@ -0,0 +1,401 @@
+use crate::compat::{HashMap, HashSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use crate::calculator::CalculateOption;
+use crate::formula::{FormulaNode, OperatorNode, Subscript};
+use crate::locale::{ErrorCode, Locale};
+
+/// 静态检查时表达式的类型，与 CalculateOption 中的值类型一一对应
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Number,
+    Bool,
+    Complex,
+    Vector,
+    Matrix,
+    Text,
+    Function,
+    /// 无法在计算前确定的类型，如未声明的变量及函数的返回值，与任意类型兼容
+    Any,
+}
+
+impl Type {
+    /// 类型名称，与 CalculateOption::type_name 一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Number => "num",
+            Type::Bool => "bool",
+            Type::Complex => "complex",
+            Type::Vector => "vector",
+            Type::Matrix => "matrix",
+            Type::Text => "text",
+            Type::Function => "function",
+            Type::Any => "any",
+        }
+    }
+
+    /// 计算结果 value 的类型，金额、宿主对象、错误及 None 为 Any
+    pub fn of(value: &CalculateOption) -> Type {
+        match value {
+            CalculateOption::Num(_) => Type::Number,
+            #[cfg(feature = "rational")]
+            CalculateOption::Rational(_) => Type::Number,
+            CalculateOption::Bool(_) => Type::Bool,
+            CalculateOption::Complex(_) => Type::Complex,
+            CalculateOption::Vector(_) => Type::Vector,
+            CalculateOption::Matrix(_) => Type::Matrix,
+            CalculateOption::Text(_) => Type::Text,
+            CalculateOption::Function(_) => Type::Function,
+            CalculateOption::Money(_)
+            | CalculateOption::Opaque(_)
+            | CalculateOption::Err(_)
+            | CalculateOption::None => Type::Any,
+        }
+    }
+
+    /// 执行环境中的定义 node 的类型，值为常量的表达式得到该常量的类型，其余为 Any
+    pub fn of_node(node: &FormulaNode) -> Type {
+        match node.unquoted() {
+            FormulaNode::Formula { formula, .. } => Type::of_node(formula),
+            FormulaNode::Constant(_) => Type::Number,
+            FormulaNode::Bool(_) => Type::Bool,
+            FormulaNode::Complex(_) => Type::Complex,
+            FormulaNode::Vector(_) => Type::Vector,
+            FormulaNode::Matrix(_) => Type::Matrix,
+            FormulaNode::Text(_) => Type::Text,
+            FormulaNode::Function { .. } | FormulaNode::BuildInFunction { .. } => Type::Function,
+            _ => Type::Any,
+        }
+    }
+
+    /// 类型为 self 的位置是否可以接受类型为 actual 的值
+    pub fn accepts(&self, actual: Type) -> bool {
+        *self == Type::Any || actual == Type::Any || *self == actual
+    }
+
+    /// 是否可以参与四则运算
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Type::Number | Type::Complex | Type::Vector | Type::Matrix | Type::Any
+        )
+    }
+}
+
+impl core::fmt::Display for Type {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// 函数的类型签名，调用时的参数多于 params 时，多出的参数按最后一个参数的类型检查
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub returns: Type,
+}
+
+/// 宿主程序声明的输入变量及函数的类型，用于在计算前检查表达式中的类型错误
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    inputs: HashMap<String, Type>,
+    functions: HashMap<String, Signature>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 声明输入变量 name 的类型
+    pub fn declare(&mut self, name: &str, ty: Type) -> &mut Self {
+        self.inputs.insert(name.to_string(), ty);
+        self
+    }
+
+    /// 声明函数 name 的参数及返回值的类型，内建函数及脚本中定义的函数均可声明
+    pub fn declare_function(&mut self, name: &str, params: &[Type], returns: Type) -> &mut Self {
+        let signature = Signature {
+            params: params.to_vec(),
+            returns,
+        };
+        self.functions.insert(name.to_string(), signature);
+        self
+    }
+
+    /// 输入变量 name 声明的类型
+    pub fn type_of(&self, name: &str) -> Option<Type> {
+        self.inputs.get(name).copied()
+    }
+
+    /// 函数 name 声明的类型签名
+    pub fn signature(&self, name: &str) -> Option<&Signature> {
+        self.functions.get(name)
+    }
+
+    /// 在不计算表达式的前提下检查 ast 中的类型错误，未声明的变量及函数不做检查
+    pub fn typecheck(&self, ast: &FormulaNode) -> Vec<TypeDiagnostic> {
+        TypeChecker::new(self, &|_| None, Locale::default()).check(ast)
+    }
+}
+
+/// 类型检查发现的问题
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDiagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    /// 类型不符的表达式，以语法树输出的规范形式表示
+    pub expression: String,
+    pub expected: Type,
+    pub found: Type,
+}
+
+/// 推导语法树中每个表达式的类型，并收集类型不符的位置
+pub(crate) struct TypeChecker<'a> {
+    schema: &'a Schema,
+    /// 查找 schema 中未声明的名称在执行环境中的类型
+    lookup: &'a dyn Fn(&str) -> Option<Type>,
+    locale: Locale,
+    /// 函数的参数及局部变量，会遮蔽同名的输入变量
+    scopes: Vec<HashSet<String>>,
+    diagnostics: Vec<TypeDiagnostic>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(
+        schema: &'a Schema,
+        lookup: &'a dyn Fn(&str) -> Option<Type>,
+        locale: Locale,
+    ) -> Self {
+        TypeChecker {
+            schema,
+            lookup,
+            locale,
+            scopes: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn check(mut self, ast: &FormulaNode) -> Vec<TypeDiagnostic> {
+        self.infer(ast);
+        self.diagnostics
+    }
+
+    fn infer(&mut self, node: &FormulaNode) -> Type {
+        match node {
+            FormulaNode::Constant(_) => Type::Number,
+            FormulaNode::Bool(_) => Type::Bool,
+            FormulaNode::Complex(_) => Type::Complex,
+            FormulaNode::Vector(_) => Type::Vector,
+            FormulaNode::Matrix(_) => Type::Matrix,
+            FormulaNode::Text(_) => Type::Text,
+            FormulaNode::Variant(name) | FormulaNode::History { name, .. } => self.variable(name),
+            FormulaNode::Index { target, index } => {
+                let indexable = |ty: &Type| matches!(ty, Type::Vector | Type::Matrix | Type::Any);
+                let found = self.operand("[]", target, Type::Vector, indexable);
+                let number = |ty: &Type| matches!(ty, Type::Number | Type::Any);
+                for node in index.nodes() {
+                    self.operand("[]", node, Type::Number, number);
+                }
+                match (found, index) {
+                    (Type::Vector, Subscript::At(_)) => Type::Number,
+                    (Type::Matrix, Subscript::At(_)) => Type::Vector,
+                    (Type::Vector | Type::Matrix, Subscript::Slice(..)) => found,
+                    _ => Type::Any,
+                }
+            }
+            FormulaNode::Operator(op) => self.operator(op),
+            FormulaNode::FunctionCall { name, args } => {
+                let types: Vec<Type> = args.iter().map(|arg| self.infer(arg)).collect();
+                let signature = match self.is_local(name) {
+                    true => None,
+                    false => self.schema.signature(name),
+                };
+                let Some(signature) = signature else {
+                    return Type::Any;
+                };
+                for (index, (arg, found)) in args.iter().zip(types).enumerate() {
+                    let expected = match signature.params.get(index) {
+                        Some(ty) => *ty,
+                        None => match signature.params.last() {
+                            Some(ty) => *ty,
+                            None => break,
+                        },
+                    };
+                    if !expected.accepts(found) {
+                        self.report(
+                            ErrorCode::ArgumentTypeMismatch,
+                            arg,
+                            expected,
+                            found,
+                            &[name, &(index + 1), &expected, &found],
+                        );
+                    }
+                }
+                signature.returns
+            }
+            FormulaNode::Function {
+                args, expressions, ..
+            } => {
+                let scope = args
+                    .iter()
+                    .filter_map(|arg| match arg.as_ref() {
+                        FormulaNode::Variant(name) => Some(name.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                self.scopes.push(scope);
+                for exp in expressions {
+                    self.infer(exp);
+                }
+                self.scopes.pop();
+                Type::Function
+            }
+            FormulaNode::BuildInFunction { .. } => Type::Function,
+            FormulaNode::Formula { name, formula } => {
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone());
+                }
+                self.infer(formula)
+            }
+            FormulaNode::Namespace { definitions, .. } => {
+                for definition in definitions {
+                    self.infer(definition);
+                }
+                Type::Any
+            }
+            FormulaNode::Arg { value, .. } => self.infer(value),
+            FormulaNode::Quote(node) => self.infer(node),
+            FormulaNode::Money(_)
+            | FormulaNode::Opaque(_)
+            | FormulaNode::Import(_)
+            | FormulaNode::UnKnow(_)
+            | FormulaNode::None => Type::Any,
+        }
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn variable(&self, name: &str) -> Type {
+        if self.is_local(name) {
+            return Type::Any;
+        }
+        self.schema
+            .type_of(name)
+            .or_else(|| (self.lookup)(name))
+            .unwrap_or(Type::Any)
+    }
+
+    fn operator(&mut self, op: &OperatorNode) -> Type {
+        let symbol = op.symbol();
+        match op {
+            OperatorNode::Plus { left, right }
+            | OperatorNode::Minus { left, right }
+            | OperatorNode::Divide { left, right }
+            | OperatorNode::Multiply { left, right }
+            | OperatorNode::Power { left, right } => {
+                // 复数不支持乘方
+                let numeric: fn(&Type) -> bool = match op {
+                    OperatorNode::Power { .. } => |ty| ty.is_numeric() && *ty != Type::Complex,
+                    _ => Type::is_numeric,
+                };
+                let l = self.operand(symbol, left, Type::Number, numeric);
+                let r = self.operand(symbol, right, Type::Number, numeric);
+                let matrix_op = matches!(op, OperatorNode::Multiply { .. })
+                    || (l == r
+                        && matches!(op, OperatorNode::Plus { .. } | OperatorNode::Minus { .. }));
+                match (l, r) {
+                    (Type::Any, _) | (_, Type::Any) => Type::Any,
+                    (Type::Matrix, Type::Vector) if matrix_op => Type::Vector,
+                    (Type::Matrix, Type::Matrix) if matrix_op => Type::Matrix,
+                    (Type::Matrix, Type::Number) | (Type::Number, Type::Matrix) => Type::Matrix,
+                    (Type::Vector, Type::Complex)
+                    | (Type::Complex, Type::Vector)
+                    | (Type::Matrix, _)
+                    | (_, Type::Matrix) => {
+                        self.report(
+                            ErrorCode::OperandTypeMismatch,
+                            right,
+                            l,
+                            r,
+                            &[&symbol, &l, &r],
+                        );
+                        Type::Any
+                    }
+                    (Type::Vector, _) | (_, Type::Vector) => Type::Vector,
+                    (Type::Complex, _) | (_, Type::Complex) => Type::Complex,
+                    _ => Type::Number,
+                }
+            }
+            OperatorNode::Less { left, right }
+            | OperatorNode::LessEqual { left, right }
+            | OperatorNode::Great { left, right }
+            | OperatorNode::GreatEqual { left, right } => {
+                let number = |ty: &Type| matches!(ty, Type::Number | Type::Any);
+                self.operand(symbol, left, Type::Number, number);
+                self.operand(symbol, right, Type::Number, number);
+                Type::Bool
+            }
+            OperatorNode::Equal { left, right } | OperatorNode::NotEqual { left, right } => {
+                let scalar =
+                    |ty: &Type| matches!(ty, Type::Number | Type::Complex | Type::Text | Type::Any);
+                self.operand(symbol, left, Type::Number, scalar);
+                self.operand(symbol, right, Type::Number, scalar);
+                Type::Bool
+            }
+            OperatorNode::And { left, right } | OperatorNode::Or { left, right } => {
+                let logic = |ty: &Type| matches!(ty, Type::Bool | Type::Number | Type::Any);
+                self.operand(symbol, left, Type::Bool, logic);
+                self.operand(symbol, right, Type::Bool, logic);
+                Type::Bool
+            }
+            OperatorNode::Not(node) => {
+                let logic = |ty: &Type| matches!(ty, Type::Bool | Type::Number | Type::Any);
+                self.operand(symbol, node, Type::Bool, logic);
+                Type::Bool
+            }
+        }
+    }
+
+    /// 推导操作数 node 的类型，不满足 allowed 时以 expected 作为期望的类型报告问题
+    fn operand(
+        &mut self,
+        symbol: &str,
+        node: &FormulaNode,
+        expected: Type,
+        allowed: fn(&Type) -> bool,
+    ) -> Type {
+        let found = self.infer(node);
+        if !allowed(&found) {
+            self.report(
+                ErrorCode::OperandTypeMismatch,
+                node,
+                expected,
+                found,
+                &[&symbol, &expected, &found],
+            );
+        }
+        found
+    }
+
+    fn report(
+        &mut self,
+        code: ErrorCode,
+        node: &FormulaNode,
+        expected: Type,
+        found: Type,
+        args: &[&dyn Display],
+    ) {
+        self.diagnostics.push(TypeDiagnostic {
+            code,
+            message: code.message(self.locale, args),
+            expression: node.to_string(),
+            expected,
+            found,
+        });
+    }
+}
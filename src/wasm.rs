@@ -0,0 +1,62 @@
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::calculator::CalculateOption;
+use crate::parser::Parser;
+
+/// 导出到 JavaScript 的解析器，语法树及计算结果均以 JSON 字符串返回，
+/// 其格式与通过 serde 序列化 FormulaNode 及 CalculateOption 得到的相同，如 {"Num":3.0}
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct WasmParser {
+    parser: Parser,
+}
+
+#[wasm_bindgen]
+impl WasmParser {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmParser {
+        WasmParser::default()
+    }
+
+    /// 解析 formula 并返回语法树的 JSON，其中具名的表达式及函数会被加入解析器的环境中
+    pub fn parse(&mut self, formula: &str) -> String {
+        to_json(self.parser.parse(formula).as_ref())
+    }
+
+    /// 计算 formula 并返回计算结果的 JSON
+    pub fn calculate(&mut self, formula: &str) -> String {
+        to_json(&self.parser.calculate(formula).value)
+    }
+
+    /// 将数值 value 绑定到变量 name 上，name 已被定义为只读时返回 false
+    pub fn set_var(&self, name: &str, value: f64) -> bool {
+        self.parser.set_var(name, value)
+    }
+
+    /// 将 JSON 格式的计算结果绑定到变量 name 上，用于绑定逻辑值、向量等非数值类型
+    pub fn set_var_json(&self, name: &str, json: &str) -> bool {
+        match serde_json::from_str::<CalculateOption>(json) {
+            Ok(value) => self.parser.set_var(name, value),
+            Err(_) => false,
+        }
+    }
+
+    /// 将解析器环境中的变量及函数序列化为 JSON，出错时返回 None
+    pub fn to_json(&self) -> Option<String> {
+        self.parser.to_json().ok()
+    }
+
+    /// 加载 to_json 得到的变量及函数，出错时返回错误信息
+    pub fn load_json(&self, json: &str) -> Option<String> {
+        self.parser.load_json(json).err()
+    }
+}
+
+/// 序列化失败时返回以 JSON 表示的错误
+fn to_json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|e| {
+        let error = CalculateOption::Err(format!("序列化结果时出错: {}", e));
+        serde_json::to_string(&error).unwrap_or_default()
+    })
+}